@@ -1,26 +1,57 @@
 use cargo_metadata::{Metadata, MetadataCommand, Package};
-use clap::{Parser, Subcommand};
-use colored::Colorize;
-use ocipkg::{error::*, ImageName};
+use clap::{Args, CommandFactory, Parser, Subcommand, ValueEnum};
+use colored::{ColoredString, Colorize};
+use oci_spec::image::{MediaType, Os, Platform};
+use ocipkg::{
+    distribution::Client,
+    error::*,
+    image::{
+        lint::{Check, LintOptions},
+        PlatformEx,
+    },
+    Digest, ImageName,
+};
+use ocipkg_cli::{
+    completion::{Shell, IMAGE_NAME},
+    exit_code::{ExitCode, EXIT_STATUS_HELP},
+};
 use std::{
-    collections::hash_map::DefaultHasher,
-    fs,
+    collections::{hash_map::DefaultHasher, HashMap},
+    fmt, fs,
     hash::{Hash, Hasher},
     path::{Path, PathBuf},
     process::Command,
+    sync::atomic::{AtomicBool, Ordering},
 };
 
 #[derive(Parser, Debug)]
 #[clap(version)]
 enum Opt {
-    #[clap(subcommand)]
-    Ocipkg(Ocipkg),
+    Ocipkg(OcipkgArgs),
+}
+
+#[derive(Args, Debug)]
+struct OcipkgArgs {
+    /// Show high-level operations, `-vv` for HTTP requests, and `-vvv` for their headers.
+    /// `RUST_LOG` overrides this flag.
+    #[arg(short, long, action = clap::ArgAction::Count, global = true)]
+    verbose: u8,
+
+    /// Suppress all output except errors
+    #[arg(short, long, global = true, conflicts_with = "verbose")]
+    quiet: bool,
+
+    #[command(subcommand)]
+    command: Ocipkg,
 }
 
 #[derive(Subcommand, Debug)]
-#[clap(version)]
+#[clap(version, after_help = EXIT_STATUS_HELP)]
 enum Ocipkg {
     /// Build library or executable, and pack as a container
+    ///
+    /// The same libraries result in the same image digest, where the modification times
+    /// and the creation time are given by `SOURCE_DATE_EPOCH` or 0.
     Build {
         #[clap(long)]
         release: bool,
@@ -29,15 +60,81 @@ enum Ocipkg {
         /// Name of container
         #[clap(short = 't', long = "tag")]
         tag: Option<String>,
+        /// Target triple to build for. Multiple targets create a multi-platform image.
+        #[clap(long = "target")]
+        targets: Vec<String>,
+        /// Fail if any of targets fails to build, instead of creating an image without it
+        #[clap(long)]
+        require_all: bool,
     },
 
     /// Publish container to OCI registry
+    ///
+    /// Names and digests of the published images are recorded in `ocipkg.lock` of the workspace.
     Publish {
         #[clap(short = 'p', long = "package-name")]
         package_name: Option<String>,
         #[clap(long)]
         release: bool,
+        /// Push even if the registry already has the same image
+        #[clap(long)]
+        force: bool,
+        /// Command run after successful push, overrides `package.metadata.ocipkg.post-publish`
+        ///
+        /// It runs for each pushed image after `ocipkg.lock` and the digest file are written,
+        /// with `OCIPKG_IMAGE`, `OCIPKG_DIGEST`, and `OCIPKG_REGISTRY` environment variables.
+        #[clap(long)]
+        post_publish: Option<String>,
+        /// Do not fail even if the post-publish command fails
+        #[clap(long)]
+        post_publish_advisory: bool,
+        /// Sign pushed images as cosign by the private key in PEM, or `env://{NAME}`
+        #[clap(long)]
+        sign_key: Option<String>,
+        /// Attach SPDX or CycloneDX SBOM in JSON to pushed images as a referrer
+        #[clap(long)]
+        sbom: Option<PathBuf>,
+        /// Write the digest of pushed manifest into the file
+        #[clap(long)]
+        digest_file: Option<PathBuf>,
+        /// Target triples used in build, to resolve `{target}` in name template
+        #[clap(long = "target")]
+        targets: Vec<String>,
+    },
+
+    /// Check the built container before release
+    Verify {
+        #[clap(short = 'p', long = "package-name")]
+        package_name: Option<String>,
+        #[clap(long)]
+        release: bool,
+        /// Name of container
+        #[clap(short = 't', long = "tag", value_name = IMAGE_NAME)]
+        tag: Option<String>,
+        /// Upper limit of total layer size in bytes, overrides `package.metadata.ocipkg.max-size`
+        #[clap(long)]
+        max_size: Option<u64>,
+        /// Skip rebuilding to check the reproducibility
+        #[clap(long)]
+        skip_reproducible: bool,
+        /// Target triples used in build, to resolve `{target}` in name template
+        #[clap(long = "target")]
+        targets: Vec<String>,
+        #[clap(long, value_enum, default_value_t = Format::Text)]
+        format: Format,
     },
+
+    /// Print completion script for the shell
+    Completions {
+        #[clap(value_enum)]
+        shell: Shell,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Format {
+    Text,
+    Json,
 }
 
 fn get_metadata() -> Metadata {
@@ -96,146 +193,851 @@ fn get_revision(manifest_path: &Path) -> String {
     hash
 }
 
-fn generate_image_name(package: &Package) -> ImageName {
+/// Image name from `package.metadata.ocipkg` in Cargo.toml
+///
+/// `registry` is used with the git revision as tag.
+/// If `name-template` is set, it is used instead of `registry`,
+/// and `{crate}`, `{version}`, `{target}`, `{profile}`, and `{revision}` are replaced.
+/// The template is validated before building to fail early.
+fn generate_image_name(package: &Package, release: bool, targets: &[String]) -> ImageName {
     use serde_json::Value;
-    match &package.metadata {
-        Value::Object(obj) => {
-            match obj
-                .get("ocipkg")
-                .expect("`package.metadata.ocipkg` is missing")
-            {
-                Value::Object(obj) => {
-                    if let Value::String(ref registry) = obj
-                        .get("registry")
-                        .expect("`package.metadata.ocipkg` does not have `registry`")
-                    {
-                        let rev = get_revision(package.manifest_path.as_std_path());
-
-                        ImageName::parse(&format!("{}:{}", registry, rev))
-                            .expect("Invalud registry URL")
-                    } else {
-                        panic!("`package.metadata.ocipkg.registry` must be a string")
-                    }
-                }
-                _ => panic!("`package.metadata.ocipkg` must be a map"),
-            }
-        }
+    let ocipkg = match &package.metadata {
+        Value::Object(obj) => obj
+            .get("ocipkg")
+            .expect("`package.metadata.ocipkg` is missing"),
         _ => {
             panic!("`package.metadata.ocipkg` in Cargo.toml is required to generate container name")
         }
+    };
+    let Value::Object(obj) = ocipkg else {
+        panic!("`package.metadata.ocipkg` must be a map")
+    };
+    let rev = get_revision(package.manifest_path.as_std_path());
+
+    if let Some(template) = obj.get("name-template") {
+        let Value::String(template) = template else {
+            panic!("`package.metadata.ocipkg.name-template` must be a string")
+        };
+        let target = match targets {
+            [] => host_target_triple(),
+            [target] => target.clone(),
+            _ if template.contains("{target}") => {
+                panic!("`{{target}}` in name-template cannot be used with multiple targets")
+            }
+            _ => String::new(),
+        };
+        let version = package.version.to_string();
+        let vars = [
+            ("crate", package.name.as_str()),
+            ("version", version.as_str()),
+            ("target", target.as_str()),
+            ("profile", if release { "release" } else { "debug" }),
+            ("revision", rev.as_str()),
+        ];
+        let name = match render_name_template(template, &vars) {
+            Ok(name) => name,
+            Err(e) => panic!("Invalid `package.metadata.ocipkg.name-template`: {}", e),
+        };
+        // Use revision as tag if the template does not have a tag
+        let has_tag = name
+            .rsplit('/')
+            .next()
+            .is_some_and(|last| last.contains(':'));
+        let name = if has_tag {
+            name
+        } else {
+            format!("{}:{}", name, rev)
+        };
+        return match ImageName::parse(&name) {
+            Ok(name) => name,
+            Err(e) => panic!(
+                "`package.metadata.ocipkg.name-template` generates invalid name {}: {}",
+                name, e
+            ),
+        };
+    }
+
+    if let Value::String(ref registry) = obj
+        .get("registry")
+        .expect("`package.metadata.ocipkg` does not have `registry` or `name-template`")
+    {
+        ImageName::parse(&format!("{}:{}", registry, rev)).expect("Invalud registry URL")
+    } else {
+        panic!("`package.metadata.ocipkg.registry` must be a string")
+    }
+}
+
+/// Replace `{name}` in template by the value of variable
+fn render_name_template(
+    template: &str,
+    vars: &[(&str, &str)],
+) -> std::result::Result<String, String> {
+    let mut out = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        out.push_str(&rest[..start]);
+        let end = rest[start..]
+            .find('}')
+            .ok_or_else(|| format!("Unclosed `{{` in {}", template))?;
+        let var = &rest[start + 1..start + end];
+        let (_, value) = vars
+            .iter()
+            .find(|(name, _)| *name == var)
+            .ok_or_else(|| format!("Unknown variable `{{{}}}`", var))?;
+        out.push_str(value);
+        rest = &rest[start + end + 1..];
     }
+    out.push_str(rest);
+    Ok(out)
+}
+
+/// Target triple of the host, i.e. the default target of cargo
+fn host_target_triple() -> String {
+    let output = Command::new("rustc")
+        .arg("-vV")
+        .output()
+        .expect("Failed to run rustc");
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .find_map(|line| line.strip_prefix("host: "))
+        .expect("rustc -vV does not return host")
+        .to_string()
 }
 
 fn generate_oci_archive_filename(
     image_name: &ImageName,
     target: &cargo_metadata::Target,
+    target_triple: Option<&str>,
 ) -> String {
     let mut hasher = DefaultHasher::new();
     image_name.hash(&mut hasher);
     target.hash(&mut hasher);
+    target_triple.hash(&mut hasher);
     let hash = hasher.finish();
     format!("ocipkg_{:x}.tar", hash)
 }
 
-fn main() -> Result<()> {
-    env_logger::Builder::new()
-        .filter_level(log::LevelFilter::Info)
-        .parse_default_env()
-        .init();
+/// Build with `cross` instead of `cargo` if `package.metadata.ocipkg.cross = true`
+fn use_cross(package: &Package) -> bool {
+    package
+        .metadata
+        .get("ocipkg")
+        .and_then(|ocipkg| ocipkg.get("cross"))
+        .and_then(|cross| cross.as_bool())
+        .unwrap_or(false)
+}
+
+fn host_os() -> Os {
+    if cfg!(target_os = "windows") {
+        Os::Windows
+    } else if cfg!(target_os = "macos") {
+        Os::Darwin
+    } else {
+        Os::Linux
+    }
+}
+
+fn library_file_name(name: &str, crate_type: &str, os: &Os) -> Option<String> {
+    let name = name.replace('-', "_");
+    match (crate_type, os) {
+        ("staticlib", Os::Windows) => Some(format!("{}.lib", name)),
+        ("staticlib", _) => Some(format!("lib{}.a", name)),
+        ("cdylib", Os::Windows) => Some(format!("{}.dll", name)),
+        ("cdylib", Os::Darwin) => Some(format!("lib{}.dylib", name)),
+        ("cdylib", _) => Some(format!("lib{}.so", name)),
+        _ => None,
+    }
+}
+
+fn get_annotations(package: &Package) -> ocipkg::image::annotations::flat::Annotations {
+    let mut annotations = ocipkg::image::annotations::flat::Annotations {
+        url: package.homepage.clone().or(package.repository.clone()),
+        source: package.repository.clone(),
+        licenses: package.license.clone(),
+        description: package.description.clone(),
+        version: Some(package.version.to_string()),
+        revision: Some(get_revision(package.manifest_path.as_std_path())),
+        ..Default::default()
+    };
+    if !package.authors.is_empty() {
+        annotations.authors = Some(package.authors.join(","))
+    }
+    annotations
+}
+
+/// Pack built libraries of the target into an oci-archive
+fn pack_target(
+    package: &Package,
+    target: &cargo_metadata::Target,
+    image_name: &ImageName,
+    build_dir: &Path,
+    platform: Option<&Platform>,
+    dest: &Path,
+) -> std::result::Result<(), String> {
+    let os = platform.map(|p| p.os().clone()).unwrap_or_else(host_os);
+    let targets: Vec<PathBuf> = target
+        .crate_types
+        .iter()
+        .filter_map(|ty| library_file_name(&target.name, ty, &os))
+        .map(|name| build_dir.join(name))
+        .collect();
+    if targets.is_empty() {
+        return Err(
+            "No target exists for packing. Only staticlib or cdylib are suppoted.".to_string(),
+        );
+    }
+
+    status(
+        "Creating".green().bold(),
+        format!("oci-archive ({})", dest.display()),
+    );
+    let mtime = source_date_epoch()?;
+    write_archive(
+        dest,
+        image_name,
+        get_annotations(package),
+        platform,
+        &targets,
+        mtime,
+    )
+    .map_err(|e| e.to_string())
+}
+
+/// Modification time of files and creation time of images given by `SOURCE_DATE_EPOCH`, or 0
+fn source_date_epoch() -> std::result::Result<u64, String> {
+    match std::env::var("SOURCE_DATE_EPOCH") {
+        Ok(epoch) => epoch
+            .parse()
+            .map_err(|_| format!("SOURCE_DATE_EPOCH is not an integer: {}", epoch)),
+        Err(_) => Ok(0),
+    }
+}
+
+/// Write oci-archive of the files, which has the same digest for the same files and `mtime`
+fn write_archive(
+    dest: &Path,
+    image_name: &ImageName,
+    annotations: ocipkg::image::annotations::flat::Annotations,
+    platform: Option<&Platform>,
+    files: &[PathBuf],
+    mtime: u64,
+) -> Result<()> {
+    let f = fs::File::create(dest)?;
+    let mut b = ocipkg::image::Builder::new(f);
+    b.set_name(image_name);
+    b.set_annotations(annotations);
+    b.set_created(chrono::DateTime::from_timestamp(mtime as i64, 0).unwrap_or_default());
+    if let Some(platform) = platform {
+        b.set_platform(platform);
+    }
+    let options = ocipkg::image::PackOptions {
+        mtime,
+        ..Default::default()
+    };
+    b.append_layer(ocipkg::image::pack_files(files, &options)?)?;
+    let _output = b.into_inner()?;
+    Ok(())
+}
+
+/// Build the package for the target triple, and pack each library target into oci-archive
+///
+/// Returns paths of created oci-archives for each library target.
+fn build_for_target(
+    metadata: &Metadata,
+    package: &Package,
+    image_name: &ImageName,
+    release: bool,
+    target_triple: &str,
+) -> std::result::Result<Vec<PathBuf>, String> {
+    let platform = Platform::from_target_triple(target_triple).map_err(|e| e.to_string())?;
+    let mut cmd = Command::new(if use_cross(package) { "cross" } else { "cargo" });
+    cmd.arg("build");
+    if release {
+        cmd.arg("--release");
+    }
+    let status = cmd
+        .args(["--target", target_triple])
+        .args(["--manifest-path", package.manifest_path.as_str()])
+        .status()
+        .map_err(|e| e.to_string())?;
+    if !status.success() {
+        return Err(format!("Build failed with {}", status));
+    }
 
-    match Opt::parse() {
-        Opt::Ocipkg(Ocipkg::Build {
+    let target_dir = metadata.target_directory.clone().into_std_path_buf();
+    let build_dir = target_dir
+        .join(target_triple)
+        .join(if release { "release" } else { "debug" });
+    let mut archives = Vec::new();
+    for target in &package.targets {
+        let dest = build_dir.join(generate_oci_archive_filename(
+            image_name,
+            target,
+            Some(target_triple),
+        ));
+        pack_target(
+            package,
+            target,
+            image_name,
+            &build_dir,
+            Some(&platform),
+            &dest,
+        )?;
+        archives.push(dest);
+    }
+    Ok(archives)
+}
+
+/// Digest of the manifest (or image index) listed in `index.json` of oci-archive
+fn get_archive_digest(archive: &Path) -> Result<Digest> {
+    let mut f = fs::File::open(archive)?;
+    let mut ar = ocipkg::image::Archive::new(&mut f);
+    let index = ar.get_index()?;
+    let desc = index.manifests().first().ok_or(Error::MissingIndex)?;
+    Digest::new(desc.digest())
+}
+
+/// Check if the image in oci-archive has been published as `image_name` with the same digest
+///
+/// When the digests differ, layers changed from the published image are logged.
+fn is_published(image_name: &ImageName, archive: &Path) -> Result<bool> {
+    let mut f = fs::File::open(archive)?;
+    let mut ar = ocipkg::image::Archive::new(&mut f);
+    let index = ar.get_index()?;
+    let desc = index.manifests().first().ok_or(Error::MissingIndex)?;
+    let local = Digest::new(desc.digest())?;
+
+    let mut client = Client::from_image_name(image_name)?;
+    let Some(remote) = client.head_manifest(&image_name.reference)? else {
+        return Ok(false);
+    };
+    if remote.digest == local {
+        return Ok(true);
+    }
+
+    log::info!(
+        "Manifest digest changed from {} to {}",
+        remote.digest,
+        local
+    );
+    if desc.media_type() == &MediaType::ImageManifest
+        && remote.media_type == MediaType::ImageManifest
+    {
+        let local_layers: Vec<_> = ar
+            .get_manifest(&local)?
+            .layers()
+            .iter()
+            .map(|layer| layer.digest().clone())
+            .collect();
+        let remote_layers: Vec<_> = client
+            .get_manifest(&image_name.reference)?
+            .layers()
+            .iter()
+            .map(|layer| layer.digest().clone())
+            .collect();
+        for layer in &local_layers {
+            if !remote_layers.contains(layer) {
+                log::info!("Layer added: {}", layer);
+            }
+        }
+        for layer in &remote_layers {
+            if !local_layers.contains(layer) {
+                log::info!("Layer removed: {}", layer);
+            }
+        }
+        if local_layers == remote_layers {
+            log::info!("Layers are not changed, only the configuration differs");
+        }
+    }
+    Ok(false)
+}
+
+/// `package.metadata.ocipkg.post-publish` in Cargo.toml
+fn get_post_publish(package: &Package) -> Option<String> {
+    package
+        .metadata
+        .get("ocipkg")
+        .and_then(|ocipkg| ocipkg.get("post-publish"))
+        .map(|command| {
+            command
+                .as_str()
+                .expect("`package.metadata.ocipkg.post-publish` must be a string")
+                .to_string()
+        })
+}
+
+/// Run post-publish command by the shell
+fn run_post_publish(
+    command: &str,
+    image_name: &ImageName,
+    digest: &Digest,
+    advisory: bool,
+) -> Result<()> {
+    let registry = if let Some(port) = image_name.port {
+        format!("{}:{}", image_name.hostname, port)
+    } else {
+        image_name.hostname.clone()
+    };
+    let mut cmd = if cfg!(target_os = "windows") {
+        let mut cmd = Command::new("cmd");
+        cmd.arg("/C");
+        cmd
+    } else {
+        let mut cmd = Command::new("sh");
+        cmd.arg("-c");
+        cmd
+    };
+    status("Running".green().bold(), command);
+    let status = cmd
+        .arg(command)
+        .env("OCIPKG_IMAGE", image_name.to_string())
+        .env("OCIPKG_DIGEST", digest.to_string())
+        .env("OCIPKG_REGISTRY", registry)
+        .status()?;
+    if !status.success() {
+        if advisory {
+            eprintln!(
+                "{:>12} post-publish command failed with {}",
+                "Warning".yellow().bold(),
+                status
+            );
+        } else {
+            return Err(Error::PostPublishFailed {
+                image: image_name.to_string(),
+                status: status.to_string(),
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Write digests of pushed manifests into the file, one per line
+fn write_digest_file(path: &Path, digests: &[Digest]) -> Result<()> {
+    let content: String = digests.iter().map(|d| format!("{}\n", d)).collect();
+    fs::write(path, content)?;
+    Ok(())
+}
+
+/// `package.metadata.ocipkg.max-size` in Cargo.toml
+fn get_max_size(package: &Package) -> Option<u64> {
+    package
+        .metadata
+        .get("ocipkg")
+        .and_then(|ocipkg| ocipkg.get("max-size"))
+        .map(|size| {
+            size.as_u64()
+                .expect("`package.metadata.ocipkg.max-size` must be an integer")
+        })
+}
+
+/// Rebuild the package in another target directory, and compare the files in the containers
+fn check_reproducible(
+    package: &Package,
+    target: &cargo_metadata::Target,
+    image_name: &ImageName,
+    release: bool,
+    archive: &Path,
+    target_dir: &Path,
+) -> Result<Check> {
+    let mut cmd = Command::new("cargo");
+    cmd.arg("build");
+    if release {
+        cmd.arg("--release");
+    }
+    let status = cmd
+        .args(["--manifest-path", package.manifest_path.as_str()])
+        .arg("--target-dir")
+        .arg(target_dir)
+        .status()?;
+    if !status.success() {
+        return Ok(Check::fail(
+            "reproducible",
+            format!("Rebuild failed with {}", status),
+        ));
+    }
+    let build_dir = target_dir.join(if release { "release" } else { "debug" });
+    let rebuilt = build_dir.join(generate_oci_archive_filename(image_name, target, None));
+    if let Err(e) = pack_target(package, target, image_name, &build_dir, None, &rebuilt) {
+        return Ok(Check::fail("reproducible", e));
+    }
+    let diff: Vec<_> = {
+        let original = ocipkg::image::lint::file_digests(archive)?;
+        let rebuilt = ocipkg::image::lint::file_digests(&rebuilt)?;
+        original
+            .keys()
+            .chain(rebuilt.keys())
+            .filter(|path| original.get(*path) != rebuilt.get(*path))
+            .map(|path| path.display().to_string())
+            .collect()
+    };
+    if diff.is_empty() {
+        Ok(Check::pass(
+            "reproducible",
+            format!("{}: rebuilt files are identical", target.name),
+        ))
+    } else {
+        Ok(Check::fail(
+            "reproducible",
+            format!("{}: rebuilt files differ: {}", target.name, diff.join(", ")),
+        ))
+    }
+}
+
+fn print_checks(checks: &[Check], format: Format) -> Result<()> {
+    match format {
+        Format::Text => {
+            for check in checks {
+                if check.passed {
+                    status("Pass".green().bold(), &check.message);
+                } else {
+                    eprintln!("{:>12} {}", "Fail".red().bold(), check.message);
+                }
+            }
+        }
+        Format::Json => println!("{}", serde_json::to_string_pretty(checks)?),
+    }
+    Ok(())
+}
+
+/// Set by `--quiet`
+static QUIET: AtomicBool = AtomicBool::new(false);
+
+/// Print a status line in the style of cargo to stderr unless `--quiet`
+fn status(label: ColoredString, message: impl fmt::Display) {
+    if !QUIET.load(Ordering::Relaxed) {
+        eprintln!("{:>12} {}", label, message);
+    }
+}
+
+fn main() {
+    ocipkg_cli::completion::complete(|| Opt::command().name("cargo-ocipkg"));
+    let Opt::Ocipkg(args) = Opt::parse();
+    ocipkg_cli::logging::builder(args.verbose, args.quiet).init();
+    QUIET.store(args.quiet, Ordering::Relaxed);
+
+    if let Err(e) = run(args.command) {
+        eprintln!("Error: {:?}", e);
+        ExitCode::from(&e).exit();
+    }
+}
+
+fn run(command: Ocipkg) -> Result<()> {
+    match command {
+        Ocipkg::Build {
             package_name,
             release,
             tag,
-        }) => {
+            targets,
+            require_all,
+        } => {
             let metadata = get_metadata();
             let package = get_package(&metadata, package_name);
             let build_dir = get_build_dir(&metadata, release);
             let image_name = if let Some(ref tag) = tag {
                 ImageName::parse(tag)?
             } else {
-                generate_image_name(&package)
+                generate_image_name(&package, release, &targets)
             };
+            status("Image".green().bold(), &image_name);
 
-            let mut cmd = Command::new("cargo");
-            cmd.arg("build");
-            if release {
-                cmd.arg("--release");
-            }
-            cmd.args(["--manifest-path", package.manifest_path.as_str()])
-                .status()?;
+            if targets.is_empty() {
+                let mut cmd = Command::new("cargo");
+                cmd.arg("build");
+                if release {
+                    cmd.arg("--release");
+                }
+                cmd.args(["--manifest-path", package.manifest_path.as_str()])
+                    .status()?;
 
-            for target in package.targets {
-                let mut targets = Vec::new();
-                for ty in &target.crate_types {
-                    // FIXME support non-Linux OS
-                    match ty.as_str() {
-                        "staticlib" => {
-                            targets.push(
-                                build_dir.join(format!("lib{}.a", target.name.replace('-', "_"))),
-                            );
-                        }
-                        "cdylib" => {
-                            targets.push(
-                                build_dir.join(format!("lib{}.so", target.name.replace('-', "_"))),
-                            );
-                        }
-                        _ => {}
+                for target in &package.targets {
+                    let dest =
+                        build_dir.join(generate_oci_archive_filename(&image_name, target, None));
+                    if let Err(e) =
+                        pack_target(&package, target, &image_name, &build_dir, None, &dest)
+                    {
+                        panic!("{}", e);
                     }
                 }
-                if targets.is_empty() {
-                    panic!("No target exists for packing. Only staticlib or cdylib are suppoted.");
-                }
+                return Ok(());
+            }
 
-                let mut annotations = ocipkg::image::annotations::flat::Annotations {
-                    url: package.homepage.clone().or(package.repository.clone()),
-                    licenses: package.license.clone(),
-                    description: package.description.clone(),
-                    version: Some(package.version.to_string()),
-                    revision: Some(get_revision(package.manifest_path.as_std_path())),
-                    ..Default::default()
-                };
-                if !package.authors.is_empty() {
-                    annotations.authors = Some(package.authors.join(","))
+            // Archives for each library target, built for each target triple
+            let mut archives: Vec<Vec<PathBuf>> = vec![Vec::new(); package.targets.len()];
+            let mut failed = Vec::new();
+            for target_triple in &targets {
+                match build_for_target(&metadata, &package, &image_name, release, target_triple) {
+                    Ok(paths) => {
+                        for (i, path) in paths.into_iter().enumerate() {
+                            archives[i].push(path);
+                        }
+                    }
+                    Err(e) => {
+                        eprintln!(
+                            "{:>12} target {}: {}",
+                            "Failed".red().bold(),
+                            target_triple,
+                            e
+                        );
+                        failed.push(target_triple.clone());
+                    }
                 }
+            }
+            if failed.len() == targets.len() {
+                panic!("Build failed for all targets");
+            }
+            if require_all && !failed.is_empty() {
+                panic!("Build failed for targets: {}", failed.join(", "));
+            }
 
-                let dest = build_dir.join(generate_oci_archive_filename(&image_name, &target));
-                eprintln!(
-                    "{:>12} oci-archive ({})",
+            for (target, archives) in package.targets.iter().zip(archives) {
+                let dest = build_dir.join(generate_oci_archive_filename(&image_name, target, None));
+                status(
                     "Creating".green().bold(),
-                    dest.display()
+                    format!("multi-platform oci-archive ({})", dest.display()),
                 );
+                fs::create_dir_all(&build_dir)?;
                 let f = fs::File::create(dest)?;
-                let mut b = ocipkg::image::Builder::new(f);
+                let mut b = ocipkg::image::IndexBuilder::new(f);
                 b.set_name(&image_name);
-                b.set_annotations(annotations);
-                b.append_files(&targets)?;
+                for archive in archives {
+                    b.append_archive(&archive)?;
+                }
                 let _output = b.into_inner()?;
             }
         }
 
-        Opt::Ocipkg(Ocipkg::Publish {
+        Ocipkg::Publish {
             release,
             package_name,
-        }) => {
+            force,
+            post_publish,
+            post_publish_advisory,
+            sign_key,
+            sbom,
+            digest_file,
+            targets,
+        } => {
+            let sign_key = sign_key
+                .map(|key| ocipkg::distribution::SigningKey::load(&key))
+                .transpose()?;
+            let sbom = match sbom {
+                Some(path) => {
+                    let blob = fs::read(&path)?;
+                    let artifact_type = ocipkg::distribution::sbom_artifact_type(&path, &blob)?;
+                    Some((blob, artifact_type))
+                }
+                None => None,
+            };
             let metadata = get_metadata();
             let package = get_package(&metadata, package_name);
             let build_dir = get_build_dir(&metadata, release);
-            let image_name = generate_image_name(&package);
+            let image_name = generate_image_name(&package, release, &targets);
+            let post_publish = post_publish.or_else(|| get_post_publish(&package));
+            // Published names are recorded for consumers pulling them by the lockfile
+            let lock_path = metadata
+                .workspace_root
+                .as_std_path()
+                .join(ocipkg::lockfile::LOCKFILE);
+            let mut lockfile = if lock_path.is_file() {
+                ocipkg::lockfile::Lockfile::from_file(&lock_path)?
+            } else {
+                ocipkg::lockfile::Lockfile::default()
+            };
+            let mut digests = Vec::new();
+            let mut pushed = Vec::new();
             for target in package.targets {
-                let dest = build_dir.join(generate_oci_archive_filename(&image_name, &target));
+                let dest =
+                    build_dir.join(generate_oci_archive_filename(&image_name, &target, None));
                 if !dest.exists() {
                     panic!("OCI archive not found: {}", dest.display());
                 }
-                eprintln!(
-                    "{:>12} container ({})",
+                if !force && is_published(&image_name, &dest)? {
+                    status(
+                        "Skip".yellow().bold(),
+                        format!("container ({}) is already published", image_name),
+                    );
+                    let digest = get_archive_digest(&dest)?;
+                    lockfile.insert(&image_name, &digest);
+                    digests.push(digest);
+                    continue;
+                }
+                status(
                     "Publish".green().bold(),
-                    image_name
+                    format!("container ({})", image_name),
                 );
-                ocipkg::distribution::push_image(&dest)?;
+                for (name, digest) in ocipkg::distribution::push_image(&dest)? {
+                    if let Some(key) = &sign_key {
+                        let mut client = ocipkg::distribution::Client::from_image_name(&name)?;
+                        ocipkg::distribution::sign_image(&mut client, &digest, key)?;
+                        status("Sign".green().bold(), format!("{}@{}", name, digest));
+                    }
+                    if let Some((blob, artifact_type)) = &sbom {
+                        let mut client = ocipkg::distribution::Client::from_image_name(&name)?;
+                        ocipkg::distribution::attach_artifact(
+                            &mut client,
+                            &digest,
+                            artifact_type,
+                            blob,
+                            HashMap::new(),
+                        )?;
+                        status(
+                            "Attach".green().bold(),
+                            format!("SBOM to {}@{}", name, digest),
+                        );
+                    }
+                    lockfile.insert(&name, &digest);
+                    digests.push(digest.clone());
+                    pushed.push((name, digest));
+                }
+            }
+            lockfile.save(&lock_path)?;
+            status("Lock".green().bold(), lock_path.display());
+            if let Some(digest_file) = digest_file {
+                write_digest_file(&digest_file, &digests)?;
+            }
+            // Run after the pushed digests are recorded, which are kept even if commands fail
+            if let Some(command) = &post_publish {
+                for (name, digest) in &pushed {
+                    run_post_publish(command, name, digest, post_publish_advisory)?;
+                }
             }
         }
+
+        Ocipkg::Verify {
+            package_name,
+            release,
+            tag,
+            max_size,
+            skip_reproducible,
+            targets,
+            format,
+        } => {
+            let metadata = get_metadata();
+            let package = get_package(&metadata, package_name);
+            let build_dir = get_build_dir(&metadata, release);
+            let image_name = if let Some(ref tag) = tag {
+                ImageName::parse(tag)?
+            } else {
+                generate_image_name(&package, release, &targets)
+            };
+            let mut checks = Vec::new();
+            for target in &package.targets {
+                let dest = build_dir.join(generate_oci_archive_filename(&image_name, target, None));
+                if !dest.exists() {
+                    panic!("OCI archive not found: {}", dest.display());
+                }
+                let mut options = LintOptions {
+                    max_size: max_size.or_else(|| get_max_size(&package)),
+                    expected_files: Some(
+                        target
+                            .crate_types
+                            .iter()
+                            .filter_map(|ty| library_file_name(&target.name, ty, &host_os()))
+                            .collect(),
+                    ),
+                    ..Default::default()
+                };
+                options
+                    .forbidden_paths
+                    .push(metadata.workspace_root.to_string());
+                checks.extend(ocipkg::image::lint::lint_archive(&dest, &options)?);
+                if !skip_reproducible {
+                    let target_dir = metadata.target_directory.join("ocipkg-verify");
+                    checks.push(check_reproducible(
+                        &package,
+                        target,
+                        &image_name,
+                        release,
+                        &dest,
+                        target_dir.as_std_path(),
+                    )?);
+                }
+            }
+            print_checks(&checks, format)?;
+            if checks.iter().any(|check| !check.passed) {
+                ExitCode::Mismatch.exit();
+            }
+        }
+
+        Ocipkg::Completions { shell } => {
+            ocipkg_cli::completion::generate(shell, "cargo-ocipkg", &mut std::io::stdout())?;
+        }
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reproducible_archive() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let lib = dir.path().join("libtest.a");
+        let image_name = ImageName::parse("localhost:5000/test_repo:v1")?;
+        let build = |name: &str, mtime: u64| -> Result<Digest> {
+            fs::write(&lib, "library")?;
+            fs::File::options()
+                .write(true)
+                .open(&lib)?
+                .set_modified(std::time::UNIX_EPOCH + std::time::Duration::from_secs(mtime))?;
+            let dest = dir.path().join(name);
+            write_archive(
+                &dest,
+                &image_name,
+                Default::default(),
+                None,
+                std::slice::from_ref(&lib),
+                0,
+            )?;
+            get_archive_digest(&dest)
+        };
+        // Modification times of the library differ
+        let first = build("first.tar", 1_000_000)?;
+        let second = build("second.tar", 2_000_000)?;
+        assert_eq!(first, second);
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn post_publish() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let out = dir.path().join("env.txt");
+        let image_name = ImageName::parse("localhost:5000/test_repo:v1")?;
+        let digest = Digest::from_buf_sha256(b"manifest");
+        let command = format!(
+            "echo \"$OCIPKG_IMAGE $OCIPKG_DIGEST $OCIPKG_REGISTRY\" > {}",
+            out.display()
+        );
+        run_post_publish(&command, &image_name, &digest, false)?;
+        assert_eq!(
+            fs::read_to_string(&out)?,
+            format!("{} {} localhost:5000\n", image_name, digest)
+        );
+
+        // Failure is an error unless advisory
+        assert!(matches!(
+            run_post_publish("exit 1", &image_name, &digest, false),
+            Err(Error::PostPublishFailed { .. })
+        ));
+        run_post_publish("exit 1", &image_name, &digest, true)?;
+        Ok(())
+    }
+
+    #[test]
+    fn digest_file() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("digests.txt");
+        let digests = [
+            Digest::from_buf_sha256(b"first"),
+            Digest::from_buf_sha256(b"second"),
+        ];
+        write_digest_file(&path, &digests)?;
+        assert_eq!(
+            fs::read_to_string(&path)?,
+            format!("{}\n{}\n", digests[0], digests[1])
+        );
+        Ok(())
+    }
+}
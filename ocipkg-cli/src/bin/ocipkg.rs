@@ -1,28 +1,101 @@
 use base64::{engine::general_purpose::STANDARD, Engine};
-use clap::Parser;
+use clap::{CommandFactory, Parser, Subcommand, ValueEnum};
 use flate2::read::GzDecoder;
-use oci_spec::image::MediaType;
-use ocipkg::error::*;
-use std::{fs, path::*};
+use indicatif::{HumanBytes, MultiProgress, ProgressBar, ProgressStyle};
+use oci_spec::image::{
+    Descriptor, ImageConfiguration, ImageIndex, ImageManifest, MediaType, Platform, PlatformBuilder,
+};
+use ocipkg::{
+    distribution::{tag_version, TagFilter, TransferEvent, TrustPolicy},
+    error::*,
+    image::{find_manifest_for_platform, platform_string, PlatformEx},
+    DescriptorExt, Digest,
+};
+use ocipkg_cli::{
+    completion::{Shell, IMAGE_NAME},
+    exit_code::{ExitCode, EXIT_STATUS_HELP},
+    output::{self, Format},
+};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+    io::{IsTerminal, Read, Write},
+    path::*,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
 
 #[derive(Debug, Parser)]
-#[command(version)]
+#[command(version, after_help = EXIT_STATUS_HELP)]
+struct Cli {
+    /// Output format. `json` prints structures documented in `ocipkg_cli::output` to stdout
+    /// for `list`, `inspect`, `tags`, `gc`, `push`, `get`, `verify`, `attachments`, and `conformance`, and errors as JSON.
+    #[arg(long, value_enum, global = true, default_value_t = Format::Table)]
+    format: Format,
+
+    /// Show high-level operations, `-vv` for HTTP requests, and `-vvv` for their headers.
+    /// `RUST_LOG` overrides this flag.
+    #[arg(short, long, action = clap::ArgAction::Count, global = true)]
+    verbose: u8,
+
+    /// Suppress all output except errors and results, e.g. progress and summaries
+    #[arg(short, long, global = true, conflicts_with = "verbose")]
+    quiet: bool,
+
+    #[command(subcommand)]
+    command: Opt,
+}
+
+#[derive(Debug, Subcommand)]
 enum Opt {
-    /// Pack a directory into an oci-archive tar file
+    /// Pack a directory into an oci-archive tar file, and/or into local storage with `--tag`
+    ///
+    /// Entries are packed deterministically, i.e. sorted and with normalized owners and
+    /// modification times given by `SOURCE_DATE_EPOCH` or 0.
     Pack {
         /// Path of input directory to be packed
         input_directory: PathBuf,
 
         /// Path of output tar archive in oci-archive format
-        output: PathBuf,
+        #[arg(required_unless_present = "tag")]
+        output: Option<PathBuf>,
 
         /// Name of container, use UUID v4 hyphenated if not set.
+        /// The image is stored in local storage if set.
         #[arg(short = 't', long = "tag")]
         tag: Option<String>,
 
         /// Path to annotations file.
         #[arg(default_value = "ocipkg.toml")]
         annotations: PathBuf,
+
+        /// Annotation of image manifest as `{key}={value}`
+        #[arg(long = "annotation", value_parser = parse_annotation)]
+        annotation: Vec<(String, String)>,
+
+        /// `artifactType` of image manifest
+        #[arg(long)]
+        artifact_type: Option<String>,
+
+        /// Compression of layers
+        #[arg(long, value_enum, default_value_t = PackCompression::Gzip)]
+        compression: PackCompression,
+
+        /// Skip paths matching the glob pattern relative to the input directory
+        #[arg(long)]
+        exclude: Vec<String>,
+
+        /// Split files into layers of about this size in bytes
+        #[arg(long)]
+        split_size: Option<u64>,
+
+        /// Platform of image, e.g. `linux/amd64`
+        #[arg(long)]
+        platform: Option<String>,
+
+        /// Push the image to registry after building
+        #[arg(long, requires = "tag")]
+        push: bool,
     },
 
     /// Compose files into an oci-archive tar file
@@ -50,16 +123,146 @@ enum Opt {
     },
 
     /// Get and save in local storage
+    #[command(alias = "pull")]
     Get {
         image_name: String,
         #[clap(short = 'f', long = "overwrite")]
         overwrite: bool,
+        /// Public key in PEM to verify cosign signature of image before storing it
+        #[arg(long, value_name = "PATH")]
+        verify_key: Option<PathBuf>,
+        /// Trusted X.509 root certificates in PEM to verify Notation signature of image before storing it
+        #[arg(long, value_name = "PATH", conflicts_with = "verify_key")]
+        trust_cert: Option<PathBuf>,
+    },
+
+    /// Extract layers of image in local storage, or in registry with `--remote`, into a directory
+    Extract {
+        image_name: String,
+        /// Destination directory
+        #[arg(short, long)]
+        output: PathBuf,
+        /// Stream layers from registry without storing them
+        #[arg(long)]
+        remote: bool,
+        /// Extract only a layer specified by its index from the bottom, or by its digest
+        #[arg(long)]
+        layer: Option<ocipkg::image::LayerSelector>,
+        /// Glob pattern of paths to be extracted, e.g. `include/*.h`. Can be repeated.
+        #[arg(long)]
+        path: Vec<String>,
+        /// Remove leading components from paths
+        #[arg(long, default_value_t = 0)]
+        strip_components: usize,
+        /// Extract symbolic links pointing outside of the destination and device files
+        #[arg(long)]
+        allow_unsafe: bool,
+    },
+
+    /// Write a file in image to stdout, or list a directory with `--list`
+    Cat {
+        #[arg(value_name = IMAGE_NAME)]
+        image_name: String,
+        /// Path of file in the filesystem of image
+        #[arg(required_unless_present = "list")]
+        path: Option<String>,
+        /// List entries of the directory in image instead, with trailing `/` for directories
+        #[arg(long, value_name = "DIR", conflicts_with = "path")]
+        list: Option<String>,
+        /// Stream layers from registry, from the top until the file is found
+        #[arg(long)]
+        remote: bool,
     },
 
     /// Push oci-archive to registry
     Push {
-        /// Input oci-archive
-        input: PathBuf,
+        /// Input oci-archive, or image name to be pushed with `--from`
+        input: String,
+        /// Push from `oci-archive:{path}` or `oci:{path}` without importing into local storage
+        #[arg(long)]
+        from: Option<String>,
+        /// Select image by its ref name when `--from` contains several images
+        #[arg(long = "ref", requires = "from", conflicts_with = "all")]
+        selector: Option<String>,
+        /// Push all images in `--from`, where input is the repository to be pushed into
+        #[arg(long, requires = "from")]
+        all: bool,
+    },
+
+    /// Copy image between registries, OCI Image Layout directories, oci-archives, and local storage
+    Copy {
+        /// Source, e.g. `docker://ghcr.io/termoshtt/ocipkg/testing:v1`, `oci:{path}[:{ref}]`, or `oci-archive:{path}[:{ref}]`
+        src: String,
+        /// Destination, also `local:{image name}` for local storage
+        dest: String,
+        /// Copy image index with manifests for all platforms
+        #[arg(long, conflicts_with = "platform")]
+        all_platforms: bool,
+        /// Copy only the manifest for the platform, e.g. `linux/amd64`, from image index. The current platform is used by default.
+        #[arg(long)]
+        platform: Option<String>,
+        /// Credentials for source registry in `{username}:{password}` form
+        #[arg(long, value_parser = parse_credentials)]
+        src_creds: Option<(String, String)>,
+        /// Credentials for destination registry in `{username}:{password}` form
+        #[arg(long, value_parser = parse_credentials)]
+        dest_creds: Option<(String, String)>,
+    },
+
+    /// List tags of repository in registry
+    Tags {
+        /// Repository, e.g. `ghcr.io/termoshtt/ocipkg/testing`
+        repository: String,
+        /// Glob pattern, e.g. `v1.*`, or regex surrounded by slashes, e.g. `/^v\d+$/`
+        #[arg(long)]
+        filter: Option<String>,
+        /// Semantic version requirement, e.g. `>=1.2, <2`. A leading `v` of tags is ignored.
+        #[arg(long)]
+        semver: Option<semver::VersionReq>,
+        /// Show digest of manifest for each tag
+        #[arg(long)]
+        with_digests: bool,
+        /// Upper limit of concurrent requests to get digests
+        #[arg(long, default_value_t = 8)]
+        concurrency: usize,
+        #[arg(long, value_enum, default_value_t = TagSort::Lexical)]
+        sort: TagSort,
+        /// Exit with error if no tag matches
+        #[arg(long)]
+        fail_if_empty: bool,
+    },
+
+    /// Create another tag for an image in local storage, or in registry with `--remote`
+    Tag {
+        /// Source image name
+        #[arg(value_name = IMAGE_NAME)]
+        src: String,
+        /// New image name. Must be in the same repository as source with `--remote`.
+        dst: String,
+        /// Retag in registry without transferring blobs
+        #[arg(long)]
+        remote: bool,
+        /// Overwrite existing tag for another manifest
+        #[arg(short = 'f', long)]
+        force: bool,
+    },
+
+    /// Modify annotations of an image in local storage, or in registry with `--remote`,
+    /// without changing layers, and print the new digest
+    ///
+    /// Values of `org.opencontainers.image.*` keys are validated as defined in OCI image spec.
+    Annotate {
+        #[arg(value_name = IMAGE_NAME)]
+        image_name: String,
+        /// Annotation to be set as `{key}={value}`
+        #[arg(long, value_parser = parse_annotation)]
+        set: Vec<(String, String)>,
+        /// Key of annotation to be removed
+        #[arg(long)]
+        remove: Vec<String>,
+        /// Push the modified manifest to registry as the same tag
+        #[arg(long)]
+        remote: bool,
     },
 
     /// Get image directory to be used by ocipkg for given container name
@@ -69,43 +272,833 @@ enum Opt {
 
     List,
 
+    /// Remove images from local storage
+    Rm {
+        /// Image names to be removed
+        #[arg(required_unless_present = "digest", value_name = IMAGE_NAME)]
+        images: Vec<String>,
+        /// Remove images whose manifest has the digest, e.g. `sha256:...`
+        #[arg(long)]
+        digest: Vec<String>,
+        /// Show what would be removed without removing
+        #[arg(long)]
+        dry_run: bool,
+        /// Do not fail for images which do not exist
+        #[arg(long)]
+        ignore_missing: bool,
+    },
+
+    /// Remove images not pulled or used for a while from local storage
+    Prune {
+        /// Remove images not pulled or used within this period, e.g. `30d` or `12h`
+        #[arg(long)]
+        older_than: humantime::Duration,
+        /// Show what would be removed without removing
+        #[arg(long)]
+        dry_run: bool,
+    },
+
+    /// Remove stale extraction cache and files left by removed images in local storage
+    Gc {
+        /// Keep files modified within this period, e.g. `7d`, to avoid racing with running builds
+        #[arg(long, default_value = "0s")]
+        keep_within: humantime::Duration,
+        /// Show what would be removed without removing
+        #[arg(long)]
+        dry_run: bool,
+    },
+
     /// Login to OCI registry
     Login {
-        /// OCI registry to be logined
+        /// OCI registry to be logined, e.g. `ghcr.io` or `https://ghcr.io`
+        registry: String,
+        /// Prompted if not set. Empty username with `--password-stdin` stores the password as a token.
+        #[clap(short = 'u', long = "username")]
+        username: Option<String>,
+        /// Prompted without echo if neither this nor `--password-stdin` is set
+        #[clap(short = 'p', long = "password", conflicts_with = "password_stdin")]
+        password: Option<String>,
+        /// Read password or token from stdin, e.g. for CI
+        #[clap(long = "password-stdin")]
+        password_stdin: bool,
+    },
+
+    /// Remove stored credentials for OCI registry
+    Logout {
+        /// OCI registry to be logouted
         registry: String,
-        #[clap(short = 'u', long = "--username")]
-        username: String,
-        #[clap(short = 'p', long = "--password")]
-        password: String,
     },
 
-    /// Inspect components in OCI archive
+    /// Inspect manifest, config, and layers of image in OCI archive, or in registry with `--remote`
     Inspect {
-        /// Input oci-archive
-        input: PathBuf,
+        /// Input oci-archive, or image name with `--remote`
+        input: String,
+        /// Inspect image in registry, where only manifest and config are fetched
+        #[arg(long)]
+        remote: bool,
+        /// Inspect the manifest for the platform, e.g. `linux/amd64`, in image index
+        #[arg(long)]
+        platform: Option<String>,
+        /// Print manifest or image index JSON as it is stored
+        #[arg(long, conflicts_with = "raw_config")]
+        raw_manifest: bool,
+        /// Print config JSON as it is stored. The current platform is used for image index without `--platform`.
+        #[arg(long)]
+        raw_config: bool,
+    },
+
+    /// Compute or verify digest of file, or print digest of image manifest
+    Digest {
+        /// Input file, `-` for stdin, or image name with `--manifest`
+        input: String,
+        #[arg(long, value_enum, default_value_t = DigestAlgo::Sha256)]
+        algo: DigestAlgo,
+        /// Expected digest, e.g. `sha256:...`. Exit with error if the content does not match.
+        #[arg(long, conflicts_with_all = ["manifest", "algo"])]
+        verify: Option<String>,
+        /// Print digest of manifest of image in local storage
+        #[arg(long, conflicts_with = "algo")]
+        manifest: bool,
+        /// Get digest of manifest from registry without pulling blobs
+        #[arg(long, requires = "manifest")]
+        remote: bool,
+    },
+
+    /// Print completion script for the shell
+    ///
+    /// Image names are completed from local storage, e.g. `ocipkg completions bash > ~/.local/share/bash-completion/completions/ocipkg`
+    Completions {
+        #[arg(value_enum)]
+        shell: Shell,
     },
+
+    /// Check oci-archive before release, or integrity of image in local storage or registry
+    ///
+    /// Image names are also checked by the trust policy, see `ocipkg policy check`.
+    Verify {
+        /// Input oci-archive, or image name
+        input: String,
+        /// Upper limit of total layer size in bytes
+        #[arg(long)]
+        max_size: Option<u64>,
+        /// Check image in registry by its manifest and `HEAD` requests without downloading layers
+        #[arg(long)]
+        remote: bool,
+        /// Public key in PEM to verify cosign signature of image
+        #[arg(long)]
+        cosign_key: Option<PathBuf>,
+    },
+
+    /// Sign image in registry, and push the signature in the same form as cosign
+    Sign {
+        #[arg(value_name = IMAGE_NAME)]
+        image_name: String,
+        /// Unencrypted PKCS#8 private key of ECDSA P-256 or Ed25519 in PEM, or `env://{NAME}`
+        #[arg(long)]
+        key: String,
+    },
+
+    /// Print SBOM attached to image in registry
+    Sbom {
+        #[arg(value_name = IMAGE_NAME)]
+        image_name: String,
+        /// Save SBOM into the file instead of stdout
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+
+    /// List artifacts attached to image in registry, e.g. SBOMs and signatures
+    Attachments {
+        #[arg(value_name = IMAGE_NAME)]
+        image_name: String,
+        /// List only in-toto attestations of the predicate type, e.g. `https://slsa.dev/provenance/v1`
+        #[arg(long)]
+        predicate_type: Option<String>,
+    },
+
+    /// Serve images in an OCI Image Layout directory as a read-only registry
+    Serve {
+        /// Address to listen, e.g. `127.0.0.1:0` for a random port
+        #[arg(long, default_value = "127.0.0.1:5000")]
+        addr: String,
+        /// OCI Image Layout directory, `OCI_LAYOUT_PATH` or the user cache directory by default
+        #[arg(long)]
+        data_dir: Option<PathBuf>,
+    },
+
+    /// Test a repository in registry satisfies OCI distribution spec as ocipkg expects
+    ///
+    /// Tests follow the categories of the OCI distribution conformance suite.
+    /// Test content is pushed to the repository, and deleted by the content management tests.
+    Conformance {
+        /// Repository, e.g. `localhost:5000/conformance`
+        repository: String,
+        /// Category of tests not to be run. Can be repeated.
+        #[arg(long, value_enum)]
+        skip: Vec<ConformanceCategory>,
+    },
+
+    /// Trust policy of repositories in `policy.toml` and `.ocipkg/policy.toml` of the project
+    Policy {
+        #[command(subcommand)]
+        command: PolicyCommand,
+    },
+
+    /// Lockfile `ocipkg.lock` of the project pinning images to digests
+    ///
+    /// Pulls of locked images fail if their tags are resolved to other digests.
+    Lock {
+        #[command(subcommand)]
+        command: LockCommand,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+enum LockCommand {
+    /// Resolve images in registry to digests, and record them in `ocipkg.lock`
+    ///
+    /// All locked images are updated if no image is given.
+    /// `ocipkg.lock` is created in the current directory if not found in it or its ancestors.
+    Update {
+        #[arg(value_name = IMAGE_NAME)]
+        image_names: Vec<String>,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+enum PolicyCommand {
+    /// Explain which rule is applied to the image, and whether pulling it passes
+    Check {
+        #[arg(value_name = IMAGE_NAME)]
+        image_name: String,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum ConformanceCategory {
+    Pull,
+    Push,
+    ContentDiscovery,
+    ContentManagement,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum DigestAlgo {
+    Sha256,
+    Sha512,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum PackCompression {
+    /// Not supported yet
+    Zstd,
+    Gzip,
+    None,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum TagSort {
+    Lexical,
+    /// Semantic version order, and other tags follow in lexical order
+    Semver,
+    /// Group tags pointing to the same manifest. This implies `--with-digests`.
+    Digest,
+}
+
+/// Report transfer of blobs by progress bars on TTY, or by log lines otherwise
+struct Reporter {
+    multi: MultiProgress,
+    tty: bool,
+    start: Instant,
+    state: Mutex<ReporterState>,
+}
+
+#[derive(Default)]
+struct ReporterState {
+    bars: HashMap<Digest, ProgressBar>,
+    sizes: HashMap<Digest, Option<u64>>,
+    transferred: HashMap<Digest, u64>,
+    last_log: HashMap<Digest, Instant>,
+    finished: usize,
+    skipped: usize,
+    skipped_bytes: u64,
+}
+
+impl Reporter {
+    fn new(multi: &MultiProgress) -> Arc<Self> {
+        Arc::new(Self {
+            multi: multi.clone(),
+            tty: std::io::stdout().is_terminal() && std::io::stderr().is_terminal(),
+            start: Instant::now(),
+            state: Mutex::default(),
+        })
+    }
+
+    fn progress(self: &Arc<Self>) -> ocipkg::distribution::Progress {
+        let reporter = self.clone();
+        Arc::new(move |event| reporter.handle(event))
+    }
+
+    fn handle(&self, event: &TransferEvent) {
+        let mut state = self.state.lock().unwrap();
+        match event {
+            TransferEvent::Started { digest, size } => {
+                state.sizes.insert(digest.clone(), *size);
+                if self.tty {
+                    let bar = match size {
+                        Some(size) => ProgressBar::new(*size).with_style(
+                            ProgressStyle::with_template(
+                                "{prefix} [{bar:30}] {bytes}/{total_bytes} {bytes_per_sec}",
+                            )
+                            .unwrap()
+                            .progress_chars("=> "),
+                        ),
+                        None => ProgressBar::new_spinner().with_style(
+                            ProgressStyle::with_template(
+                                "{prefix} {spinner} {bytes} {bytes_per_sec}",
+                            )
+                            .unwrap(),
+                        ),
+                    };
+                    let bar = self.multi.add(bar.with_prefix(short_digest(digest)));
+                    state.bars.insert(digest.clone(), bar);
+                } else {
+                    eprintln!("Transfer {} ({})", digest, format_size(*size));
+                    state.last_log.insert(digest.clone(), Instant::now());
+                }
+            }
+            TransferEvent::Progress {
+                digest,
+                transferred,
+            } => {
+                state.transferred.insert(digest.clone(), *transferred);
+                if let Some(bar) = state.bars.get(digest) {
+                    bar.set_position(*transferred);
+                } else if !self.tty {
+                    let now = Instant::now();
+                    let last = state.last_log.get(digest).copied().unwrap_or(now);
+                    if now - last >= Duration::from_secs(2) {
+                        let size = state.sizes.get(digest).copied().flatten();
+                        eprintln!(
+                            "Transfer {} {}/{}",
+                            digest,
+                            HumanBytes(*transferred),
+                            format_size(size)
+                        );
+                        state.last_log.insert(digest.clone(), now);
+                    }
+                }
+            }
+            TransferEvent::Finished { digest } => {
+                state.finished += 1;
+                if let Some(bar) = state.bars.remove(digest) {
+                    bar.finish();
+                } else if !self.tty {
+                    eprintln!("Finished {}", digest);
+                }
+            }
+            TransferEvent::Skipped { digest, size } => {
+                state.skipped += 1;
+                state.skipped_bytes += size;
+                if self.tty {
+                    self.multi
+                        .println(format!("{} already exists", short_digest(digest)))
+                        .unwrap();
+                } else {
+                    eprintln!("Skip {} (already exists)", digest);
+                }
+            }
+        }
+    }
+
+    fn summary(&self) {
+        let state = self.state.lock().unwrap();
+        let bytes: u64 = state.transferred.values().sum();
+        eprintln!(
+            "{} blobs transferred ({}), {} blobs skipped ({}) in {:.1?}",
+            state.finished,
+            HumanBytes(bytes),
+            state.skipped,
+            HumanBytes(state.skipped_bytes),
+            self.start.elapsed()
+        );
+    }
+}
+
+fn short_digest(digest: &Digest) -> String {
+    format!(
+        "{}:{}",
+        digest.algorithm,
+        &digest.encoded[..12.min(digest.encoded.len())]
+    )
+}
+
+/// Open file, or stdin for `-`
+fn open_input(input: &str) -> Result<Box<dyn std::io::Read>> {
+    if input == "-" {
+        Ok(Box::new(std::io::stdin().lock()))
+    } else {
+        Ok(Box::new(fs::File::open(input)?))
+    }
+}
+
+/// Parse `{username}:{password}`
+fn parse_credentials(input: &str) -> std::result::Result<(String, String), String> {
+    input
+        .split_once(':')
+        .map(|(username, password)| (username.to_string(), password.to_string()))
+        .ok_or_else(|| "must be {username}:{password}".to_string())
+}
+
+/// Parse `{key}={value}`
+fn parse_annotation(input: &str) -> std::result::Result<(String, String), String> {
+    match input.split_once('=') {
+        Some((key, value)) if !key.is_empty() => Ok((key.to_string(), value.to_string())),
+        _ => Err("must be {key}={value}".to_string()),
+    }
+}
+
+/// Registry given as `ghcr.io` is regarded as `https://ghcr.io`
+fn registry_url(registry: &str) -> Result<url::Url> {
+    if registry.contains("://") {
+        Ok(url::Url::parse(registry)?)
+    } else {
+        Ok(url::Url::parse(&format!("https://{}", registry))?)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum InspectMode {
+    Summary,
+    RawManifest,
+    RawConfig,
+}
+
+/// Where `ocipkg inspect` reads manifests and blobs
+enum InspectSource {
+    Archive(fs::File),
+    Remote(Box<ocipkg::distribution::Client>),
+}
+
+impl InspectSource {
+    /// Manifest or image index for the reference in registry as it is stored
+    fn get_root(&mut self, reference: &ocipkg::Reference) -> Result<(MediaType, Vec<u8>)> {
+        match self {
+            InspectSource::Archive(_) => unreachable!("oci-archive starts from index.json"),
+            InspectSource::Remote(client) => client.get_raw_manifest(reference),
+        }
+    }
+
+    /// Manifest, image index, or blob for the descriptor as it is stored
+    fn get_raw(&mut self, desc: &Descriptor) -> Result<Vec<u8>> {
+        let digest = Digest::new(desc.digest())?;
+        match self {
+            InspectSource::Archive(f) => {
+                let mut ar = ocipkg::image::Archive::new(f);
+                let mut buf = Vec::new();
+                ar.get_blob(&digest)?.read_to_end(&mut buf)?;
+                Ok(buf)
+            }
+            InspectSource::Remote(client) => {
+                if is_manifest_or_index(desc.media_type()) {
+                    let (_media_type, buf) =
+                        client.get_raw_manifest(&ocipkg::Reference::new(desc.digest())?)?;
+                    Ok(buf)
+                } else {
+                    client.get_blob(&digest)
+                }
+            }
+        }
+    }
+
+    /// Print raw manifest or config, or returns the summary of the image
+    fn inspect(
+        &mut self,
+        name: &str,
+        media_type: &MediaType,
+        buf: &[u8],
+        platform: Option<&Platform>,
+        mode: InspectMode,
+    ) -> Result<Option<output::Inspected>> {
+        if !is_index(media_type) {
+            return self.inspect_manifest(name, buf, mode);
+        }
+        let index = ImageIndex::from_reader(buf)?;
+        let platform = match (platform, mode) {
+            (Some(platform), _) => platform.clone(),
+            (None, InspectMode::RawConfig) => Platform::from_cfg_macro(),
+            (None, InspectMode::RawManifest) => {
+                print_raw(buf)?;
+                return Ok(None);
+            }
+            (None, InspectMode::Summary) => {
+                return Ok(Some(output::Inspected::Index {
+                    name: name.to_string(),
+                    digest: Digest::from_buf_sha256(buf).to_string(),
+                    media_type: media_type.to_string(),
+                    manifests: index
+                        .manifests()
+                        .iter()
+                        .map(|desc| output::PlatformEntry {
+                            platform: desc.platform().as_ref().map(platform_string),
+                            digest: desc.digest().clone(),
+                            size: desc.size() as u64,
+                        })
+                        .collect(),
+                    annotations: sorted_annotations(index.annotations()),
+                }));
+            }
+        };
+        let desc = find_manifest_for_platform(&index, &platform)
+            .ok_or_else(|| Error::MissingPlatform(platform_string(&platform)))?;
+        let buf = self.get_raw(desc)?;
+        self.inspect_manifest(name, &buf, mode)
+    }
+
+    fn inspect_manifest(
+        &mut self,
+        name: &str,
+        buf: &[u8],
+        mode: InspectMode,
+    ) -> Result<Option<output::Inspected>> {
+        if mode == InspectMode::RawManifest {
+            print_raw(buf)?;
+            return Ok(None);
+        }
+        let manifest = ImageManifest::from_reader(buf)?;
+        let config_buf = self.get_raw(manifest.config())?;
+        if mode == InspectMode::RawConfig {
+            print_raw(&config_buf)?;
+            return Ok(None);
+        }
+        let platform = ImageConfiguration::from_reader(config_buf.as_slice())
+            .ok()
+            .map(|config| {
+                let mut builder = PlatformBuilder::default()
+                    .os(config.os().clone())
+                    .architecture(config.architecture().clone());
+                if let Some(variant) = config.variant() {
+                    builder = builder.variant(variant.clone());
+                }
+                platform_string(&builder.build().unwrap())
+            });
+        let blob = |desc: &Descriptor| output::BlobEntry {
+            digest: desc.digest().clone(),
+            media_type: desc.media_type().to_string(),
+            size: desc.size() as u64,
+        };
+        let mut layers = Vec::new();
+        for layer in manifest.layers() {
+            let files = if let (InspectSource::Archive(f), MediaType::ImageLayerGzip) =
+                (&mut *self, layer.media_type())
+            {
+                let mut ar = ocipkg::image::Archive::new(f);
+                let entry = ar.get_blob(&Digest::new(layer.digest())?)?;
+                let mut ar = tar::Archive::new(GzDecoder::new(entry));
+                let paths: Vec<_> = ar
+                    .entries()?
+                    .filter_map(|entry| Some(entry.ok()?.path().ok()?.display().to_string()))
+                    .collect();
+                Some(paths)
+            } else {
+                None
+            };
+            let blob = blob(layer);
+            layers.push(output::LayerEntry {
+                digest: blob.digest,
+                media_type: blob.media_type,
+                size: blob.size,
+                files,
+            });
+        }
+        Ok(Some(output::Inspected::Manifest {
+            name: name.to_string(),
+            digest: Digest::from_buf_sha256(buf).to_string(),
+            media_type: manifest
+                .media_type()
+                .as_ref()
+                .unwrap_or(&MediaType::ImageManifest)
+                .to_string(),
+            platform,
+            config: blob(manifest.config()),
+            layers,
+            annotations: sorted_annotations(manifest.annotations()),
+        }))
+    }
+}
+
+/// Print summary of `ocipkg inspect` as text
+fn print_inspected(inspected: &output::Inspected) {
+    match inspected {
+        output::Inspected::Index {
+            digest,
+            media_type,
+            manifests,
+            annotations,
+            ..
+        } => {
+            println!("Digest: {}", digest);
+            println!("Media type: {}", media_type);
+            println!("Manifests:");
+            for entry in manifests {
+                println!(
+                    "  {} {} {} bytes",
+                    entry.platform.as_deref().unwrap_or("unknown"),
+                    entry.digest,
+                    entry.size
+                );
+            }
+            print_annotations(annotations);
+        }
+        output::Inspected::Manifest {
+            digest,
+            media_type,
+            platform,
+            config,
+            layers,
+            annotations,
+            ..
+        } => {
+            println!("Digest: {}", digest);
+            println!("Media type: {}", media_type);
+            if let Some(platform) = platform {
+                println!("Platform: {}", platform);
+            }
+            println!(
+                "Config: {} {} {} bytes",
+                config.digest, config.media_type, config.size
+            );
+            println!("Layers:");
+            for layer in layers {
+                println!(
+                    "  {} {} {} bytes",
+                    layer.digest, layer.media_type, layer.size
+                );
+                let files = layer.files.as_deref().unwrap_or_default();
+                for (i, path) in files.iter().enumerate() {
+                    if i < files.len() - 1 {
+                        println!("    ├─ {}", path);
+                    } else {
+                        println!("    └─ {}", path);
+                    }
+                }
+            }
+            print_annotations(annotations);
+        }
+    }
+}
+
+/// Image index, including Docker manifest list
+fn is_index(media_type: &MediaType) -> bool {
+    match media_type {
+        MediaType::ImageIndex => true,
+        MediaType::Other(ty) => ty == "application/vnd.docker.distribution.manifest.list.v2+json",
+        _ => false,
+    }
+}
+
+/// Image manifest or index, including Docker manifest and manifest list
+fn is_manifest_or_index(media_type: &MediaType) -> bool {
+    match media_type {
+        MediaType::ImageManifest | MediaType::ImageIndex => true,
+        MediaType::Other(ty) => ty.starts_with("application/vnd.docker.distribution.manifest."),
+        _ => false,
+    }
+}
+
+/// Name of image in `index.json` of oci-archive, or its digest if not named
+fn descriptor_name(desc: &Descriptor) -> String {
+    ocipkg::image::get_image_name(desc)
+        .map(|name| name.to_string())
+        .unwrap_or_else(|_| desc.digest().to_string())
+}
+
+fn print_raw(buf: &[u8]) -> Result<()> {
+    std::io::stdout().write_all(buf)?;
+    if !buf.ends_with(b"\n") {
+        println!();
+    }
+    Ok(())
+}
+
+/// Annotations sorted by key to keep the output stable
+fn sorted_annotations(annotations: &Option<HashMap<String, String>>) -> BTreeMap<String, String> {
+    annotations
+        .iter()
+        .flatten()
+        .map(|(k, v)| (k.clone(), v.clone()))
+        .collect()
+}
+
+fn print_annotations(annotations: &BTreeMap<String, String>) {
+    if annotations.is_empty() {
+        return;
+    }
+    println!("Annotations:");
+    for (key, value) in annotations {
+        println!("  {} = {}", key, value);
+    }
+}
+
+fn print_remove_report(report: &ocipkg::local::RemoveReport, dry_run: bool, quiet: bool) {
+    let verb = if dry_run { "Would remove" } else { "Removed" };
+    for name in &report.removed {
+        println!("{} {}", verb, name);
+    }
+    if quiet {
+        return;
+    }
+    eprintln!(
+        "{} {} images ({}), {} of extraction cache becomes reclaimable",
+        verb,
+        report.removed.len(),
+        HumanBytes(report.bytes_freed),
+        HumanBytes(report.reclaimable)
+    );
+}
+
+/// Entry of image in local storage for JSON output
+fn image_entry(name: &ocipkg::ImageName) -> Result<output::ImageEntry> {
+    Ok(output::ImageEntry {
+        name: name.to_string(),
+        digest: ocipkg::local::image_digest(name)
+            .ok()
+            .map(|d| d.to_string()),
+        path: ocipkg::local::image_dir(name)?.display().to_string(),
+    })
+}
+
+fn print_pushed(pushed: impl Iterator<Item = (String, Digest)>, format: Format) -> Result<()> {
+    match format {
+        Format::Table => {
+            for (name, digest) in pushed {
+                println!("{}@{}", name, digest);
+            }
+            Ok(())
+        }
+        Format::Json => print_json(
+            &pushed
+                .map(|(name, digest)| output::PushedImage {
+                    name,
+                    digest: digest.to_string(),
+                })
+                .collect::<Vec<_>>(),
+        ),
+    }
+}
+
+fn format_size(size: Option<u64>) -> String {
+    size.map(|size| HumanBytes(size).to_string())
+        .unwrap_or_else(|| "unknown size".to_string())
 }
 
 fn main() -> Result<()> {
-    env_logger::Builder::new()
-        .filter_level(log::LevelFilter::Info)
-        .parse_default_env()
-        .init();
+    ocipkg_cli::completion::complete(|| Cli::command().name("ocipkg"));
+    let cli = Cli::parse();
+    let logger = ocipkg_cli::logging::builder(cli.verbose, cli.quiet).build();
+    let level = logger.filter();
+    // Logs are printed above progress bars not to break them
+    let multi = MultiProgress::new();
+    indicatif_log_bridge::LogWrapper::new(multi.clone(), logger)
+        .try_init()
+        .expect("Logger is initialized twice");
+    log::set_max_level(level);
+
+    let format = cli.format;
+    if let Err(e) = run(cli.command, format, cli.quiet, &multi) {
+        match format {
+            Format::Json => print_json(&output::ErrorOutput::from(&e))?,
+            Format::Table => eprintln!("Error: {:?}", e),
+        }
+        ExitCode::from(&e).exit();
+    }
+    Ok(())
+}
+
+fn print_json(value: &impl serde::Serialize) -> Result<()> {
+    println!("{}", serde_json::to_string_pretty(value)?);
+    Ok(())
+}
 
-    match Opt::parse() {
+/// Print results of `verify` or `policy check`, and exit with [ExitCode::Mismatch] if any fails
+fn print_checks(checks: &[ocipkg::image::lint::Check], format: Format) -> Result<()> {
+    match format {
+        Format::Table => {
+            for check in checks {
+                let status = if check.passed { "PASS" } else { "FAIL" };
+                println!("{} {}: {}", status, check.name, check.message);
+            }
+        }
+        Format::Json => print_json(
+            &checks
+                .iter()
+                .map(output::CheckEntry::from)
+                .collect::<Vec<_>>(),
+        )?,
+    }
+    if checks.iter().any(|check| !check.passed) {
+        ExitCode::Mismatch.exit();
+    }
+    Ok(())
+}
+
+fn run(command: Opt, format: Format, quiet: bool, multi: &MultiProgress) -> Result<()> {
+    match command {
         Opt::Pack {
             input_directory,
             output,
             tag,
             annotations,
+            annotation,
+            artifact_type,
+            compression,
+            exclude,
+            split_size,
+            platform,
+            push,
         } => {
-            let mut output = output;
-            output.set_extension("tar");
-            let f = fs::File::create(output)?;
+            let compression = match compression {
+                PackCompression::Gzip => ocipkg::image::LayerCompression::Gzip,
+                PackCompression::None => ocipkg::image::LayerCompression::None,
+                PackCompression::Zstd => {
+                    eprintln!("zstd compression is not supported yet, use gzip or none");
+                    ExitCode::Usage.exit();
+                }
+            };
+            let mtime = match std::env::var("SOURCE_DATE_EPOCH") {
+                Ok(epoch) => epoch.parse().unwrap_or_else(|_| {
+                    eprintln!("SOURCE_DATE_EPOCH is not an integer: {}", epoch);
+                    ExitCode::Usage.exit();
+                }),
+                Err(_) => 0,
+            };
+            let options = ocipkg::image::PackOptions {
+                compression,
+                exclude,
+                split_size,
+                mtime,
+            };
+            let layers = ocipkg::image::pack_dir(&input_directory, &options)?;
+
+            let image_name = tag
+                .map(|name| ocipkg::ImageName::parse(&name))
+                .transpose()?;
+            let temporary = output.is_none();
+            let path = match output {
+                Some(mut output) => {
+                    output.set_extension("tar");
+                    output
+                }
+                None => {
+                    std::env::temp_dir().join(format!("ocipkg-pack-{}.tar", std::process::id()))
+                }
+            };
+            let f = fs::File::create(&path)?;
             let mut b = ocipkg::image::Builder::new(f);
-            if let Some(name) = tag {
-                b.set_name(&ocipkg::ImageName::parse(&name)?);
+            if let Some(name) = &image_name {
+                b.set_name(name);
             }
+            b.set_created(chrono::DateTime::from_timestamp(mtime as i64, 0).unwrap_or_default());
             if annotations.is_file() {
                 let f = fs::read(annotations)?;
                 let input = String::from_utf8(f).expect("Non-UTF8 string in TOML");
@@ -113,8 +1106,40 @@ fn main() -> Result<()> {
                     ocipkg::image::annotations::nested::Annotations::from_toml(&input)?.into(),
                 )
             }
-            b.append_dir_all(&input_directory)?;
-            let _output = b.into_inner()?;
+            for (key, value) in &annotation {
+                b.add_annotation(key, value);
+            }
+            if let Some(artifact_type) = artifact_type {
+                b.set_artifact_type(MediaType::from(artifact_type.as_str()));
+            }
+            if let Some(platform) = platform {
+                b.set_platform(&ocipkg::image::parse_platform(&platform)?);
+            }
+            for layer in layers {
+                b.append_layer(layer)?;
+            }
+            b.into_inner()?;
+
+            let mut f = fs::File::open(&path)?;
+            let index = ocipkg::image::Archive::new(&mut f).get_index()?;
+            let desc = &index.manifests()[0];
+            let digest = Digest::new(desc.digest())?;
+            let name = ocipkg::image::get_image_name(desc)?;
+
+            if image_name.is_some() {
+                // Rebuilt image replaces the one in local storage
+                if ocipkg::local::image_dir(&name)?.exists() {
+                    ocipkg::local::remove_images(std::slice::from_ref(&name), false)?;
+                }
+                ocipkg::image::load(&path)?;
+                if push {
+                    ocipkg::distribution::push_image(&path)?;
+                }
+            }
+            if temporary {
+                fs::remove_file(&path)?;
+            }
+            println!("{}@{}", name, digest);
         }
 
         Opt::Compose {
@@ -147,13 +1172,286 @@ fn main() -> Result<()> {
         Opt::Get {
             image_name,
             overwrite,
+            verify_key,
+            trust_cert,
+        } => {
+            let image_name = ocipkg::ImageName::parse(&image_name)?;
+            let policy = match (verify_key, trust_cert) {
+                (Some(path), _) => Some(ocipkg::distribution::VerificationPolicy::PublicKey(
+                    fs::read_to_string(path)?,
+                )),
+                (None, Some(path)) => {
+                    Some(ocipkg::distribution::VerificationPolicy::notation_from_pem(
+                        &fs::read_to_string(path)?,
+                    )?)
+                }
+                (None, None) => None,
+            };
+            let reporter = (!quiet).then(|| Reporter::new(multi));
+            let progress = reporter.as_ref().map(|r| r.progress());
+            match (&policy, progress) {
+                (Some(policy), progress) => ocipkg::distribution::get_verified_image(
+                    &image_name,
+                    overwrite,
+                    policy,
+                    progress,
+                )?,
+                (None, Some(progress)) => {
+                    ocipkg::distribution::get_image_with_progress(&image_name, overwrite, progress)?
+                }
+                (None, None) => ocipkg::distribution::get_image(&image_name, overwrite)?,
+            }
+            if let Some(reporter) = reporter {
+                reporter.summary();
+            }
+            if format == Format::Json {
+                print_json(&image_entry(&image_name)?)?;
+            }
+        }
+
+        Opt::Extract {
+            image_name,
+            output,
+            remote,
+            layer,
+            path,
+            strip_components,
+            allow_unsafe,
+        } => {
+            let image_name = ocipkg::ImageName::parse(&image_name)?;
+            let options = ocipkg::image::ExtractOptions {
+                layer,
+                paths: path,
+                strip_components,
+                allow_unsafe,
+            };
+            if remote {
+                ocipkg::distribution::extract_remote_image(&image_name, &output, options)?;
+            } else {
+                ocipkg::local::extract_image(&image_name, &output, options)?;
+            }
+        }
+
+        Opt::Cat {
+            image_name,
+            path,
+            list,
+            remote,
         } => {
             let image_name = ocipkg::ImageName::parse(&image_name)?;
-            ocipkg::distribution::get_image(&image_name, overwrite)?;
+            if let Some(dir) = list {
+                let entries = if remote {
+                    ocipkg::distribution::list_remote_image_dir(&image_name, &dir)?
+                } else {
+                    ocipkg::local::list_image_dir(&image_name, &dir)?
+                };
+                for entry in entries {
+                    println!("{}", entry);
+                }
+            } else {
+                let path = path.expect("required by clap");
+                let mut out = std::io::stdout().lock();
+                if remote {
+                    ocipkg::distribution::read_remote_image_file(&image_name, &path, &mut out)?;
+                } else {
+                    ocipkg::local::read_image_file(&image_name, &path, &mut out)?;
+                }
+                out.flush()?;
+            }
+        }
+
+        Opt::Push {
+            input,
+            from: Some(from),
+            selector,
+            all,
+        } => {
+            let source: ocipkg::distribution::ImageSource = from.parse()?;
+            let image_name = ocipkg::ImageName::parse(&input)?;
+            let mut client = ocipkg::distribution::Client::from_image_name(&image_name)?;
+            let reporter = Reporter::new(multi);
+            if !quiet {
+                client.set_progress(reporter.progress());
+            }
+            let pushed = if all {
+                source.push_all(&mut client, &input)?
+            } else {
+                let digest =
+                    source.push(&mut client, &image_name.reference, selector.as_deref())?;
+                vec![(image_name.to_string(), digest)]
+            };
+            if !quiet {
+                reporter.summary();
+            }
+            print_pushed(pushed.into_iter(), format)?;
+        }
+
+        Opt::Push {
+            input, from: None, ..
+        } => {
+            let input = PathBuf::from(input);
+            let pushed = if quiet {
+                ocipkg::distribution::push_image(&input)?
+            } else {
+                let reporter = Reporter::new(multi);
+                let pushed =
+                    ocipkg::distribution::push_image_with_progress(&input, reporter.progress())?;
+                reporter.summary();
+                pushed
+            };
+            // Names are printed only in JSON to keep the output of previous versions
+            if format == Format::Json {
+                print_pushed(
+                    pushed
+                        .into_iter()
+                        .map(|(name, digest)| (name.to_string(), digest)),
+                    format,
+                )?;
+            }
+        }
+
+        Opt::Copy {
+            src,
+            dest,
+            all_platforms,
+            platform,
+            src_creds,
+            dest_creds,
+        } => {
+            let src: ocipkg::distribution::Transport = src.parse()?;
+            let dest: ocipkg::distribution::Transport = dest.parse()?;
+            let reporter = Reporter::new(multi);
+            let options = ocipkg::distribution::CopyOptions {
+                all_platforms,
+                platform: platform
+                    .as_deref()
+                    .map(ocipkg::image::parse_platform)
+                    .transpose()?,
+                src_credentials: src_creds,
+                dest_credentials: dest_creds,
+                progress: (!quiet).then(|| reporter.progress()),
+                cancel: None,
+            };
+            let digest = ocipkg::distribution::copy_image(&src, &dest, &options)?;
+            if !quiet {
+                reporter.summary();
+            }
+            println!("{}@{}", dest, digest);
         }
 
-        Opt::Push { input } => {
-            ocipkg::distribution::push_image(&input)?;
+        Opt::Tags {
+            repository,
+            filter,
+            semver,
+            with_digests,
+            concurrency,
+            sort,
+            fail_if_empty,
+        } => {
+            let image_name = ocipkg::ImageName::parse(&repository)?;
+            let mut client = ocipkg::distribution::Client::for_pull(&image_name)?;
+            let mut tag_filter = filter.as_deref().map(TagFilter::from_pattern).transpose()?;
+            if let Some(req) = semver {
+                let range = TagFilter::semver_range(req);
+                tag_filter = Some(match tag_filter {
+                    Some(f) => f.combine(range),
+                    None => range,
+                });
+            }
+            let mut tags = client
+                .tags_iter(100, tag_filter)
+                .collect::<Result<Vec<_>>>()?;
+
+            let mut digests = HashMap::new();
+            if with_digests || sort == TagSort::Digest {
+                let references = tags
+                    .iter()
+                    .map(|tag| ocipkg::Reference::new(tag))
+                    .collect::<Result<Vec<_>>>()?;
+                for (reference, stat) in client.get_manifests_metadata(&references, concurrency)? {
+                    if let Some(stat) = stat {
+                        digests.insert(reference.to_string(), stat.digest.to_string());
+                    }
+                }
+            }
+            match sort {
+                TagSort::Lexical => tags.sort(),
+                TagSort::Semver => tags.sort_by(|a, b| match (tag_version(a), tag_version(b)) {
+                    (Some(x), Some(y)) => x.cmp(&y).then_with(|| a.cmp(b)),
+                    (Some(_), None) => std::cmp::Ordering::Less,
+                    (None, Some(_)) => std::cmp::Ordering::Greater,
+                    (None, None) => a.cmp(b),
+                }),
+                TagSort::Digest => {
+                    tags.sort_by(|a, b| (digests.get(a), a).cmp(&(digests.get(b), b)))
+                }
+            }
+
+            let with_digests = with_digests || sort == TagSort::Digest;
+            match format {
+                Format::Table => {
+                    for tag in &tags {
+                        match digests.get(tag) {
+                            Some(digest) if with_digests => println!("{}\t{}", tag, digest),
+                            _ => println!("{}", tag),
+                        }
+                    }
+                }
+                Format::Json => {
+                    let entries: Vec<_> = tags
+                        .iter()
+                        .map(|tag| output::TagEntry {
+                            tag: tag.clone(),
+                            digest: digests.get(tag).filter(|_| with_digests).cloned(),
+                        })
+                        .collect();
+                    print_json(&entries)?;
+                }
+            }
+            if fail_if_empty && tags.is_empty() {
+                eprintln!("No tag matches in {}", repository);
+                ExitCode::NotFound.exit();
+            }
+        }
+
+        Opt::Tag {
+            src,
+            dst,
+            remote,
+            force,
+        } => {
+            let src = ocipkg::ImageName::parse(&src)?;
+            let dst = ocipkg::ImageName::parse(&dst)?;
+            if remote {
+                if (&src.hostname, src.port, &src.name) != (&dst.hostname, dst.port, &dst.name) {
+                    return Err(Error::InvalidName(format!(
+                        "remote tag must be in the same repository: {} -> {}",
+                        src, dst
+                    )));
+                }
+                let mut client = ocipkg::distribution::Client::from_image_name(&src)?;
+                let digest = client.retag(&src.reference, &dst.reference, force)?;
+                println!("{}@{}", dst, digest);
+            } else {
+                let dir = ocipkg::local::tag_image(&src, &dst, force)?;
+                println!("{}", dir.display());
+            }
+        }
+
+        Opt::Annotate {
+            image_name,
+            set,
+            remove,
+            remote,
+        } => {
+            let image_name = ocipkg::ImageName::parse(&image_name)?;
+            let digest = if remote {
+                let mut client = ocipkg::distribution::Client::from_image_name(&image_name)?;
+                client.annotate_manifest(&image_name.reference, &set, &remove)?
+            } else {
+                ocipkg::local::annotate_image(&image_name, &set, &remove)?
+            };
+            println!("{}@{}", image_name, digest);
         }
 
         Opt::ImageDirectory { image_name } => {
@@ -163,8 +1461,93 @@ fn main() -> Result<()> {
 
         Opt::List => {
             let images = ocipkg::local::get_image_list()?;
+            match format {
+                Format::Table => {
+                    for image in images {
+                        println!("{}", image);
+                    }
+                }
+                Format::Json => {
+                    let entries = images.iter().map(image_entry).collect::<Result<Vec<_>>>()?;
+                    print_json(&entries)?;
+                }
+            }
+        }
+
+        Opt::Completions { shell } => {
+            ocipkg_cli::completion::generate(shell, "ocipkg", &mut std::io::stdout())?;
+        }
+
+        Opt::Rm {
+            images,
+            digest,
+            dry_run,
+            ignore_missing,
+        } => {
+            let mut names = Vec::new();
             for image in images {
-                println!("{}", image);
+                let name = ocipkg::ImageName::parse(&image)?;
+                if ignore_missing && !ocipkg::local::image_dir(&name)?.is_dir() {
+                    log::warn!("Image not found in local storage: {}", name);
+                    continue;
+                }
+                names.push(name);
+            }
+            for digest in digest {
+                let found = ocipkg::local::find_images_by_digest(&Digest::new(&digest)?)?;
+                if found.is_empty() {
+                    if !ignore_missing {
+                        return Err(Error::UnknownManifestRef(digest));
+                    }
+                    log::warn!("Image not found in local storage: {}", digest);
+                }
+                names.extend(found);
+            }
+            let report = ocipkg::local::remove_images(&names, dry_run)?;
+            print_remove_report(&report, dry_run, quiet);
+        }
+
+        Opt::Prune {
+            older_than,
+            dry_run,
+        } => {
+            let report = ocipkg::local::prune_images(older_than.into(), dry_run)?;
+            print_remove_report(&report, dry_run, quiet);
+        }
+
+        Opt::Gc {
+            keep_within,
+            dry_run,
+        } => {
+            let report = ocipkg::local::gc(keep_within.into(), dry_run)?;
+            let verb = if dry_run { "Would remove" } else { "Removed" };
+            match format {
+                Format::Table => {
+                    for (path, size) in &report.removed {
+                        println!("{} {} ({})", verb, path.display(), HumanBytes(*size));
+                    }
+                }
+                Format::Json => print_json(&output::GcOutput {
+                    dry_run,
+                    removed: report
+                        .removed
+                        .iter()
+                        .map(|(path, size)| output::RemovedEntry {
+                            path: path.display().to_string(),
+                            size: *size,
+                        })
+                        .collect(),
+                    bytes_freed: report.bytes_freed,
+                })?,
+            }
+            if !quiet {
+                eprintln!(
+                    "{} {} entries, {} {}",
+                    verb,
+                    report.removed.len(),
+                    HumanBytes(report.bytes_freed),
+                    if dry_run { "reclaimable" } else { "reclaimed" }
+                );
             }
         }
 
@@ -172,44 +1555,418 @@ fn main() -> Result<()> {
             registry,
             username,
             password,
+            password_stdin,
         } => {
-            let url = url::Url::parse(&registry)?;
-            let octet = STANDARD.encode(format!("{}:{}", username, password,));
+            let url = registry_url(&registry)?;
+            let keys = ocipkg::distribution::registry_keys(&url);
+            let key = keys.first().expect("Registry must have hostname");
+            let username = match username {
+                Some(username) => username,
+                None if password_stdin => String::new(),
+                None => {
+                    eprint!("Username: ");
+                    let mut line = String::new();
+                    std::io::stdin().read_line(&mut line)?;
+                    line.trim().to_string()
+                }
+            };
+            let password = match password {
+                Some(password) => password,
+                None if password_stdin => {
+                    let mut input = String::new();
+                    std::io::stdin().read_to_string(&mut input)?;
+                    input.trim_end_matches(['\r', '\n']).to_string()
+                }
+                None => rpassword::prompt_password("Password: ")?,
+            };
             let mut new_auth = ocipkg::distribution::StoredAuth::default();
-            new_auth.insert(url.domain().unwrap(), octet);
+            if username.is_empty() {
+                new_auth.insert_token(key, password);
+            } else {
+                let octet = STANDARD.encode(format!("{}:{}", username, password,));
+                new_auth.insert(key, octet);
+            }
             let _token = new_auth.get_token(&url)?;
-            println!("Login succeed");
+            if !quiet {
+                println!("Login succeed");
+            }
 
             let mut auth = ocipkg::distribution::StoredAuth::load()?;
             auth.append(new_auth)?;
             auth.save()?;
         }
 
-        Opt::Inspect { input } => {
-            let mut f = fs::File::open(input)?;
-            let mut ar = ocipkg::image::Archive::new(&mut f);
-            for (name, manifest) in ar.get_manifests()? {
-                println!("[{}]", name);
-                for layer in manifest.layers() {
-                    let digest = ocipkg::Digest::new(layer.digest())?;
-                    let entry = ar.get_blob(&digest)?;
-                    if let MediaType::ImageLayerGzip = layer.media_type() {
-                        let buf = GzDecoder::new(entry);
-                        let mut ar = tar::Archive::new(buf);
-                        let paths: Vec<_> = ar
-                            .entries()?
-                            .filter_map(|entry| Some(entry.ok()?.path().ok()?.to_path_buf()))
+        Opt::Logout { registry } => {
+            let url = registry_url(&registry)?;
+            let mut auth = ocipkg::distribution::StoredAuth::load()?;
+            let mut removed = false;
+            for key in ocipkg::distribution::registry_keys(&url) {
+                removed |= auth.remove(&key);
+            }
+            if removed {
+                auth.save()?;
+                if !quiet {
+                    println!("Logout succeed");
+                }
+            } else if !quiet {
+                println!("Not logged in to {}", registry);
+            }
+        }
+
+        Opt::Inspect {
+            input,
+            remote,
+            platform,
+            raw_manifest,
+            raw_config,
+        } => {
+            let platform = platform
+                .as_deref()
+                .map(ocipkg::image::parse_platform)
+                .transpose()?;
+            let mode = if raw_manifest {
+                InspectMode::RawManifest
+            } else if raw_config {
+                InspectMode::RawConfig
+            } else {
+                InspectMode::Summary
+            };
+            let mut inspected = Vec::new();
+            if remote {
+                let image_name = ocipkg::ImageName::parse(&input)?;
+                let client = ocipkg::distribution::Client::for_pull(&image_name)?;
+                let mut source = InspectSource::Remote(Box::new(client));
+                let (media_type, buf) = source.get_root(&image_name.reference)?;
+                inspected.extend(source.inspect(
+                    &image_name.to_string(),
+                    &media_type,
+                    &buf,
+                    platform.as_ref(),
+                    mode,
+                )?);
+            } else {
+                let mut f = fs::File::open(&input)?;
+                let index = ocipkg::image::Archive::new(&mut f).get_index()?;
+                let manifests = index.manifests();
+                if mode != InspectMode::Summary && manifests.len() > 1 {
+                    return Err(Error::AmbiguousImage(
+                        manifests.iter().map(descriptor_name).collect(),
+                    ));
+                }
+                let mut source = InspectSource::Archive(f);
+                for desc in manifests {
+                    let buf = source.get_raw(desc)?;
+                    inspected.extend(source.inspect(
+                        &descriptor_name(desc),
+                        desc.media_type(),
+                        &buf,
+                        platform.as_ref(),
+                        mode,
+                    )?);
+                }
+            }
+            match format {
+                Format::Table => {
+                    for (i, summary) in inspected.iter().enumerate() {
+                        // Name is shown for images in oci-archive, which may contain several images
+                        if !remote {
+                            if i > 0 {
+                                println!();
+                            }
+                            let (output::Inspected::Index { name, .. }
+                            | output::Inspected::Manifest { name, .. }) = summary;
+                            println!("[{}]", name);
+                        }
+                        print_inspected(summary);
+                    }
+                }
+                Format::Json if mode == InspectMode::Summary => print_json(&inspected)?,
+                Format::Json => {}
+            }
+        }
+
+        Opt::Digest {
+            input,
+            algo,
+            verify,
+            manifest,
+            remote,
+        } => {
+            if manifest {
+                let image_name = ocipkg::ImageName::parse(&input)?;
+                let digest = if remote {
+                    let mut client = ocipkg::distribution::Client::for_pull(&image_name)?;
+                    client.get_manifest_digest(&image_name.reference)?
+                } else {
+                    ocipkg::local::image_digest(&image_name)?
+                };
+                println!("{}", digest);
+            } else if let Some(expected) = verify {
+                let expected = ocipkg::Digest::new(&expected)?;
+                match expected.verify_reader(open_input(&input)?) {
+                    Ok(()) => {}
+                    Err(e @ Error::DigestMismatch { .. }) => {
+                        eprintln!("{}", e);
+                        ExitCode::Mismatch.exit();
+                    }
+                    Err(e) => return Err(e),
+                }
+            } else {
+                let reader = open_input(&input)?;
+                let digest = match algo {
+                    DigestAlgo::Sha256 => ocipkg::Digest::from_reader_sha256(reader)?,
+                    DigestAlgo::Sha512 => ocipkg::Digest::from_reader_sha512(reader)?,
+                };
+                println!("{}", digest);
+            }
+        }
+
+        Opt::Sign { image_name, key } => {
+            let image_name = ocipkg::ImageName::parse(&image_name)?;
+            let key = ocipkg::distribution::SigningKey::load(&key)?;
+            let mut client = ocipkg::distribution::Client::from_image_name(&image_name)?;
+            let digest = client.get_manifest_digest(&image_name.reference)?;
+            let signature = ocipkg::distribution::sign_image(&mut client, &digest, &key)?;
+            if !quiet {
+                eprintln!("Signed {}@{}", image_name, digest);
+            }
+            println!("{}:{}", client.repository(), signature);
+        }
+
+        Opt::Sbom { image_name, output } => {
+            let image_name = ocipkg::ImageName::parse(&image_name)?;
+            let mut client = ocipkg::distribution::Client::for_pull(&image_name)?;
+            let digest = client.get_manifest_digest(&image_name.reference)?;
+            let sboms: Vec<_> =
+                ocipkg::distribution::get_attached_artifacts(&mut client, &digest, None)?
+                    .into_iter()
+                    .filter(|desc| {
+                        desc.artifact_type().as_ref().is_some_and(|ty| {
+                            let ty = ty.to_string();
+                            ty == ocipkg::distribution::SPDX_ARTIFACT_TYPE
+                                || ty == ocipkg::distribution::CYCLONEDX_ARTIFACT_TYPE
+                        })
+                    })
+                    .collect();
+            let sbom = sboms.first().ok_or(Error::SbomNotFound(digest))?;
+            if sboms.len() > 1 && !quiet {
+                eprintln!("{} SBOMs are attached, use {}", sboms.len(), sbom.digest());
+            }
+            let blob = ocipkg::distribution::get_attached_artifact_blob(&mut client, sbom)?;
+            match output {
+                Some(path) => fs::write(path, blob)?,
+                None => std::io::stdout().write_all(&blob)?,
+            }
+        }
+
+        Opt::Attachments {
+            image_name,
+            predicate_type,
+        } => {
+            let image_name = ocipkg::ImageName::parse(&image_name)?;
+            let mut client = ocipkg::distribution::Client::for_pull(&image_name)?;
+            let digest = client.get_manifest_digest(&image_name.reference)?;
+            let mut artifacts =
+                ocipkg::distribution::get_attached_artifacts(&mut client, &digest, None)?;
+            if let Some(predicate_type) = &predicate_type {
+                artifacts.retain(|desc| {
+                    desc.annotation(ocipkg::distribution::PREDICATE_TYPE_ANNOTATION)
+                        == Some(predicate_type.as_str())
+                });
+            }
+            let entries: Vec<_> = artifacts
+                .iter()
+                .map(|desc| output::AttachmentEntry {
+                    digest: desc.digest().to_string(),
+                    artifact_type: desc.artifact_type().as_ref().map(|ty| ty.to_string()),
+                    size: desc.size() as u64,
+                    annotations: desc
+                        .annotations()
+                        .clone()
+                        .unwrap_or_default()
+                        .into_iter()
+                        .collect(),
+                })
+                .collect();
+            match format {
+                Format::Table => {
+                    for entry in &entries {
+                        let annotations: Vec<_> = entry
+                            .annotations
+                            .iter()
+                            .map(|(key, value)| format!("{}={}", key, value))
                             .collect();
-                        for (i, path) in paths.iter().enumerate() {
-                            if i < paths.len() - 1 {
-                                println!("  ├─ {}", path.display());
-                            } else {
-                                println!("  └─ {}", path.display());
+                        println!(
+                            "{}\t{}\t{}\t{}",
+                            entry.digest,
+                            entry.artifact_type.as_deref().unwrap_or("-"),
+                            HumanBytes(entry.size),
+                            annotations.join(",")
+                        );
+                    }
+                }
+                Format::Json => print_json(&entries)?,
+            }
+        }
+
+        Opt::Verify {
+            input,
+            max_size,
+            remote,
+            cosign_key,
+        } => {
+            let path = PathBuf::from(&input);
+            let checks = if !remote && path.is_file() {
+                if cosign_key.is_some() {
+                    eprintln!("--cosign-key is only for image name");
+                    ExitCode::Usage.exit();
+                }
+                let options = ocipkg::image::lint::LintOptions {
+                    max_size,
+                    ..Default::default()
+                };
+                ocipkg::image::lint::lint_archive(&path, &options)?
+            } else {
+                let image_name = ocipkg::ImageName::parse(&input)?;
+                let public_key = cosign_key.map(fs::read_to_string).transpose()?;
+                let mut checks = if remote {
+                    let mut client = ocipkg::distribution::Client::for_pull(&image_name)?;
+                    let mut checks = ocipkg::distribution::verify_remote_image(
+                        &mut client,
+                        &image_name,
+                        max_size,
+                    )?;
+                    if let Some(public_key) = public_key {
+                        let digest = client.get_manifest_digest(&image_name.reference)?;
+                        checks.push(ocipkg::distribution::verify_cosign_signature(
+                            &mut client,
+                            &digest,
+                            &public_key,
+                        )?);
+                    }
+                    checks
+                } else {
+                    let mut checks = ocipkg::local::verify_image(&image_name, max_size)?;
+                    if let Some(public_key) = public_key {
+                        checks.extend(ocipkg::distribution::verify_local_image_signature(
+                            &image_name,
+                            &public_key,
+                        )?);
+                    }
+                    checks
+                };
+                checks.extend(TrustPolicy::load()?.explain(&image_name)?);
+                checks
+            };
+            print_checks(&checks, format)?;
+        }
+
+        Opt::Serve { addr, data_dir } => {
+            let layout = match data_dir {
+                Some(path) => ocipkg::image::OciLayout::open(&path)?,
+                None => ocipkg::image::OciLayout::from_env()?,
+            };
+            let listener = std::net::TcpListener::bind(&addr)?;
+            eprintln!(
+                "Serving {} at http://{}",
+                layout.path().display(),
+                listener.local_addr()?
+            );
+            ocipkg::distribution::serve(&layout, listener)?;
+        }
+
+        Opt::Conformance { repository, skip } => {
+            use ocipkg::distribution::ConformanceCategory as Category;
+            let image_name = ocipkg::ImageName::parse(&repository)?;
+            let mut client = ocipkg::distribution::Client::from_image_name(&image_name)?;
+            let options = ocipkg::distribution::ConformanceOptions {
+                skip: skip
+                    .into_iter()
+                    .map(|category| match category {
+                        ConformanceCategory::Pull => Category::Pull,
+                        ConformanceCategory::Push => Category::Push,
+                        ConformanceCategory::ContentDiscovery => Category::ContentDiscovery,
+                        ConformanceCategory::ContentManagement => Category::ContentManagement,
+                    })
+                    .collect(),
+            };
+            let report = ocipkg::distribution::run_conformance(&mut client, &options)?;
+            let entries: Vec<_> = report
+                .tests
+                .iter()
+                .map(output::ConformanceEntry::from)
+                .collect();
+            match format {
+                Format::Table => {
+                    for entry in &entries {
+                        println!(
+                            "{} {}: {}{}",
+                            entry.outcome.to_uppercase(),
+                            entry.category,
+                            entry.name,
+                            entry
+                                .message
+                                .as_ref()
+                                .map(|message| format!(" ({})", message))
+                                .unwrap_or_default()
+                        );
+                        if let Some(exchange) = &entry.exchange {
+                            println!("    > {} {}", exchange.method, exchange.url);
+                            for (name, value) in &exchange.request_headers {
+                                println!("    > {}: {}", name, value);
+                            }
+                            match exchange.status {
+                                Some(status) => println!("    < {}", status),
+                                None => println!("    < no response"),
+                            }
+                            for (name, value) in &exchange.response_headers {
+                                println!("    < {}: {}", name, value);
                             }
                         }
                     }
                 }
+                Format::Json => print_json(&entries)?,
+            }
+            if !report.passed() {
+                ExitCode::Failure.exit();
+            }
+        }
+
+        Opt::Policy {
+            command: PolicyCommand::Check { image_name },
+        } => {
+            let image_name = ocipkg::ImageName::parse(&image_name)?;
+            let checks = TrustPolicy::load()?.explain(&image_name)?;
+            print_checks(&checks, format)?;
+        }
+
+        Opt::Lock {
+            command: LockCommand::Update { image_names },
+        } => {
+            let path = ocipkg::lockfile::lockfile_path()
+                .unwrap_or_else(|| PathBuf::from(ocipkg::lockfile::LOCKFILE));
+            let mut lockfile = if path.is_file() {
+                ocipkg::lockfile::Lockfile::from_file(&path)?
+            } else {
+                ocipkg::lockfile::Lockfile::default()
+            };
+            let image_names = if image_names.is_empty() {
+                lockfile.image_names()?
+            } else {
+                image_names
+                    .iter()
+                    .map(|name| ocipkg::ImageName::parse(name))
+                    .collect::<Result<Vec<_>>>()?
+            };
+            for image_name in image_names {
+                let mut client = ocipkg::distribution::Client::for_pull(&image_name)?;
+                let digest = client.get_manifest_digest(&image_name.reference)?;
+                if lockfile.get(&image_name).as_ref() != Some(&digest) {
+                    log::info!("Lock {} to {}", image_name, digest);
+                }
+                lockfile.insert(&image_name, &digest);
             }
+            lockfile.save(&path)?;
         }
     }
     Ok(())
@@ -0,0 +1,187 @@
+//! Shell completion by [clap_complete]
+//!
+//! Scripts written by [generate] call the program back with the `COMPLETE` environment variable
+//! while completing, and then [complete] answers the candidates from the definition of [clap::Command].
+//! In addition to subcommands, flags, and possible values of them,
+//! arguments whose value name is [IMAGE_NAME] are completed with the names of images in local storage.
+
+use clap::{Arg, Command, ValueEnum};
+use clap_complete::{
+    env::{Bash, EnvCompleter, Fish, Powershell as Pwsh, Zsh},
+    ArgValueCompleter, CompleteEnv, CompletionCandidate,
+};
+use std::{
+    ffi::OsStr,
+    io::{self, Write},
+};
+
+/// Value name of arguments completed with images in local storage
+pub const IMAGE_NAME: &str = "IMAGE_NAME";
+
+/// Environment variable to call the program back for completion
+const COMPLETE_VAR: &str = "COMPLETE";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Shell {
+    Bash,
+    Zsh,
+    Fish,
+    Powershell,
+}
+
+impl Shell {
+    fn completer(self) -> &'static dyn EnvCompleter {
+        match self {
+            Shell::Bash => &Bash,
+            Shell::Zsh => &Zsh,
+            Shell::Fish => &Fish,
+            Shell::Powershell => &Pwsh,
+        }
+    }
+}
+
+/// Write completion script of `bin` for `shell`
+pub fn generate(shell: Shell, bin: &str, out: &mut impl Write) -> io::Result<()> {
+    shell
+        .completer()
+        .write_registration(COMPLETE_VAR, bin, bin, bin, out)
+}
+
+/// Answer the completion and exit if the program is called back by the script of [generate]
+///
+/// This must be called before anything is written to stdout.
+pub fn complete(cmd: impl Fn() -> Command) {
+    CompleteEnv::with_factory(|| with_image_names(cmd()))
+        .var(COMPLETE_VAR)
+        .complete();
+}
+
+/// Complete arguments whose value name is [IMAGE_NAME] in `cmd` and its subcommands with images in local storage
+pub fn with_image_names(cmd: Command) -> Command {
+    cmd.mut_args(|arg| {
+        if is_image(&arg) {
+            arg.add(ArgValueCompleter::new(image_names))
+        } else {
+            arg
+        }
+    })
+    .mut_subcommands(with_image_names)
+}
+
+fn is_image(arg: &Arg) -> bool {
+    arg.get_value_names()
+        .is_some_and(|names| names.iter().any(|n| n.as_str() == IMAGE_NAME))
+}
+
+fn image_names(current: &OsStr) -> Vec<CompletionCandidate> {
+    let current = current.to_string_lossy();
+    // Completion must not be disturbed by errors, e.g. when local storage is not created yet
+    ocipkg::local::get_image_list()
+        .unwrap_or_default()
+        .into_iter()
+        .map(|name| name.to_string())
+        .filter(|name| name.starts_with(current.as_ref()))
+        .map(CompletionCandidate::new)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process::Command as Process;
+
+    fn command() -> Command {
+        Command::new("app").subcommand(
+            Command::new("get")
+                .about("Get image")
+                .arg(Arg::new("image_name").value_name(IMAGE_NAME))
+                .arg(
+                    Arg::new("format")
+                        .long("format")
+                        .help("Output format")
+                        .value_parser(["text", "json"]),
+                ),
+        )
+    }
+
+    fn complete(args: &[&str]) -> Vec<String> {
+        let mut cmd = with_image_names(command());
+        let args: Vec<_> = args.iter().map(Into::into).collect();
+        let index = args.len() - 1;
+        clap_complete::engine::complete(&mut cmd, args, index, None)
+            .unwrap()
+            .into_iter()
+            .map(|c| c.get_value().to_string_lossy().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn complete_words() {
+        assert!(complete(&["app", ""]).contains(&"get".to_string()));
+        assert_eq!(complete(&["app", "get", "--fo"]), ["--format"]);
+        assert_eq!(complete(&["app", "get", "--format", ""]), ["text", "json"]);
+        // Images in local storage
+        let cmd = with_image_names(command());
+        let arg = cmd
+            .find_subcommand("get")
+            .unwrap()
+            .get_arguments()
+            .find(|arg| arg.get_id() == "image_name")
+            .unwrap();
+        assert!(arg.get::<ArgValueCompleter>().is_some());
+        let candidates = complete(&["app", "get", ""]);
+        for image in image_names(OsStr::new("")) {
+            assert!(candidates.contains(&image.get_value().to_string_lossy().to_string()));
+        }
+    }
+
+    #[test]
+    fn generate_scripts() {
+        for shell in Shell::value_variants() {
+            let mut buf = Vec::new();
+            generate(*shell, "app", &mut buf).unwrap();
+            let script = String::from_utf8(buf).unwrap();
+            assert!(script.contains(COMPLETE_VAR), "{:?}", shell);
+        }
+    }
+
+    /// Load the script in the shell if it is installed
+    fn load_script(shell: Shell, program: &str, args: &[&str]) {
+        if Process::new(program).arg("--version").output().is_err() {
+            eprintln!(
+                "Skip loading {:?} script: {} is not installed",
+                shell, program
+            );
+            return;
+        }
+        let dir = std::env::temp_dir().join(format!("ocipkg-completion-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(format!("{:?}", shell));
+        let mut f = std::fs::File::create(&path).unwrap();
+        generate(shell, "app", &mut f).unwrap();
+        let output = Process::new(program)
+            .args(args)
+            .arg(&path)
+            .output()
+            .unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(
+            output.status.success(),
+            "{:?}: {}",
+            shell,
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+
+    #[test]
+    fn load_scripts() {
+        load_script(Shell::Bash, "bash", &["-c", "source \"$0\""]);
+        load_script(
+            Shell::Zsh,
+            "zsh",
+            &["-c", "autoload -U compinit && compinit -u && source \"$0\""],
+        );
+        load_script(Shell::Fish, "fish", &["-c", "source $argv[1]"]);
+        load_script(Shell::Powershell, "pwsh", &["-NoProfile", "-File"]);
+    }
+}
@@ -0,0 +1,227 @@
+//! Exit status of `ocipkg` and `cargo-ocipkg` for each class of failures
+
+use oci_spec::distribution::ErrorCode;
+use ocipkg::error::Error;
+
+/// Shown in `--help`
+pub const EXIT_STATUS_HELP: &str = "\
+Exit status:
+  0  Success
+  1  Other failure
+  2  Usage error, e.g. invalid argument or image name
+  3  Not found, e.g. manifest or tag in registry, or file in image
+  4  Authentication or authorization failed
+  5  Network error, e.g. connection failure or timeout
+  6  Digest, verification, or signature mismatch
+  7  Local storage is locked or corrupted";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExitCode {
+    Failure = 1,
+    Usage = 2,
+    NotFound = 3,
+    Auth = 4,
+    Network = 5,
+    Mismatch = 6,
+    LocalStore = 7,
+}
+
+impl ExitCode {
+    pub fn exit(self) -> ! {
+        std::process::exit(self as i32)
+    }
+}
+
+impl From<&Error> for ExitCode {
+    fn from(e: &Error) -> Self {
+        match e {
+            Error::InvalidDigest(_)
+            | Error::InvalidName(_)
+            | Error::InvalidPort(_)
+            | Error::InvalidReference(_)
+            | Error::InvalidUrl(_)
+            | Error::InvalidTargetTriple(_)
+            | Error::InvalidPlatform(_)
+            | Error::InvalidSourceReplace(_)
+            | Error::UnpinnedImage(_)
+            | Error::DigestRequired { .. }
+            | Error::InvalidTrustPolicy(..)
+            | Error::InvalidImageSource(_)
+            | Error::UnsupportedTransport(_)
+            | Error::InvalidTagFilter(_)
+            | Error::InvalidPublicKey(_)
+            | Error::InvalidPrivateKey(_)
+            | Error::InvalidCertificate(_)
+            | Error::UnknownSbomFormat(_)
+            | Error::InvalidDsseEnvelope(_)
+            | Error::InvalidImageConfig(_)
+            | Error::InvalidAnnotation(_)
+            | Error::NotAFile(_)
+            | Error::NotADirectory(_)
+            | Error::DirectoryNotEmpty(_)
+            | Error::ReservedTag(_)
+            | Error::AmbiguousImage(_)
+            | Error::UnknownLayer(_)
+            | Error::MissingEnv(_) => ExitCode::Usage,
+
+            Error::ManifestNotFound(_)
+            | Error::BlobNotFound(_)
+            | Error::UnknownManifestRef(_)
+            | Error::MissingPlatform(_)
+            | Error::OfflineImageMissing(_)
+            | Error::PathNotFoundInImage(_)
+            | Error::SbomNotFound(_)
+            | Error::UnexpectedStatus(404) => ExitCode::NotFound,
+
+            Error::AuthorizationFailed(_)
+            | Error::RegistryNotAllowed { .. }
+            | Error::UnSupportedAuthHeader(_)
+            | Error::UnexpectedStatus(401 | 403) => ExitCode::Auth,
+
+            Error::NetworkError(_) => ExitCode::Network,
+
+            Error::DigestMismatch { .. }
+            | Error::LockedDigestMismatch { .. }
+            | Error::SubjectMismatch { .. }
+            | Error::SignatureMissing(_)
+            | Error::SignatureInvalid(_) => ExitCode::Mismatch,
+
+            Error::ImageAlreadyExists(_) | Error::NoValidRuntimeDirectory => ExitCode::LocalStore,
+
+            Error::RegistryError { status: 404, .. } => ExitCode::NotFound,
+            Error::RegistryError {
+                status: 401 | 403, ..
+            } => ExitCode::Auth,
+            Error::RegistryError { response, .. } => {
+                match response.detail().first().map(|info| info.code()) {
+                    Some(
+                        ErrorCode::ManifestUnknown
+                        | ErrorCode::NameUnknown
+                        | ErrorCode::BlobUnknown,
+                    ) => ExitCode::NotFound,
+                    Some(ErrorCode::Unauthorized | ErrorCode::Denied) => ExitCode::Auth,
+                    Some(ErrorCode::DigestInvalid) => ExitCode::Mismatch,
+                    _ => ExitCode::Failure,
+                }
+            }
+
+            Error::UnknownIo(e) => match e.kind() {
+                std::io::ErrorKind::TimedOut
+                | std::io::ErrorKind::ConnectionRefused
+                | std::io::ErrorKind::ConnectionReset
+                | std::io::ErrorKind::ConnectionAborted => ExitCode::Network,
+                std::io::ErrorKind::WouldBlock => ExitCode::LocalStore,
+                _ => ExitCode::Failure,
+            },
+
+            _ => ExitCode::Failure,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ocipkg::{distribution::Client, Digest, ImageName, Name, Reference};
+    use std::{
+        io::{BufRead, BufReader, Write},
+        net::TcpListener,
+    };
+
+    /// Serve `(status, headers, body)` for each request path in a background thread
+    fn mock_registry(handler: fn(&str, u16) -> (u16, String, String)) -> url::Url {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                let path = line.split(' ').nth(1).unwrap_or_default().to_string();
+                loop {
+                    let mut header = String::new();
+                    reader.read_line(&mut header).unwrap();
+                    if header.trim().is_empty() {
+                        break;
+                    }
+                }
+                let (status, headers, body) = handler(&path, port);
+                write!(
+                    stream,
+                    "HTTP/1.1 {} X\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n{}\r\n{}",
+                    status,
+                    body.len(),
+                    headers,
+                    body
+                )
+                .unwrap();
+            }
+        });
+        url::Url::parse(&format!("http://localhost:{}", port)).unwrap()
+    }
+
+    fn get_manifest(url: url::Url) -> Error {
+        let mut client = Client::new(url, Name::new("test/repo").unwrap()).unwrap();
+        client
+            .get_manifest(&Reference::new("v1").unwrap())
+            .unwrap_err()
+    }
+
+    #[test]
+    fn not_found() {
+        let url = mock_registry(|_, _| {
+            (
+                404,
+                String::new(),
+                r#"{"errors":[{"code":"MANIFEST_UNKNOWN","message":"manifest unknown"}]}"#
+                    .to_string(),
+            )
+        });
+        assert_eq!(ExitCode::from(&get_manifest(url)), ExitCode::NotFound);
+    }
+
+    #[test]
+    fn auth() {
+        let url = mock_registry(|path, port| {
+            if path.starts_with("/token") {
+                (401, String::new(), String::new())
+            } else {
+                let challenge = format!(
+                    "WWW-Authenticate: Bearer realm=\"http://localhost:{}/token\",service=\"test\",scope=\"repository:test/repo:pull\"\r\n",
+                    port
+                );
+                (401, challenge, String::new())
+            }
+        });
+        assert_eq!(ExitCode::from(&get_manifest(url)), ExitCode::Auth);
+    }
+
+    #[test]
+    fn network() {
+        // Bind and drop to obtain a port where nothing listens
+        let port = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let url = url::Url::parse(&format!("http://127.0.0.1:{}", port)).unwrap();
+        assert_eq!(ExitCode::from(&get_manifest(url)), ExitCode::Network);
+    }
+
+    #[test]
+    fn others() {
+        let e = ImageName::parse("INVALID NAME").unwrap_err();
+        assert_eq!(ExitCode::from(&e), ExitCode::Usage);
+
+        let e = Digest::from_buf_sha256(b"a")
+            .verify_reader(b"b".as_slice())
+            .unwrap_err();
+        assert_eq!(ExitCode::from(&e), ExitCode::Mismatch);
+        let e = Error::SignatureMissing(Digest::from_buf_sha256(b"a"));
+        assert_eq!(ExitCode::from(&e), ExitCode::Mismatch);
+
+        let e = Error::from(std::io::Error::from(std::io::ErrorKind::WouldBlock));
+        assert_eq!(ExitCode::from(&e), ExitCode::LocalStore);
+    }
+}
@@ -0,0 +1,6 @@
+//! Shared parts of `ocipkg` and `cargo-ocipkg` commands
+
+pub mod completion;
+pub mod exit_code;
+pub mod logging;
+pub mod output;
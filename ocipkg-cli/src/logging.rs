@@ -0,0 +1,51 @@
+//! Verbosity of logs shared by `ocipkg` and `cargo-ocipkg`
+
+use log::LevelFilter;
+
+/// Configure logger by the number of `-v` flags and `--quiet`
+///
+/// - `--quiet`: errors only
+/// - default: warnings
+/// - `-v`: high-level operations, e.g. pulling a manifest
+/// - `-vv`: each HTTP request with method, URL, status, and duration
+/// - `-vvv`: request and response headers in addition
+///
+/// Other crates are never more verbose than `info`.
+/// `RUST_LOG` overrides everything when it is set.
+/// Credentials are redacted by [ocipkg::distribution::HTTP_LOG_TARGET] at any level.
+pub fn builder(verbose: u8, quiet: bool) -> env_logger::Builder {
+    let mut builder = env_logger::Builder::new();
+    if std::env::var_os("RUST_LOG").is_some() {
+        builder.parse_default_env();
+        return builder;
+    }
+    let level = level(verbose, quiet);
+    builder
+        .filter_level(level.min(LevelFilter::Info))
+        .filter_module("ocipkg", level)
+        .filter_module("cargo_ocipkg", level);
+    builder
+}
+
+fn level(verbose: u8, quiet: bool) -> LevelFilter {
+    match (quiet, verbose) {
+        (true, _) => LevelFilter::Error,
+        (false, 0) => LevelFilter::Warn,
+        (false, 1) => LevelFilter::Info,
+        (false, 2) => LevelFilter::Debug,
+        _ => LevelFilter::Trace,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn levels() {
+        assert_eq!(level(3, true), LevelFilter::Error);
+        assert_eq!(level(0, false), LevelFilter::Warn);
+        assert_eq!(level(2, false), LevelFilter::Debug);
+        assert_eq!(level(5, false), LevelFilter::Trace);
+    }
+}
@@ -0,0 +1,401 @@
+//! Structures printed to stdout by `ocipkg --format json`
+//!
+//! Field names and shapes are a compatibility surface for scripts,
+//! and must not be changed without a breaking release.
+//! Human-facing messages, e.g. progress and summaries, are printed to stderr in both formats.
+
+use clap::ValueEnum;
+use ocipkg::{
+    distribution::{ConformanceOutcome, ConformanceTest, HttpExchange},
+    error::Error,
+    image::lint::Check,
+};
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Format {
+    /// Human readable text
+    #[value(alias = "text")]
+    Table,
+    /// JSON documented in [crate::output]
+    Json,
+}
+
+/// Image in local storage, an element of `list`, and the result of `get`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ImageEntry {
+    pub name: String,
+    /// Digest of manifest, `null` for images loaded from oci-archive
+    pub digest: Option<String>,
+    /// Directory where the layers are expanded
+    pub path: String,
+}
+
+/// Element of `tags`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct TagEntry {
+    pub tag: String,
+    /// Digest of manifest, only with `--with-digests` or `--sort digest`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub digest: Option<String>,
+}
+
+/// Element of `push`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PushedImage {
+    pub name: String,
+    pub digest: String,
+}
+
+/// Result of `gc`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct GcOutput {
+    pub dry_run: bool,
+    pub removed: Vec<RemovedEntry>,
+    pub bytes_freed: u64,
+}
+
+/// Path removed by `gc`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct RemovedEntry {
+    pub path: String,
+    pub size: u64,
+}
+
+/// Element of `verify`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct CheckEntry {
+    pub name: String,
+    pub passed: bool,
+    pub message: String,
+}
+
+impl From<&Check> for CheckEntry {
+    fn from(check: &Check) -> Self {
+        Self {
+            name: check.name.clone(),
+            passed: check.passed,
+            message: check.message.clone(),
+        }
+    }
+}
+
+/// Result of `inspect` for an image index or an image manifest, distinguished by `kind`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Inspected {
+    Index {
+        /// Name in `index.json` of oci-archive, or image name with `--remote`
+        name: String,
+        digest: String,
+        media_type: String,
+        manifests: Vec<PlatformEntry>,
+        annotations: BTreeMap<String, String>,
+    },
+    Manifest {
+        name: String,
+        digest: String,
+        media_type: String,
+        /// `{os}/{architecture}[/{variant}]` in the image configuration
+        platform: Option<String>,
+        config: BlobEntry,
+        layers: Vec<LayerEntry>,
+        annotations: BTreeMap<String, String>,
+    },
+}
+
+/// Manifest listed in an image index
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PlatformEntry {
+    /// `{os}/{architecture}[/{variant}]`, `null` if unknown
+    pub platform: Option<String>,
+    pub digest: String,
+    pub size: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct BlobEntry {
+    pub digest: String,
+    pub media_type: String,
+    pub size: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct LayerEntry {
+    pub digest: String,
+    pub media_type: String,
+    pub size: u64,
+    /// Paths in the layer, only for gzip layers in oci-archive
+    pub files: Option<Vec<String>>,
+}
+
+/// Element of `attachments`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct AttachmentEntry {
+    /// Digest of the artifact manifest
+    pub digest: String,
+    /// e.g. `application/spdx+json`, `null` if the artifact does not declare it
+    pub artifact_type: Option<String>,
+    /// Size of the artifact manifest
+    pub size: u64,
+    pub annotations: BTreeMap<String, String>,
+}
+
+/// Element of `conformance`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ConformanceEntry {
+    /// `pull`, `push`, `content-discovery`, or `content-management`
+    pub category: String,
+    pub name: String,
+    /// `pass`, `fail`, or `skip`
+    pub outcome: String,
+    /// Reason of failure or skip
+    pub message: Option<String>,
+    /// Request which caused the failure
+    pub exchange: Option<ExchangeEntry>,
+}
+
+impl From<&ConformanceTest> for ConformanceEntry {
+    fn from(test: &ConformanceTest) -> Self {
+        let (outcome, message) = match &test.outcome {
+            ConformanceOutcome::Pass => ("pass", None),
+            ConformanceOutcome::Fail(message) => ("fail", Some(message.clone())),
+            ConformanceOutcome::Skip(message) => ("skip", Some(message.clone())),
+        };
+        Self {
+            category: test.category.to_string(),
+            name: test.name.clone(),
+            outcome: outcome.to_string(),
+            message,
+            exchange: test.exchange.as_ref().map(ExchangeEntry::from),
+        }
+    }
+}
+
+/// HTTP request and response, where credentials are redacted
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ExchangeEntry {
+    pub method: String,
+    pub url: String,
+    pub request_headers: BTreeMap<String, String>,
+    /// `null` if no response is received
+    pub status: Option<u16>,
+    pub response_headers: BTreeMap<String, String>,
+}
+
+impl From<&HttpExchange> for ExchangeEntry {
+    fn from(exchange: &HttpExchange) -> Self {
+        Self {
+            method: exchange.method.clone(),
+            url: exchange.url.clone(),
+            request_headers: exchange.request_headers.iter().cloned().collect(),
+            status: exchange.status,
+            response_headers: exchange.response_headers.iter().cloned().collect(),
+        }
+    }
+}
+
+/// Printed instead of the result when the command fails
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ErrorOutput {
+    pub error: ErrorBody,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ErrorBody {
+    /// Name of the variant of [ocipkg::error::Error], e.g. `ManifestNotFound`
+    pub kind: String,
+    pub message: String,
+}
+
+impl From<&Error> for ErrorOutput {
+    fn from(e: &Error) -> Self {
+        let debug = format!("{:?}", e);
+        let kind = debug
+            .split(|c: char| !c.is_alphanumeric() && c != '_')
+            .next()
+            .unwrap_or_default()
+            .to_string();
+        Self {
+            error: ErrorBody {
+                kind,
+                message: e.to_string(),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn field_names() {
+        let image = ImageEntry {
+            name: "ghcr.io/termoshtt/ocipkg/testing:v1".to_string(),
+            digest: None,
+            path: "/data/ocipkg/testing".to_string(),
+        };
+        assert_eq!(
+            serde_json::to_value(image).unwrap(),
+            json!({ "name": "ghcr.io/termoshtt/ocipkg/testing:v1", "digest": null, "path": "/data/ocipkg/testing" })
+        );
+
+        let tags = [
+            TagEntry {
+                tag: "v1".to_string(),
+                digest: None,
+            },
+            TagEntry {
+                tag: "v2".to_string(),
+                digest: Some("sha256:00".to_string()),
+            },
+        ];
+        assert_eq!(
+            serde_json::to_value(tags).unwrap(),
+            json!([{ "tag": "v1" }, { "tag": "v2", "digest": "sha256:00" }])
+        );
+
+        let pushed = PushedImage {
+            name: "localhost:5000/test:v1".to_string(),
+            digest: "sha256:00".to_string(),
+        };
+        assert_eq!(
+            serde_json::to_value(pushed).unwrap(),
+            json!({ "name": "localhost:5000/test:v1", "digest": "sha256:00" })
+        );
+
+        let gc = GcOutput {
+            dry_run: true,
+            removed: vec![RemovedEntry {
+                path: "/data/ocipkg/.cache/x".to_string(),
+                size: 3,
+            }],
+            bytes_freed: 3,
+        };
+        assert_eq!(
+            serde_json::to_value(gc).unwrap(),
+            json!({ "dry_run": true, "removed": [{ "path": "/data/ocipkg/.cache/x", "size": 3 }], "bytes_freed": 3 })
+        );
+
+        let attachment = AttachmentEntry {
+            digest: "sha256:00".to_string(),
+            artifact_type: Some("application/spdx+json".to_string()),
+            size: 10,
+            annotations: BTreeMap::new(),
+        };
+        assert_eq!(
+            serde_json::to_value(attachment).unwrap(),
+            json!({ "digest": "sha256:00", "artifact_type": "application/spdx+json", "size": 10, "annotations": {} })
+        );
+
+        let check = CheckEntry::from(&Check::fail("size", "too large"));
+        assert_eq!(
+            serde_json::to_value(check).unwrap(),
+            json!({ "name": "size", "passed": false, "message": "too large" })
+        );
+
+        let blob = BlobEntry {
+            digest: "sha256:00".to_string(),
+            media_type: "application/vnd.oci.image.config.v1+json".to_string(),
+            size: 2,
+        };
+        let manifest = Inspected::Manifest {
+            name: "test".to_string(),
+            digest: "sha256:11".to_string(),
+            media_type: "application/vnd.oci.image.manifest.v1+json".to_string(),
+            platform: Some("linux/amd64".to_string()),
+            config: blob,
+            layers: vec![LayerEntry {
+                digest: "sha256:22".to_string(),
+                media_type: "application/vnd.oci.image.layer.v1.tar+gzip".to_string(),
+                size: 5,
+                files: Some(vec!["a.txt".to_string()]),
+            }],
+            annotations: BTreeMap::new(),
+        };
+        assert_eq!(
+            serde_json::to_value(manifest).unwrap(),
+            json!({
+                "kind": "manifest",
+                "name": "test",
+                "digest": "sha256:11",
+                "media_type": "application/vnd.oci.image.manifest.v1+json",
+                "platform": "linux/amd64",
+                "config": {
+                    "digest": "sha256:00",
+                    "media_type": "application/vnd.oci.image.config.v1+json",
+                    "size": 2
+                },
+                "layers": [{
+                    "digest": "sha256:22",
+                    "media_type": "application/vnd.oci.image.layer.v1.tar+gzip",
+                    "size": 5,
+                    "files": ["a.txt"]
+                }],
+                "annotations": {}
+            })
+        );
+        let index = Inspected::Index {
+            name: "test".to_string(),
+            digest: "sha256:11".to_string(),
+            media_type: "application/vnd.oci.image.index.v1+json".to_string(),
+            manifests: vec![PlatformEntry {
+                platform: None,
+                digest: "sha256:22".to_string(),
+                size: 5,
+            }],
+            annotations: BTreeMap::new(),
+        };
+        assert_eq!(
+            serde_json::to_value(index).unwrap(),
+            json!({
+                "kind": "index",
+                "name": "test",
+                "digest": "sha256:11",
+                "media_type": "application/vnd.oci.image.index.v1+json",
+                "manifests": [{ "platform": null, "digest": "sha256:22", "size": 5 }],
+                "annotations": {}
+            })
+        );
+
+        let test = ConformanceTest {
+            category: ocipkg::distribution::ConformanceCategory::Push,
+            name: "push blob".to_string(),
+            outcome: ConformanceOutcome::Fail("unexpected status".to_string()),
+            exchange: Some(HttpExchange {
+                method: "POST".to_string(),
+                url: "http://localhost:5000/v2/test/blobs/uploads/".to_string(),
+                request_headers: vec![],
+                status: Some(405),
+                response_headers: vec![("content-length".to_string(), "0".to_string())],
+            }),
+        };
+        assert_eq!(
+            serde_json::to_value(ConformanceEntry::from(&test)).unwrap(),
+            json!({
+                "category": "push",
+                "name": "push blob",
+                "outcome": "fail",
+                "message": "unexpected status",
+                "exchange": {
+                    "method": "POST",
+                    "url": "http://localhost:5000/v2/test/blobs/uploads/",
+                    "request_headers": {},
+                    "status": 405,
+                    "response_headers": { "content-length": "0" }
+                }
+            })
+        );
+
+        let error = ErrorOutput::from(&Error::ManifestNotFound("v1".to_string()));
+        assert_eq!(
+            serde_json::to_value(error).unwrap(),
+            json!({ "error": { "kind": "ManifestNotFound", "message": "Manifest not found in registry: v1" } })
+        );
+        let error = ErrorOutput::from(&Error::MissingIndex);
+        assert_eq!(error.error.kind, "MissingIndex");
+    }
+}
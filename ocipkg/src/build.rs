@@ -0,0 +1,334 @@
+//! Helpers for [build script](https://doc.rust-lang.org/cargo/reference/build-scripts.html) a.k.a. `build.rs`
+
+use crate::{error::*, Digest, ImageName};
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+};
+
+/// Marker file written after the extraction completes
+///
+/// Each line is `{digest} {image name}` of an image owning the extracted directory,
+/// since images of different names may have the same manifest.
+const MARKER: &str = ".ocipkg-extracted";
+
+/// Extract the contents of a container image, and returns the extracted directory
+///
+/// The image is pulled into local storage if not exists, and extracted only when
+/// the digest of its manifest changes.
+///
+/// By default, the image is extracted into the shared cache [crate::local::extract_cache_dir]
+/// to be reused across workspaces. The returned directory is read-only, and stale entries
+/// are removed by [crate::local::gc_extract_cache] or [crate::local::gc].
+/// If `OCIPKG_NO_SHARED_CACHE=1` is set, the image is extracted into `$OUT_DIR/ocipkg/<digest>/`,
+/// and directories extracted from older manifests of the same image are removed.
+///
+/// This is aimed to use in `build.rs`, e.g. to get headers for bindgen.
+/// Build scripts extracting the same image concurrently are serialized by file locks.
+pub fn extract_to_out_dir(image_name: &str) -> Result<PathBuf> {
+    let image_name = ImageName::parse(image_name)?;
+    let out_dir = env::var_os("OUT_DIR").ok_or_else(|| Error::MissingEnv("OUT_DIR".into()))?;
+
+    let _lock = crate::local::lock_store(false)?;
+    let dir = ensure_local(&image_name)?;
+
+    let dest = if env::var("OCIPKG_NO_SHARED_CACHE").is_ok_and(|v| v == "1") {
+        extract(
+            &dir,
+            &image_name,
+            &Path::new(&out_dir).join("ocipkg"),
+            false,
+        )?
+    } else {
+        extract(&dir, &image_name, &crate::local::extract_cache_dir()?, true)?
+    };
+    println!(
+        "cargo:rerun-if-changed={}",
+        dir.join(".manifest.json").display()
+    );
+    println!("cargo:rerun-if-env-changed=XDG_DATA_HOME");
+    println!("cargo:rerun-if-env-changed=OCIPKG_NO_SHARED_CACHE");
+    Ok(dest)
+}
+
+/// Check if network access is disabled by `OCIPKG_OFFLINE=1` or cargo's `CARGO_NET_OFFLINE=true`
+pub fn is_offline() -> bool {
+    env::var("OCIPKG_OFFLINE").is_ok_and(|v| v == "1")
+        || env::var("CARGO_NET_OFFLINE").is_ok_and(|v| v == "true")
+}
+
+/// Get the image into local storage if not exists, and returns its directory
+///
+/// In offline mode or without `network` feature, this fails immediately
+/// with [Error::OfflineImageMissing] instead of trying to access the registry.
+/// Images not pinned to a digest nor locked in [crate::lockfile::LOCKFILE] are rejected
+/// under [crate::config::PullPolicy::RequireDigest] even if they exist in local storage.
+pub(crate) fn ensure_local(image_name: &ImageName) -> Result<PathBuf> {
+    if let Some(path) = crate::lockfile::lockfile_path() {
+        println!("cargo:rerun-if-changed={}", path.display());
+    }
+    // Locked images are pinned by the lockfile, and checked when fetched
+    if crate::lockfile::Lockfile::load()?.get(image_name).is_none() {
+        crate::config::Config::load()?
+            .pull_policy
+            .check(image_name)?;
+    }
+    println!("cargo:rerun-if-env-changed=OCIPKG_REQUIRE_DIGEST");
+    let dir = crate::local::image_dir(image_name)?;
+    fs::create_dir_all(dir.parent().unwrap())?;
+    let lock = fs::File::create(lock_path(&dir))?;
+    lock.lock()?;
+    if !dir.exists() {
+        fetch(image_name)?;
+    }
+    crate::local::touch_image(image_name)?;
+    println!("cargo:rerun-if-env-changed=OCIPKG_OFFLINE");
+    println!("cargo:rerun-if-env-changed=CARGO_NET_OFFLINE");
+    Ok(dir)
+}
+
+#[cfg(feature = "network")]
+fn fetch(image_name: &ImageName) -> Result<()> {
+    if is_offline() {
+        return Err(offline_error(image_name));
+    }
+    crate::distribution::get_image(image_name, false)
+}
+
+#[cfg(not(feature = "network"))]
+fn fetch(image_name: &ImageName) -> Result<()> {
+    Err(offline_error(image_name))
+}
+
+fn offline_error(image_name: &ImageName) -> Error {
+    let err = Error::OfflineImageMissing(image_name.clone());
+    println!("cargo:warning={}", err);
+    err
+}
+
+/// Copy an image in local storage `src` into `{root}/<digest>/`
+///
+/// The directory is keyed only by the digest, and shared by images of the same manifest,
+/// which are recorded in the marker file as its owners.
+/// If `shared` is set, the copied files are made read-only, and older extractions are kept.
+fn extract(src: &Path, image_name: &ImageName, root: &Path, shared: bool) -> Result<PathBuf> {
+    let digest = Digest::from_buf_sha256(&fs::read(src.join(".manifest.json"))?);
+    let image_name = image_name.to_string();
+    let dest = root.join(&digest.encoded);
+    fs::create_dir_all(root)?;
+
+    {
+        let lock = fs::File::create(lock_path(&dest))?;
+        lock.lock()?;
+        let marker = read_marker(&dest).filter(|(extracted, _)| *extracted == digest.to_string());
+        if let Some((_, mut owners)) = marker {
+            log::info!("Already extracted: {}", dest.display());
+            if !owners.contains(&image_name) {
+                owners.push(image_name.clone());
+                write_marker(&dest, &digest, &owners)?;
+            }
+        } else {
+            // Remove partially extracted contents
+            if dest.exists() {
+                remove_extracted(&dest)?;
+            }
+            for entry in walkdir::WalkDir::new(src).min_depth(1) {
+                let entry = entry?;
+                let rel = entry
+                    .path()
+                    .strip_prefix(src)
+                    .expect("WalkDir returns path under src");
+                if rel.starts_with(".blob")
+                    || rel == Path::new(".manifest.json")
+                    || rel == Path::new(crate::local::LAST_ACCESS)
+                    || rel == Path::new(crate::local::SOURCE)
+                {
+                    continue;
+                }
+                let target = dest.join(rel);
+                if entry.file_type().is_dir() {
+                    fs::create_dir_all(&target)?;
+                } else {
+                    fs::create_dir_all(target.parent().unwrap())?;
+                    fs::copy(entry.path(), &target)?;
+                    if shared {
+                        let mut permissions = fs::metadata(&target)?.permissions();
+                        permissions.set_readonly(true);
+                        fs::set_permissions(&target, permissions)?;
+                    }
+                }
+            }
+            fs::create_dir_all(&dest)?;
+            write_marker(&dest, &digest, std::slice::from_ref(&image_name))?;
+        }
+    }
+
+    if shared {
+        return Ok(dest);
+    }
+
+    // Clean up directories extracted from older manifests of the same image,
+    // which are kept while other images own them
+    for entry in fs::read_dir(root)? {
+        let path = entry?.path();
+        if !path.is_dir() || path == dest {
+            continue;
+        }
+        let lock = fs::File::create(lock_path(&path))?;
+        lock.lock()?;
+        let Some((extracted, mut owners)) = read_marker(&path) else {
+            continue;
+        };
+        if !owners.contains(&image_name) {
+            continue;
+        }
+        owners.retain(|name| *name != image_name);
+        if owners.is_empty() {
+            log::info!("Remove stale extraction: {}", path.display());
+            remove_extracted(&path)?;
+            drop(lock);
+            fs::remove_file(lock_path(&path))?;
+        } else {
+            write_marker(&path, &Digest::new(&extracted)?, &owners)?;
+        }
+    }
+    Ok(dest)
+}
+
+/// Digest and names of images owning the extracted directory recorded in the marker file
+pub(crate) fn read_marker(dir: &Path) -> Option<(String, Vec<String>)> {
+    let content = fs::read_to_string(dir.join(MARKER)).ok()?;
+    let mut digest = None;
+    let mut owners = Vec::new();
+    for line in content.lines() {
+        let (d, name) = line.split_once(' ')?;
+        if *digest.get_or_insert(d) != d {
+            return None;
+        }
+        owners.push(name.to_string());
+    }
+    Some((digest?.to_string(), owners))
+}
+
+fn write_marker(dir: &Path, digest: &Digest, owners: &[String]) -> Result<()> {
+    let content: String = owners
+        .iter()
+        .map(|name| format!("{} {}\n", digest, name))
+        .collect();
+    fs::write(dir.join(MARKER), content)?;
+    Ok(())
+}
+
+/// Remove extracted directory which may contain read-only files
+pub(crate) fn remove_extracted(dir: &Path) -> Result<()> {
+    if cfg!(target_os = "windows") {
+        for entry in walkdir::WalkDir::new(dir) {
+            let entry = entry?;
+            if entry.file_type().is_file() {
+                let mut permissions = entry.metadata()?.permissions();
+                #[allow(clippy::permissions_set_readonly_false)]
+                permissions.set_readonly(false);
+                fs::set_permissions(entry.path(), permissions)?;
+            }
+        }
+    }
+    fs::remove_dir_all(dir)?;
+    Ok(())
+}
+
+pub(crate) fn lock_path(dir: &Path) -> PathBuf {
+    let mut path = dir.as_os_str().to_owned();
+    path.push(".lock");
+    PathBuf::from(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn extract_and_cleanup() -> Result<()> {
+        let src = tempfile::tempdir()?;
+        let root = tempfile::tempdir()?;
+        let image_name = ImageName::parse("ghcr.io/termoshtt/ocipkg/test:v1")?;
+
+        fs::create_dir_all(src.path().join(".blob/sha256"))?;
+        fs::create_dir_all(src.path().join("include"))?;
+        fs::write(src.path().join("include/a.h"), "int a;")?;
+        fs::write(src.path().join(".manifest.json"), "v1")?;
+
+        let first = extract(src.path(), &image_name, root.path(), false)?;
+        assert_eq!(fs::read_to_string(first.join("include/a.h"))?, "int a;");
+        assert!(!first.join(".blob").exists());
+        assert!(!first.join(".manifest.json").exists());
+
+        // Skip extraction when the marker exists
+        fs::write(first.join("include/a.h"), "int b;")?;
+        assert_eq!(extract(src.path(), &image_name, root.path(), false)?, first);
+        assert_eq!(fs::read_to_string(first.join("include/a.h"))?, "int b;");
+
+        // Another manifest replaces old one
+        fs::write(src.path().join(".manifest.json"), "v2")?;
+        let second = extract(src.path(), &image_name, root.path(), false)?;
+        assert_ne!(first, second);
+        assert!(!first.exists());
+        assert_eq!(fs::read_to_string(second.join("include/a.h"))?, "int a;");
+        Ok(())
+    }
+
+    #[test]
+    fn extract_shared() -> Result<()> {
+        let src = tempfile::tempdir()?;
+        let root = tempfile::tempdir()?;
+        let image_name = ImageName::parse("ghcr.io/termoshtt/ocipkg/test:v1")?;
+        fs::write(src.path().join("a.txt"), "a")?;
+        fs::write(src.path().join(".manifest.json"), "v1")?;
+
+        let first = extract(src.path(), &image_name, root.path(), true)?;
+        assert!(fs::metadata(first.join("a.txt"))?.permissions().readonly());
+        assert_eq!(read_marker(&first).unwrap().1, [image_name.to_string()]);
+
+        // Older extraction is kept for other workspaces
+        fs::write(src.path().join(".manifest.json"), "v2")?;
+        let second = extract(src.path(), &image_name, root.path(), true)?;
+        assert!(first.exists());
+        assert!(second.exists());
+
+        remove_extracted(&first)?;
+        remove_extracted(&second)?;
+        Ok(())
+    }
+
+    #[test]
+    fn extract_same_manifest() -> Result<()> {
+        let src = tempfile::tempdir()?;
+        let root = tempfile::tempdir()?;
+        let a = ImageName::parse("ghcr.io/termoshtt/ocipkg/a:v1")?;
+        let b = ImageName::parse("ghcr.io/termoshtt/ocipkg/b:v1")?;
+        fs::write(src.path().join("a.txt"), "a")?;
+        fs::write(src.path().join(".manifest.json"), "v1")?;
+
+        // Shared by both names without extracting again
+        let first = extract(src.path(), &a, root.path(), false)?;
+        fs::write(first.join("a.txt"), "b")?;
+        assert_eq!(extract(src.path(), &b, root.path(), false)?, first);
+        assert_eq!(fs::read_to_string(first.join("a.txt"))?, "b");
+        assert_eq!(extract(src.path(), &a, root.path(), false)?, first);
+        assert_eq!(
+            read_marker(&first).unwrap().1,
+            [a.to_string(), b.to_string()]
+        );
+
+        // Kept while another image owns it
+        fs::write(src.path().join(".manifest.json"), "v2")?;
+        let second = extract(src.path(), &a, root.path(), false)?;
+        assert_eq!(read_marker(&first).unwrap().1, [b.to_string()]);
+        assert_eq!(extract(src.path(), &b, root.path(), false)?, second);
+        assert!(!first.exists());
+        assert_eq!(
+            read_marker(&second).unwrap().1,
+            [a.to_string(), b.to_string()]
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,361 @@
+//! Configuration of ocipkg
+//!
+//! The configuration is loaded from `config.toml` in the config directory,
+//! e.g. `~/.config/ocipkg/config.toml` on Linux:
+//!
+//! ```toml
+//! # Get images of `ghcr.io/...` from `artifactory.internal/mirror/...`
+//! [source-replace]
+//! "ghcr.io" = "artifactory.internal/mirror"
+//! ```
+//!
+//! The `OCIPKG_SOURCE_REPLACE` environment variable, e.g. `ghcr.io=artifactory.internal/mirror`,
+//! overrides the source replacement in the configuration file. Multiple replacements are separated by `,`.
+//!
+//! Pulls of images not pinned to a digest can be restricted by [PullPolicy]:
+//!
+//! ```toml
+//! pull-policy = "require-digest"
+//! ```
+//!
+//! `OCIPKG_REQUIRE_DIGEST=1` sets [PullPolicy::RequireDigest] regardless of the configuration file, e.g. in CI.
+//!
+//! Registries where images are fetched from can be restricted by [RegistryRule]s,
+//! evaluated in order after the source replacement:
+//!
+//! ```toml
+//! registry-rules = [
+//!     { deny = "ghcr.io/untrusted" },
+//!     { allow = "ghcr.io" },
+//!     { allow = "*.internal" },
+//! ]
+//! ```
+//!
+//! The `OCIPKG_ALLOWED_REGISTRIES` environment variable, e.g. `ghcr.io,*.internal`,
+//! replaces the rules in the configuration file with allow rules of the patterns separated by `,`.
+//!
+//! Pulls of layers larger than the limit in bytes are refused before downloading,
+//! and the size is unlimited if not set:
+//!
+//! ```toml
+//! max-blob-size = 10737418240 # 10 GiB
+//! ```
+
+use crate::{error::*, ImageName, Name};
+use serde::Deserialize;
+use std::{collections::BTreeMap, env, fs, path::PathBuf};
+
+/// Content of the configuration file
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Config {
+    /// Map from `hostname[:port][/prefix]` of requested images to the one actually used
+    #[serde(default)]
+    pub source_replace: BTreeMap<String, String>,
+    #[serde(default)]
+    pub pull_policy: PullPolicy,
+    /// Rules for registries, where the first matching rule is applied
+    #[serde(default)]
+    pub registry_rules: Vec<RegistryRule>,
+    /// Limit of blob size in bytes, see [crate::distribution::Client::set_max_blob_size]
+    #[serde(default)]
+    pub max_blob_size: Option<u64>,
+}
+
+/// Rule to allow or deny registries by a glob pattern
+///
+/// The pattern is matched to `hostname[:port]`, or to `hostname[:port]/{prefix}` of repositories
+/// if it contains `/`, where `*` matches any characters including `/`, and `?` matches a character.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum RegistryRule {
+    Allow(String),
+    Deny(String),
+}
+
+impl std::fmt::Display for RegistryRule {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RegistryRule::Allow(pattern) => write!(f, "allow = \"{}\"", pattern),
+            RegistryRule::Deny(pattern) => write!(f, "deny = \"{}\"", pattern),
+        }
+    }
+}
+
+impl RegistryRule {
+    /// Check if the pattern matches to the registry or a prefix of the repository
+    pub fn matches(&self, image_name: &ImageName) -> bool {
+        let (RegistryRule::Allow(pattern) | RegistryRule::Deny(pattern)) = self;
+        matches_repository(pattern, image_name)
+    }
+}
+
+/// Check if the glob pattern matches to `hostname[:port]` or `hostname[:port]/{prefix}` of the repository
+pub(crate) fn matches_repository(pattern: &str, image_name: &ImageName) -> bool {
+    let registry = match image_name.port {
+        Some(port) => format!("{}:{}", image_name.hostname, port),
+        None => image_name.hostname.clone(),
+    };
+    let mut target = registry;
+    if glob_match(pattern.as_bytes(), target.as_bytes()) {
+        return true;
+    }
+    for component in image_name.name.split('/') {
+        target = format!("{}/{}", target, component);
+        if glob_match(pattern.as_bytes(), target.as_bytes()) {
+            return true;
+        }
+    }
+    false
+}
+
+fn glob_match(pattern: &[u8], input: &[u8]) -> bool {
+    match (pattern.split_first(), input.split_first()) {
+        (None, None) => true,
+        (Some((b'*', rest)), _) => {
+            glob_match(rest, input) || (!input.is_empty() && glob_match(pattern, &input[1..]))
+        }
+        (Some((b'?', p)), Some((_, i))) => glob_match(p, i),
+        (Some((c, p)), Some((d, i))) if c == d => glob_match(p, i),
+        _ => false,
+    }
+}
+
+/// Whether images may be pulled by tag, checked by [crate::distribution::get_image] and build script helpers
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum PullPolicy {
+    /// Images are pulled by tag as requested
+    #[default]
+    Permissive,
+    /// Tags are resolved to digests first, and the manifests are pulled by the digests with a warning
+    PreferDigest,
+    /// Images must be pinned to digests, e.g. `name@sha256:...`, otherwise [Error::UnpinnedImage]
+    RequireDigest,
+}
+
+impl PullPolicy {
+    /// Check the image can be pulled under this policy
+    pub fn check(&self, image_name: &ImageName) -> Result<()> {
+        if image_name.reference.is_digest() {
+            return Ok(());
+        }
+        match self {
+            PullPolicy::Permissive => Ok(()),
+            PullPolicy::PreferDigest => {
+                log::warn!(
+                    "{} is not pinned to a digest, pin it as `name@sha256:...`",
+                    image_name
+                );
+                Ok(())
+            }
+            PullPolicy::RequireDigest => Err(Error::UnpinnedImage(image_name.clone())),
+        }
+    }
+}
+
+impl Config {
+    /// Load configuration file and environment variables
+    pub fn load() -> Result<Self> {
+        let mut config = match config_path() {
+            Some(path) if path.is_file() => toml::from_str(&fs::read_to_string(path)?)?,
+            _ => Self::default(),
+        };
+        if let Ok(replace) = env::var("OCIPKG_SOURCE_REPLACE") {
+            for entry in replace.split(',').filter(|e| !e.trim().is_empty()) {
+                let (from, to) = entry
+                    .split_once('=')
+                    .ok_or_else(|| Error::InvalidSourceReplace(entry.to_string()))?;
+                config
+                    .source_replace
+                    .insert(from.trim().to_string(), to.trim().to_string());
+            }
+        }
+        if env::var("OCIPKG_REQUIRE_DIGEST").is_ok_and(|v| v == "1") {
+            config.pull_policy = PullPolicy::RequireDigest;
+        }
+        if let Ok(allowed) = env::var("OCIPKG_ALLOWED_REGISTRIES") {
+            config.registry_rules = allowed
+                .split(',')
+                .map(str::trim)
+                .filter(|pattern| !pattern.is_empty())
+                .map(|pattern| RegistryRule::Allow(pattern.to_string()))
+                .collect();
+        }
+        Ok(config)
+    }
+
+    /// Check the registry of the image by [Config::registry_rules]
+    ///
+    /// The first matching rule is applied. If no rule matches, the image is denied
+    /// only when allow rules exist, i.e. the rules are used as an allowlist.
+    /// This should be checked after [Config::replace_source] to evaluate the registry actually used.
+    pub fn check_registry(&self, image_name: &ImageName) -> Result<()> {
+        let rule = match self
+            .registry_rules
+            .iter()
+            .find(|rule| rule.matches(image_name))
+        {
+            Some(RegistryRule::Allow(_)) => return Ok(()),
+            Some(rule) => rule.to_string(),
+            None if self
+                .registry_rules
+                .iter()
+                .any(|rule| matches!(rule, RegistryRule::Allow(_))) =>
+            {
+                "no allow rule matches".to_string()
+            }
+            None => return Ok(()),
+        };
+        Err(Error::RegistryNotAllowed {
+            image: image_name.clone(),
+            rule,
+        })
+    }
+
+    /// Image name where the image is actually fetched from
+    ///
+    /// The longest `hostname[:port][/prefix]` matching to the image name is replaced.
+    /// The reference is kept as it is, i.e. images pinned by digest are still verified.
+    pub fn replace_source(&self, image_name: &ImageName) -> Result<ImageName> {
+        let registry = if let Some(port) = image_name.port {
+            format!("{}:{}", image_name.hostname, port)
+        } else {
+            image_name.hostname.clone()
+        };
+        let full = format!("{}/{}", registry, image_name.name);
+        let Some((from, to)) = self
+            .source_replace
+            .iter()
+            .filter(|(from, _)| {
+                from.as_str() == registry
+                    || full == **from
+                    || full.starts_with(&format!("{}/", from))
+            })
+            .max_by_key(|(from, _)| from.len())
+        else {
+            return Ok(image_name.clone());
+        };
+        let rest = full[from.len()..].trim_start_matches('/');
+        let (registry, prefix) = to.split_once('/').unwrap_or((to, ""));
+        let (hostname, port) = crate::image_name::split_host_port(registry)?;
+        let name = match (prefix.is_empty(), rest.is_empty()) {
+            (true, true) => return Err(Error::InvalidSourceReplace(format!("{}={}", from, to))),
+            (true, false) => rest.to_string(),
+            (false, true) => prefix.to_string(),
+            (false, false) => format!("{}/{}", prefix, rest),
+        };
+        Ok(ImageName {
+            hostname: hostname.to_string(),
+            port,
+            name: Name::new(&name)?,
+            reference: image_name.reference.clone(),
+        })
+    }
+}
+
+/// Path of the configuration file
+pub fn config_path() -> Option<PathBuf> {
+    let dirs = directories::ProjectDirs::from("", "", "ocipkg")?;
+    Some(dirs.config_dir().join("config.toml"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replace_source() -> Result<()> {
+        let config: Config = toml::from_str(
+            r#"
+            [source-replace]
+            "ghcr.io" = "artifactory.internal/mirror"
+            "ghcr.io/termoshtt/special" = "localhost:5000/special"
+            "#,
+        )?;
+
+        let name = ImageName::parse("ghcr.io/termoshtt/ocipkg/testing:v1")?;
+        assert_eq!(
+            config.replace_source(&name)?,
+            ImageName::parse("artifactory.internal/mirror/termoshtt/ocipkg/testing:v1")?
+        );
+
+        let name = ImageName::parse("ghcr.io/termoshtt/special/lib:v1")?;
+        assert_eq!(
+            config.replace_source(&name)?,
+            ImageName::parse("localhost:5000/special/lib:v1")?
+        );
+
+        let name = ImageName::parse("ghcr.io.example.com/termoshtt/lib:v1")?;
+        assert_eq!(config.replace_source(&name)?, name);
+        Ok(())
+    }
+
+    #[test]
+    fn registry_rules() -> Result<()> {
+        let config: Config = toml::from_str(
+            r#"
+            registry-rules = [
+                { deny = "ghcr.io/untrusted" },
+                { allow = "ghcr.io" },
+                { allow = "*.internal" },
+                { allow = "localhost:5000/test_*" },
+            ]
+            "#,
+        )?;
+        for allowed in [
+            "ghcr.io/termoshtt/ocipkg/testing:v1",
+            "artifactory.internal/mirror/lib:v1",
+            "localhost:5000/test_repo:tag1",
+        ] {
+            config.check_registry(&ImageName::parse(allowed)?)?;
+        }
+        let denied = ImageName::parse("ghcr.io/untrusted/lib:v1")?;
+        match config.check_registry(&denied) {
+            Err(Error::RegistryNotAllowed { rule, .. }) => {
+                assert_eq!(rule, r#"deny = "ghcr.io/untrusted""#)
+            }
+            other => panic!("Unexpected: {:?}", other),
+        }
+        for denied in [
+            "ghcr.io.example.com/lib:v1",
+            "localhost:5000/other:v1",
+            "ubuntu:20.04",
+        ] {
+            assert!(matches!(
+                config.check_registry(&ImageName::parse(denied)?),
+                Err(Error::RegistryNotAllowed { .. })
+            ));
+        }
+        Config::default().check_registry(&denied)?;
+        Ok(())
+    }
+
+    #[test]
+    fn pull_policy() -> Result<()> {
+        let config: Config = toml::from_str(r#"pull-policy = "require-digest""#)?;
+        assert_eq!(config.pull_policy, PullPolicy::RequireDigest);
+        assert_eq!(Config::default().pull_policy, PullPolicy::Permissive);
+        assert_eq!(config.max_blob_size, None);
+        let config: Config = toml::from_str("max-blob-size = 1024")?;
+        assert_eq!(config.max_blob_size, Some(1024));
+
+        let tag = ImageName::parse("ghcr.io/termoshtt/ocipkg/testing:v1")?;
+        let pinned = ImageName::parse(
+            "ghcr.io/termoshtt/ocipkg/testing@sha256:e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855",
+        )?;
+        for policy in [
+            PullPolicy::Permissive,
+            PullPolicy::PreferDigest,
+            PullPolicy::RequireDigest,
+        ] {
+            policy.check(&pinned)?;
+        }
+        PullPolicy::PreferDigest.check(&tag)?;
+        assert!(matches!(
+            PullPolicy::RequireDigest.check(&tag),
+            Err(Error::UnpinnedImage(_))
+        ));
+        Ok(())
+    }
+}
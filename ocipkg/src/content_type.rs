@@ -0,0 +1,168 @@
+use oci_spec::image::MediaType;
+use std::fmt;
+
+/// Compression of image layer
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LayerCompression {
+    None,
+    Gzip,
+    Zstd,
+}
+
+/// Media type of contents in OCI registries, including Docker and well-known artifact types
+///
+/// [MediaType] of oci-spec lacks Docker specific types and artifacts like Helm charts,
+/// which are kept as raw strings in [MediaType::Other].
+///
+/// ```
+/// use ocipkg::{LayerCompression, MediaType, OciContentType};
+///
+/// let ty = OciContentType::from_str("application/vnd.oci.image.layer.v1.tar+gzip");
+/// assert_eq!(ty, OciContentType::OciLayer(LayerCompression::Gzip));
+/// assert_eq!(MediaType::from(ty), MediaType::ImageLayerGzip);
+///
+/// let ty = OciContentType::from(&MediaType::Other(
+///     "application/vnd.docker.distribution.manifest.list.v2+json".to_string(),
+/// ));
+/// assert_eq!(ty, OciContentType::DockerManifestList);
+/// assert!(ty.is_manifest_type());
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum OciContentType {
+    OciImageManifest,
+    OciImageIndex,
+    OciImageConfig,
+    OciLayer(LayerCompression),
+    /// Docker image manifest V2 schema 2
+    DockerManifestV2,
+    DockerManifestList,
+    DockerImageConfig,
+    /// Gzip compressed layer of Docker image
+    DockerLayer,
+    HelmChartContent,
+    /// WebAssembly module as layer, see [wasm-to-oci](https://github.com/engineerd/wasm-to-oci)
+    WasmContent,
+    Unknown(String),
+}
+
+impl OciContentType {
+    pub fn to_str(&self) -> &str {
+        match self {
+            OciContentType::OciImageManifest => "application/vnd.oci.image.manifest.v1+json",
+            OciContentType::OciImageIndex => "application/vnd.oci.image.index.v1+json",
+            OciContentType::OciImageConfig => "application/vnd.oci.image.config.v1+json",
+            OciContentType::OciLayer(LayerCompression::None) => {
+                "application/vnd.oci.image.layer.v1.tar"
+            }
+            OciContentType::OciLayer(LayerCompression::Gzip) => {
+                "application/vnd.oci.image.layer.v1.tar+gzip"
+            }
+            OciContentType::OciLayer(LayerCompression::Zstd) => {
+                "application/vnd.oci.image.layer.v1.tar+zstd"
+            }
+            OciContentType::DockerManifestV2 => {
+                "application/vnd.docker.distribution.manifest.v2+json"
+            }
+            OciContentType::DockerManifestList => {
+                "application/vnd.docker.distribution.manifest.list.v2+json"
+            }
+            OciContentType::DockerImageConfig => "application/vnd.docker.container.image.v1+json",
+            OciContentType::DockerLayer => "application/vnd.docker.image.rootfs.diff.tar.gzip",
+            OciContentType::HelmChartContent => {
+                "application/vnd.cncf.helm.chart.content.v1.tar+gzip"
+            }
+            OciContentType::WasmContent => "application/vnd.wasm.content.layer.v1+wasm",
+            OciContentType::Unknown(s) => s,
+        }
+    }
+
+    /// Parse media type, where unknown types are kept as [OciContentType::Unknown]
+    #[allow(clippy::should_implement_trait)]
+    pub fn from_str(s: &str) -> OciContentType {
+        [
+            OciContentType::OciImageManifest,
+            OciContentType::OciImageIndex,
+            OciContentType::OciImageConfig,
+            OciContentType::OciLayer(LayerCompression::None),
+            OciContentType::OciLayer(LayerCompression::Gzip),
+            OciContentType::OciLayer(LayerCompression::Zstd),
+            OciContentType::DockerManifestV2,
+            OciContentType::DockerManifestList,
+            OciContentType::DockerImageConfig,
+            OciContentType::DockerLayer,
+            OciContentType::HelmChartContent,
+            OciContentType::WasmContent,
+        ]
+        .into_iter()
+        .find(|ty| ty.to_str() == s)
+        .unwrap_or_else(|| OciContentType::Unknown(s.to_string()))
+    }
+
+    /// `true` for image manifest and image index of OCI and Docker, which are served at `/v2/<name>/manifests/`
+    pub fn is_manifest_type(&self) -> bool {
+        matches!(
+            self,
+            OciContentType::OciImageManifest
+                | OciContentType::OciImageIndex
+                | OciContentType::DockerManifestV2
+                | OciContentType::DockerManifestList
+        )
+    }
+}
+
+impl fmt::Display for OciContentType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.to_str())
+    }
+}
+
+impl From<&MediaType> for OciContentType {
+    fn from(media_type: &MediaType) -> Self {
+        OciContentType::from_str(&media_type.to_string())
+    }
+}
+
+impl From<MediaType> for OciContentType {
+    fn from(media_type: MediaType) -> Self {
+        OciContentType::from(&media_type)
+    }
+}
+
+impl From<OciContentType> for MediaType {
+    fn from(ty: OciContentType) -> Self {
+        MediaType::from(ty.to_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        for ty in [
+            OciContentType::OciImageManifest,
+            OciContentType::OciImageIndex,
+            OciContentType::OciImageConfig,
+            OciContentType::OciLayer(LayerCompression::None),
+            OciContentType::OciLayer(LayerCompression::Gzip),
+            OciContentType::OciLayer(LayerCompression::Zstd),
+            OciContentType::DockerManifestV2,
+            OciContentType::DockerManifestList,
+            OciContentType::DockerImageConfig,
+            OciContentType::DockerLayer,
+            OciContentType::HelmChartContent,
+            OciContentType::WasmContent,
+            OciContentType::Unknown("application/spdx+json".to_string()),
+        ] {
+            assert_eq!(OciContentType::from_str(ty.to_str()), ty);
+            assert_eq!(OciContentType::from(MediaType::from(ty.clone())), ty);
+        }
+        assert_eq!(
+            OciContentType::from(&MediaType::ImageManifest),
+            OciContentType::OciImageManifest
+        );
+        assert!(OciContentType::DockerManifestV2.is_manifest_type());
+        assert!(!OciContentType::OciImageConfig.is_manifest_type());
+    }
+}
@@ -1,7 +1,10 @@
 use crate::error::*;
 use regex::Regex;
-use sha2::{Digest as _, Sha256};
-use std::{fmt, io, path::PathBuf};
+use sha2::{Digest as _, Sha256, Sha512};
+use std::{
+    fmt, fs, io,
+    path::{Path, PathBuf},
+};
 
 /// Digest of contents
 ///
@@ -15,7 +18,7 @@ use std::{fmt, io, path::PathBuf};
 /// algorithm-separator   ::= [+._-]
 /// encoded               ::= [a-zA-Z0-9=_-]+
 /// ```
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct Digest {
     pub algorithm: String,
     pub encoded: String,
@@ -50,6 +53,38 @@ impl Digest {
         }
     }
 
+    /// Create SHA-256 digest from 64 lowercase hex characters without `sha256:` prefix
+    ///
+    /// ```
+    /// use ocipkg::Digest;
+    ///
+    /// let hex = "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+    /// let digest = Digest::from_hex_sha256(hex).unwrap();
+    /// assert_eq!(digest, Digest::from_buf_sha256(&[]));
+    ///
+    /// assert!(Digest::from_hex_sha256("e3b0c442").is_err());
+    /// assert!(Digest::from_hex_sha256(&hex.to_uppercase()).is_err());
+    /// ```
+    pub fn from_hex_sha256(hex: &str) -> Result<Self> {
+        Self::from_hex("sha256", hex, 64)
+    }
+
+    /// Create SHA-512 digest from 128 lowercase hex characters without `sha512:` prefix
+    pub fn from_hex_sha512(hex: &str) -> Result<Self> {
+        Self::from_hex("sha512", hex, 128)
+    }
+
+    fn from_hex(algorithm: &str, hex: &str, len: usize) -> Result<Self> {
+        if hex.len() == len && hex.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f')) {
+            Ok(Digest {
+                algorithm: algorithm.to_string(),
+                encoded: hex.to_string(),
+            })
+        } else {
+            Err(Error::InvalidDigest(format!("{}:{}", algorithm, hex)))
+        }
+    }
+
     /// As a path used in oci-archive
     pub fn as_path(&self) -> PathBuf {
         PathBuf::from(format!("blobs/{}/{}", self.algorithm, self.encoded))
@@ -64,6 +99,118 @@ impl Digest {
             encoded: digest,
         }
     }
+
+    /// Calc digest using SHA-256 algorithm while reading to the end
+    ///
+    /// ```
+    /// use ocipkg::Digest;
+    ///
+    /// let digest = Digest::from_reader_sha256(&b"test"[..]).unwrap();
+    /// assert_eq!(digest, Digest::from_buf_sha256(b"test"));
+    /// ```
+    pub fn from_reader_sha256(reader: impl io::Read) -> Result<Self> {
+        Self::from_reader::<Sha256>("sha256", reader)
+    }
+
+    /// Calc digest of the file using SHA-256 algorithm without loading it into memory
+    pub fn from_file(path: &Path) -> Result<Self> {
+        Self::from_reader_sha256(io::BufReader::new(fs::File::open(path)?))
+    }
+
+    /// Calc digest using SHA-512 algorithm while reading to the end
+    pub fn from_reader_sha512(reader: impl io::Read) -> Result<Self> {
+        Self::from_reader::<Sha512>("sha512", reader)
+    }
+
+    fn from_reader<D: sha2::Digest + io::Write>(
+        algorithm: &str,
+        mut reader: impl io::Read,
+    ) -> Result<Self> {
+        let mut hasher = D::new();
+        io::copy(&mut reader, &mut hasher)?;
+        Ok(Self {
+            algorithm: algorithm.to_string(),
+            encoded: base16ct::lower::encode_string(&hasher.finalize()),
+        })
+    }
+
+    /// Check the content read from reader has this digest
+    ///
+    /// Returns [Error::DigestMismatch] if not, and [Error::InvalidDigest]
+    /// if the algorithm is neither `sha256` nor `sha512`.
+    pub fn verify_reader(&self, reader: impl io::Read) -> Result<()> {
+        let actual = match self.algorithm.as_str() {
+            "sha256" => Self::from_reader_sha256(reader)?,
+            "sha512" => Self::from_reader_sha512(reader)?,
+            _ => return Err(Error::InvalidDigest(self.to_string())),
+        };
+        if &actual != self {
+            return Err(Error::DigestMismatch {
+                expected: self.clone(),
+                actual,
+            });
+        }
+        Ok(())
+    }
+}
+
+/// Reader failing with [Error::DigestMismatch] at the end if the content does not have the digest
+#[cfg_attr(not(feature = "network"), allow(dead_code))]
+pub(crate) struct VerifyingReader<R: io::Read> {
+    inner: R,
+    expected: Digest,
+    hasher: Hasher,
+}
+
+#[cfg_attr(not(feature = "network"), allow(dead_code))]
+enum Hasher {
+    Sha256(Sha256),
+    Sha512(Sha512),
+}
+
+#[cfg_attr(not(feature = "network"), allow(dead_code))]
+impl<R: io::Read> VerifyingReader<R> {
+    /// Returns [Error::InvalidDigest] if the algorithm is neither `sha256` nor `sha512`
+    pub(crate) fn new(inner: R, expected: &Digest) -> Result<Self> {
+        let hasher = match expected.algorithm.as_str() {
+            "sha256" => Hasher::Sha256(Sha256::new()),
+            "sha512" => Hasher::Sha512(Sha512::new()),
+            _ => return Err(Error::InvalidDigest(expected.to_string())),
+        };
+        Ok(Self {
+            inner,
+            expected: expected.clone(),
+            hasher,
+        })
+    }
+
+    fn actual(&self) -> Digest {
+        let (algorithm, hash) = match &self.hasher {
+            Hasher::Sha256(hasher) => ("sha256", hasher.clone().finalize().to_vec()),
+            Hasher::Sha512(hasher) => ("sha512", hasher.clone().finalize().to_vec()),
+        };
+        Digest {
+            algorithm: algorithm.to_string(),
+            encoded: base16ct::lower::encode_string(&hash),
+        }
+    }
+}
+
+impl<R: io::Read> io::Read for VerifyingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        match &mut self.hasher {
+            Hasher::Sha256(hasher) => hasher.update(&buf[..n]),
+            Hasher::Sha512(hasher) => hasher.update(&buf[..n]),
+        }
+        if n == 0 && !buf.is_empty() {
+            let actual = self.actual();
+            if actual != self.expected {
+                return Err(digest_mismatch_io(self.expected.clone(), actual));
+            }
+        }
+        Ok(n)
+    }
 }
 
 /// Wrapper for calculating hash
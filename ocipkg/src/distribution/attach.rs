@@ -0,0 +1,209 @@
+use crate::{distribution::Client, error::*, Digest, Reference};
+use oci_spec::image::{
+    Descriptor, ImageIndexBuilder, ImageManifestBuilder, MediaType, SCHEMA_VERSION,
+};
+use std::{collections::HashMap, path::Path};
+
+/// Artifact type of SPDX SBOM in JSON
+pub const SPDX_ARTIFACT_TYPE: &str = "application/spdx+json";
+
+/// Artifact type of CycloneDX SBOM in JSON
+pub const CYCLONEDX_ARTIFACT_TYPE: &str = "application/vnd.cyclonedx+json";
+
+/// Artifact type of SBOM file, [SPDX_ARTIFACT_TYPE] or [CYCLONEDX_ARTIFACT_TYPE]
+///
+/// Detected by the file name, e.g. `*.spdx.json` or `*.cdx.json`,
+/// and then by the `spdxVersion` or `bomFormat` field of the document.
+pub fn sbom_artifact_type(path: &Path, blob: &[u8]) -> Result<&'static str> {
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    if name.ends_with(".spdx.json") {
+        return Ok(SPDX_ARTIFACT_TYPE);
+    }
+    if name.ends_with(".cdx.json") {
+        return Ok(CYCLONEDX_ARTIFACT_TYPE);
+    }
+    let doc: serde_json::Value =
+        serde_json::from_slice(blob).map_err(|_| Error::UnknownSbomFormat(path.to_owned()))?;
+    if doc.get("spdxVersion").is_some() {
+        return Ok(SPDX_ARTIFACT_TYPE);
+    }
+    if doc.get("bomFormat").and_then(|f| f.as_str()) == Some("CycloneDX") {
+        return Ok(CYCLONEDX_ARTIFACT_TYPE);
+    }
+    Err(Error::UnknownSbomFormat(path.to_owned()))
+}
+
+/// Attach an artifact, e.g. SBOM, to the manifest in the repository of client as its referrer
+///
+/// The artifact manifest has the `subject` of the manifest, the empty config,
+/// and a layer holding `blob` with `artifact_type` as media type.
+/// If the registry does not support the referrers API, the artifact is also added
+/// to the image index of the referrers tag schema `sha256-<hex>`, as `oras attach` does.
+/// Returns the digest of the artifact manifest.
+pub fn attach_artifact(
+    client: &mut Client,
+    subject_digest: &Digest,
+    artifact_type: &str,
+    blob: &[u8],
+    annotations: HashMap<String, String>,
+) -> Result<Digest> {
+    let (media_type, buf) =
+        client.get_raw_manifest(&Reference::new(&subject_digest.to_string())?)?;
+    let subject = Descriptor::new(media_type, buf.len() as i64, subject_digest.to_string());
+
+    let blob_digest = Digest::from_buf_sha256(blob);
+    if client.head_blob(&blob_digest)?.is_none() {
+        client.push_blob(blob)?;
+    }
+    let empty = b"{}";
+    let empty_digest = Digest::from_buf_sha256(empty);
+    if client.head_blob(&empty_digest)?.is_none() {
+        client.push_blob(empty)?;
+    }
+    let artifact_type = MediaType::Other(artifact_type.to_string());
+    let mut manifest = ImageManifestBuilder::default()
+        .schema_version(SCHEMA_VERSION)
+        .media_type(MediaType::ImageManifest)
+        .artifact_type(artifact_type.clone())
+        .config(Descriptor::new(
+            MediaType::EmptyJSON,
+            empty.len() as i64,
+            empty_digest.to_string(),
+        ))
+        .layers(vec![Descriptor::new(
+            artifact_type.clone(),
+            blob.len() as i64,
+            blob_digest.to_string(),
+        )])
+        .build()?;
+    manifest.set_subject(Some(subject.clone()));
+    if !annotations.is_empty() {
+        manifest.set_annotations(Some(annotations.clone()));
+    }
+    let mut buf = Vec::new();
+    manifest.to_writer(&mut buf)?;
+    let digest = Digest::from_buf_sha256(&buf);
+    client.push_manifest_with_subject(
+        &Reference::new(&digest.to_string())?,
+        &manifest,
+        &subject,
+    )?;
+
+    match client.get_referrers(subject_digest, None) {
+        Ok(Some(_)) => return Ok(digest),
+        Ok(None) => {}
+        Err(e) => log::debug!("Referrers API is not available: {}", e),
+    }
+    // Referrers tag schema, where existing referrers are kept
+    log::info!("Update referrers tag for {}", subject_digest);
+    let mut desc = Descriptor::new(
+        MediaType::ImageManifest,
+        buf.len() as i64,
+        digest.to_string(),
+    );
+    desc.set_artifact_type(Some(artifact_type));
+    if !annotations.is_empty() {
+        desc.set_annotations(Some(annotations));
+    }
+    let tag = Reference::new(&format!(
+        "{}-{}",
+        subject_digest.algorithm, subject_digest.encoded
+    ))?;
+    let mut manifests = match client.head_manifest(&tag)? {
+        Some(_) => client.get_index(&tag)?.manifests().clone(),
+        None => Vec::new(),
+    };
+    if !manifests.iter().any(|m| m.digest() == desc.digest()) {
+        manifests.push(desc);
+    }
+    let index = ImageIndexBuilder::default()
+        .schema_version(SCHEMA_VERSION)
+        .media_type(MediaType::ImageIndex)
+        .manifests(manifests)
+        .build()?;
+    client.push_index(&tag, &index)?;
+    Ok(digest)
+}
+
+/// Artifacts attached to the manifest in the repository of client, e.g. SBOMs or signatures
+///
+/// Both the referrers API and the referrers tag schema `sha256-<hex>` are queried,
+/// and an artifact found by both is listed once.
+/// Only artifacts of `artifact_type` are listed if it is given.
+pub fn get_attached_artifacts(
+    client: &mut Client,
+    digest: &Digest,
+    artifact_type: Option<&str>,
+) -> Result<Vec<Descriptor>> {
+    let mut artifacts = match client.get_referrers(digest, artifact_type) {
+        Ok(Some(index)) => index.manifests().clone(),
+        Ok(None) => Vec::new(),
+        Err(e) => {
+            log::debug!("Referrers API is not available: {}", e);
+            Vec::new()
+        }
+    };
+    let tag = Reference::new(&format!("{}-{}", digest.algorithm, digest.encoded))?;
+    if client.head_manifest(&tag)?.is_some() {
+        for desc in client.get_index(&tag)?.manifests() {
+            if !artifacts.iter().any(|a| a.digest() == desc.digest()) {
+                artifacts.push(desc.clone());
+            }
+        }
+    }
+    if let Some(artifact_type) = artifact_type {
+        artifacts.retain(|desc| {
+            desc.artifact_type()
+                .as_ref()
+                .map(|ty| ty.to_string())
+                .as_deref()
+                == Some(artifact_type)
+        });
+    }
+    Ok(artifacts)
+}
+
+/// Content of the attached artifact listed by [get_attached_artifacts], i.e. the blob of its first layer
+pub fn get_attached_artifact_blob(client: &mut Client, artifact: &Descriptor) -> Result<Vec<u8>> {
+    let manifest = client.get_manifest(&Reference::new(artifact.digest())?)?;
+    let layer = manifest.layers().first().ok_or(Error::MissingLayer)?;
+    client.get_blob(&Digest::new(layer.digest())?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detect_sbom_artifact_type() -> Result<()> {
+        assert_eq!(
+            sbom_artifact_type(Path::new("target/app.spdx.json"), b"")?,
+            SPDX_ARTIFACT_TYPE
+        );
+        assert_eq!(
+            sbom_artifact_type(Path::new("app.CDX.json"), b"")?,
+            CYCLONEDX_ARTIFACT_TYPE
+        );
+        assert_eq!(
+            sbom_artifact_type(Path::new("sbom.json"), br#"{"spdxVersion": "SPDX-2.3"}"#)?,
+            SPDX_ARTIFACT_TYPE
+        );
+        assert_eq!(
+            sbom_artifact_type(
+                Path::new("bom.json"),
+                br#"{"bomFormat": "CycloneDX", "specVersion": "1.5"}"#
+            )?,
+            CYCLONEDX_ARTIFACT_TYPE
+        );
+        for blob in [&b"{}"[..], b"not json"] {
+            assert!(matches!(
+                sbom_artifact_type(Path::new("sbom.json"), blob),
+                Err(Error::UnknownSbomFormat(_))
+            ));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,208 @@
+use crate::{
+    distribution::{attach_artifact, get_attached_artifact_blob, get_attached_artifacts, Client},
+    error::*,
+    DescriptorExt, Digest,
+};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use oci_spec::image::Descriptor;
+use std::collections::HashMap;
+
+/// Artifact type of in-toto attestations in DSSE envelopes, e.g. SLSA provenance
+pub const IN_TOTO_ARTIFACT_TYPE: &str = "application/vnd.in-toto+json";
+
+/// Annotation key of the attestation manifest recording the predicate type of the statement
+pub const PREDICATE_TYPE_ANNOTATION: &str = "in-toto.io/predicate-type";
+
+/// `payloadType` of in-toto statements in DSSE envelopes
+const IN_TOTO_PAYLOAD_TYPE: &str = "application/vnd.in-toto+json";
+
+/// [DSSE envelope](https://github.com/secure-systems-lab/dsse/blob/master/envelope.md)
+///
+/// Signatures are not verified here, but exposed with the payload for external verification.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DsseEnvelope {
+    pub payload_type: String,
+    /// Decoded payload, e.g. in-toto statement in JSON
+    pub payload: Vec<u8>,
+    pub signatures: Vec<DsseSignature>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DsseSignature {
+    pub keyid: Option<String>,
+    /// Decoded signature
+    pub sig: Vec<u8>,
+}
+
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RawEnvelope {
+    payload_type: String,
+    payload: String,
+    signatures: Vec<RawSignature>,
+}
+
+#[derive(serde::Deserialize)]
+struct RawSignature {
+    #[serde(default)]
+    keyid: Option<String>,
+    sig: String,
+}
+
+impl DsseEnvelope {
+    pub fn from_slice(buf: &[u8]) -> Result<Self> {
+        let raw: RawEnvelope =
+            serde_json::from_slice(buf).map_err(|e| Error::InvalidDsseEnvelope(e.to_string()))?;
+        let decode = |s: &str| {
+            STANDARD
+                .decode(s)
+                .map_err(|_| Error::InvalidDsseEnvelope("not a base64-encoded field".to_string()))
+        };
+        let signatures = raw
+            .signatures
+            .iter()
+            .map(|s| {
+                Ok(DsseSignature {
+                    keyid: s.keyid.clone().filter(|id| !id.is_empty()),
+                    sig: decode(&s.sig)?,
+                })
+            })
+            .collect::<Result<_>>()?;
+        Ok(DsseEnvelope {
+            payload_type: raw.payload_type,
+            payload: decode(&raw.payload)?,
+            signatures,
+        })
+    }
+
+    /// `predicateType` of the in-toto statement in the payload
+    pub fn predicate_type(&self) -> Result<String> {
+        if self.payload_type != IN_TOTO_PAYLOAD_TYPE {
+            return Err(Error::InvalidDsseEnvelope(format!(
+                "payload is not an in-toto statement: {}",
+                self.payload_type
+            )));
+        }
+        let statement: serde_json::Value = serde_json::from_slice(&self.payload)
+            .map_err(|e| Error::InvalidDsseEnvelope(e.to_string()))?;
+        statement
+            .get("predicateType")
+            .and_then(|ty| ty.as_str())
+            .map(str::to_string)
+            .ok_or_else(|| Error::InvalidDsseEnvelope("predicateType is missing".to_string()))
+    }
+}
+
+/// Attach an in-toto attestation in DSSE envelope to the manifest as a referrer, as [attach_artifact]
+///
+/// `predicate_type`, e.g. `https://slsa.dev/provenance/v1`, is recorded in [PREDICATE_TYPE_ANNOTATION].
+/// Returns the digest of the attestation manifest.
+pub fn attach_attestation(
+    client: &mut Client,
+    subject_digest: &Digest,
+    dsse: &[u8],
+    predicate_type: &str,
+) -> Result<Digest> {
+    let envelope = DsseEnvelope::from_slice(dsse)?;
+    let actual = envelope.predicate_type()?;
+    if actual != predicate_type {
+        return Err(Error::InvalidDsseEnvelope(format!(
+            "predicate type of statement is {}, but {} is given",
+            actual, predicate_type
+        )));
+    }
+    attach_artifact(
+        client,
+        subject_digest,
+        IN_TOTO_ARTIFACT_TYPE,
+        dsse,
+        HashMap::from([(
+            PREDICATE_TYPE_ANNOTATION.to_string(),
+            predicate_type.to_string(),
+        )]),
+    )
+}
+
+/// Attestations attached to the manifest, filtered by `predicate_type` if given
+///
+/// Returns the descriptors of the attestation manifests with their envelopes.
+pub fn get_attestations(
+    client: &mut Client,
+    digest: &Digest,
+    predicate_type: Option<&str>,
+) -> Result<Vec<(Descriptor, DsseEnvelope)>> {
+    let mut attestations = Vec::new();
+    for desc in get_attached_artifacts(client, digest, Some(IN_TOTO_ARTIFACT_TYPE))? {
+        if predicate_type.is_some() && desc.annotation(PREDICATE_TYPE_ANNOTATION) != predicate_type
+        {
+            continue;
+        }
+        let envelope = DsseEnvelope::from_slice(&get_attached_artifact_blob(client, &desc)?)?;
+        attestations.push((desc, envelope));
+    }
+    Ok(attestations)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{distribution::TestRegistry, Reference};
+
+    const SLSA_PROVENANCE: &str = "https://slsa.dev/provenance/v1";
+
+    fn envelope(predicate_type: &str) -> Vec<u8> {
+        let statement = serde_json::json!({
+            "_type": "https://in-toto.io/Statement/v1",
+            "subject": [{ "name": "test_repo", "digest": { "sha256": "00" } }],
+            "predicateType": predicate_type,
+            "predicate": { "buildDefinition": {}, "runDetails": {} }
+        });
+        serde_json::to_vec(&serde_json::json!({
+            "payloadType": IN_TOTO_PAYLOAD_TYPE,
+            "payload": STANDARD.encode(statement.to_string()),
+            "signatures": [{ "keyid": "", "sig": STANDARD.encode(b"signature") }]
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn parse_dsse_envelope() -> Result<()> {
+        let envelope = DsseEnvelope::from_slice(&envelope(SLSA_PROVENANCE))?;
+        assert_eq!(envelope.payload_type, IN_TOTO_PAYLOAD_TYPE);
+        assert_eq!(envelope.predicate_type()?, SLSA_PROVENANCE);
+        assert_eq!(
+            envelope.signatures,
+            [DsseSignature {
+                keyid: None,
+                sig: b"signature".to_vec()
+            }]
+        );
+        assert!(matches!(
+            DsseEnvelope::from_slice(br#"{"payloadType": "a", "payload": "!", "signatures": []}"#),
+            Err(Error::InvalidDsseEnvelope(_))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn attestation_round_trip() -> Result<()> {
+        let registry = TestRegistry::with_fixture()?;
+        let mut client = registry.client("test_repo")?;
+        let digest = client.get_manifest_digest(&Reference::new("tag1")?)?;
+        let dsse = envelope(SLSA_PROVENANCE);
+        let attestation = attach_attestation(&mut client, &digest, &dsse, SLSA_PROVENANCE)?;
+
+        let found = get_attestations(&mut client, &digest, Some(SLSA_PROVENANCE))?;
+        let (_, envelope) = found
+            .iter()
+            .find(|(desc, _)| desc.digest() == &attestation.to_string())
+            .expect("Attestation not found");
+        assert_eq!(envelope, &DsseEnvelope::from_slice(&dsse)?);
+        assert!(
+            get_attestations(&mut client, &digest, Some("https://spdx.dev/Document"))?
+                .iter()
+                .all(|(desc, _)| desc.digest() != &attestation.to_string())
+        );
+        Ok(())
+    }
+}
@@ -1,5 +1,5 @@
 use crate::error::*;
-use oci_spec::distribution::ErrorResponse;
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use std::{collections::HashMap, fs, io, path::*};
 use url::Url;
@@ -42,16 +42,51 @@ impl StoredAuth {
     }
 
     pub fn insert(&mut self, domain: &str, octet: String) {
-        self.auths.insert(domain.to_string(), Auth { auth: octet });
+        self.auths.insert(
+            domain.to_string(),
+            Auth {
+                auth: octet,
+                registrytoken: None,
+            },
+        );
     }
 
+    /// Insert a token used directly as the bearer token for registries without username
+    pub fn insert_token(&mut self, domain: &str, token: String) {
+        self.auths.insert(
+            domain.to_string(),
+            Auth {
+                auth: String::new(),
+                registrytoken: Some(token),
+            },
+        );
+    }
+
+    /// Remove authentication info for the domain, and returns if it exists
+    pub fn remove(&mut self, domain: &str) -> bool {
+        self.auths.remove(domain).is_some()
+    }
+
+    /// Token inserted by [StoredAuth::insert_token] for the domain
+    pub fn registry_token(&self, domain: &str) -> Option<&str> {
+        self.auths.get(domain)?.registrytoken.as_deref()
+    }
+
+    /// Token for the registry at `url`, looked up by [registry_keys]
+    pub(crate) fn registry_token_for(&self, url: &Url) -> Option<&str> {
+        self.find(url)?.registrytoken.as_deref()
+    }
+
+    fn find(&self, url: &Url) -> Option<&Auth> {
+        registry_keys(url)
+            .iter()
+            .find_map(|key| self.auths.get(key))
+    }
+
+    /// Save into ocipkg setting. The file is created with mode `0o600` on Unix.
     pub fn save(&self) -> Result<()> {
         let path = auth_path().ok_or(Error::NoValidRuntimeDirectory)?;
-        let parent = path.parent().unwrap();
-        if !parent.exists() {
-            fs::create_dir_all(parent)?;
-        }
-        let f = fs::File::create(&path)?;
+        let f = create_private_file(&path)?;
         serde_json::to_writer_pretty(f, self)?;
         Ok(())
     }
@@ -60,18 +95,28 @@ impl StoredAuth {
     ///
     /// Returns `None` if no authentication is required.
     pub fn get_token(&self, url: &url::Url) -> Result<Option<String>> {
-        let test_url = url.join("/v2/").unwrap();
-        let www_auth = match ureq::get(test_url.as_str()).call() {
+        let test_url = super::client::base_url(url.clone()).join("v2/").unwrap();
+        if let Some(token) = self.registry_token_for(url) {
+            return match super::http_log::agent()
+                .get(test_url.as_str())
+                .set("Authorization", &format!("Bearer {}", token))
+                .call()
+            {
+                Ok(_) => Ok(Some(token.to_string())),
+                Err(ureq::Error::Status(..)) => Err(Error::AuthorizationFailed(test_url)),
+                Err(ureq::Error::Transport(e)) => Err(e.into()),
+            };
+        }
+        let www_auth = match super::http_log::agent().get(test_url.as_str()).call() {
             Ok(_) => return Ok(None),
             Err(ureq::Error::Status(status, res)) => {
                 if status == 401 {
                     res.header("www-authenticate").unwrap().to_string()
                 } else {
-                    let err = res.into_json::<ErrorResponse>()?;
-                    return Err(Error::RegistryError(err));
+                    return Err(Error::from_status(status, res));
                 }
             }
-            Err(ureq::Error::Transport(e)) => return Err(Error::NetworkError(e.into())),
+            Err(ureq::Error::Transport(e)) => return Err(e.into()),
         };
 
         let challenge = AuthChallenge::from_header(&www_auth)?;
@@ -80,25 +125,45 @@ impl StoredAuth {
 
     /// Get token based on WWW-Authentication header
     pub fn challenge(&self, challenge: &AuthChallenge) -> Result<String> {
-        let token_url = Url::parse(&challenge.url)?;
-        let domain = token_url
-            .domain()
-            .expect("www-authenticate header returns invalid URL");
+        Ok(self
+            .request_token(&super::http_log::agent(), challenge)?
+            .token)
+    }
+
+    /// Get token as [StoredAuth::challenge] by the agent of client, which may be sent to a [super::HttpTransport],
+    /// with its scope and expiry to be cached by [RegistryAuthConfig::save]
+    pub(crate) fn issue_token(
+        &self,
+        agent: &ureq::Agent,
+        challenge: &AuthChallenge,
+        registry: &str,
+    ) -> Result<RegistryAuthConfig> {
+        let token = self.request_token(agent, challenge)?;
+        Ok(RegistryAuthConfig {
+            registry: registry.to_string(),
+            scope: challenge.scope.clone(),
+            expires_at: token.issued_at.unwrap_or_else(Utc::now)
+                + chrono::Duration::seconds(token.expires_in.unwrap_or(DEFAULT_EXPIRES_IN)),
+            token: token.token,
+            refresh_token: token.refresh_token,
+        })
+    }
 
-        let mut req = ureq::get(token_url.as_str()).set("Accept", "application/json");
-        if let Some(auth) = self.auths.get(domain) {
+    fn request_token(&self, agent: &ureq::Agent, challenge: &AuthChallenge) -> Result<Token> {
+        let token_url = Url::parse(&challenge.url)?;
+        let mut req = agent
+            .get(token_url.as_str())
+            .set("Accept", "application/json");
+        if let Some(auth) = self.find(&token_url) {
             req = req.set("Authorization", &format!("Basic {}", auth.auth))
         }
         req = req
             .query("scope", &challenge.scope)
             .query("service", &challenge.service);
         match req.call() {
-            Ok(res) => {
-                let token = res.into_json::<Token>()?;
-                Ok(token.token)
-            }
+            Ok(res) => Ok(res.into_json::<Token>()?),
             Err(ureq::Error::Status(..)) => Err(Error::AuthorizationFailed(token_url.clone())),
-            Err(ureq::Error::Transport(e)) => Err(Error::NetworkError(e.into())),
+            Err(ureq::Error::Transport(e)) => Err(e.into()),
         }
     }
 
@@ -119,9 +184,61 @@ impl StoredAuth {
     }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Clone, Serialize, Deserialize)]
 struct Auth {
+    #[serde(default, skip_serializing_if = "String::is_empty")]
     auth: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    registrytoken: Option<String>,
+}
+
+/// Credentials are redacted
+impl std::fmt::Debug for Auth {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Auth")
+            .field("auth", &REDACTED)
+            .field(
+                "registrytoken",
+                &self.registrytoken.as_ref().map(|_| REDACTED),
+            )
+            .finish()
+    }
+}
+
+/// Placeholder of secrets in [std::fmt::Debug] output
+const REDACTED: &str = "<redacted>";
+
+/// Create or truncate a file only readable by the owner on Unix
+fn create_private_file(path: &Path) -> Result<fs::File> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+        options.mode(0o600);
+        // `mode` is only used for a new file
+        if path.exists() {
+            fs::set_permissions(path, fs::Permissions::from_mode(0o600))?;
+        }
+    }
+    Ok(options.open(path)?)
+}
+
+/// Keys of the registry at `url` in authentication files, `hostname:port` first and then `hostname`
+///
+/// An IPv6 address is in brackets as docker does, e.g. `[fd00::5]:5000`.
+/// The port is omitted if it is the default one of the scheme.
+pub fn registry_keys(url: &Url) -> Vec<String> {
+    let Some(host) = url.host_str() else {
+        return Vec::new();
+    };
+    match url.port() {
+        Some(port) => vec![format!("{}:{}", host, port), host.to_string()],
+        None => vec![host.to_string()],
+    }
 }
 
 fn auth_path() -> Option<PathBuf> {
@@ -145,6 +262,80 @@ fn podman_auth_path() -> Option<PathBuf> {
     Some(dirs.runtime_dir()?.join("auth.json"))
 }
 
+/// Token for a registry cached in filesystem to be reused across program runs
+///
+/// Bearer tokens are issued for a scope, e.g. `repository:owner/name:pull`,
+/// and entries are keyed by the registry and the scope.
+/// Tokens are redacted in [std::fmt::Debug] output.
+#[derive(Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RegistryAuthConfig {
+    /// `hostname[:port]` of the registry
+    pub registry: String,
+    /// Scope of the token in the `WWW-Authenticate` challenge,
+    /// empty for entries saved without scope
+    #[serde(default)]
+    pub scope: String,
+    pub token: String,
+    pub expires_at: DateTime<Utc>,
+    pub refresh_token: Option<String>,
+}
+
+impl std::fmt::Debug for RegistryAuthConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RegistryAuthConfig")
+            .field("registry", &self.registry)
+            .field("scope", &self.scope)
+            .field("token", &REDACTED)
+            .field("expires_at", &self.expires_at)
+            .field(
+                "refresh_token",
+                &self.refresh_token.as_ref().map(|_| REDACTED),
+            )
+            .finish()
+    }
+}
+
+impl RegistryAuthConfig {
+    pub fn is_expired(&self) -> bool {
+        self.expires_at <= Utc::now()
+    }
+
+    /// If the scope grants access to the repository `name`
+    pub fn is_for_repository(&self, name: &str) -> bool {
+        self.scope.split(' ').any(|scope| {
+            scope
+                .strip_prefix("repository:")
+                .and_then(|scope| scope.rsplit_once(':'))
+                .is_some_and(|(repository, _actions)| repository == name)
+        })
+    }
+
+    /// Save into the file, replacing the existing entry for the same registry and scope
+    ///
+    /// Expired entries in the file are dropped. The file is created with mode `0o600` on Unix.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let mut configs: Vec<_> = Self::load(path)?
+            .into_iter()
+            .filter(|c| (c.registry != self.registry || c.scope != self.scope) && !c.is_expired())
+            .collect();
+        configs.push(self.clone());
+        let f = create_private_file(path)?;
+        serde_json::to_writer_pretty(f, &configs)?;
+        Ok(())
+    }
+
+    /// Load all entries in the file, including expired ones
+    ///
+    /// Returns an empty list if the file does not exist.
+    pub fn load(path: &Path) -> Result<Vec<Self>> {
+        if !path.is_file() {
+            return Ok(Vec::new());
+        }
+        let f = fs::File::open(path)?;
+        Ok(serde_json::from_reader(io::BufReader::new(f))?)
+    }
+}
+
 /// WWW-Authentication challenge
 ///
 /// ```
@@ -196,7 +387,135 @@ impl AuthChallenge {
     }
 }
 
+/// Lifetime of tokens without `expires_in`, defined in the token authentication specification
+const DEFAULT_EXPIRES_IN: i64 = 60;
+
+/// Response of the token server
 #[derive(Deserialize)]
 struct Token {
+    #[serde(alias = "access_token")]
     token: String,
+    #[serde(default)]
+    expires_in: Option<i64>,
+    #[serde(default)]
+    issued_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    refresh_token: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn registry_auth_config() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("tokens.json");
+        assert!(RegistryAuthConfig::load(&path)?.is_empty());
+
+        let config = RegistryAuthConfig {
+            registry: "ghcr.io".to_string(),
+            scope: "repository:owner/a:pull".to_string(),
+            token: "token1".to_string(),
+            expires_at: Utc::now() + chrono::Duration::hours(1),
+            refresh_token: None,
+        };
+        config.save(&path)?;
+        let other = RegistryAuthConfig {
+            scope: "repository:owner/b:pull".to_string(),
+            token: "token3".to_string(),
+            ..config.clone()
+        };
+        other.save(&path)?;
+        let updated = RegistryAuthConfig {
+            token: "token2".to_string(),
+            ..config
+        };
+        updated.save(&path)?;
+        assert_eq!(RegistryAuthConfig::load(&path)?, vec![other, updated]);
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(fs::metadata(&path)?.permissions().mode() & 0o777, 0o600);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn registry_auth_config_scope() -> Result<()> {
+        let config: RegistryAuthConfig = serde_json::from_str(
+            r#"{"registry":"ghcr.io","token":"t","expires_at":"2026-01-01T00:00:00Z","refresh_token":null}"#,
+        )?;
+        assert!(!config.is_for_repository("owner/a"));
+
+        let config = RegistryAuthConfig {
+            scope: "repository:owner/a:pull,push repository:owner/b/c:pull".to_string(),
+            ..config
+        };
+        assert!(config.is_for_repository("owner/a"));
+        assert!(config.is_for_repository("owner/b/c"));
+        assert!(!config.is_for_repository("owner/b"));
+        Ok(())
+    }
+
+    #[test]
+    fn stored_auth_token() -> Result<()> {
+        let mut auth: StoredAuth =
+            serde_json::from_str(r#"{"auths":{"ghcr.io":{"auth":"dXNlcjpwYXNz"}}}"#)?;
+        assert_eq!(auth.registry_token("ghcr.io"), None);
+        auth.insert_token("ghcr.io", "token".to_string());
+        assert_eq!(auth.registry_token("ghcr.io"), Some("token"));
+        assert_eq!(
+            serde_json::to_string(&auth)?,
+            r#"{"auths":{"ghcr.io":{"registrytoken":"token"}}}"#
+        );
+        assert!(auth.remove("ghcr.io"));
+        assert!(!auth.remove("ghcr.io"));
+        Ok(())
+    }
+
+    #[test]
+    fn registry_keys_with_port() {
+        let keys = |url: &str| registry_keys(&Url::parse(url).unwrap());
+        assert_eq!(keys("https://ghcr.io"), vec!["ghcr.io"]);
+        assert_eq!(
+            keys("http://localhost:5000/v2/"),
+            vec!["localhost:5000", "localhost"]
+        );
+        assert_eq!(
+            keys("http://[fd00::5]:5000"),
+            vec!["[fd00::5]:5000", "[fd00::5]"]
+        );
+        assert_eq!(keys("https://[fd00::5]"), vec!["[fd00::5]"]);
+
+        let mut auth = StoredAuth::default();
+        auth.insert_token("[fd00::5]:5000", "token1".to_string());
+        auth.insert_token("localhost", "token2".to_string());
+        let token = |url: &str| auth.registry_token_for(&Url::parse(url).unwrap());
+        assert_eq!(token("http://[fd00::5]:5000"), Some("token1"));
+        assert_eq!(token("http://[fd00::5]:5001"), None);
+        // Falls back to the hostname
+        assert_eq!(token("http://localhost:5000"), Some("token2"));
+    }
+
+    #[test]
+    fn debug_redacted() {
+        let mut auth = StoredAuth::default();
+        auth.insert("ghcr.io", "dXNlcjpwYXNz".to_string());
+        auth.insert_token("quay.io", "token1".to_string());
+        let debug = format!("{:?}", auth);
+        assert!(!debug.contains("dXNlcjpwYXNz") && !debug.contains("token1"));
+
+        let config = RegistryAuthConfig {
+            registry: "ghcr.io".to_string(),
+            scope: String::new(),
+            token: "token1".to_string(),
+            expires_at: Utc::now(),
+            refresh_token: Some("token2".to_string()),
+        };
+        let debug = format!("{:?}", config);
+        assert!(debug.contains("ghcr.io"));
+        assert!(!debug.contains("token1") && !debug.contains("token2"));
+    }
 }
@@ -0,0 +1,97 @@
+use crate::error::cancelled_io;
+use std::{
+    io::{self, Read},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+
+/// Token to cancel long-running operations, e.g. pull, push, and copy of images, from another thread
+///
+/// A clone shares the cancellation. Once [CancellationToken::cancel] is called,
+/// new requests of the [crate::distribution::Client] set by
+/// [crate::distribution::Client::set_cancellation_token] are not sent,
+/// and blobs in transfer are aborted at their next read or write.
+/// The operations return [crate::error::Error::Cancelled].
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+
+    pub(crate) fn check(&self) -> io::Result<()> {
+        if self.is_cancelled() {
+            Err(cancelled_io())
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Middleware failing requests without sending them once the token is cancelled
+pub(crate) struct Cancel(pub(crate) CancellationToken);
+
+impl ureq::Middleware for Cancel {
+    fn handle(
+        &self,
+        request: ureq::Request,
+        next: ureq::MiddlewareNext,
+    ) -> Result<ureq::Response, ureq::Error> {
+        self.0.check()?;
+        next.handle(request)
+    }
+}
+
+/// Reader failing once the token is cancelled, or as is if the token is not given
+pub(crate) struct CancellableReader<R: Read> {
+    inner: R,
+    cancel: Option<CancellationToken>,
+}
+
+impl<R: Read> CancellableReader<R> {
+    pub(crate) fn new(inner: R, cancel: Option<&CancellationToken>) -> Self {
+        Self {
+            inner,
+            cancel: cancel.cloned(),
+        }
+    }
+}
+
+impl<R: Read> Read for CancellableReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if let Some(cancel) = &self.cancel {
+            cancel.check()?;
+        }
+        self.inner.read(buf)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::Error;
+
+    #[test]
+    fn cancellable_reader() {
+        let token = CancellationToken::new();
+        let mut reader = CancellableReader::new(&b"abcd"[..], Some(&token));
+        let mut buf = [0u8; 2];
+        assert_eq!(reader.read(&mut buf).unwrap(), 2);
+
+        token.clone().cancel();
+        assert!(token.is_cancelled());
+        let err = reader.read(&mut buf).unwrap_err();
+        assert!(matches!(Error::from(err), Error::Cancelled));
+    }
+}
@@ -0,0 +1,108 @@
+use crate::error::*;
+use std::io::Read;
+
+/// Split a blob into chunks for chunked upload by `PATCH` requests
+///
+/// Each item is the offset of the chunk in the blob, used for `Content-Range` header,
+/// and its content. Every chunk has `chunk_size` bytes except the last one.
+///
+/// ```
+/// use ocipkg::distribution::BlobChunker;
+///
+/// let chunks = BlobChunker::new(&b"0123456789"[..], 4)
+///     .collect::<ocipkg::error::Result<Vec<_>>>()
+///     .unwrap();
+/// assert_eq!(
+///     chunks,
+///     vec![(0, b"0123".to_vec()), (4, b"4567".to_vec()), (8, b"89".to_vec())]
+/// );
+/// ```
+pub struct BlobChunker<R: Read> {
+    reader: R,
+    chunk_size: usize,
+    processed: u64,
+    finished: bool,
+}
+
+impl<R: Read> BlobChunker<R> {
+    /// Panics if `chunk_size` is zero
+    pub fn new(reader: R, chunk_size: usize) -> Self {
+        assert!(chunk_size > 0, "Chunk size must be positive");
+        Self {
+            reader,
+            chunk_size,
+            processed: 0,
+            finished: false,
+        }
+    }
+
+    /// Total bytes of chunks yielded so far
+    pub fn bytes_processed(&self) -> u64 {
+        self.processed
+    }
+
+    /// Fill a chunk, since a single read may return fewer bytes than requested
+    fn read_chunk(&mut self) -> Result<Vec<u8>> {
+        let mut chunk = Vec::with_capacity(self.chunk_size);
+        (&mut self.reader)
+            .take(self.chunk_size as u64)
+            .read_to_end(&mut chunk)?;
+        Ok(chunk)
+    }
+}
+
+impl<R: Read> Iterator for BlobChunker<R> {
+    type Item = Result<(u64, Vec<u8>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.finished {
+            return None;
+        }
+        let chunk = match self.read_chunk() {
+            Ok(chunk) => chunk,
+            Err(e) => {
+                self.finished = true;
+                return Some(Err(e));
+            }
+        };
+        if chunk.len() < self.chunk_size {
+            self.finished = true;
+        }
+        if chunk.is_empty() {
+            return None;
+        }
+        let offset = self.processed;
+        self.processed += chunk.len() as u64;
+        Some(Ok((offset, chunk)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Reader returning at most 3 bytes for each read
+    struct Slow<'a>(&'a [u8]);
+
+    impl Read for Slow<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            let n = buf.len().min(3).min(self.0.len());
+            buf[..n].copy_from_slice(&self.0[..n]);
+            self.0 = &self.0[n..];
+            Ok(n)
+        }
+    }
+
+    #[test]
+    fn chunks() -> Result<()> {
+        let mut chunker = BlobChunker::new(Slow(b"0123456789"), 5);
+        assert_eq!(chunker.next().unwrap()?, (0, b"01234".to_vec()));
+        assert_eq!(chunker.bytes_processed(), 5);
+        assert_eq!(chunker.next().unwrap()?, (5, b"56789".to_vec()));
+        assert!(chunker.next().is_none());
+        assert_eq!(chunker.bytes_processed(), 10);
+
+        assert!(BlobChunker::new(Slow(b""), 5).next().is_none());
+        Ok(())
+    }
+}
@@ -1,9 +1,134 @@
+use base64::Engine;
+use chrono::{DateTime, Utc};
 use oci_spec::{distribution::*, image::*};
+use std::{
+    collections::{BTreeSet, HashMap, HashSet},
+    fs,
+    io::Read,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
 use url::Url;
 
-use crate::{distribution::*, error::*};
+use crate::{
+    digest::VerifyingReader,
+    distribution::{cancel::CancellableReader, operation, progress::ProgressReader, *},
+    error::*,
+    image::{
+        decompress, find_manifest_for_platform, is_index_media_type, platform_string,
+        ImageConfigBuilder, LayerBuilder, PlatformEx,
+    },
+    DescriptorExt, ManifestExt, OciContentType,
+};
+
+/// Size and digest of a blob stored in registry
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlobStat {
+    pub digest: Digest,
+    pub size: u64,
+}
+
+/// Digest, size, and media type of a manifest stored in registry
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ManifestStat {
+    pub digest: Digest,
+    pub size: u64,
+    pub media_type: MediaType,
+}
+
+/// Parse `HEAD /v2/<name>/blobs/<digest>` response
+fn blob_stat(res: &ureq::Response, digest: &Digest) -> Result<BlobStat> {
+    let size = res
+        .header("Content-Length")
+        .and_then(|len| len.parse().ok())
+        .unwrap_or_default();
+    let digest = match res.header("Docker-Content-Digest") {
+        Some(digest) => Digest::new(digest)?,
+        None => digest.clone(),
+    };
+    Ok(BlobStat { digest, size })
+}
+
+/// Status of a blob upload session, see [Client::get_upload_progress]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct UploadProgress {
+    /// Number of bytes the registry has received
+    pub bytes_received: u64,
+    /// Inclusive byte range the registry has received, `None` if nothing has been received
+    pub range: Option<(u64, u64)>,
+}
+
+impl UploadProgress {
+    /// Parse `Range` header, e.g. `0-1023`
+    ///
+    /// `bytes=` prefix used by some registries is also accepted.
+    fn from_range_header(range: Option<&str>) -> Result<Self> {
+        let Some(range) = range else {
+            return Ok(Self::default());
+        };
+        let invalid = || Error::InvalidRangeHeader(range.to_string());
+        let (start, end) = range
+            .trim()
+            .trim_start_matches("bytes=")
+            .split_once('-')
+            .ok_or_else(invalid)?;
+        let start: u64 = start.parse().map_err(|_| invalid())?;
+        let end: u64 = end.parse().map_err(|_| invalid())?;
+        if end < start {
+            return Err(invalid());
+        }
+        Ok(Self {
+            bytes_received: end + 1,
+            range: Some((start, end)),
+        })
+    }
+}
+
+/// Parse `HEAD /v2/<name>/manifests/<reference>` response
+///
+/// `get` fetches the manifest to compute its digest when the registry does not return it.
+fn manifest_stat(
+    res: &ureq::Response,
+    reference: &Reference,
+    get: impl FnOnce() -> Result<Vec<u8>>,
+) -> Result<ManifestStat> {
+    let size = res
+        .header("Content-Length")
+        .and_then(|len| len.parse().ok())
+        .unwrap_or_default();
+    let digest = match res.header("Docker-Content-Digest") {
+        Some(digest) => Digest::new(digest)?,
+        None if reference.is_digest() => Digest::new(reference)?,
+        None => Digest::from_buf_sha256(&get()?),
+    };
+    Ok(ManifestStat {
+        digest,
+        size,
+        media_type: MediaType::from(res.content_type()),
+    })
+}
+
+/// Rejected as the operation is not supported by the registry,
+/// i.e. `400 Bad Request`, `405 Method Not Allowed`, or `UNSUPPORTED` error
+fn is_unsupported(e: &Error) -> bool {
+    match e {
+        Error::UnexpectedStatus(status) => matches!(status, 400 | 405),
+        Error::RegistryError { status, response } => {
+            matches!(status, 400 | 405)
+                || response
+                    .detail()
+                    .iter()
+                    .any(|info| info.code() == &ErrorCode::Unsupported)
+        }
+        _ => false,
+    }
+}
 
 /// A client for `/v2/<name>/` API endpoint
+///
+/// A clone shares the connection pool, and starts with the cached token.
+#[derive(Clone)]
 pub struct Client {
     agent: ureq::Agent,
     /// URL to registry server
@@ -14,54 +139,595 @@ pub struct Client {
     auth: StoredAuth,
     /// Cached token
     token: Option<String>,
+    /// Token issued by the token server in this run, see [Client::save_auth_config]
+    issued: Option<RegistryAuthConfig>,
+    /// Policy for tags to be pushed
+    tag_policy: TagPolicy,
+    /// Callback for transfer of blobs
+    progress: Option<Progress>,
+    /// Upper limit of concurrent requests sent by a single method call
+    max_concurrent_requests: usize,
+    /// Retry with a new agent on connection-level failures
+    auto_reconnect: bool,
+    /// Transport used instead of the network, see [Client::set_transport]
+    transport: Option<Arc<dyn HttpTransport>>,
+    /// See [Client::set_cancellation_token]
+    cancel: Option<CancellationToken>,
+    /// See [Client::set_max_blob_size]
+    max_blob_size: Option<u64>,
+    /// See [Client::set_max_manifest_size]
+    max_manifest_size: Option<u64>,
+}
+
+/// Default of [Client::set_max_concurrent_requests]
+pub const DEFAULT_MAX_CONCURRENT_REQUESTS: usize = 8;
+
+/// Default of [Client::set_max_manifest_size], 4 MiB
+pub const DEFAULT_MAX_MANIFEST_SIZE: u64 = 4 * 1024 * 1024;
+
+/// Response of `GET /v2/<name>/tags/list`
+#[derive(serde::Deserialize)]
+struct TagListPage {
+    #[serde(default)]
+    tags: Vec<String>,
+    /// Non-standard field of Google Container Registry and Artifact Registry,
+    /// which maps the digest of each manifest to its tags
+    #[serde(default)]
+    manifest: HashMap<String, TaggedManifest>,
+}
+
+#[derive(serde::Deserialize)]
+struct TaggedManifest {
+    #[serde(default)]
+    tag: Vec<String>,
+}
+
+impl TagListPage {
+    /// Digests of tags found in the non-standard `manifest` field
+    fn digests(&self) -> HashMap<String, Digest> {
+        let mut digests = HashMap::new();
+        for (digest, manifest) in &self.manifest {
+            let Ok(digest) = Digest::new(digest) else {
+                continue;
+            };
+            for tag in &manifest.tag {
+                digests.insert(tag.clone(), digest.clone());
+            }
+        }
+        digests
+    }
+}
+
+/// Whether the content sent to registry is stored, distinguished by the status code
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LocationResult {
+    /// `true` for `201 Created`. Otherwise, e.g. `202 Accepted`, the registry has only accepted the data,
+    /// and the upload is in progress or the content is being stored asynchronously.
+    pub fully_stored: bool,
+}
+
+/// Result of [Client::bulk_delete_tags]
+#[derive(Debug, Default)]
+pub struct BulkDeleteReport {
+    /// Tags removed by deleting their manifests
+    pub deleted: Vec<String>,
+    /// Unique manifests deleted. Tags pointing to the same manifest share an entry.
+    pub manifests: Vec<Digest>,
+    /// Tags not deleted with the reason. Tags pointing to the same manifest share the error of its deletion.
+    pub failed: Vec<(Vec<String>, Error)>,
+}
+
+/// Result of [Client::health_check]
+///
+/// Steps after a failed one are not run, and their flags remain `false`.
+#[derive(Debug, Default)]
+pub struct HealthCheckResult {
+    /// `GET /v2/` succeeded
+    pub ping_ok: bool,
+    /// The test blob and the manifest referring it are pushed
+    pub push_ok: bool,
+    /// The test blob is pulled back with the same content
+    pub pull_ok: bool,
+    /// Round trip time of `GET /v2/`
+    pub latency: Duration,
+    /// Error of the failed step
+    pub error: Option<Error>,
+}
+
+impl HealthCheckResult {
+    /// `true` if all steps succeeded
+    pub fn ok(&self) -> bool {
+        self.ping_ok && self.push_ok && self.pull_ok
+    }
+}
+
+/// Content of the blob pushed and pulled by [Client::health_check]
+const HEALTH_CHECK_BLOB: &[u8] = b"ocipkg-health-check";
+
+/// Tag of the manifest pushed by [Client::health_check]
+const HEALTH_CHECK_TAG: &str = "ocipkg-health-check";
+
+/// Iterator of tags created by [Client::tags_iter]
+pub struct TagIterator<'a> {
+    client: &'a mut Client,
+    page_size: usize,
+    filter: Option<TagFilter>,
+    /// The last tag seen, used as the cursor for the next page
+    last: Option<String>,
+    /// URL of the next page given by `Link` header
+    next: Option<Url>,
+    page: std::vec::IntoIter<String>,
+    finished: bool,
+}
+
+impl TagIterator<'_> {
+    /// Get the next page, and finish if it is the last one
+    ///
+    /// Registries capping the page size, e.g. GHCR and Docker Hub, return a smaller page
+    /// with `Link: <...>; rel="next"` header, which is followed as it is.
+    /// Without the header, a page smaller than requested is the last one.
+    fn next_page(&mut self) -> Result<TagListPage> {
+        let client = &mut self.client;
+        let (url, req) = match self.next.take() {
+            Some(next) => (next.clone(), client.get(&next)),
+            None => {
+                let url = client.url.join(&format!("v2/{}/tags/list", client.name))?;
+                let mut req = client.get(&url).query("n", &self.page_size.to_string());
+                if let Some(last) = &self.last {
+                    req = req.query("last", last);
+                }
+                (url, req)
+            }
+        };
+        let res = client.call(req)?;
+        let next = res
+            .header("Link")
+            .map(|link| next_link(&client.url, &url, link))
+            .transpose()?
+            .flatten();
+        let page = res.into_json::<TagListPage>()?;
+        if page.tags.is_empty() || (next.is_none() && page.tags.len() < self.page_size) {
+            self.finished = true;
+        }
+        self.next = next;
+        Ok(page)
+    }
+
+    fn accepts(&mut self, tag: &str) -> bool {
+        let Some(filter) = &self.filter else {
+            return true;
+        };
+        // Pages are in lexicographic order, so no more tags are accepted
+        if filter.is_past(tag) {
+            self.finished = true;
+            self.page = Vec::new().into_iter();
+        }
+        filter.matches(tag)
+    }
+}
+
+impl Iterator for TagIterator<'_> {
+    type Item = Result<String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(tag) = self.page.next() {
+                self.last = Some(tag.clone());
+                if self.accepts(&tag) {
+                    return Some(Ok(tag));
+                }
+                continue;
+            }
+            if self.finished {
+                return None;
+            }
+            match self.next_page() {
+                Ok(page) => self.page = page.tags.into_iter(),
+                Err(e) => {
+                    self.finished = true;
+                    return Some(Err(e));
+                }
+            }
+        }
+    }
+}
+
+/// Iterator of tags and digests of their manifests created by [Client::get_tags_with_digests]
+pub struct TagDigestIterator<'a> {
+    tags: TagIterator<'a>,
+    page: std::vec::IntoIter<(String, Digest)>,
+}
+
+impl TagDigestIterator<'_> {
+    fn next_page(&mut self) -> Result<Vec<(String, Digest)>> {
+        let page = self.tags.next_page()?;
+        self.tags.last = page.tags.last().cloned();
+
+        let mut digests = page.digests();
+        // Filter before getting digests not to send requests for skipped tags
+        let tags: Vec<String> = page
+            .tags
+            .into_iter()
+            .filter(|tag| self.tags.accepts(tag))
+            .collect();
+        let missing = tags
+            .iter()
+            .filter(|tag| !digests.contains_key(*tag))
+            .map(|tag| Reference::new(tag))
+            .collect::<Result<Vec<_>>>()?;
+        let client = &mut self.tags.client;
+        let concurrency = client.max_concurrent_requests;
+        for (reference, stat) in client.get_manifests_metadata(&missing, concurrency)? {
+            if let Some(stat) = stat {
+                digests.insert(reference.to_string(), stat.digest);
+            }
+        }
+        // Tags removed after listed are skipped
+        Ok(tags
+            .into_iter()
+            .filter_map(|tag| {
+                let digest = digests.remove(&tag)?;
+                Some((tag, digest))
+            })
+            .collect())
+    }
+}
+
+impl Iterator for TagDigestIterator<'_> {
+    type Item = Result<(String, Digest)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(item) = self.page.next() {
+                return Some(Ok(item));
+            }
+            if self.tags.finished {
+                return None;
+            }
+            match self.next_page() {
+                Ok(page) => self.page = page.into_iter(),
+                Err(e) => {
+                    self.tags.finished = true;
+                    return Some(Err(e));
+                }
+            }
+        }
+    }
 }
 
 impl Client {
+    /// Client of the repository `name` in the registry at `url`
+    ///
+    /// The registry may be mounted under a path, e.g. `https://gateway.example.com/registry`,
+    /// and requests are sent to `{url}/v2/...`.
     pub fn new(url: Url, name: Name) -> Result<Self> {
         let auth = StoredAuth::load_all()?;
         Ok(Client {
-            agent: ureq::Agent::new(),
-            url,
+            agent: super::http_log::agent(),
+            url: base_url(url),
             name,
             auth,
             token: None,
+            issued: None,
+            tag_policy: TagPolicy::default(),
+            progress: None,
+            max_concurrent_requests: DEFAULT_MAX_CONCURRENT_REQUESTS,
+            auto_reconnect: false,
+            transport: None,
+            cancel: None,
+            max_blob_size: None,
+            max_manifest_size: Some(DEFAULT_MAX_MANIFEST_SIZE),
         })
     }
 
+    /// Set upper limit of concurrent requests, used e.g. by [Client::get_tags_with_digests]
+    pub fn set_max_concurrent_requests(&mut self, max: usize) {
+        self.max_concurrent_requests = max.max(1);
+    }
+
+    pub fn max_concurrent_requests(&self) -> usize {
+        self.max_concurrent_requests
+    }
+
+    /// Refuse blobs larger than `limit` bytes with [Error::BlobTooLarge], `None` for unlimited (default)
+    ///
+    /// The size is checked by `Content-Length` before downloading, and while downloading
+    /// in case the header is missing or wrong. Image configurations are limited by
+    /// [Client::set_max_manifest_size] instead.
+    pub fn set_max_blob_size(&mut self, limit: Option<u64>) {
+        self.max_blob_size = limit;
+    }
+
+    pub fn max_blob_size(&self) -> Option<u64> {
+        self.max_blob_size
+    }
+
+    /// Refuse manifests, image indices, and image configurations larger than `limit` bytes
+    /// with [Error::BlobTooLarge], `None` for unlimited
+    ///
+    /// They are loaded into memory to be parsed, and [DEFAULT_MAX_MANIFEST_SIZE] is used by default.
+    pub fn set_max_manifest_size(&mut self, limit: Option<u64>) {
+        self.max_manifest_size = limit;
+    }
+
+    pub fn max_manifest_size(&self) -> Option<u64> {
+        self.max_manifest_size
+    }
+
+    /// Refuse a blob of the size, e.g. given by its descriptor, before downloading it
+    pub fn check_blob_size(&self, size: u64) -> Result<()> {
+        match self.max_blob_size {
+            Some(limit) if size > limit => Err(Error::BlobTooLarge { size, limit }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Retry a request once with a new connection pool when the connection fails,
+    /// e.g. reset by the registry or a TLS session expired (default: `false`)
+    ///
+    /// This is useful for a long-lived client, e.g. in a daemon process.
+    /// Only requests without body, e.g. `GET` of manifests and tags, are retried.
+    pub fn set_auto_reconnect(&mut self, auto_reconnect: bool) {
+        self.auto_reconnect = auto_reconnect;
+    }
+
+    /// Send all requests of this client into `transport` instead of the network
+    ///
+    /// This is mainly for scripting responses of a registry in tests, see [HttpTransport].
+    pub fn set_transport(&mut self, transport: Arc<dyn HttpTransport>) {
+        self.transport = Some(transport);
+        self.agent = super::transport::agent(self.transport.as_ref(), self.cancel.as_ref());
+    }
+
+    /// Abort requests of this client once `cancel` is cancelled
+    ///
+    /// New requests fail with [Error::Cancelled] without being sent,
+    /// and blobs in transfer are aborted at their next read or write.
+    /// An upload session aborted by the cancellation is deleted from the registry.
+    pub fn set_cancellation_token(&mut self, cancel: CancellationToken) {
+        self.cancel = Some(cancel);
+        self.agent = super::transport::agent(self.transport.as_ref(), self.cancel.as_ref());
+    }
+
+    /// Delete the upload session at `location` aborted by the cancellation
+    ///
+    /// This is best-effort, and sent even though the token is cancelled.
+    fn abort_upload(&self, location: &Url) {
+        abort_upload(
+            &super::transport::agent(self.transport.as_ref(), None),
+            self.token.as_deref(),
+            location,
+        )
+    }
+
+    /// Set callback reporting the transfer of blobs by [Client::get_blob] and [Client::push_blob]
+    pub fn set_progress(&mut self, progress: Progress) {
+        self.progress = Some(progress);
+    }
+
+    pub(crate) fn report(&self, event: TransferEvent) {
+        if let Some(progress) = &self.progress {
+            progress(&event);
+        }
+    }
+
+    /// Use a token cached by [Client::save_auth_config] for this repository
+    ///
+    /// Expired tokens and tokens for other repositories are skipped,
+    /// and then a new token is fetched on demand as usual.
+    /// A new token is also fetched if the registry rejects the cached one.
+    pub fn load_auth_config(&mut self, path: &Path) -> Result<()> {
+        let registry = self.registry();
+        if let Some(config) = RegistryAuthConfig::load(path)?.into_iter().find(|c| {
+            c.registry == registry && c.is_for_repository(self.name.as_str()) && !c.is_expired()
+        }) {
+            self.token = Some(config.token);
+        }
+        Ok(())
+    }
+
+    /// Token issued by the token server in this run with its scope and expiry
+    pub fn auth_config(&self) -> Option<&RegistryAuthConfig> {
+        self.issued.as_ref()
+    }
+
+    /// Save the token issued in this run by [RegistryAuthConfig::save] to reuse it by [Client::load_auth_config]
+    ///
+    /// Nothing is saved if no token has been issued, e.g. the registry does not require authentication.
+    pub fn save_auth_config(&self, path: &Path) -> Result<()> {
+        if let Some(config) = &self.issued {
+            config.save(path)?;
+        }
+        Ok(())
+    }
+
+    /// Use the username and password for this registry instead of stored authentication info
+    pub fn set_credentials(&mut self, username: &str, password: &str) {
+        if let Some(key) = registry_keys(&self.url).first() {
+            let octet = base64::engine::general_purpose::STANDARD
+                .encode(format!("{}:{}", username, password));
+            self.auth.insert(key, octet);
+        }
+        self.token = None;
+        self.issued = None;
+    }
+
+    /// Start an operation logged into [OPERATION_LOG_TARGET] on this repository
+    fn operation(&self, name: &'static str) -> operation::Operation {
+        operation::start(name, format_args!("{}/{}", self.registry(), self.name))
+    }
+
+    /// `hostname[:port]` of the registry
+    fn registry(&self) -> String {
+        let host = self.url.host_str().unwrap_or_default();
+        if let Some(port) = self.url.port() {
+            format!("{}:{}", host, port)
+        } else {
+            host.to_string()
+        }
+    }
+
+    /// Set tags which are rejected on push unless [Client::set_allow_reserved_tags] is set
+    pub fn set_reserved_tags(&mut self, tags: Vec<String>) {
+        self.tag_policy.reserved_tags = tags;
+    }
+
+    /// Allow to push to reserved tags (default: `false`)
+    pub fn set_allow_reserved_tags(&mut self, allow: bool) {
+        self.tag_policy.allow_reserved_tags = allow;
+    }
+
+    pub fn set_tag_policy(&mut self, policy: TagPolicy) {
+        self.tag_policy = policy;
+    }
+
+    pub fn tag_policy(&self) -> &TagPolicy {
+        &self.tag_policy
+    }
+
+    /// Repository as `{host}[:{port}]/{name}`, e.g. `ghcr.io/termoshtt/ocipkg/testing`
+    pub fn repository(&self) -> String {
+        format!("{}/{}", self.registry(), self.name)
+    }
+
+    /// Create a client for the image
+    ///
+    /// The registry is checked by [crate::config::Config::check_registry].
+    /// The source replacement is not applied, use [Client::for_pull] to read images.
     pub fn from_image_name(image: &ImageName) -> Result<Self> {
+        crate::config::Config::load()?.check_registry(image)?;
         Self::new(image.registry_url()?, image.name.clone())
     }
 
+    /// Create a client to read the image, where the source replacement in [crate::config::Config] is applied
+    ///
+    /// The registry actually used is checked by [crate::config::Config::check_registry].
+    /// Images must be pushed by [Client::from_image_name] not to be redirected to a mirror.
+    pub fn for_pull(image: &ImageName) -> Result<Self> {
+        let config = crate::config::Config::load()?;
+        let actual = config.replace_source(image)?;
+        if &actual != image {
+            log::info!("Replace source: {} -> {}", image, actual);
+        }
+        config.check_registry(&actual)?;
+        Self::new(actual.registry_url()?, actual.name.clone())
+    }
+
     fn call(&mut self, req: ureq::Request) -> Result<ureq::Response> {
-        if let Some(token) = &self.token {
-            return Ok(req
-                .set("Authorization", &format!("Bearer {}", token))
-                .call()?);
+        // Token stored by `ocipkg login` is used as it is
+        if self.token.is_none() {
+            self.token = self.auth.registry_token_for(&self.url).map(str::to_string);
         }
 
-        // Try get token
-        let try_req = req.clone();
-        let www_auth = match try_req.call() {
+        // Try with the cached token, or without token
+        let try_req = match &self.token {
+            Some(token) => req
+                .clone()
+                .set("Authorization", &format!("Bearer {}", token)),
+            None => req.clone(),
+        };
+        let www_auth = match self.send(try_req) {
             Ok(res) => return Ok(res),
             Err(ureq::Error::Status(status, res)) => {
                 if status == 401 && res.has("www-authenticate") {
                     res.header("www-authenticate").unwrap().to_string()
                 } else {
-                    let err = res.into_json::<ErrorResponse>()?;
-                    return Err(Error::RegistryError(err));
+                    return Err(Error::from_status(status, res));
                 }
             }
-            Err(ureq::Error::Transport(e)) => return Err(Error::NetworkError(e.into())),
+            Err(ureq::Error::Transport(e)) => return Err(e.into()),
         };
+
+        // The cached token may be expired or for another scope, and then get a new one
+        if self.token.is_some() {
+            log::info!("Token rejected by {}, get a new one", self.registry());
+        }
         let challenge = AuthChallenge::from_header(&www_auth)?;
-        self.token = Some(self.auth.challenge(&challenge)?);
-        self.call(req)
+        let issued = self
+            .auth
+            .issue_token(&self.agent, &challenge, &self.registry())?;
+        let req = req.set("Authorization", &format!("Bearer {}", issued.token));
+        self.token = Some(issued.token.clone());
+        self.issued = Some(issued);
+        Ok(self.send(req)?)
+    }
+
+    /// Send request without body, and retry it once after `Retry-After`
+    /// if the registry responds `429 Too Many Requests`
+    #[allow(clippy::result_large_err)] // same as `ureq::Request::call`
+    fn send(&mut self, req: ureq::Request) -> std::result::Result<ureq::Response, ureq::Error> {
+        let retry = req.clone();
+        match self.send_reconnecting(req) {
+            Err(ureq::Error::Status(429, res)) => {
+                let wait = retry_after(&res);
+                log::info!(
+                    "Rate limited by {}, retry after {:?}",
+                    self.registry(),
+                    wait
+                );
+                std::thread::sleep(wait);
+                operation::record_retry();
+                self.send_reconnecting(retry)
+            }
+            result => result,
+        }
+    }
+
+    /// Send request without body, and retry it once with a new agent
+    /// on connection-level failures if [Client::set_auto_reconnect] is set
+    #[allow(clippy::result_large_err)] // same as `ureq::Request::call`
+    fn send_reconnecting(
+        &mut self,
+        req: ureq::Request,
+    ) -> std::result::Result<ureq::Response, ureq::Error> {
+        if !self.auto_reconnect {
+            return req.call();
+        }
+        let retry = req.clone();
+        match req.call() {
+            Err(ureq::Error::Transport(e))
+                if matches!(
+                    e.kind(),
+                    ureq::ErrorKind::ConnectionFailed | ureq::ErrorKind::Io
+                ) =>
+            {
+                log::info!("Reconnect to {}: {}", self.registry(), e);
+                operation::record_retry();
+                // Requests keep the agent which created them, and then be created again
+                self.agent = super::transport::agent(self.transport.as_ref(), self.cancel.as_ref());
+                let mut req = self.agent.request(retry.method(), retry.url());
+                for name in retry.header_names() {
+                    if let Some(value) = retry.header(&name) {
+                        req = req.set(&name, value);
+                    }
+                }
+                req.call()
+            }
+            result => result,
+        }
+    }
+
+    /// URL of the manifest, e.g. to be returned as the location of pushed manifest
+    pub(crate) fn manifest_url(&self, reference: &Reference) -> Result<Url> {
+        Ok(self
+            .url
+            .join(&format!("v2/{}/manifests/{}", self.name, reference))?)
+    }
+
+    fn blob_url(&self, digest: &Digest) -> Result<Url> {
+        Ok(self
+            .url
+            .join(&format!("v2/{}/blobs/{}", self.name, digest))?)
     }
 
     fn get(&self, url: &Url) -> ureq::Request {
         self.agent.get(url.as_str())
     }
 
+    fn head(&self, url: &Url) -> ureq::Request {
+        self.agent.head(url.as_str())
+    }
+
     fn put(&self, url: &Url) -> ureq::Request {
         self.agent.put(url.as_str())
     }
@@ -70,18 +736,148 @@ impl Client {
         self.agent.post(url.as_str())
     }
 
+    fn delete(&self, url: &Url) -> ureq::Request {
+        self.agent.delete(url.as_str())
+    }
+
+    /// Check the registry works end-to-end, beyond `GET /v2/`
+    ///
+    /// A tiny blob is pushed with a manifest tagged `ocipkg-health-check`, pulled back,
+    /// and compared with the original. They are deleted afterwards on a best-effort basis,
+    /// since some registries do not allow deletion.
+    /// Failures of steps are recorded in [HealthCheckResult] instead of returned as error.
+    pub fn health_check(&mut self) -> Result<HealthCheckResult> {
+        let mut result = HealthCheckResult::default();
+        let url = self.url.join("v2/")?;
+        let tag = Reference::new(HEALTH_CHECK_TAG)?;
+
+        let start = Instant::now();
+        let ping = self.call(self.get(&url));
+        result.latency = start.elapsed();
+        if let Err(e) = ping {
+            result.error = Some(e);
+            return Ok(result);
+        }
+        result.ping_ok = true;
+
+        let digest = match self.health_check_push(&tag) {
+            Ok(digest) => digest,
+            Err(e) => {
+                result.error = Some(e);
+                return Ok(result);
+            }
+        };
+        result.push_ok = true;
+
+        match self.health_check_pull(&tag) {
+            Ok(()) => result.pull_ok = true,
+            Err(e) => result.error = Some(e),
+        }
+
+        if let Err(e) = self.delete_manifest(&Reference::new(&digest.to_string())?) {
+            log::warn!("Failed to delete manifest of health check: {}", e);
+        }
+        if let Err(e) = self.delete_blob(&Digest::from_buf_sha256(HEALTH_CHECK_BLOB)) {
+            log::warn!("Failed to delete blob of health check: {}", e);
+        }
+        Ok(result)
+    }
+
+    /// Push the blob and the manifest of [Client::health_check], and returns the digest of manifest
+    fn health_check_push(&mut self, tag: &Reference) -> Result<Digest> {
+        self.push_blob(HEALTH_CHECK_BLOB)?;
+        let empty = b"{}";
+        self.push_blob(empty)?;
+        let manifest = ImageManifestBuilder::default()
+            .schema_version(SCHEMA_VERSION)
+            .media_type(MediaType::ImageManifest)
+            .config(Descriptor::new(
+                MediaType::EmptyJSON,
+                empty.len() as i64,
+                Digest::from_buf_sha256(empty).to_string(),
+            ))
+            .layers(vec![Descriptor::new(
+                MediaType::Other("application/octet-stream".to_string()),
+                HEALTH_CHECK_BLOB.len() as i64,
+                Digest::from_buf_sha256(HEALTH_CHECK_BLOB).to_string(),
+            )])
+            .build()?;
+        self.push_manifest(tag, &manifest)?;
+        let mut buf = Vec::new();
+        manifest.to_writer(&mut buf)?;
+        Ok(Digest::from_buf_sha256(&buf))
+    }
+
+    fn health_check_pull(&mut self, tag: &Reference) -> Result<()> {
+        let manifest = self.get_manifest(tag)?;
+        let layer = manifest.layers().first().ok_or(Error::MissingLayer)?;
+        let expected = Digest::from_buf_sha256(HEALTH_CHECK_BLOB);
+        let blob = self.get_blob(&Digest::new(layer.digest())?)?;
+        let actual = Digest::from_buf_sha256(&blob);
+        if actual != expected {
+            return Err(Error::DigestMismatch { expected, actual });
+        }
+        Ok(())
+    }
+
     /// Get tags of `<name>` repository.
     ///
     /// ```text
     /// GET /v2/<name>/tags/list
     /// ```
     ///
+    /// Only tags accepted by `filter` are returned if it is given.
+    ///
     /// See [corresponding OCI distribution spec document](https://github.com/opencontainers/distribution-spec/blob/main/spec.md#content-discovery) for detail.
-    pub fn get_tags(&mut self) -> Result<Vec<String>> {
-        let url = self.url.join(&format!("/v2/{}/tags/list", self.name))?;
+    pub fn get_tags(&mut self, filter: Option<TagFilter>) -> Result<Vec<String>> {
+        let url = self.url.join(&format!("v2/{}/tags/list", self.name))?;
         let res = self.call(self.get(&url))?;
         let tag_list = res.into_json::<TagList>()?;
-        Ok(tag_list.tags().to_vec())
+        let mut tags = tag_list.tags().to_vec();
+        if let Some(filter) = filter {
+            tags.retain(|tag| filter.matches(tag));
+        }
+        Ok(tags)
+    }
+
+    /// Iterate tags of `<name>` repository in lexicographic order
+    ///
+    /// ```text
+    /// GET /v2/<name>/tags/list?n=<page_size>&last=<last>
+    /// ```
+    ///
+    /// Tags are fetched by pages of `page_size` tags when the previous page is exhausted,
+    /// and only tags accepted by `filter` are yielded if it is given.
+    ///
+    /// See [corresponding OCI distribution spec document](https://github.com/opencontainers/distribution-spec/blob/main/spec.md#content-discovery) for detail.
+    pub fn tags_iter(&mut self, page_size: usize, filter: Option<TagFilter>) -> TagIterator<'_> {
+        TagIterator {
+            client: self,
+            page_size,
+            filter,
+            last: None,
+            next: None,
+            page: Vec::new().into_iter(),
+            finished: false,
+        }
+    }
+
+    /// Iterate tags with the digests of their manifests
+    ///
+    /// Tags are fetched and filtered by pages as [Client::tags_iter], and then the manifests
+    /// of each page are checked by concurrent `HEAD` requests as [Client::get_manifests_metadata],
+    /// bounded by [Client::set_max_concurrent_requests].
+    /// The digests are taken from the tag list response without `HEAD` requests
+    /// if the registry includes them as non-standard `manifest` field, e.g. Google Artifact Registry.
+    pub fn get_tags_with_digests(
+        &mut self,
+        page_size: usize,
+        filter: Option<TagFilter>,
+    ) -> TagDigestIterator<'_> {
+        TagDigestIterator {
+            tags: self.tags_iter(page_size, filter),
+            page: Vec::new().into_iter(),
+        }
     }
 
     /// Get manifest for given repository
@@ -90,11 +886,15 @@ impl Client {
     /// GET /v2/<name>/manifests/<reference>
     /// ```
     ///
+    /// If the reference is a digest, the content is checked to have the digest
+    /// as the other methods getting manifests, and [Error::DigestMismatch] is returned if not.
+    ///
     /// See [corresponding OCI distribution spec document](https://github.com/opencontainers/distribution-spec/blob/main/spec.md#pulling-manifests) for detail.
     pub fn get_manifest(&mut self, reference: &Reference) -> Result<ImageManifest> {
+        let _op = self.operation("pull-manifest").reference(reference);
         let url = self
             .url
-            .join(&format!("/v2/{}/manifests/{}", self.name, reference))?;
+            .join(&format!("v2/{}/manifests/{}", self.name, reference))?;
         let res = self.call(self.get(&url).set(
             "Accept",
             &format!(
@@ -103,114 +903,2246 @@ impl Client {
                 MediaType::ImageManifest,
             ),
         ))?;
-        let manifest = ImageManifest::from_reader(res.into_reader())?;
+        let buf = read_manifest(res, reference, self.max_manifest_size)?;
+        let manifest = ImageManifest::from_reader(buf.as_slice())?;
         Ok(manifest)
     }
 
-    /// Push manifest to registry
+    /// Get digest of the configuration blob of the manifest, to be fetched by [Client::get_blob]
+    pub fn get_manifest_config_digest(&mut self, reference: &Reference) -> Result<Digest> {
+        self.get_manifest(reference)?.config_digest()
+    }
+
+    /// Get manifest or image index as it is stored in registry with its media type
     ///
     /// ```text
-    /// PUT /v2/<name>/manifests/<reference>
+    /// GET /v2/<name>/manifests/<reference>
     /// ```
     ///
-    /// Manifest must be pushed after blobs are updated.
-    ///
-    /// See [corresponding OCI distribution spec document](https://github.com/opencontainers/distribution-spec/blob/main/spec.md#pushing-manifests) for detail.
-    pub fn push_manifest(&self, reference: &Reference, manifest: &ImageManifest) -> Result<Url> {
-        let mut buf = Vec::new();
-        manifest.to_writer(&mut buf)?;
+    /// The bytes are not parsed, and then the digest is kept when pushed again.
+    pub fn get_raw_manifest(&mut self, reference: &Reference) -> Result<(MediaType, Vec<u8>)> {
+        let _op = self.operation("pull-manifest").reference(reference);
         let url = self
             .url
-            .join(&format!("/v2/{}/manifests/{}", self.name, reference))?;
-        let mut req = self
-            .put(&url)
-            .set("Content-Type", &MediaType::ImageManifest.to_string());
-        if let Some(token) = self.token.as_ref() {
-            // Authorization must be done while blobs push
-            req = req.set("Authorization", &format!("Bearer {}", token));
+            .join(&format!("v2/{}/manifests/{}", self.name, reference))?;
+        let res = self.call(self.get(&url).set("Accept", &accept_manifest_or_index()))?;
+        let media_type = MediaType::from(res.content_type());
+        let buf = read_manifest(res, reference, self.max_manifest_size)?;
+        Ok((media_type, buf))
+    }
+
+    /// Put an existing manifest or image index as another tag in the same repository
+    ///
+    /// The manifest is fetched by its digest and pushed without any change,
+    /// and blobs are not transferred since they are shared.
+    /// An existing `dst` tag for another manifest is overwritten only if `force` is set.
+    /// Returns the digest of the manifest.
+    pub fn retag(&mut self, src: &Reference, dst: &Reference, force: bool) -> Result<Digest> {
+        let stat = self
+            .head_manifest(src)?
+            .ok_or_else(|| Error::ManifestNotFound(src.to_string()))?;
+        if let Some(current) = self.head_manifest(dst)? {
+            if current.digest == stat.digest {
+                return Ok(stat.digest);
+            }
+            if !force {
+                return Err(Error::TagAlreadyExists(dst.to_string()));
+            }
         }
-        let res = req.send_bytes(&buf)?;
-        let loc = res
-            .header("Location")
-            .expect("Location header is lacked in OCI registry response");
-        Ok(Url::parse(loc).or_else(|_| self.url.join(loc))?)
+        // The content is checked to have the digest
+        let (media_type, buf) =
+            self.get_raw_manifest(&Reference::new(&stat.digest.to_string())?)?;
+        self.push_raw_manifest(dst, &media_type, &buf)?;
+        Ok(stat.digest)
     }
 
-    /// Get blob for given digest
+    /// Get image index for given repository
     ///
     /// ```text
-    /// GET /v2/<name>/blobs/<digest>
+    /// GET /v2/<name>/manifests/<reference>
     /// ```
     ///
-    /// See [corresponding OCI distribution spec document](https://github.com/opencontainers/distribution-spec/blob/main/spec.md#pulling-blobs) for detail.
-    pub fn get_blob(&mut self, digest: &Digest) -> Result<Vec<u8>> {
+    /// This is same API as [Client::get_manifest], but the reference must point to an image index,
+    /// i.e. a multi-platform image.
+    pub fn get_index(&mut self, reference: &Reference) -> Result<ImageIndex> {
         let url = self
             .url
-            .join(&format!("/v2/{}/blobs/{}", self.name.as_str(), digest,))?;
-        let res = self.call(self.get(&url))?;
-        let mut bytes = Vec::new();
-        res.into_reader().read_to_end(&mut bytes)?;
-        Ok(bytes)
+            .join(&format!("v2/{}/manifests/{}", self.name, reference))?;
+        let res = self.call(self.get(&url).set(
+            "Accept",
+            &format!(
+                "{}, {}",
+                MediaType::ImageIndex.to_docker_v2s2().unwrap(),
+                MediaType::ImageIndex,
+            ),
+        ))?;
+        let buf = read_manifest(res, reference, self.max_manifest_size)?;
+        let index = ImageIndex::from_reader(buf.as_slice())?;
+        Ok(index)
     }
 
-    /// Push blob to registry
+    /// Get image index or Docker manifest list for given repository
     ///
     /// ```text
-    /// POST /v2/<name>/blobs/uploads/
+    /// GET /v2/<name>/manifests/<reference>
     /// ```
     ///
-    /// and following `PUT` to URL obtained by `POST`.
-    ///
-    /// See [corresponding OCI distribution spec document](https://github.com/opencontainers/distribution-spec/blob/main/spec.md#pushing-manifests) for detail.
-    pub fn push_blob(&mut self, blob: &[u8]) -> Result<Url> {
+    /// Unlike [Client::get_index], the Docker manifest list is kept in its own format,
+    /// and both can be converted into [MultiArchManifest] by [ManifestListResponse::into_multi_arch].
+    pub fn get_manifest_list(&mut self, reference: &Reference) -> Result<ManifestListResponse> {
         let url = self
             .url
-            .join(&format!("/v2/{}/blobs/uploads/", self.name))?;
+            .join(&format!("v2/{}/manifests/{}", self.name, reference))?;
+        let res = self.call(self.get(&url).set(
+            "Accept",
+            &format!("{}, {}", DOCKER_MANIFEST_LIST, MediaType::ImageIndex),
+        ))?;
+        let media_type = MediaType::from(res.content_type());
+        let body = read_manifest(res, reference, self.max_manifest_size)?;
+        ManifestListResponse::from_slice(&media_type, &body)
+    }
+
+    /// Get manifests referring to the manifest by their `subject` field, e.g. signatures or SBOMs
+    ///
+    /// ```text
+    /// GET /v2/<name>/referrers/<digest>?artifactType=<artifact_type>
+    /// ```
+    ///
+    /// Returns `None` if the registry does not support the referrers API.
+    /// Registries may ignore `artifact_type`, so the result should be filtered again by callers.
+    ///
+    /// See [corresponding OCI distribution spec document](https://github.com/opencontainers/distribution-spec/blob/main/spec.md#listing-referrers) for detail.
+    pub fn get_referrers(
+        &mut self,
+        digest: &Digest,
+        artifact_type: Option<&str>,
+    ) -> Result<Option<ImageIndex>> {
+        let url = self
+            .url
+            .join(&format!("v2/{}/referrers/{}", self.name, digest))?;
+        let mut req = self
+            .get(&url)
+            .set("Accept", &MediaType::ImageIndex.to_string());
+        if let Some(artifact_type) = artifact_type {
+            req = req.query("artifactType", artifact_type);
+        }
+        let res = match self.call(req) {
+            Ok(res) => res,
+            // with or without error body
+            Err(e) if e.status() == Some(404) => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        Ok(Some(ImageIndex::from_reader(res.into_reader())?))
+    }
+
+    /// Get manifest for the platform where this program is running
+    ///
+    /// If the reference points to an image index, the manifest matching to
+    /// [PlatformEx::from_cfg_macro] is chosen from it.
+    /// Otherwise, this is same as [Client::get_manifest].
+    pub fn get_manifest_for_current_platform(
+        &mut self,
+        reference: &Reference,
+    ) -> Result<ImageManifest> {
+        Ok(self.get_raw_manifest_for_current_platform(reference)?.0)
+    }
+
+    /// Get manifest as [Client::get_manifest_for_current_platform] with its content as it is stored in registry
+    ///
+    /// The content has the digest of the manifest, unlike the manifest serialized again.
+    pub fn get_raw_manifest_for_current_platform(
+        &mut self,
+        reference: &Reference,
+    ) -> Result<(ImageManifest, Vec<u8>)> {
+        let (media_type, buf) = self.get_raw_manifest(reference)?;
+        if !is_index_media_type(&media_type) {
+            return Ok((ImageManifest::from_reader(buf.as_slice())?, buf));
+        }
+        let index = ImageIndex::from_reader(buf.as_slice())?;
+        let platform = Platform::from_cfg_macro();
+        let desc = find_manifest_for_platform(&index, &platform)
+            .ok_or_else(|| Error::MissingPlatform(platform_string(&platform)))?;
+        let (_media_type, buf) = self.get_raw_manifest(&Reference::new(desc.digest())?)?;
+        Ok((ImageManifest::from_reader(buf.as_slice())?, buf))
+    }
+
+    /// Get the manifest annotated by `org.opencontainers.image.ref.name` as `ref_name` in the image index
+    ///
+    /// Some tools name each manifest in an image index by the annotation,
+    /// which selects a manifest more specifically than [Client::get_manifest_for_current_platform].
+    /// Returns [Error::ManifestNotFound] if no manifest in the index has the name.
+    pub fn get_manifest_by_ref_annotation(
+        &mut self,
+        index: &Reference,
+        ref_name: &str,
+    ) -> Result<ImageManifest> {
+        let index = self.get_index(index)?;
+        let desc = index
+            .manifests()
+            .iter()
+            .find(|desc| desc.annotation("org.opencontainers.image.ref.name") == Some(ref_name))
+            .ok_or_else(|| Error::ManifestNotFound(ref_name.to_string()))?;
+        self.get_manifest(&Reference::new(desc.digest())?)
+    }
+
+    /// Get the date time when the image was created
+    ///
+    /// The `org.opencontainers.image.created` annotation of the manifest is used if exists.
+    /// Otherwise, the `created` field of the image configuration is used,
+    /// which requires to fetch the config blob.
+    pub fn get_image_created_at(&mut self, reference: &Reference) -> Result<Option<DateTime<Utc>>> {
+        let manifest = self.get_manifest_for_current_platform(reference)?;
+        if let Some(created) = manifest
+            .annotations()
+            .as_ref()
+            .and_then(|a| a.get("org.opencontainers.image.created"))
+        {
+            return parse_date_time(created).map(Some);
+        }
+        let digest = Digest::new(manifest.config().digest())?;
+        let config = ImageConfiguration::from_reader(self.get_blob(&digest)?.as_slice())?;
+        config.created().as_deref().map(parse_date_time).transpose()
+    }
+
+    /// Get OS and architecture which the image declares in its configuration
+    ///
+    /// Unlike [Client::get_manifest_for_current_platform], this does not match the platform,
+    /// but returns what the image claims to be.
+    pub fn get_image_os_and_arch(&mut self, reference: &Reference) -> Result<(String, String)> {
+        let manifest = self.get_manifest_for_current_platform(reference)?;
+        let digest = Digest::new(manifest.config().digest())?;
+        let config = ImageConfiguration::from_reader(self.get_blob(&digest)?.as_slice())?;
+        Ok((config.os().to_string(), config.architecture().to_string()))
+    }
+
+    /// Compressed sizes of layers in bytes, the base layer first
+    ///
+    /// Only the manifest is fetched, and the manifest for the current platform is used
+    /// if the reference points to an image index.
+    pub fn get_layer_sizes(&mut self, reference: &Reference) -> Result<Vec<u64>> {
+        let manifest = self.get_manifest_for_current_platform(reference)?;
+        Ok(manifest
+            .layers()
+            .iter()
+            .map(|layer| layer.size() as u64)
+            .collect())
+    }
+
+    /// Sum of [Client::get_layer_sizes], excluding the configuration and manifest
+    pub fn get_total_layer_size(&mut self, reference: &Reference) -> Result<u64> {
+        Ok(self.get_layer_sizes(reference)?.into_iter().sum())
+    }
+
+    /// Get the platform which a single-arch image declares in its configuration
+    ///
+    /// Unlike [Client::get_image_os_and_arch], the reference must point to an image manifest,
+    /// and [Error::UnexpectedImageIndex] is returned for a multi-platform image.
+    pub fn get_image_platform(&mut self, reference: &Reference) -> Result<Platform> {
+        let (media_type, buf) = self.get_raw_manifest(reference)?;
+        if is_index_media_type(&media_type) {
+            return Err(Error::UnexpectedImageIndex(reference.to_string()));
+        }
+        let manifest = ImageManifest::from_reader(buf.as_slice())?;
+        let config = ImageConfiguration::from_reader(
+            self.get_config_blob(&manifest.config_digest()?)?.as_slice(),
+        )?;
+        let mut platform = PlatformBuilder::default()
+            .os(config.os().clone())
+            .architecture(config.architecture().clone())
+            .build()?;
+        platform.set_variant(config.variant().clone());
+        platform.set_os_version(config.os_version().clone());
+        platform.set_os_features(config.os_features().clone());
+        Ok(platform)
+    }
+
+    /// Get the URL of source repository in `org.opencontainers.image.source` annotation of the manifest
+    ///
+    /// Returns `None` if the annotation does not exist.
+    pub fn get_image_source_url(&mut self, reference: &Reference) -> Result<Option<Url>> {
+        let manifest = self.get_manifest_for_current_platform(reference)?;
+        let Some(source) = manifest
+            .annotations()
+            .as_ref()
+            .and_then(|a| a.get("org.opencontainers.image.source"))
+        else {
+            return Ok(None);
+        };
+        Ok(Some(Url::parse(source)?))
+    }
+
+    /// Check if the manifest exists in registry
+    ///
+    /// ```text
+    /// HEAD /v2/<name>/manifests/<reference>
+    /// ```
+    ///
+    /// Returns `None` if the manifest does not exist.
+    /// Both image manifest and image index are accepted.
+    ///
+    /// See [corresponding OCI distribution spec document](https://github.com/opencontainers/distribution-spec/blob/main/spec.md#checking-if-content-exists-in-the-registry) for detail.
+    pub fn head_manifest(&mut self, reference: &Reference) -> Result<Option<ManifestStat>> {
+        let url = self
+            .url
+            .join(&format!("v2/{}/manifests/{}", self.name, reference))?;
+        let res = match self.call(self.head(&url).set("Accept", &accept_manifest_or_index())) {
+            Ok(res) => res,
+            Err(Error::UnexpectedStatus(404)) => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        let stat = manifest_stat(&res, reference, || {
+            // Digest is not returned by HEAD on some registries, then fallback to GET
+            let res = self.call(self.get(&url).set("Accept", &accept_manifest_or_index()))?;
+            let mut buf = Vec::new();
+            res.into_reader().read_to_end(&mut buf)?;
+            Ok(buf)
+        })?;
+        Ok(Some(stat))
+    }
+
+    /// Check if the tag exists in registry by [Client::head_manifest]
+    pub fn tag_exists(&mut self, tag: &Reference) -> Result<bool> {
+        Ok(self.head_manifest(tag)?.is_some())
+    }
+
+    /// Update annotations of manifest or image index as [crate::image::annotations::update],
+    /// and push it as the same tag
+    ///
+    /// Blobs are not transferred since they are not changed.
+    /// The old manifest is left in the registry without the tag.
+    /// Returns the digest of the new manifest.
+    pub fn annotate_manifest(
+        &mut self,
+        reference: &Reference,
+        set: &[(String, String)],
+        remove: &[String],
+    ) -> Result<Digest> {
+        if reference.is_digest() {
+            return Err(Error::InvalidReference(format!(
+                "annotated manifest has another digest, use tag: {}",
+                reference
+            )));
+        }
+        let (media_type, buf) = self.get_raw_manifest(reference)?;
+        let buf = if is_index_media_type(&media_type) {
+            let mut index = ImageIndex::from_reader(buf.as_slice())?;
+            let mut annotations = index.annotations().clone().unwrap_or_default();
+            crate::image::annotations::update(&mut annotations, set, remove)?;
+            index.set_annotations((!annotations.is_empty()).then_some(annotations));
+            serde_json::to_vec(&index)?
+        } else {
+            let mut manifest = ImageManifest::from_reader(buf.as_slice())?;
+            let mut annotations = manifest.annotations().clone().unwrap_or_default();
+            crate::image::annotations::update(&mut annotations, set, remove)?;
+            manifest.set_annotations((!annotations.is_empty()).then_some(annotations));
+            serde_json::to_vec(&manifest)?
+        };
+        self.push_raw_manifest(reference, &media_type, &buf)?;
+        Ok(Digest::from_buf_sha256(&buf))
+    }
+
+    /// Size of manifest or image index in bytes as stored in registry
+    ///
+    /// ```text
+    /// HEAD /v2/<name>/manifests/<reference>
+    /// GET /v2/<name>/manifests/<reference>
+    /// ```
+    ///
+    /// `Content-Length` of `HEAD` response is used if exists, and otherwise the manifest is
+    /// downloaded without parsing to count its bytes, which may be up to a few hundred KiB
+    /// for large image indexes. Use [Client::head_manifest] instead when the registry is known
+    /// to return `Content-Length`.
+    pub fn get_manifest_size(&mut self, reference: &Reference) -> Result<u64> {
+        let url = self
+            .url
+            .join(&format!("v2/{}/manifests/{}", self.name, reference))?;
+        let res = match self.call(self.head(&url).set("Accept", &accept_manifest_or_index())) {
+            Ok(res) => res,
+            Err(Error::UnexpectedStatus(404)) => {
+                return Err(Error::ManifestNotFound(reference.to_string()))
+            }
+            Err(e) => return Err(e),
+        };
+        if let Some(size) = res
+            .header("Content-Length")
+            .and_then(|len| len.parse().ok())
+            .filter(|&len: &u64| len > 0)
+        {
+            return Ok(size);
+        }
+        let (_media_type, buf) = self.get_raw_manifest(reference)?;
+        Ok(buf.len() as u64)
+    }
+
+    /// Check if the manifests exist in registry as [Client::head_manifest] by concurrent requests
+    ///
+    /// At most `concurrency` requests are sent at the same time.
+    /// The result contains all references, and `None` means the manifest does not exist.
+    pub fn get_manifests_metadata(
+        &mut self,
+        references: &[Reference],
+        concurrency: usize,
+    ) -> Result<HashMap<Reference, Option<ManifestStat>>> {
+        self.head_manifests(references, concurrency)?
+            .into_iter()
+            .map(|(reference, stat)| Ok((reference, stat?)))
+            .collect()
+    }
+
+    /// [Client::get_manifests_metadata] keeping the result of each reference
+    fn head_manifests(
+        &mut self,
+        references: &[Reference],
+        concurrency: usize,
+    ) -> Result<HashMap<Reference, Result<Option<ManifestStat>>>> {
+        let mut stats = HashMap::new();
+        let Some((first, rest)) = references.split_first() else {
+            return Ok(stats);
+        };
+        // The first request is sent alone to get the token shared by the others
+        stats.insert(first.clone(), self.head_manifest(first));
+
+        let mut queue = Vec::new();
+        for reference in rest {
+            if !stats.contains_key(reference) && !queue.iter().any(|(r, _)| r == reference) {
+                let url = self
+                    .url
+                    .join(&format!("v2/{}/manifests/{}", self.name, reference))?;
+                queue.push((reference.clone(), url));
+            }
+        }
+        let queue = Mutex::new(queue.into_iter());
+        let results = Mutex::new(Vec::new());
+        let (agent, token) = (&self.agent, &self.token);
+        let accept = accept_manifest_or_index();
+        std::thread::scope(|s| {
+            for _ in 0..concurrency.max(1) {
+                s.spawn(|| loop {
+                    let Some((reference, url)) = queue.lock().unwrap().next() else {
+                        break;
+                    };
+                    let request = |req: ureq::Request| {
+                        let req = req.set("Accept", &accept);
+                        match token {
+                            Some(token) => req.set("Authorization", &format!("Bearer {}", token)),
+                            None => req,
+                        }
+                    };
+                    let stat = match request(agent.head(url.as_str())).call() {
+                        Ok(res) => manifest_stat(&res, &reference, || {
+                            let mut buf = Vec::new();
+                            request(agent.get(url.as_str()))
+                                .call()?
+                                .into_reader()
+                                .read_to_end(&mut buf)?;
+                            Ok(buf)
+                        })
+                        .map(Some),
+                        Err(ureq::Error::Status(404, _)) => Ok(None),
+                        Err(e) => Err(e.into()),
+                    };
+                    results.lock().unwrap().push((reference, stat));
+                });
+            }
+        });
+        stats.extend(results.into_inner().unwrap());
+        Ok(stats)
+    }
+
+    /// Push manifest to registry
+    ///
+    /// ```text
+    /// PUT /v2/<name>/manifests/<reference>
+    /// ```
+    ///
+    /// Manifest must be pushed after blobs are updated.
+    ///
+    /// See [corresponding OCI distribution spec document](https://github.com/opencontainers/distribution-spec/blob/main/spec.md#pushing-manifests) for detail.
+    pub fn push_manifest(&self, reference: &Reference, manifest: &ImageManifest) -> Result<Url> {
+        let mut buf = Vec::new();
+        manifest.to_writer(&mut buf)?;
+        self.push_raw_manifest(reference, &MediaType::ImageManifest, &buf)
+    }
+
+    /// Push manifest with `subject` field, e.g. signature or SBOM attached to another manifest
+    ///
+    /// ```text
+    /// PUT /v2/<name>/manifests/<reference>
+    /// ```
+    ///
+    /// The `OCI-Subject` response header is checked to confirm the registry processed the subject.
+    /// If the header is absent, the registry does not support the referrers API,
+    /// and only a warning is logged.
+    ///
+    /// See [corresponding OCI distribution spec document](https://github.com/opencontainers/distribution-spec/blob/main/spec.md#pushing-manifests-with-subject) for detail.
+    pub fn push_manifest_with_subject(
+        &self,
+        reference: &Reference,
+        manifest: &ImageManifest,
+        subject: &Descriptor,
+    ) -> Result<Url> {
+        let mut manifest = manifest.clone();
+        manifest.set_subject(Some(subject.clone()));
+        let mut buf = Vec::new();
+        manifest.to_writer(&mut buf)?;
+        let (url, res) = self.put_manifest(reference, &MediaType::ImageManifest, &buf)?;
+        match res.header("OCI-Subject") {
+            Some(digest) if digest == subject.digest() => {}
+            Some(digest) => {
+                return Err(Error::SubjectMismatch {
+                    expected: subject.digest().clone(),
+                    actual: digest.to_string(),
+                })
+            }
+            None => log::warn!(
+                "Registry does not return OCI-Subject header, referrers API may be unsupported"
+            ),
+        }
+        location(&self.url, &url, &res)
+    }
+
+    /// Push image index to registry
+    ///
+    /// ```text
+    /// PUT /v2/<name>/manifests/<reference>
+    /// ```
+    ///
+    /// Manifests listed in the index must be pushed before.
+    pub fn push_index(&self, reference: &Reference, index: &ImageIndex) -> Result<Url> {
+        let mut buf = Vec::new();
+        index.to_writer(&mut buf)?;
+        self.push_raw_manifest(reference, &MediaType::ImageIndex, &buf)
+    }
+
+    /// Push serialized manifest or index as it is
+    ///
+    /// The digest of the pushed manifest is the digest of `buf`,
+    /// which is required when the manifest is referenced by its digest, e.g. from an image index.
+    pub fn push_raw_manifest(
+        &self,
+        reference: &Reference,
+        media_type: impl Into<OciContentType>,
+        buf: &[u8],
+    ) -> Result<Url> {
+        let (url, res) = self.put_manifest(reference, media_type, buf)?;
+        location(&self.url, &url, &res)
+    }
+
+    fn put_manifest(
+        &self,
+        reference: &Reference,
+        media_type: impl Into<OciContentType>,
+        buf: &[u8],
+    ) -> Result<(Url, ureq::Response)> {
+        self.tag_policy.check(reference)?;
+        let _op = self.operation("push-manifest").reference(reference);
+        let url = self
+            .url
+            .join(&format!("v2/{}/manifests/{}", self.name, reference))?;
+        let mut req = self
+            .put(&url)
+            .set("Content-Type", media_type.into().to_str());
+        if let Some(token) = self.token.as_ref() {
+            // Authorization must be done while blobs push
+            req = req.set("Authorization", &format!("Bearer {}", token));
+        }
+        let res = req.send_bytes(buf)?;
+        operation::record_bytes(buf.len() as u64);
+        Ok((url, res))
+    }
+
+    /// Resolve the reference, e.g. a tag, to the digest of manifest
+    ///
+    /// Returns [Error::ManifestNotFound] if the manifest does not exist.
+    pub fn get_manifest_digest(&mut self, reference: &Reference) -> Result<Digest> {
+        let stat = self
+            .head_manifest(reference)?
+            .ok_or_else(|| Error::ManifestNotFound(reference.to_string()))?;
+        Ok(stat.digest)
+    }
+
+    /// Wait until the manifest of the reference appears in registry, and return its digest
+    ///
+    /// The manifest is polled by [Client::get_manifest_digest] every `poll_interval`,
+    /// jittered by ±10% not to be polled at once by many waiters.
+    /// Returns [Error::Timeout] if it does not appear within `timeout`.
+    pub fn await_manifest(
+        &mut self,
+        reference: &Reference,
+        poll_interval: Duration,
+        timeout: Duration,
+    ) -> Result<Digest> {
+        let _op = self.operation("await-manifest").reference(reference);
+        let deadline = Instant::now() + timeout;
+        loop {
+            match self.get_manifest_digest(reference) {
+                Ok(digest) => return Ok(digest),
+                Err(Error::ManifestNotFound(_)) => {}
+                Err(e) => return Err(e),
+            }
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Err(Error::Timeout {
+                    reference: reference.to_string(),
+                    timeout,
+                });
+            }
+            std::thread::sleep(jitter(poll_interval).min(remaining));
+        }
+    }
+
+    /// Delete manifest
+    ///
+    /// ```text
+    /// DELETE /v2/<name>/manifests/<reference>
+    /// ```
+    ///
+    /// The reference should be a digest. Deleting by tag is only supported by some registries.
+    ///
+    /// See [corresponding OCI distribution spec document](https://github.com/opencontainers/distribution-spec/blob/main/spec.md#deleting-manifests) for detail.
+    pub fn delete_manifest(&mut self, reference: &Reference) -> Result<()> {
+        let url = self
+            .url
+            .join(&format!("v2/{}/manifests/{}", self.name, reference))?;
+        self.call(self.delete(&url))?;
+        Ok(())
+    }
+
+    /// Delete blob
+    ///
+    /// ```text
+    /// DELETE /v2/<name>/blobs/<digest>
+    /// ```
+    ///
+    /// See [corresponding OCI distribution spec document](https://github.com/opencontainers/distribution-spec/blob/main/spec.md#deleting-blobs) for detail.
+    pub fn delete_blob(&mut self, digest: &Digest) -> Result<()> {
+        let url = self
+            .url
+            .join(&format!("v2/{}/blobs/{}", self.name, digest))?;
+        self.call(self.delete(&url))?;
+        Ok(())
+    }
+
+    /// Delete the tag while keeping other tags pointing to the same manifest
+    ///
+    /// This is same as [Client::delete_tag_force] without deleting the manifest.
+    pub fn delete_tag(&mut self, tag: &Reference) -> Result<()> {
+        self.delete_tag_force(tag, false)
+    }
+
+    /// Delete the tag
+    ///
+    /// If `also_delete_manifest` is set, the manifest is deleted by its digest,
+    /// which removes all tags pointing to it.
+    ///
+    /// Otherwise, deleting by tag is tried first. If the registry does not support it,
+    /// i.e. responds `400`, `405`, or `UNSUPPORTED` error,
+    /// the manifest is deleted by digest only when no other tag points to it,
+    /// and [Error::TagSharedWithOthers] is returned if some tags do.
+    /// Other errors, e.g. `DENIED` or `NAME_UNKNOWN`, are returned as they are.
+    pub fn delete_tag_force(&mut self, tag: &Reference, also_delete_manifest: bool) -> Result<()> {
+        let digest = self.get_manifest_digest(tag)?;
+        if !also_delete_manifest {
+            match self.delete_manifest(tag) {
+                Ok(()) => return Ok(()),
+                Err(e) if is_unsupported(&e) => {
+                    log::info!("Registry does not support deleting by tag: {}", tag);
+                }
+                // e.g. permission or not found errors are not resolved by deleting by digest
+                Err(e) => return Err(e),
+            }
+        }
+        let others = self.get_other_tags(tag, &digest)?;
+        if !others.is_empty() {
+            if !also_delete_manifest {
+                return Err(Error::TagSharedWithOthers {
+                    tag: tag.to_string(),
+                    others,
+                });
+            }
+            log::warn!(
+                "Deleting manifest {} also removes other tags: {}",
+                digest,
+                others.join(", ")
+            );
+        }
+        self.delete_manifest(&Reference::new(&digest.to_string())?)
+    }
+
+    /// Delete the tags by deleting their manifests with concurrent requests
+    ///
+    /// Tags are resolved to their digests as [Client::get_manifests_metadata],
+    /// and then each manifest is deleted only once even if several tags point to it.
+    /// Tags failed to be resolved or deleted are reported in [BulkDeleteReport::failed]
+    /// without stopping the others.
+    /// At most `concurrency` requests are sent at the same time.
+    /// As [Client::delete_tag_force] with `also_delete_manifest`, other tags pointing
+    /// to the deleted manifests are also removed.
+    pub fn bulk_delete_tags(
+        &mut self,
+        tags: &[Reference],
+        concurrency: usize,
+    ) -> Result<BulkDeleteReport> {
+        let mut report = BulkDeleteReport::default();
+        let mut stats = self.head_manifests(tags, concurrency)?;
+        let mut groups: Vec<(Digest, Vec<String>)> = Vec::new();
+        for tag in tags {
+            // Removed at the first occurrence, and duplicated tags are skipped
+            let Some(stat) = stats.remove(tag) else {
+                continue;
+            };
+            match stat {
+                Ok(Some(stat)) => match groups.iter_mut().find(|(d, _)| d == &stat.digest) {
+                    Some((_, tags)) => tags.push(tag.to_string()),
+                    None => groups.push((stat.digest, vec![tag.to_string()])),
+                },
+                Ok(None) => report.failed.push((
+                    vec![tag.to_string()],
+                    Error::ManifestNotFound(tag.to_string()),
+                )),
+                Err(e) => report.failed.push((vec![tag.to_string()], e)),
+            }
+        }
+        let mut groups = groups.into_iter();
+        let Some((digest, tags)) = groups.next() else {
+            return Ok(report);
+        };
+
+        // The first request is sent alone to get the token shared by the others
+        let result = self.delete_manifest(&Reference::new(&digest.to_string())?);
+        let mut results = vec![(digest, tags, result)];
+        let mut queue = Vec::new();
+        for (digest, tags) in groups {
+            let url = self
+                .url
+                .join(&format!("v2/{}/manifests/{}", self.name, digest))?;
+            queue.push((digest, tags, url));
+        }
+        let queue = Mutex::new(queue.into_iter());
+        let deleted = Mutex::new(Vec::new());
+        let (agent, token) = (&self.agent, &self.token);
+        std::thread::scope(|s| {
+            for _ in 0..concurrency.max(1) {
+                s.spawn(|| loop {
+                    let Some((digest, tags, url)) = queue.lock().unwrap().next() else {
+                        break;
+                    };
+                    let mut req = agent.delete(url.as_str());
+                    if let Some(token) = token {
+                        req = req.set("Authorization", &format!("Bearer {}", token));
+                    }
+                    let result = req.call().map(|_| ()).map_err(Error::from);
+                    deleted.lock().unwrap().push((digest, tags, result));
+                });
+            }
+        });
+        results.extend(deleted.into_inner().unwrap());
+
+        for (digest, tags, result) in results {
+            match result {
+                Ok(()) => {
+                    report.deleted.extend(tags);
+                    report.manifests.push(digest);
+                }
+                Err(e) => report.failed.push((tags, e)),
+            }
+        }
+        Ok(report)
+    }
+
+    /// Tags other than `tag` pointing to the manifest of `digest`
+    fn get_other_tags(&mut self, tag: &Reference, digest: &Digest) -> Result<Vec<String>> {
+        let mut others = Vec::new();
+        for other in self.get_tags(None)? {
+            if other == tag.as_str() {
+                continue;
+            }
+            if self.get_manifest_digest(&Reference::new(&other)?)? == *digest {
+                others.push(other);
+            }
+        }
+        Ok(others)
+    }
+
+    /// Digests of blobs in `<name>` repository, i.e. configs and layers of manifests reachable from tags
+    ///
+    /// The distribution spec does not define an API to enumerate blobs,
+    /// and no known registry exposes one, so this walks all tags as [Client::tags_iter]
+    /// and the manifests in image indexes as a best-effort approximation.
+    /// The result may be incomplete, e.g. blobs only referenced by untagged manifests are not listed,
+    /// and this sends a request for each manifest, which takes long on large repositories.
+    pub fn list_blobs(&mut self) -> Result<Vec<Digest>> {
+        let tags = self.tags_iter(100, None).collect::<Result<Vec<_>>>()?;
+        let mut manifests: Vec<Reference> = tags
+            .iter()
+            .map(|tag| Reference::new(tag))
+            .collect::<Result<_>>()?;
+        let mut visited = HashSet::new();
+        let mut blobs = BTreeSet::new();
+        while let Some(reference) = manifests.pop() {
+            let (media_type, buf) = self.get_raw_manifest(&reference)?;
+            if !visited.insert(Digest::from_buf_sha256(&buf)) {
+                continue;
+            }
+            if is_index_media_type(&media_type) {
+                for desc in ImageIndex::from_reader(buf.as_slice())?.manifests() {
+                    manifests.push(Reference::new(desc.digest())?);
+                }
+                continue;
+            }
+            let manifest = ImageManifest::from_reader(buf.as_slice())?;
+            blobs.insert(manifest.config_digest()?);
+            blobs.extend(manifest.layer_digests()?);
+        }
+        Ok(blobs.into_iter().collect())
+    }
+
+    /// Get blob for given digest
+    ///
+    /// ```text
+    /// GET /v2/<name>/blobs/<digest>
+    /// ```
+    ///
+    /// See [corresponding OCI distribution spec document](https://github.com/opencontainers/distribution-spec/blob/main/spec.md#pulling-blobs) for detail.
+    pub fn get_blob(&mut self, digest: &Digest) -> Result<Vec<u8>> {
+        self.get_blob_limited(digest, self.max_blob_size)
+    }
+
+    /// Get image configuration blob as [Client::get_blob] under [Client::set_max_manifest_size]
+    pub fn get_config_blob(&mut self, digest: &Digest) -> Result<Vec<u8>> {
+        self.get_blob_limited(digest, self.max_manifest_size)
+    }
+
+    fn get_blob_limited(&mut self, digest: &Digest, limit: Option<u64>) -> Result<Vec<u8>> {
+        let _op = self.operation("pull-blob").digest(digest);
+        let url = self
+            .url
+            .join(&format!("v2/{}/blobs/{}", self.name.as_str(), digest,))?;
+        let res = self.call(self.get(&url))?;
+        check_content_length(&res, limit)?;
+        self.report(TransferEvent::Started {
+            digest: digest.clone(),
+            size: res
+                .header("Content-Length")
+                .and_then(|len| len.parse().ok()),
+        });
+        let mut bytes = Vec::new();
+        ProgressReader::new(
+            SizeLimitedReader::new(
+                CancellableReader::new(res.into_reader(), self.cancel.as_ref()),
+                limit,
+            ),
+            digest,
+            self.progress.as_ref(),
+        )
+        .read_to_end(&mut bytes)?;
+        operation::record_bytes(bytes.len() as u64);
+        digest.verify_reader(bytes.as_slice())?;
+        self.report(TransferEvent::Finished {
+            digest: digest.clone(),
+        });
+        Ok(bytes)
+    }
+
+    /// Get blob as a stream without loading it into memory
+    ///
+    /// ```text
+    /// GET /v2/<name>/blobs/<digest>
+    /// ```
+    ///
+    /// Unlike [Client::get_blob], the transfer is not reported to the progress callback.
+    /// The reader fails once more than [Client::set_max_blob_size] bytes are read,
+    /// and fails with [Error::DigestMismatch] at the end if the content does not have the digest.
+    /// The content must not be trusted until the reader is read to the end.
+    pub fn get_blob_reader(&mut self, digest: &Digest) -> Result<impl Read + Send + 'static> {
+        let _op = self.operation("pull-blob").digest(digest);
+        let url = self
+            .url
+            .join(&format!("v2/{}/blobs/{}", self.name.as_str(), digest))?;
+        let res = self.call(self.get(&url))?;
+        check_content_length(&res, self.max_blob_size)?;
+        VerifyingReader::new(
+            SizeLimitedReader::new(
+                CancellableReader::new(res.into_reader(), self.cancel.as_ref()),
+                self.max_blob_size,
+            ),
+            digest,
+        )
+    }
+
+    /// Check if the blob exists in registry
+    ///
+    /// ```text
+    /// HEAD /v2/<name>/blobs/<digest>
+    /// ```
+    ///
+    /// Returns `None` if the blob does not exist.
+    ///
+    /// See [corresponding OCI distribution spec document](https://github.com/opencontainers/distribution-spec/blob/main/spec.md#checking-if-content-exists-in-the-registry) for detail.
+    pub fn head_blob(&mut self, digest: &Digest) -> Result<Option<BlobStat>> {
+        let url = self
+            .url
+            .join(&format!("v2/{}/blobs/{}", self.name.as_str(), digest))?;
+        let res = match self.call(self.head(&url)) {
+            Ok(res) => res,
+            Err(Error::UnexpectedStatus(404)) => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        Ok(Some(blob_stat(&res, digest)?))
+    }
+
+    /// Check if the blobs exist in registry as [Client::head_blob] by concurrent requests
+    ///
+    /// At most `concurrency` requests are sent at the same time.
+    /// The result contains all digests, and `None` means the blob does not exist.
+    pub fn get_blobs_metadata(
+        &mut self,
+        digests: &[Digest],
+        concurrency: usize,
+    ) -> Result<HashMap<Digest, Option<BlobStat>>> {
+        let mut stats = HashMap::new();
+        let Some((first, rest)) = digests.split_first() else {
+            return Ok(stats);
+        };
+        // The first request is sent alone to get the token shared by the others
+        stats.insert(first.clone(), self.head_blob(first)?);
+
+        let mut queue = Vec::new();
+        for digest in rest {
+            if !stats.contains_key(digest) && !queue.iter().any(|(d, _)| d == digest) {
+                let url = self
+                    .url
+                    .join(&format!("v2/{}/blobs/{}", self.name.as_str(), digest))?;
+                queue.push((digest.clone(), url));
+            }
+        }
+        let queue = Mutex::new(queue.into_iter());
+        let results = Mutex::new(Vec::new());
+        let (agent, token) = (&self.agent, &self.token);
+        std::thread::scope(|s| {
+            for _ in 0..concurrency.max(1) {
+                s.spawn(|| loop {
+                    let Some((digest, url)) = queue.lock().unwrap().next() else {
+                        break;
+                    };
+                    let mut req = agent.head(url.as_str());
+                    if let Some(token) = token {
+                        req = req.set("Authorization", &format!("Bearer {}", token));
+                    }
+                    let stat = match req.call() {
+                        Ok(res) => blob_stat(&res, &digest).map(Some),
+                        Err(ureq::Error::Status(404, _)) => Ok(None),
+                        Err(e) => Err(e.into()),
+                    };
+                    results.lock().unwrap().push((digest, stat));
+                });
+            }
+        });
+        for (digest, stat) in results.into_inner().unwrap() {
+            stats.insert(digest, stat?);
+        }
+        Ok(stats)
+    }
+
+    /// Get the status of a blob upload session
+    ///
+    /// ```text
+    /// GET /v2/<name>/blobs/uploads/<reference>
+    /// ```
+    ///
+    /// `session_url` is the `Location` returned when the session is started,
+    /// which may be owned by another process. The offset for the next `PATCH` is
+    /// [UploadProgress::bytes_received].
+    ///
+    /// See [corresponding OCI distribution spec document](https://github.com/opencontainers/distribution-spec/blob/main/spec.md#pushing-a-blob-in-chunks) for detail.
+    pub fn get_upload_progress(&mut self, session_url: &Url) -> Result<UploadProgress> {
+        let res = self.call(self.get(session_url))?;
+        UploadProgress::from_range_header(res.header("Range"))
+    }
+
+    /// Push blob to registry
+    ///
+    /// ```text
+    /// POST /v2/<name>/blobs/uploads/
+    /// ```
+    ///
+    /// and following `PUT` to URL obtained by `POST`.
+    ///
+    /// See [corresponding OCI distribution spec document](https://github.com/opencontainers/distribution-spec/blob/main/spec.md#pushing-manifests) for detail.
+    pub fn push_blob(&mut self, blob: &[u8]) -> Result<Url> {
+        Ok(self.push_blob_typed(blob)?.1)
+    }
+
+    /// Push blob as [Client::push_blob] with whether the registry has fully stored it
+    ///
+    /// The registry may return `202 Accepted` instead of `201 Created`
+    /// if it stores the blob asynchronously.
+    pub fn push_blob_typed(&mut self, blob: &[u8]) -> Result<(LocationResult, Url)> {
+        let digest = Digest::from_buf_sha256(blob);
+        let _op = self.operation("push-blob").digest(&digest);
+        let url = self.url.join(&format!("v2/{}/blobs/uploads/", self.name))?;
         let res = self.call(self.post(&url))?;
-        let loc = res
-            .header("Location")
-            .expect("Location header is lacked in OCI registry response");
-        let url = Url::parse(loc).or_else(|_| self.url.join(loc))?;
+        let url = session_location(&self.url, &url, &res)?;
+
+        let mut req = self
+            .put(&url)
+            .query("digest", &digest.to_string())
+            .set("Content-Length", &blob.len().to_string())
+            .set("Content-Type", "application/octet-stream");
+        if let Some(token) = self.token.as_ref() {
+            // Authorization must be done while the first POST
+            req = req.set("Authorization", &format!("Bearer {}", token))
+        }
+        self.report(TransferEvent::Started {
+            digest: digest.clone(),
+            size: Some(blob.len() as u64),
+        });
+        let res = req
+            .send(ProgressReader::new(
+                CancellableReader::new(blob, self.cancel.as_ref()),
+                &digest,
+                self.progress.as_ref(),
+            ))
+            .map_err(Error::from)
+            .inspect_err(|e| {
+                if matches!(e, Error::Cancelled) {
+                    self.abort_upload(&url);
+                }
+            })?;
+        operation::record_bytes(blob.len() as u64);
+        self.report(TransferEvent::Finished {
+            digest: digest.clone(),
+        });
+        let blob = self.blob_url(&digest)?;
+        location_typed(&self.url, &url, &res, blob)
+    }
+
+    /// Push the content of file as a blob without loading it into memory
+    ///
+    /// The file is read twice, to calculate the digest and then to upload it by a single `PUT`.
+    /// Returns the digest and the location of the blob.
+    pub fn push_blob_from_file(&mut self, path: &Path) -> Result<(Digest, Url)> {
+        let digest = Digest::from_file(path)?;
+        let _op = self.operation("push-blob").digest(&digest);
+        let url = self.url.join(&format!("v2/{}/blobs/uploads/", self.name))?;
+        let res = self.call(self.post(&url))?;
+        let url = session_location(&self.url, &url, &res)?;
+        let res = put_blob_file(
+            &self.agent,
+            self.token.as_deref(),
+            &url,
+            path,
+            &digest,
+            self.progress.as_ref(),
+            self.cancel.as_ref(),
+        )
+        .inspect_err(|e| {
+            if matches!(e, Error::Cancelled) {
+                self.abort_upload(&url);
+            }
+        })?;
+        let blob = self.blob_url(&digest)?;
+        Ok((digest, blob_location(&self.url, &url, &res, blob)?))
+    }
+
+    /// Push files as blobs by [Client::push_blob_from_file] concurrently
+    ///
+    /// At most `concurrency` files are uploaded at the same time.
+    /// The result is in the same order as `paths`, and a file failed to open or upload
+    /// has its error in the result without aborting the others.
+    #[allow(clippy::type_complexity)]
+    pub fn push_blob_batch_from_files(
+        &mut self,
+        paths: Vec<PathBuf>,
+        concurrency: usize,
+    ) -> Result<Vec<(PathBuf, Result<(Digest, Url)>)>> {
+        let mut results: Vec<Option<Result<(Digest, Url)>>> = paths.iter().map(|_| None).collect();
+        // Files are pushed alone until one succeeds to get the token shared by the others
+        let mut rest = paths.iter().enumerate();
+        for (i, path) in rest.by_ref() {
+            let result = self.push_blob_from_file(path);
+            let pushed = result.is_ok();
+            results[i] = Some(result);
+            if pushed {
+                break;
+            }
+        }
+
+        let upload_url = self.url.join(&format!("v2/{}/blobs/uploads/", self.name))?;
+        let queue = Mutex::new(rest);
+        let pushed = Mutex::new(Vec::new());
+        let (agent, token, base, progress, cancel) = (
+            &self.agent,
+            self.token.as_deref(),
+            &self.url,
+            self.progress.as_ref(),
+            self.cancel.as_ref(),
+        );
+        let cleanup = super::transport::agent(self.transport.as_ref(), None);
+        std::thread::scope(|s| {
+            for _ in 0..concurrency.max(1) {
+                s.spawn(|| loop {
+                    let Some((i, path)) = queue.lock().unwrap().next() else {
+                        break;
+                    };
+                    let result = upload_file(
+                        agent,
+                        token,
+                        base,
+                        &upload_url,
+                        path,
+                        progress,
+                        cancel.map(|cancel| (cancel, &cleanup)),
+                    );
+                    pushed.lock().unwrap().push((i, result));
+                });
+            }
+        });
+        for (i, result) in pushed.into_inner().unwrap() {
+            results[i] = Some(result);
+        }
+        Ok(paths
+            .into_iter()
+            .zip(results)
+            .map(|(path, result)| (path, result.expect("All files are pushed")))
+            .collect())
+    }
+
+    /// Push a new image which puts a layer on the top of layers of the base image
+    ///
+    /// `base` is an image in this repository, and the manifest for the current platform is used
+    /// if it is an image index. `layer` must be a gzip compressed tar archive.
+    ///
+    /// The config of the base image is inherited with the new layer appended to `rootfs`
+    /// and a new `history` entry, and the manifest extending the layers of the base manifest
+    /// is pushed as `reference` and returned.
+    pub fn push_layer_sequence(
+        &mut self,
+        base: &Reference,
+        layer: &[u8],
+        reference: &Reference,
+    ) -> Result<ImageManifest> {
+        self.tag_policy.check(reference)?;
+        let mut manifest = self.get_manifest_for_current_platform(base)?;
+        let base_config = manifest.config().clone();
+        let mut config = ImageConfiguration::from_reader(
+            self.get_config_blob(&Digest::new(base_config.digest())?)?
+                .as_slice(),
+        )?;
+
+        let mut tar = Vec::new();
+        flate2::read::GzDecoder::new(layer).read_to_end(&mut tar)?;
+        let mut rootfs = config.rootfs().clone();
+        let mut diff_ids = rootfs.diff_ids().clone();
+        diff_ids.push(Digest::from_buf_sha256(&tar).to_string());
+        rootfs.set_diff_ids(diff_ids);
+        config.set_rootfs(rootfs);
+
+        let created = Utc::now().to_rfc3339();
+        let mut history = config.history().clone();
+        history.push(
+            HistoryBuilder::default()
+                .created(created.clone())
+                .created_by("ocipkg".to_string())
+                .comment(format!("Add layer on top of {}", base))
+                .build()?,
+        );
+        config.set_history(history);
+        config.set_created(Some(created));
+
+        let layer_digest = Digest::from_buf_sha256(layer);
+        if self.head_blob(&layer_digest)?.is_none() {
+            self.push_blob(layer)?;
+        }
+        let mut buf = Vec::new();
+        config.to_writer(&mut buf)?;
+        self.push_blob(&buf)?;
+
+        let mut layers = manifest.layers().clone();
+        layers.push(Descriptor::new(
+            MediaType::ImageLayerGzip,
+            layer.len() as i64,
+            layer_digest.to_string(),
+        ));
+        manifest.set_layers(layers);
+        manifest.set_config(Descriptor::new(
+            base_config.media_type().clone(),
+            buf.len() as i64,
+            Digest::from_buf_sha256(&buf).to_string(),
+        ));
+        self.push_manifest(reference, &manifest)?;
+        Ok(manifest)
+    }
+
+    /// Push a new image which puts layers on the top of layers of the base image
+    ///
+    /// `base` is an image in this repository, and the manifest for the current platform is used
+    /// if it is an image index. Each layer is a tar archive of the media type, compressed by gzip or not.
+    ///
+    /// The config of the base image is updated by `config`, as [ImageConfigBuilder::from_base],
+    /// with the layers appended to `rootfs` and `history`.
+    /// Blobs already in the repository are not pushed again.
+    pub fn push_image_with_base(
+        &mut self,
+        base: &Reference,
+        layers: &[(&[u8], MediaType)],
+        config: &ImageConfigBuilder,
+        reference: &Reference,
+    ) -> Result<Url> {
+        self.tag_policy.check(reference)?;
+        let mut manifest = self.get_manifest_for_current_platform(base)?;
+        let base_config = manifest.config().clone();
+        let base_image = ImageConfiguration::from_reader(
+            self.get_config_blob(&Digest::new(base_config.digest())?)?
+                .as_slice(),
+        )?;
+        let mut image = config.clone().base(&base_image).build()?;
+
+        let created = Utc::now().to_rfc3339();
+        let mut descriptors = manifest.layers().clone();
+        let mut diff_ids = image.rootfs().diff_ids().clone();
+        let mut history = image.history().clone();
+        for (blob, media_type) in layers {
+            let diff_id = Digest::from_reader_sha256(decompress(media_type, *blob)?)?;
+            diff_ids.push(diff_id.to_string());
+            history.push(
+                HistoryBuilder::default()
+                    .created(created.clone())
+                    .created_by("ocipkg".to_string())
+                    .comment(format!("Add layer on top of {}", base))
+                    .build()?,
+            );
+
+            let digest = Digest::from_buf_sha256(blob);
+            if self.head_blob(&digest)?.is_none() {
+                self.push_blob(blob)?;
+            }
+            descriptors.push(Descriptor::new(
+                media_type.clone(),
+                blob.len() as i64,
+                digest.to_string(),
+            ));
+        }
+        let mut rootfs = image.rootfs().clone();
+        rootfs.set_diff_ids(diff_ids);
+        image.set_rootfs(rootfs);
+        image.set_history(history);
+        image.set_created(Some(created));
+
+        let mut buf = Vec::new();
+        image.to_writer(&mut buf)?;
+        let config_digest = Digest::from_buf_sha256(&buf);
+        if self.head_blob(&config_digest)?.is_none() {
+            self.push_blob(&buf)?;
+        }
+        manifest.set_layers(descriptors);
+        manifest.set_config(Descriptor::new(
+            base_config.media_type().clone(),
+            buf.len() as i64,
+            config_digest.to_string(),
+        ));
+        self.push_manifest(reference, &manifest)
+    }
+
+    /// Pack a directory into a layer, and push it as a new image
+    ///
+    /// If `base_image` is given, the new layer is put on the top of layers of the base image
+    /// for the current platform, and its blobs are copied into this repository if missing.
+    ///
+    /// `config` is used as the image configuration except `rootfs`, which is composed from
+    /// the layers. The runtime config and history of the base image are inherited
+    /// if `config` does not have them.
+    pub fn push_image_from_directory(
+        &mut self,
+        dir: &Path,
+        base_image: Option<&ImageName>,
+        reference: &Reference,
+        config: ImageConfiguration,
+    ) -> Result<Url> {
+        self.tag_policy.check(reference)?;
+        let layer = LayerBuilder::from_dir(dir)?;
+        let mut config = config;
+        let mut layers = Vec::new();
+        let mut diff_ids = Vec::new();
+
+        if let Some(base_image) = base_image {
+            let mut base = Client::for_pull(base_image)?;
+            let base_manifest = base.get_manifest_for_current_platform(&base_image.reference)?;
+            let base_config =
+                base.get_config_blob(&Digest::new(base_manifest.config().digest())?)?;
+            let base_config = ImageConfiguration::from_reader(base_config.as_slice())?;
+            for desc in base_manifest.layers() {
+                let digest = Digest::new(desc.digest())?;
+                if self.head_blob(&digest)?.is_none() {
+                    log::info!("Copy blob from base image: {}", digest);
+                    let blob = base.get_blob(&digest)?;
+                    self.push_blob(&blob)?;
+                }
+                layers.push(desc.clone());
+            }
+            diff_ids.extend(base_config.rootfs().diff_ids().iter().cloned());
+            if config.config().is_none() {
+                config.set_config(base_config.config().clone());
+            }
+            if config.history().is_empty() {
+                config.set_history(base_config.history().clone());
+            }
+        }
+
+        self.push_blob(&layer.blob)?;
+        layers.push(layer.descriptor());
+        diff_ids.push(layer.diff_id.to_string());
+        config.set_rootfs(
+            RootFsBuilder::default()
+                .typ("layers".to_string())
+                .diff_ids(diff_ids)
+                .build()?,
+        );
+
+        let mut buf = Vec::new();
+        config.to_writer(&mut buf)?;
+        self.push_blob(&buf)?;
+        let config_desc = Descriptor::new(
+            MediaType::ImageConfig,
+            buf.len() as i64,
+            Digest::from_buf_sha256(&buf).to_string(),
+        );
+
+        let manifest = ImageManifestBuilder::default()
+            .schema_version(SCHEMA_VERSION)
+            .config(config_desc)
+            .layers(layers)
+            .build()?;
+        self.push_manifest(reference, &manifest)
+    }
+}
+
+/// `PUT` the file to the upload location as a single chunk
+fn put_blob_file(
+    agent: &ureq::Agent,
+    token: Option<&str>,
+    location: &Url,
+    path: &Path,
+    digest: &Digest,
+    progress: Option<&Progress>,
+    cancel: Option<&CancellationToken>,
+) -> Result<ureq::Response> {
+    let file = fs::File::open(path)?;
+    let size = file.metadata()?.len();
+    let mut req = agent
+        .put(location.as_str())
+        .query("digest", &digest.to_string())
+        .set("Content-Length", &size.to_string())
+        .set("Content-Type", "application/octet-stream");
+    if let Some(token) = token {
+        req = req.set("Authorization", &format!("Bearer {}", token));
+    }
+    if let Some(progress) = progress {
+        progress(&TransferEvent::Started {
+            digest: digest.clone(),
+            size: Some(size),
+        });
+    }
+    let res = req.send(ProgressReader::new(
+        CancellableReader::new(file, cancel),
+        digest,
+        progress,
+    ))?;
+    operation::record_bytes(size);
+    if let Some(progress) = progress {
+        progress(&TransferEvent::Finished {
+            digest: digest.clone(),
+        });
+    }
+    Ok(res)
+}
+
+/// Upload the file by `POST` and `PUT` with the token shared by threads of [Client::push_blob_batch_from_files]
+///
+/// The session aborted by the cancellation is deleted by the agent paired with the token.
+#[allow(clippy::too_many_arguments)]
+fn upload_file(
+    agent: &ureq::Agent,
+    token: Option<&str>,
+    base: &Url,
+    upload_url: &Url,
+    path: &Path,
+    progress: Option<&Progress>,
+    cancel: Option<(&CancellationToken, &ureq::Agent)>,
+) -> Result<(Digest, Url)> {
+    let digest = Digest::from_file(path)?;
+    let mut req = agent.post(upload_url.as_str());
+    if let Some(token) = token {
+        req = req.set("Authorization", &format!("Bearer {}", token));
+    }
+    let res = req.call()?;
+    let session = session_location(base, upload_url, &res)?;
+    let res = put_blob_file(
+        agent,
+        token,
+        &session,
+        path,
+        &digest,
+        progress,
+        cancel.map(|(cancel, _)| cancel),
+    )
+    .inspect_err(|e| {
+        if let (Error::Cancelled, Some((_, cleanup))) = (e, cancel) {
+            abort_upload(cleanup, token, &session);
+        }
+    })?;
+    // `upload_url` is `/v2/<name>/blobs/uploads/`, and the blob is `/v2/<name>/blobs/<digest>`
+    let blob = upload_url.join(&format!("../{}", digest))?;
+    Ok((digest, blob_location(base, &session, &res, blob)?))
+}
+
+/// Delete the upload session at `location` by best effort
+///
+/// ```text
+/// DELETE /v2/<name>/blobs/uploads/<reference>
+/// ```
+fn abort_upload(agent: &ureq::Agent, token: Option<&str>, location: &Url) {
+    let mut req = agent.delete(location.as_str());
+    if let Some(token) = token {
+        req = req.set("Authorization", &format!("Bearer {}", token));
+    }
+    if let Err(e) = req.call() {
+        log::warn!("Failed to delete the upload session {}: {}", location, e);
+    }
+}
+
+/// `url` with a trailing slash, to which paths of API, e.g. `v2/<name>/tags/list`, are joined
+pub(crate) fn base_url(mut url: Url) -> Url {
+    if !url.path().ends_with('/') {
+        let path = format!("{}/", url.path());
+        url.set_path(&path);
+    }
+    url
+}
+
+/// URL in `Location` header of the response for `request`
+///
+/// Relative references are resolved against the URL of the request.
+/// Paths starting with `/v2/` are taken under the path of `base`,
+/// since a registry mounted under a path prefix by a gateway may not know the prefix.
+fn location(base: &Url, request: &Url, res: &ureq::Response) -> Result<Url> {
+    let loc = res
+        .header("Location")
+        .ok_or_else(|| Error::MissingLocation(request.clone()))?;
+    resolve_url(base, request, loc)
+}
+
+/// Resolve a URL given by registry as [location]
+fn resolve_url(base: &Url, request: &Url, loc: &str) -> Result<Url> {
+    match loc.strip_prefix("/v2/") {
+        Some(path) => Ok(base.join(&format!("v2/{}", path))?),
+        None => Ok(request.join(loc)?),
+    }
+}
+
+/// URL of `rel="next"` in `Link` header, e.g. `</v2/<name>/tags/list?n=100&last=v1>; rel="next"`
+fn next_link(base: &Url, request: &Url, link: &str) -> Result<Option<Url>> {
+    for value in link.split(',') {
+        let Some((target, params)) = value.split_once(';') else {
+            continue;
+        };
+        let is_next = params
+            .split(';')
+            .filter_map(|param| param.split_once('='))
+            .any(|(key, value)| key.trim() == "rel" && value.trim().trim_matches('"') == "next");
+        let target = target.trim();
+        if let Some(target) = target.strip_prefix('<').and_then(|t| t.strip_suffix('>')) {
+            if is_next {
+                return Ok(Some(resolve_url(base, request, target)?));
+            }
+        }
+    }
+    Ok(None)
+}
+
+/// URL of the upload session started by `POST` to `request`, i.e. `/v2/<name>/blobs/uploads/`
+///
+/// Some registries, e.g. older Artifactory, answer `202 Accepted` without `Location`
+/// but with `Docker-Upload-UUID` header, and the session is at `/v2/<name>/blobs/uploads/<uuid>`.
+fn session_location(base: &Url, request: &Url, res: &ureq::Response) -> Result<Url> {
+    if res.header("Location").is_none() && res.status() == 202 {
+        if let Some(uuid) = res.header("Docker-Upload-UUID") {
+            return Ok(request.join(&format!("./{}", uuid.trim()))?);
+        }
+    }
+    location(base, request, res)
+}
+
+/// URL of the blob uploaded by `PUT` to `request`, or `blob` if `Location` header is missing
+fn blob_location(base: &Url, request: &Url, res: &ureq::Response, blob: Url) -> Result<Url> {
+    if res.header("Location").is_none() {
+        return Ok(blob);
+    }
+    location(base, request, res)
+}
+
+/// [blob_location] with whether the content is fully stored, see [LocationResult]
+fn location_typed(
+    base: &Url,
+    request: &Url,
+    res: &ureq::Response,
+    blob: Url,
+) -> Result<(LocationResult, Url)> {
+    let result = LocationResult {
+        fully_stored: res.status() == 201,
+    };
+    Ok((result, blob_location(base, request, res, blob)?))
+}
+
+/// `interval` scaled randomly within ±10%
+fn jitter(interval: Duration) -> Duration {
+    let random = uuid::Uuid::new_v4().as_u128() as u64 as f64 / u64::MAX as f64;
+    interval.mul_f64(0.9 + 0.2 * random)
+}
+
+/// Upper limit of waiting by `Retry-After` header
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(60);
+
+/// Seconds in `Retry-After` header, or a second if it is missing or an HTTP date
+fn retry_after(res: &ureq::Response) -> Duration {
+    res.header("Retry-After")
+        .and_then(|secs| secs.trim().parse().ok())
+        .map(Duration::from_secs)
+        .unwrap_or(Duration::from_secs(1))
+        .min(MAX_RETRY_AFTER)
+}
+
+fn parse_date_time(input: &str) -> Result<DateTime<Utc>> {
+    Ok(DateTime::parse_from_rfc3339(input)
+        .map_err(|_| Error::InvalidDateTime(input.to_string()))?
+        .with_timezone(&Utc))
+}
+
+/// Media types of Docker image manifest V2 schema 1, signed and unsigned
+const DOCKER_SCHEMA1_MEDIA_TYPES: [&str; 2] = [
+    "application/vnd.docker.distribution.manifest.v1+prettyjws",
+    "application/vnd.docker.distribution.manifest.v1+json",
+];
+
+/// Read the body of manifest response, and check its digest if the manifest is requested by digest
+///
+/// The raw bytes are hashed since re-serialization may change the digest.
+fn read_manifest(
+    res: ureq::Response,
+    reference: &Reference,
+    limit: Option<u64>,
+) -> Result<Vec<u8>> {
+    let content_type = OciContentType::from_str(res.content_type());
+    let mut buf = Vec::new();
+    check_content_length(&res, limit)?;
+    SizeLimitedReader::new(res.into_reader(), limit).read_to_end(&mut buf)?;
+    operation::record_bytes(buf.len() as u64);
+    // Some registries serve schema 1 without its media type
+    if !content_type.is_manifest_type() && is_schema1(&content_type, &buf) {
+        return Err(Error::UnsupportedSchema1 {
+            reference: reference.to_string(),
+            suggestion: "Pull it with docker or skopeo, and push it again to convert into schema 2 or OCI manifest.".to_string(),
+        });
+    }
+    if reference.is_digest() {
+        Digest::new(reference)?.verify_reader(buf.as_slice())?;
+    }
+    Ok(buf)
+}
+
+/// Refuse the response before reading its body if `Content-Length` exceeds `limit`
+fn check_content_length(res: &ureq::Response, limit: Option<u64>) -> Result<()> {
+    let size = res
+        .header("Content-Length")
+        .and_then(|len| len.parse::<u64>().ok());
+    match (size, limit) {
+        (Some(size), Some(limit)) if size > limit => Err(Error::BlobTooLarge { size, limit }),
+        _ => Ok(()),
+    }
+}
+
+/// Reader failing with [Error::BlobTooLarge] once more than `limit` bytes are read,
+/// even if `Content-Length` is not given or wrong
+struct SizeLimitedReader<R: Read> {
+    inner: R,
+    limit: Option<u64>,
+    read: u64,
+}
+
+impl<R: Read> SizeLimitedReader<R> {
+    fn new(inner: R, limit: Option<u64>) -> Self {
+        Self {
+            inner,
+            limit,
+            read: 0,
+        }
+    }
+}
+
+impl<R: Read> Read for SizeLimitedReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.read += n as u64;
+        match self.limit {
+            Some(limit) if self.read > limit => Err(too_large_io(self.read, limit)),
+            _ => Ok(n),
+        }
+    }
+}
+
+fn is_schema1(content_type: &OciContentType, buf: &[u8]) -> bool {
+    if DOCKER_SCHEMA1_MEDIA_TYPES.contains(&content_type.to_str()) {
+        return true;
+    }
+    #[derive(serde::Deserialize)]
+    #[serde(rename_all = "camelCase")]
+    struct Versioned {
+        schema_version: Option<u32>,
+    }
+    serde_json::from_slice::<Versioned>(buf).is_ok_and(|v| v.schema_version == Some(1))
+}
+
+/// `Accept` header value for both image manifest and image index
+fn accept_manifest_or_index() -> String {
+    format!(
+        "{}, {}, {}, {}",
+        OciContentType::DockerManifestV2,
+        OciContentType::OciImageManifest,
+        OciContentType::DockerManifestList,
+        OciContentType::OciImageIndex,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn upload_progress_from_range_header() -> Result<()> {
+        assert_eq!(
+            UploadProgress::from_range_header(None)?,
+            UploadProgress::default()
+        );
+        let expected = UploadProgress {
+            bytes_received: 1024,
+            range: Some((0, 1023)),
+        };
+        assert_eq!(UploadProgress::from_range_header(Some("0-1023"))?, expected);
+        assert_eq!(
+            UploadProgress::from_range_header(Some("bytes=0-1023"))?,
+            expected
+        );
+        assert!(UploadProgress::from_range_header(Some("1023")).is_err());
+        assert!(UploadProgress::from_range_header(Some("10-5")).is_err());
+        Ok(())
+    }
+
+    fn test_url() -> Url {
+        Url::parse("http://localhost:5000").unwrap()
+    }
+    fn test_name() -> Name {
+        Name::new("test_repo").unwrap()
+    }
+
+    /// Client of `registry.test` answered by the scripted responses
+    fn mock_client() -> Result<(Client, Arc<transport::ScriptedTransport>)> {
+        let transport = transport::ScriptedTransport::new();
+        let mut client = Client::new(Url::parse("http://registry.test").unwrap(), test_name())?;
+        client.set_transport(transport.clone());
+        Ok((client, transport))
+    }
+
+    fn json_response(body: serde_json::Value) -> String {
+        let body = body.to_string();
+        format!(
+            "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+            body.len(),
+            body
+        )
+    }
+
+    fn tags_response(tags: &[&str]) -> String {
+        json_response(serde_json::json!({ "name": "test_repo", "tags": tags }))
+    }
+
+    #[test]
+    fn auth_challenge() -> Result<()> {
+        let (mut client, transport) = mock_client()?;
+        transport.respond(
+            "HTTP/1.1 401 Unauthorized\r\n\
+             WWW-Authenticate: Bearer realm=\"http://auth.registry.test/token\",service=\"registry.test\",scope=\"repository:test_repo:pull\"\r\n\
+             Content-Length: 0\r\n\r\n",
+        );
+        transport.respond(&json_response(serde_json::json!({ "token": "abc" })));
+        transport.respond(&tags_response(&["tag1"]));
+        transport.respond(&tags_response(&["tag1", "tag2"]));
+        assert_eq!(client.get_tags(None)?, ["tag1"]);
+        // Token is cached
+        assert_eq!(client.get_tags(None)?, ["tag1", "tag2"]);
+        assert!(transport.finished());
+
+        let requests = transport.requests();
+        assert_eq!(requests.len(), 4);
+        assert_eq!(requests[0].header("authorization"), None);
+        let token_url = &requests[1].url;
+        assert_eq!(token_url.path(), "/token");
+        assert!(token_url
+            .query_pairs()
+            .any(|(key, value)| key == "scope" && value == "repository:test_repo:pull"));
+        assert_eq!(requests[2].header("authorization"), Some("Bearer abc"));
+        assert_eq!(requests[3].header("authorization"), Some("Bearer abc"));
+        Ok(())
+    }
+
+    #[test]
+    fn auth_config_reused() -> Result<()> {
+        let challenge = |repository: &str| {
+            format!(
+                "HTTP/1.1 401 Unauthorized\r\n\
+                 WWW-Authenticate: Bearer realm=\"http://auth.registry.test/token\",service=\"registry.test\",scope=\"repository:{}:pull\"\r\n\
+                 Content-Length: 0\r\n\r\n",
+                repository
+            )
+        };
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("tokens.json");
+
+        let (mut client, transport) = mock_client()?;
+        transport.respond(&challenge("test_repo"));
+        transport.respond(&json_response(
+            serde_json::json!({ "token": "abc", "expires_in": 300 }),
+        ));
+        transport.respond(&tags_response(&["tag1"]));
+        client.get_tags(None)?;
+        let issued = client.auth_config().unwrap();
+        assert_eq!(issued.registry, "registry.test");
+        assert_eq!(issued.scope, "repository:test_repo:pull");
+        assert!(issued.expires_at > Utc::now() + chrono::Duration::seconds(200));
+        client.save_auth_config(&path)?;
+
+        // Reused in the next run for the same repository
+        let (mut client, transport) = mock_client()?;
+        client.load_auth_config(&path)?;
+        transport.respond(&tags_response(&["tag1"]));
+        client.get_tags(None)?;
+        assert_eq!(
+            transport.requests()[0].header("authorization"),
+            Some("Bearer abc")
+        );
+
+        // Not used for another repository in the same registry
+        let (mut client, transport) = mock_client()?;
+        client.name = Name::new("other_repo")?;
+        client.load_auth_config(&path)?;
+        transport.respond(&challenge("other_repo"));
+        transport.respond(&json_response(serde_json::json!({ "token": "def" })));
+        transport.respond(&tags_response(&["tag1"]));
+        client.get_tags(None)?;
+        assert_eq!(transport.requests()[0].header("authorization"), None);
+        client.save_auth_config(&path)?;
+        assert_eq!(RegistryAuthConfig::load(&path)?.len(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn auth_cached_token_rejected() -> Result<()> {
+        let (mut client, transport) = mock_client()?;
+        client.token = Some("stale".to_string());
+        transport.respond(
+            "HTTP/1.1 401 Unauthorized\r\n\
+             WWW-Authenticate: Bearer realm=\"http://auth.registry.test/token\",service=\"registry.test\",scope=\"repository:test_repo:pull\"\r\n\
+             Content-Length: 0\r\n\r\n",
+        );
+        transport.respond(&json_response(serde_json::json!({ "token": "abc" })));
+        transport.respond(&tags_response(&["tag1"]));
+        assert_eq!(client.get_tags(None)?, ["tag1"]);
+        assert!(transport.finished());
+
+        let requests = transport.requests();
+        assert_eq!(requests[0].header("authorization"), Some("Bearer stale"));
+        assert_eq!(requests[2].header("authorization"), Some("Bearer abc"));
+        assert_eq!(client.token.as_deref(), Some("abc"));
+        Ok(())
+    }
+
+    #[test]
+    fn auth_challenge_rejected() -> Result<()> {
+        let (mut client, transport) = mock_client()?;
+        transport.respond(
+            "HTTP/1.1 401 Unauthorized\r\n\
+             WWW-Authenticate: Bearer realm=\"http://auth.registry.test/token\",service=\"registry.test\",scope=\"repository:test_repo:pull\"\r\n\
+             Content-Length: 0\r\n\r\n",
+        );
+        transport.respond("HTTP/1.1 403 Forbidden\r\nContent-Length: 0\r\n\r\n");
+        assert!(matches!(
+            client.get_tags(None),
+            Err(Error::AuthorizationFailed(_))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn retry_rate_limited() -> Result<()> {
+        let (mut client, transport) = mock_client()?;
+        transport.respond(
+            "HTTP/1.1 429 Too Many Requests\r\nRetry-After: 0\r\nContent-Length: 0\r\n\r\n",
+        );
+        transport.respond(&tags_response(&["tag1"]));
+        assert_eq!(client.get_tags(None)?, ["tag1"]);
+        assert!(transport.finished());
+
+        // Retried only once
+        transport.respond(
+            "HTTP/1.1 429 Too Many Requests\r\nRetry-After: 0\r\nContent-Length: 0\r\n\r\n",
+        );
+        transport.respond(
+            "HTTP/1.1 429 Too Many Requests\r\nRetry-After: 0\r\nContent-Length: 0\r\n\r\n",
+        );
+        let err = client.get_tags(None).unwrap_err();
+        assert!(matches!(err, Error::UnexpectedStatus(429)));
+        assert!(err.is_transient());
+        assert!(transport.finished());
+        Ok(())
+    }
+
+    fn error_response(status: &str, code: &str) -> String {
+        let body = serde_json::json!({ "errors": [{ "code": code, "message": "m" }] }).to_string();
+        format!(
+            "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+            status,
+            body.len(),
+            body
+        )
+    }
+
+    #[test]
+    fn classify_error_responses() -> Result<()> {
+        let (mut client, transport) = mock_client()?;
+        // Classified by the status even if the body has another code
+        transport.respond(&error_response("503 Service Unavailable", "UNSUPPORTED"));
+        let err = client.get_tags(None).unwrap_err();
+        assert!(matches!(err, Error::RegistryError { status: 503, .. }));
+        assert!(err.is_transient());
+        assert!(!err.is_auth_error());
+
+        // Code out of the spec, which registries commonly send
+        transport.respond(&error_response("502 Bad Gateway", "UNKNOWN"));
+        let err = client.get_tags(None).unwrap_err();
+        assert_eq!(err.status(), Some(502));
+        assert!(err.is_transient());
+
+        transport.respond(&error_response("401 Unauthorized", "UNSUPPORTED"));
+        let err = client.get_tags(None).unwrap_err();
+        assert!(matches!(err, Error::RegistryError { status: 401, .. }));
+        assert!(err.is_auth_error());
+        assert!(!err.is_transient());
+
+        transport.respond(&error_response("403 Forbidden", "NAME_UNKNOWN"));
+        assert!(client.get_tags(None).unwrap_err().is_auth_error());
+
+        transport.respond(&error_response("404 Not Found", "NAME_UNKNOWN"));
+        let err = client.get_tags(None).unwrap_err();
+        assert_eq!(err.status(), Some(404));
+        assert!(!err.is_transient());
+        assert!(!err.is_auth_error());
+        assert!(transport.finished());
+        Ok(())
+    }
+
+    #[test]
+    fn referrers_unsupported() -> Result<()> {
+        let (mut client, transport) = mock_client()?;
+        let digest = Digest::from_buf_sha256(b"manifest");
+        transport.respond("HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n\r\n");
+        assert!(client.get_referrers(&digest, None)?.is_none());
+        transport.respond(&error_response("404 Not Found", "UNSUPPORTED"));
+        assert!(client.get_referrers(&digest, None)?.is_none());
+        transport.respond(&error_response("403 Forbidden", "DENIED"));
+        assert!(client.get_referrers(&digest, None).is_err());
+        assert!(transport.finished());
+        Ok(())
+    }
+
+    #[test]
+    fn delete_tag_fallback() -> Result<()> {
+        let (mut client, transport) = mock_client()?;
+        let tag = Reference::new("tag1")?;
+        let digest = Digest::from_buf_sha256(b"manifest");
+        let found = format!(
+            "HTTP/1.1 200 OK\r\nDocker-Content-Digest: {}\r\nContent-Length: 0\r\n\r\n",
+            digest
+        );
+
+        // Deleted by digest if deleting by tag is not supported
+        transport.respond(&found);
+        transport.respond(&error_response("405 Method Not Allowed", "UNSUPPORTED"));
+        transport.respond(&tags_response(&["tag1"]));
+        transport.respond("HTTP/1.1 202 Accepted\r\nContent-Length: 0\r\n\r\n");
+        client.delete_tag(&tag)?;
+        assert!(transport.finished());
+        let requests = transport.requests();
+        assert_eq!(requests[3].method, "DELETE");
+        assert!(requests[3].url.path().ends_with(&digest.to_string()));
+
+        // Other errors are returned without looking up other tags
+        transport.respond(&found);
+        transport.respond(&error_response("403 Forbidden", "DENIED"));
+        let err = client.delete_tag(&tag).unwrap_err();
+        assert!(err.is_auth_error());
+        assert!(transport.finished());
+
+        transport.respond(&found);
+        transport.respond(&error_response("404 Not Found", "NAME_UNKNOWN"));
+        let err = client.delete_tag(&tag).unwrap_err();
+        assert_eq!(err.status(), Some(404));
+        assert!(transport.finished());
+        Ok(())
+    }
+
+    #[test]
+    fn retry_after() {
+        let res = |header: &str| -> ureq::Response {
+            format!("HTTP/1.1 429 Too Many Requests\r\n{}\r\n", header)
+                .parse()
+                .unwrap()
+        };
+        assert_eq!(
+            super::retry_after(&res("Retry-After: 3\r\n")),
+            Duration::from_secs(3)
+        );
+        assert_eq!(super::retry_after(&res("")), Duration::from_secs(1));
+        assert_eq!(
+            super::retry_after(&res("Retry-After: Wed, 21 Oct 2015 07:28:00 GMT\r\n")),
+            Duration::from_secs(1)
+        );
+        assert_eq!(
+            super::retry_after(&res("Retry-After: 3600\r\n")),
+            MAX_RETRY_AFTER
+        );
+    }
+
+    #[test]
+    fn auto_reconnect_transport() -> Result<()> {
+        let (mut client, transport) = mock_client()?;
+        transport.fail();
+        assert!(matches!(client.get_tags(None), Err(Error::NetworkError(_))));
+
+        // The new agent keeps sending into the transport
+        client.set_auto_reconnect(true);
+        transport.fail();
+        transport.respond(&tags_response(&["tag1"]));
+        assert_eq!(client.get_tags(None)?, ["tag1"]);
+        assert!(transport.finished());
+        Ok(())
+    }
+
+    #[test]
+    fn tags_pagination() -> Result<()> {
+        let (mut client, transport) = mock_client()?;
+        transport.respond(&tags_response(&["tag1", "tag2"]));
+        transport.respond(&tags_response(&["tag3", "tag4"]));
+        transport.respond(&tags_response(&["tag5"]));
+        let tags = client.tags_iter(2, None).collect::<Result<Vec<_>>>()?;
+        assert_eq!(tags, ["tag1", "tag2", "tag3", "tag4", "tag5"]);
+        assert!(transport.finished());
+
+        let queries = transport
+            .requests()
+            .iter()
+            .map(|req| req.url.query().unwrap_or_default().to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(queries, ["n=2", "n=2&last=tag2", "n=2&last=tag4"]);
+
+        // Stop listing once tags are past the prefix
+        transport.respond(&tags_response(&["a1", "a2"]));
+        transport.respond(&tags_response(&["b1", "b2"]));
+        let tags = client
+            .tags_iter(2, Some(TagFilter::prefix("a")))
+            .collect::<Result<Vec<_>>>()?;
+        assert_eq!(tags, ["a1", "a2"]);
+        assert!(transport.finished());
+        Ok(())
+    }
+
+    #[test]
+    fn push_blob_relative_location() -> Result<()> {
+        let (mut client, transport) = mock_client()?;
+        let blob = b"blob";
+        let digest = Digest::from_buf_sha256(blob);
+        transport.respond(
+            "HTTP/1.1 202 Accepted\r\nLocation: /v2/test_repo/blobs/uploads/uuid?state=1\r\nContent-Length: 0\r\n\r\n",
+        );
+        transport.respond(&format!(
+            "HTTP/1.1 201 Created\r\nLocation: /v2/test_repo/blobs/{}\r\nContent-Length: 0\r\n\r\n",
+            digest
+        ));
+        let (result, url) = client.push_blob_typed(blob)?;
+        assert!(result.fully_stored);
+        assert_eq!(
+            url.as_str(),
+            format!("http://registry.test/v2/test_repo/blobs/{}", digest)
+        );
+
+        let requests = transport.requests();
+        assert_eq!(requests[1].method, "PUT");
+        assert_eq!(requests[1].url.path(), "/v2/test_repo/blobs/uploads/uuid");
+        assert!(requests[1]
+            .url
+            .query_pairs()
+            .any(|(key, value)| key == "state" && value == "1"));
+        assert!(requests[1]
+            .url
+            .query_pairs()
+            .any(|(key, value)| key == "digest" && value == digest.to_string()));
+        Ok(())
+    }
+
+    #[test]
+    fn push_blob_without_location() -> Result<()> {
+        let (mut client, transport) = mock_client()?;
+        let blob = b"blob";
+        let digest = Digest::from_buf_sha256(blob);
+
+        // Session from Docker-Upload-UUID, and the blob at the canonical URL
+        transport.respond(
+            "HTTP/1.1 202 Accepted\r\nDocker-Upload-UUID: uuid\r\nContent-Length: 0\r\n\r\n",
+        );
+        transport.respond("HTTP/1.1 201 Created\r\nContent-Length: 0\r\n\r\n");
+        let (result, url) = client.push_blob_typed(blob)?;
+        assert!(result.fully_stored);
+        assert_eq!(
+            url.as_str(),
+            format!("http://registry.test/v2/test_repo/blobs/{}", digest)
+        );
+        let requests = transport.requests();
+        assert_eq!(requests[1].method, "PUT");
+        assert_eq!(requests[1].url.path(), "/v2/test_repo/blobs/uploads/uuid");
+
+        // Location is still required if the session is not accepted as 202
+        transport.respond(
+            "HTTP/1.1 201 Created\r\nDocker-Upload-UUID: uuid\r\nContent-Length: 0\r\n\r\n",
+        );
+        assert!(matches!(
+            client.push_blob_typed(blob),
+            Err(Error::MissingLocation(_))
+        ));
+        assert!(transport.finished());
+        Ok(())
+    }
+
+    #[test]
+    fn await_manifest() -> Result<()> {
+        let (mut client, transport) = mock_client()?;
+        let reference = Reference::new("latest")?;
+        let digest = Digest::from_buf_sha256(b"manifest");
+        transport.respond("HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n\r\n");
+        transport.respond("HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n\r\n");
+        transport.respond(&format!(
+            "HTTP/1.1 200 OK\r\nDocker-Content-Digest: {}\r\nContent-Length: 0\r\n\r\n",
+            digest
+        ));
+        let found = client.await_manifest(
+            &reference,
+            Duration::from_millis(1),
+            Duration::from_secs(10),
+        )?;
+        assert_eq!(found, digest);
+        assert!(transport.finished());
+
+        // Polled once more at the deadline
+        transport.respond("HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n\r\n");
+        transport.respond("HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n\r\n");
+        assert!(matches!(
+            client.await_manifest(
+                &reference,
+                Duration::from_secs(1),
+                Duration::from_millis(10)
+            ),
+            Err(Error::Timeout { .. })
+        ));
+        assert!(transport.finished());
+
+        for _ in 0..100 {
+            let interval = jitter(Duration::from_secs(10));
+            assert!(interval >= Duration::from_secs(9) && interval <= Duration::from_secs(11));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn max_blob_size() -> Result<()> {
+        let (mut client, transport) = mock_client()?;
+        client.set_max_blob_size(Some(4));
+        let digest = Digest::from_buf_sha256(b"0123456789");
+        assert!(matches!(
+            client.check_blob_size(10),
+            Err(Error::BlobTooLarge { size: 10, limit: 4 })
+        ));
+
+        // Refused by Content-Length before reading the body
+        transport.respond("HTTP/1.1 200 OK\r\nContent-Length: 10\r\n\r\n0123456789");
+        assert!(matches!(
+            client.get_blob(&digest),
+            Err(Error::BlobTooLarge { size: 10, limit: 4 })
+        ));
+        // Refused while reading without Content-Length
+        transport.respond(
+            "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\na\r\n0123456789\r\n0\r\n\r\n",
+        );
+        assert!(matches!(
+            client.get_blob(&digest),
+            Err(Error::BlobTooLarge { limit: 4, .. })
+        ));
+
+        // Configurations are limited as manifests
+        transport.respond("HTTP/1.1 200 OK\r\nContent-Length: 10\r\n\r\n0123456789");
+        assert_eq!(client.get_config_blob(&digest)?, b"0123456789");
+        client.set_max_manifest_size(Some(4));
+        transport.respond("HTTP/1.1 200 OK\r\nContent-Length: 10\r\n\r\n0123456789");
+        assert!(matches!(
+            client.get_manifest(&Reference::new("tag1")?),
+            Err(Error::BlobTooLarge { size: 10, limit: 4 })
+        ));
+
+        client.set_max_blob_size(None);
+        transport.respond("HTTP/1.1 200 OK\r\nContent-Length: 10\r\n\r\n0123456789");
+        assert_eq!(client.get_blob(&digest)?, b"0123456789");
+        Ok(())
+    }
+
+    #[test]
+    fn get_blob_truncated() -> Result<()> {
+        let (mut client, transport) = mock_client()?;
+        let digest = Digest::from_buf_sha256(b"0123456789");
+        transport.respond("HTTP/1.1 200 OK\r\nContent-Length: 10\r\n\r\n01234");
+        assert!(client.get_blob(&digest).is_err());
+        transport.respond("HTTP/1.1 200 OK\r\nContent-Length: 10\r\n\r\n0123456789");
+        assert_eq!(client.get_blob(&digest)?, b"0123456789");
+        Ok(())
+    }
+
+    #[test]
+    fn blob_digest_mismatch() -> Result<()> {
+        let (mut client, transport) = mock_client()?;
+        let digest = Digest::from_buf_sha256(b"0123456789");
+        let tampered = "HTTP/1.1 200 OK\r\nContent-Length: 10\r\n\r\n9876543210";
+        transport.respond(tampered);
+        assert!(matches!(
+            client.get_blob(&digest),
+            Err(Error::DigestMismatch { .. })
+        ));
+        transport.respond(tampered);
+        assert!(matches!(
+            client.get_config_blob(&digest),
+            Err(Error::DigestMismatch { .. })
+        ));
+
+        // Streamed blob fails at the end
+        transport.respond(tampered);
+        let mut buf = Vec::new();
+        let err = client.get_blob_reader(&digest)?.read_to_end(&mut buf);
+        assert!(matches!(
+            err.map_err(Error::from),
+            Err(Error::DigestMismatch { .. })
+        ));
+        transport.respond("HTTP/1.1 200 OK\r\nContent-Length: 10\r\n\r\n0123456789");
+        let mut buf = Vec::new();
+        client.get_blob_reader(&digest)?.read_to_end(&mut buf)?;
+        assert_eq!(buf, b"0123456789");
+        Ok(())
+    }
 
-        let digest = Digest::from_buf_sha256(blob);
-        let mut req = self
-            .put(&url)
-            .query("digest", &digest.to_string())
-            .set("Content-Length", &blob.len().to_string())
-            .set("Content-Type", "application/octet-stream");
-        if let Some(token) = self.token.as_ref() {
-            // Authorization must be done while the first POST
-            req = req.set("Authorization", &format!("Bearer {}", token))
-        }
-        let res = req.send_bytes(blob)?;
-        let loc = res
-            .header("Location")
-            .expect("Location header is lacked in OCI registry response");
-        Ok(Url::parse(loc).or_else(|_| self.url.join(loc))?)
+    /// Progress callback cancelling `cancel` when a transfer is started
+    fn cancel_on_start(cancel: &CancellationToken) -> Progress {
+        let cancel = cancel.clone();
+        Arc::new(move |event| {
+            if matches!(event, TransferEvent::Started { .. }) {
+                cancel.cancel();
+            }
+        })
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    #[test]
+    fn cancelled_requests() -> Result<()> {
+        let (mut client, transport) = mock_client()?;
+        let cancel = CancellationToken::new();
+        client.set_cancellation_token(cancel.clone());
+        client.set_progress(cancel_on_start(&cancel));
 
-    //
-    // Following tests need registry server. See test/fixture.sh for setting.
-    // These tests are ignored by default.
-    //
+        // Aborted while reading the body
+        let digest = Digest::from_buf_sha256(b"0123456789");
+        transport.respond("HTTP/1.1 200 OK\r\nContent-Length: 10\r\n\r\n0123456789");
+        assert!(matches!(client.get_blob(&digest), Err(Error::Cancelled)));
 
-    fn test_url() -> Url {
-        Url::parse("http://localhost:5000").unwrap()
+        // Not sent at all
+        assert!(matches!(client.get_tags(None), Err(Error::Cancelled)));
+        assert_eq!(transport.requests().len(), 1);
+        Ok(())
     }
-    fn test_name() -> Name {
-        Name::new("test_repo").unwrap()
+
+    #[test]
+    fn cancelled_push_deletes_session() -> Result<()> {
+        let (mut client, transport) = mock_client()?;
+        let cancel = CancellationToken::new();
+        client.set_cancellation_token(cancel.clone());
+        client.set_progress(cancel_on_start(&cancel));
+        transport.respond(
+            "HTTP/1.1 202 Accepted\r\nLocation: /v2/test_repo/blobs/uploads/uuid\r\nContent-Length: 0\r\n\r\n",
+        );
+        transport.respond("HTTP/1.1 204 No Content\r\nContent-Length: 0\r\n\r\n");
+        assert!(matches!(client.push_blob(b"data"), Err(Error::Cancelled)));
+        assert!(transport.finished());
+
+        let requests = transport.requests();
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests[1].method, "DELETE");
+        assert_eq!(requests[1].url.path(), "/v2/test_repo/blobs/uploads/uuid");
+        Ok(())
+    }
+
+    #[test]
+    fn schema1_manifest() -> Result<()> {
+        let (mut client, transport) = mock_client()?;
+        let body = serde_json::json!({
+            "schemaVersion": 1,
+            "name": "test_repo",
+            "tag": "old",
+            "fsLayers": [{ "blobSum": Digest::from_buf_sha256(b"").to_string() }],
+            "history": [{ "v1Compatibility": "{}" }],
+        })
+        .to_string();
+        transport.respond(&format!(
+            "HTTP/1.1 200 OK\r\nContent-Type: application/vnd.docker.distribution.manifest.v1+prettyjws\r\nContent-Length: {}\r\n\r\n{}",
+            body.len(),
+            body
+        ));
+        // Served without the media type
+        transport.respond(&format!(
+            "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+            body.len(),
+            body
+        ));
+        let reference = Reference::new("old")?;
+        assert!(matches!(
+            client.get_manifest(&reference),
+            Err(Error::UnsupportedSchema1 { reference, .. }) if reference == "old"
+        ));
+        assert!(matches!(
+            client.get_raw_manifest(&reference),
+            Err(Error::UnsupportedSchema1 { .. })
+        ));
+        Ok(())
     }
 
+    //
+    // Tests below run against [TestRegistry] serving `test_repo` as test/fixture.sh.
+    //
+
     #[test]
-    #[ignore]
     fn get_tags() -> Result<()> {
-        let mut client = Client::new(test_url(), test_name())?;
-        let mut tags = client.get_tags()?;
+        let registry = TestRegistry::with_fixture()?;
+        let mut client = registry.client("test_repo")?;
+        let mut tags = client.get_tags(None)?;
         tags.sort_unstable();
         assert_eq!(
             tags,
@@ -220,9 +3152,294 @@ mod tests {
     }
 
     #[test]
-    #[ignore]
+    fn get_image_created_at() -> Result<()> {
+        let registry = TestRegistry::with_fixture()?;
+        let mut client = registry.client("test_repo")?;
+        // Neither the annotation nor the config has the date time
+        assert_eq!(client.get_image_created_at(&Reference::new("tag1")?)?, None);
+
+        // From the image configuration
+        let platform = Platform::from_cfg_macro();
+        let config = ImageConfigurationBuilder::default()
+            .os(platform.os().clone())
+            .architecture(platform.architecture().clone())
+            .created("2024-01-02T03:04:05Z")
+            .build()?;
+        let mut buf = Vec::new();
+        config.to_writer(&mut buf)?;
+        client.push_blob(&buf)?;
+        let mut manifest = ImageManifestBuilder::default()
+            .schema_version(SCHEMA_VERSION)
+            .media_type(MediaType::ImageManifest)
+            .config(Descriptor::new(
+                MediaType::ImageConfig,
+                buf.len() as i64,
+                Digest::from_buf_sha256(&buf).to_string(),
+            ))
+            .layers(Vec::new())
+            .build()?;
+        let config_created = Reference::new("config-created")?;
+        client.push_manifest(&config_created, &manifest)?;
+        assert_eq!(
+            client.get_image_created_at(&config_created)?,
+            Some(parse_date_time("2024-01-02T03:04:05Z")?)
+        );
+
+        // The annotation is preferred
+        manifest.set_annotations(Some(HashMap::from([(
+            "org.opencontainers.image.created".to_string(),
+            "2025-06-07T08:09:10+09:00".to_string(),
+        )])));
+        let annotated = Reference::new("annotated")?;
+        client.push_manifest(&annotated, &manifest)?;
+        assert_eq!(
+            client.get_image_created_at(&annotated)?,
+            Some(parse_date_time("2025-06-06T23:09:10Z")?)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn layer_sizes() -> Result<()> {
+        let registry = TestRegistry::with_fixture()?;
+        let mut client = registry.client("test_repo")?;
+        let tag = Reference::new("tag1")?;
+        let manifest = client.get_manifest(&tag)?;
+        let sizes = client.get_layer_sizes(&tag)?;
+        assert_eq!(sizes.len(), manifest.layer_count());
+        assert_eq!(sizes[0], manifest.layers()[0].size() as u64);
+        assert_eq!(
+            client.get_total_layer_size(&tag)?,
+            sizes.iter().sum::<u64>()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn tags_iter() -> Result<()> {
+        let registry = TestRegistry::with_fixture()?;
+        let mut client = registry.client("test_repo")?;
+        let tags = client.tags_iter(2, None).collect::<Result<Vec<_>>>()?;
+        assert_eq!(
+            tags,
+            &["tag1".to_string(), "tag2".to_string(), "tag3".to_string()]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn next_link() -> Result<()> {
+        let request = test_url().join("v2/test_repo/tags/list?n=10")?;
+        let next = |link: &str| super::next_link(&test_url(), &request, link);
+        assert_eq!(
+            next("</v2/test_repo/tags/list?n=2&last=b>; rel=\"next\"")?,
+            Some(test_url().join("v2/test_repo/tags/list?n=2&last=b")?)
+        );
+        assert_eq!(
+            next("<list?last=b>; rel=prev, <list?last=d>; rel=\"next\"")?,
+            Some(test_url().join("v2/test_repo/tags/list?last=d")?)
+        );
+        assert_eq!(next("<list?last=b>; rel=\"prev\"")?, None);
+        Ok(())
+    }
+
+    #[test]
+    fn location_typed() -> Result<()> {
+        let request = test_url().join("v2/test_repo/blobs/uploads/")?;
+        let res: ureq::Response =
+            "HTTP/1.1 201 Created\r\nLocation: /v2/test_repo/blobs/sha256:abc\r\n\r\n".parse()?;
+        let blob = test_url().join("v2/test_repo/blobs/sha256:abc")?;
+        let (result, url) = super::location_typed(&test_url(), &request, &res, blob.clone())?;
+        assert!(result.fully_stored);
+        assert_eq!(url, test_url().join("/v2/test_repo/blobs/sha256:abc")?);
+
+        let res: ureq::Response =
+            "HTTP/1.1 202 Accepted\r\nLocation: http://localhost:5000/v2/test_repo/blobs/uploads/uuid\r\n\r\n"
+                .parse()?;
+        let (result, _url) = super::location_typed(&test_url(), &request, &res, blob)?;
+        assert!(!result.fully_stored);
+        Ok(())
+    }
+
+    #[test]
+    fn location_under_path_prefix() -> Result<()> {
+        let base = Url::parse("https://gateway.test/registry/")?;
+        let request = base.join("v2/test_repo/blobs/uploads/")?;
+        let location = |loc: &str| -> Result<String> {
+            let res: ureq::Response =
+                format!("HTTP/1.1 202 Accepted\r\nLocation: {}\r\n\r\n", loc).parse()?;
+            Ok(super::location(&base, &request, &res)?.to_string())
+        };
+        // Registry not knowing the prefix
+        assert_eq!(
+            location("/v2/test_repo/blobs/uploads/uuid?state=1")?,
+            "https://gateway.test/registry/v2/test_repo/blobs/uploads/uuid?state=1"
+        );
+        // Rewritten by the gateway
+        assert_eq!(
+            location("/registry/v2/test_repo/blobs/uploads/uuid")?,
+            "https://gateway.test/registry/v2/test_repo/blobs/uploads/uuid"
+        );
+        // Relative to the request
+        assert_eq!(
+            location("uuid")?,
+            "https://gateway.test/registry/v2/test_repo/blobs/uploads/uuid"
+        );
+        assert_eq!(
+            location("https://storage.test/upload/uuid")?,
+            "https://storage.test/upload/uuid"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn base_url_with_path() -> Result<()> {
+        for (base, expected) in [
+            (
+                "http://registry.test",
+                "http://registry.test/v2/test_repo/tags/list",
+            ),
+            (
+                "http://registry.test/registry",
+                "http://registry.test/registry/v2/test_repo/tags/list",
+            ),
+            (
+                "http://registry.test/registry/",
+                "http://registry.test/registry/v2/test_repo/tags/list",
+            ),
+            (
+                "http://registry.test:5000/a/b",
+                "http://registry.test:5000/a/b/v2/test_repo/tags/list",
+            ),
+        ] {
+            let transport = transport::ScriptedTransport::new();
+            let mut client = Client::new(Url::parse(base)?, test_name())?;
+            client.set_transport(transport.clone());
+            transport.respond(&tags_response(&["v1"]));
+            assert_eq!(client.get_tags(None)?, ["v1"]);
+            assert_eq!(transport.requests()[0].url.as_str(), expected);
+        }
+
+        // Blob push through the gateway
+        let transport = transport::ScriptedTransport::new();
+        let mut client = Client::new(
+            Url::parse("https://gateway.test:8443/registry")?,
+            test_name(),
+        )?;
+        client.set_transport(transport.clone());
+        let blob = b"blob";
+        let digest = Digest::from_buf_sha256(blob);
+        transport.respond(
+            "HTTP/1.1 202 Accepted\r\nLocation: /v2/test_repo/blobs/uploads/uuid\r\nContent-Length: 0\r\n\r\n",
+        );
+        transport.respond(&format!(
+            "HTTP/1.1 201 Created\r\nLocation: /v2/test_repo/blobs/{}\r\nContent-Length: 0\r\n\r\n",
+            digest
+        ));
+        let url = client.push_blob(blob)?;
+        assert_eq!(
+            url.as_str(),
+            format!(
+                "https://gateway.test:8443/registry/v2/test_repo/blobs/{}",
+                digest
+            )
+        );
+        let requests = transport.requests();
+        assert_eq!(
+            requests[0].url.as_str(),
+            "https://gateway.test:8443/registry/v2/test_repo/blobs/uploads/"
+        );
+        assert_eq!(
+            requests[1].url.path(),
+            "/registry/v2/test_repo/blobs/uploads/uuid"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn tag_list_digests() -> Result<()> {
+        let page: TagListPage = serde_json::from_str(
+            r#"{
+              "name": "test_repo",
+              "tags": ["tag1", "tag2", "tag3"],
+              "manifest": {
+                "sha256:e692418e4cbaf90ca69d05a66403747baa33ee08806650b51fab815ad7fc331f": { "tag": ["tag1", "tag2"] },
+                "sha256:5b0bcabd1ed22e9fb1310cf6c2dec7cdef19f0ad69efa1f392e94a4333501270": { "tag": [] }
+              }
+            }"#,
+        )?;
+        let digests = page.digests();
+        assert_eq!(digests.len(), 2);
+        assert_eq!(digests["tag1"], digests["tag2"]);
+        assert!(!digests.contains_key("tag3"));
+        Ok(())
+    }
+
+    #[test]
+    fn get_manifest_config_digest() -> Result<()> {
+        let registry = TestRegistry::with_fixture()?;
+        let mut client = registry.client("test_repo")?;
+        let reference = Reference::new("tag1")?;
+        let digest = client.get_manifest_config_digest(&reference)?;
+        assert_eq!(digest, client.get_manifest(&reference)?.config_digest()?);
+        assert_eq!(Digest::from_buf_sha256(&client.get_blob(&digest)?), digest);
+        Ok(())
+    }
+
+    #[test]
+    fn list_blobs() -> Result<()> {
+        let registry = TestRegistry::with_fixture()?;
+        let mut client = registry.client("test_repo")?;
+        let blobs = client.list_blobs()?;
+        let manifest = client.get_manifest(&Reference::new("tag1")?)?;
+        assert!(blobs.contains(&manifest.config_digest()?));
+        for digest in manifest.layer_digests()? {
+            assert!(blobs.contains(&digest));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn health_check() -> Result<()> {
+        let registry = TestRegistry::with_fixture()?;
+        let mut client = registry.client("test_repo")?;
+        let result = client.health_check()?;
+        assert!(result.ok(), "{:?}", result);
+        assert!(result.error.is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn get_image_platform() -> Result<()> {
+        let registry = TestRegistry::with_fixture()?;
+        let mut client = registry.client("test_repo")?;
+        let reference = Reference::new("tag1")?;
+        let platform = client.get_image_platform(&reference)?;
+        let (os, arch) = client.get_image_os_and_arch(&reference)?;
+        assert_eq!(platform.os().to_string(), os);
+        assert_eq!(platform.architecture().to_string(), arch);
+        Ok(())
+    }
+
+    #[test]
+    fn get_tags_with_digests() -> Result<()> {
+        let registry = TestRegistry::with_fixture()?;
+        let mut client = registry.client("test_repo")?;
+        client.set_max_concurrent_requests(2);
+        let tags = client
+            .get_tags_with_digests(2, None)
+            .collect::<Result<Vec<_>>>()?;
+        assert!(tags.len() >= 3);
+        for (tag, digest) in tags {
+            assert_eq!(digest, client.get_manifest_digest(&Reference::new(&tag)?)?);
+        }
+        Ok(())
+    }
+
+    #[test]
     fn get_images() -> Result<()> {
-        let mut client = Client::new(test_url(), test_name())?;
+        let registry = TestRegistry::with_fixture()?;
+        let mut client = registry.client("test_repo")?;
         for tag in ["tag1", "tag2", "tag3"] {
             let manifest = client.get_manifest(&Reference::new(tag)?)?;
             for layer in manifest.layers() {
@@ -234,11 +3451,507 @@ mod tests {
     }
 
     #[test]
-    #[ignore]
+    fn head_manifest() -> Result<()> {
+        let registry = TestRegistry::with_fixture()?;
+        let mut client = registry.client("test_repo")?;
+        let stat = client.head_manifest(&Reference::new("tag1")?)?.unwrap();
+        let stat_by_digest = client
+            .head_manifest(&Reference::new(&stat.digest.to_string())?)?
+            .unwrap();
+        assert_eq!(stat, stat_by_digest);
+        assert!(client
+            .head_manifest(&Reference::new("no_such_tag")?)?
+            .is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn tag_exists() -> Result<()> {
+        let registry = TestRegistry::with_fixture()?;
+        let mut client = registry.client("test_repo")?;
+        assert!(client.tag_exists(&Reference::new("tag1")?)?);
+        assert!(!client.tag_exists(&Reference::new("no_such_tag")?)?);
+        Ok(())
+    }
+
+    #[test]
+    fn raw_manifest_for_current_platform() -> Result<()> {
+        let registry = TestRegistry::with_fixture()?;
+        let mut client = registry.client("test_repo")?;
+        let reference = Reference::new("tag1")?;
+        let (manifest, buf) = client.get_raw_manifest_for_current_platform(&reference)?;
+        assert_eq!(
+            Digest::from_buf_sha256(&buf),
+            client.get_manifest_digest(&reference)?
+        );
+        assert_eq!(manifest, client.get_manifest(&reference)?);
+        Ok(())
+    }
+
+    #[test]
+    fn get_manifest_size() -> Result<()> {
+        let registry = TestRegistry::with_fixture()?;
+        let mut client = registry.client("test_repo")?;
+        let reference = Reference::new("tag1")?;
+        let (_media_type, buf) = client.get_raw_manifest(&reference)?;
+        assert_eq!(client.get_manifest_size(&reference)?, buf.len() as u64);
+        assert!(matches!(
+            client.get_manifest_size(&Reference::new("no_such_tag")?),
+            Err(Error::ManifestNotFound(_))
+        ));
+        Ok(())
+    }
+
+    #[test]
     fn push_blob() -> Result<()> {
-        let mut client = Client::new(test_url(), test_name())?;
+        let registry = TestRegistry::with_fixture()?;
+        let mut client = registry.client("test_repo")?;
         let url = client.push_blob("test string".as_bytes())?;
         dbg!(url);
         Ok(())
     }
+
+    #[test]
+    fn push_blob_batch_from_files() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let mut paths = Vec::new();
+        for i in 0..4 {
+            let path = dir.path().join(format!("blob{}", i));
+            fs::write(&path, format!("batch blob {}", i))?;
+            paths.push(path);
+        }
+        paths.insert(2, dir.path().join("missing"));
+
+        let registry = TestRegistry::with_fixture()?;
+        let mut client = registry.client("test_repo")?;
+        let results = client.push_blob_batch_from_files(paths.clone(), 2)?;
+        assert_eq!(
+            results.iter().map(|(path, _)| path).collect::<Vec<_>>(),
+            paths.iter().collect::<Vec<_>>()
+        );
+        for (path, result) in results {
+            if path.ends_with("missing") {
+                assert!(matches!(result, Err(Error::UnknownIo(_))));
+                continue;
+            }
+            let (digest, _url) = result?;
+            assert_eq!(digest, Digest::from_buf_sha256(&fs::read(&path)?));
+            assert!(client.head_blob(&digest)?.is_some());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn push_blob_batch_from_missing_files() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let paths = vec![dir.path().join("a"), dir.path().join("b")];
+        let mut client = Client::new(test_url(), test_name())?;
+        let results = client.push_blob_batch_from_files(paths.clone(), 2)?;
+        assert_eq!(results.len(), 2);
+        for ((path, result), expected) in results.into_iter().zip(paths) {
+            assert_eq!(path, expected);
+            assert!(matches!(result, Err(Error::UnknownIo(_))));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn get_upload_progress() -> Result<()> {
+        let registry = TestRegistry::with_fixture()?;
+        let mut client = registry.client("test_repo")?;
+        let url = client
+            .url
+            .join(&format!("v2/{}/blobs/uploads/", client.name))?;
+        let res = client.call(client.post(&url))?;
+        let session = location(&client.url, &url, &res)?;
+        let progress = client.get_upload_progress(&session)?;
+        assert_eq!(progress.bytes_received, 0);
+        Ok(())
+    }
+
+    #[test]
+    fn retag() -> Result<()> {
+        let registry = TestRegistry::with_fixture()?;
+        let mut client = registry.client("test_repo")?;
+        let src = Reference::new("tag1")?;
+        let dst = Reference::new("retag")?;
+        let digest = client.retag(&src, &dst, true)?;
+        assert_eq!(client.get_manifest_digest(&dst)?, digest);
+        // Same manifest is not regarded as clobbering
+        client.retag(&src, &dst, false)?;
+        Ok(())
+    }
+
+    #[test]
+    fn get_blobs_metadata() -> Result<()> {
+        let registry = TestRegistry::with_fixture()?;
+        let mut client = registry.client("test_repo")?;
+        client.push_blob("test string".as_bytes())?;
+        let exists = Digest::from_buf_sha256("test string".as_bytes());
+        let absent = Digest::from_buf_sha256("no such blob".as_bytes());
+        let stats = client.get_blobs_metadata(&[exists.clone(), absent.clone()], 4)?;
+        assert_eq!(stats.len(), 2);
+        assert_eq!(stats[&exists].as_ref().unwrap().size, 11);
+        assert!(stats[&absent].is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn push_layer_sequence() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        std::fs::write(dir.path().join("test.txt"), "test string")?;
+        let layer = LayerBuilder::from_dir(dir.path())?;
+        let registry = TestRegistry::with_fixture()?;
+        let mut client = registry.client("test_repo")?;
+        let base = Reference::new("tag1")?;
+        let reference = Reference::new("layer_sequence")?;
+        let base_manifest = client.get_manifest(&base)?;
+        let manifest = client.push_layer_sequence(&base, &layer.blob, &reference)?;
+        assert_eq!(manifest.layers().len(), base_manifest.layers().len() + 1);
+        assert_eq!(client.get_manifest(&reference)?, manifest);
+        let config = client.get_blob(&Digest::new(manifest.config().digest())?)?;
+        let config = ImageConfiguration::from_reader(config.as_slice())?;
+        assert_eq!(
+            config.rootfs().diff_ids().last(),
+            Some(&layer.diff_id.to_string())
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn push_image_with_base() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        std::fs::write(dir.path().join("test.txt"), "with base")?;
+        let layer = LayerBuilder::from_dir(dir.path())?;
+        let registry = TestRegistry::with_fixture()?;
+        let mut client = registry.client("test_repo")?;
+        let base = Reference::new("tag1")?;
+        let reference = Reference::new("with_base")?;
+        let base_manifest = client.get_manifest(&base)?;
+        client.push_image_with_base(
+            &base,
+            &[(&layer.blob, MediaType::ImageLayerGzip)],
+            ImageConfigBuilder::new().env("A", "B"),
+            &reference,
+        )?;
+        let manifest = client.get_manifest(&reference)?;
+        assert_eq!(manifest.layers().len(), base_manifest.layers().len() + 1);
+        let config = client.get_blob(&Digest::new(manifest.config().digest())?)?;
+        let config = ImageConfiguration::from_reader(config.as_slice())?;
+        assert_eq!(
+            config.rootfs().diff_ids().last(),
+            Some(&layer.diff_id.to_string())
+        );
+        assert!(config
+            .config()
+            .as_ref()
+            .and_then(|c| c.env().as_ref())
+            .is_some_and(|env| env.contains(&"A=B".to_string())));
+        Ok(())
+    }
+
+    #[test]
+    fn get_manifests_metadata() -> Result<()> {
+        let registry = TestRegistry::with_fixture()?;
+        let mut client = registry.client("test_repo")?;
+        let exists = Reference::new("tag1")?;
+        let absent = Reference::new("no_such_tag")?;
+        let stats = client.get_manifests_metadata(&[exists.clone(), absent.clone()], 4)?;
+        assert_eq!(stats.len(), 2);
+        assert_eq!(
+            stats[&exists].as_ref().unwrap().digest,
+            client.get_manifest_digest(&exists)?
+        );
+        assert!(stats[&absent].is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn bulk_delete_tags() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        std::fs::write(dir.path().join("test.txt"), "bulk delete")?;
+        let registry = TestRegistry::with_fixture()?;
+        let mut client = registry.client("test_repo")?;
+        let tags = [
+            Reference::new("bulk_delete1")?,
+            Reference::new("bulk_delete2")?,
+            Reference::new("no_such_tag")?,
+        ];
+        client.push_image_from_directory(
+            dir.path(),
+            None,
+            &tags[0],
+            ImageConfiguration::default(),
+        )?;
+        let digest = client.retag(&tags[0], &tags[1], true)?;
+        let report = client.bulk_delete_tags(&tags, 4)?;
+        assert_eq!(report.deleted, vec!["bulk_delete1", "bulk_delete2"]);
+        assert_eq!(report.manifests, vec![digest]);
+        assert_eq!(report.failed.len(), 1);
+        assert_eq!(report.failed[0].0, vec!["no_such_tag"]);
+        assert!(client.head_manifest(&tags[0])?.is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn bulk_delete_tags_failed() -> Result<()> {
+        let (mut client, transport) = mock_client()?;
+        let digest = Digest::from_buf_sha256(b"manifest");
+        let found = format!(
+            "HTTP/1.1 200 OK\r\nDocker-Content-Digest: {}\r\nContent-Length: 0\r\n\r\n",
+            digest
+        );
+        transport.respond(&found);
+        transport.respond(&found);
+        transport.respond("HTTP/1.1 500 Internal Server Error\r\nContent-Length: 0\r\n\r\n");
+        transport.respond(&error_response("405 Method Not Allowed", "UNSUPPORTED"));
+        let tags = [
+            Reference::new("a")?,
+            Reference::new("b")?,
+            Reference::new("c")?,
+        ];
+        // A tag failed to be resolved does not stop the others
+        let report = client.bulk_delete_tags(&tags, 1)?;
+        assert!(transport.finished());
+        assert!(report.deleted.is_empty());
+        assert_eq!(report.failed.len(), 2);
+        assert_eq!(report.failed[0].0, vec!["c"]);
+        assert_eq!(report.failed[0].1.status(), Some(500));
+        // Each tag of the manifest failed to be deleted
+        assert_eq!(report.failed[1].0, vec!["a", "b"]);
+        assert_eq!(report.failed[1].1.status(), Some(405));
+        Ok(())
+    }
+
+    #[test]
+    fn get_manifest_list() -> Result<()> {
+        let registry = TestRegistry::with_fixture()?;
+        let mut client = registry.client("test_repo")?;
+        let stat = client.head_manifest(&Reference::new("tag1")?)?.unwrap();
+        let desc = DescriptorBuilder::default()
+            .media_type(MediaType::ImageManifest)
+            .digest(stat.digest.to_string())
+            .size(stat.size as i64)
+            .platform(Platform::from_cfg_macro())
+            .build()
+            .unwrap();
+        let index = ImageIndexBuilder::default()
+            .schema_version(2_u32)
+            .media_type(MediaType::ImageIndex)
+            .manifests(vec![desc])
+            .build()
+            .unwrap();
+        let reference = Reference::new("manifest_list")?;
+        client.push_index(&reference, &index)?;
+
+        let list = client.get_manifest_list(&reference)?;
+        assert_eq!(list, ManifestListResponse::OciIndex(Box::new(index)));
+        let multi = list.into_multi_arch()?;
+        assert_eq!(
+            multi.find(&Platform::from_cfg_macro()).unwrap().digest,
+            stat.digest
+        );
+        assert!(client.get_manifest_list(&Reference::new("tag1")?).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn get_manifest_by_ref_annotation() -> Result<()> {
+        let registry = TestRegistry::with_fixture()?;
+        let mut client = registry.client("test_repo")?;
+        let named = |client: &mut Client, tag: &str, ref_name: &str| -> Result<Descriptor> {
+            let stat = client.head_manifest(&Reference::new(tag)?)?.unwrap();
+            Ok(DescriptorBuilder::default()
+                .media_type(MediaType::ImageManifest)
+                .digest(stat.digest.to_string())
+                .size(stat.size as i64)
+                .annotations(HashMap::from([(
+                    "org.opencontainers.image.ref.name".to_string(),
+                    ref_name.to_string(),
+                )]))
+                .build()
+                .unwrap())
+        };
+        let index = ImageIndexBuilder::default()
+            .schema_version(2_u32)
+            .media_type(MediaType::ImageIndex)
+            .manifests(vec![
+                named(&mut client, "tag1", "v1-a")?,
+                named(&mut client, "tag2", "v1-b")?,
+            ])
+            .build()
+            .unwrap();
+        let reference = Reference::new("named_index")?;
+        client.push_index(&reference, &index)?;
+
+        assert_eq!(
+            client.get_manifest_by_ref_annotation(&reference, "v1-b")?,
+            client.get_manifest(&Reference::new("tag2")?)?
+        );
+        assert!(matches!(
+            client.get_manifest_by_ref_annotation(&reference, "v1-c"),
+            Err(Error::ManifestNotFound(name)) if name == "v1-c"
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn push_image_from_directory() -> Result<()> {
+        let registry = TestRegistry::with_fixture()?;
+        let mut client = registry.client("test_repo")?;
+
+        // Base image with runtime config and history
+        let base_dir = tempfile::tempdir()?;
+        std::fs::write(base_dir.path().join("base.txt"), "base string")?;
+        let base_config = ImageConfigurationBuilder::default()
+            .config(
+                ConfigBuilder::default()
+                    .entrypoint(vec!["/bin/sh".to_string()])
+                    .build()?,
+            )
+            .history(vec![HistoryBuilder::default()
+                .created_by("base".to_string())
+                .build()?])
+            .build()?;
+        client.push_image_from_directory(
+            base_dir.path(),
+            None,
+            &Reference::new("from_directory_base")?,
+            base_config.clone(),
+        )?;
+
+        let dir = tempfile::tempdir()?;
+        std::fs::write(dir.path().join("test.txt"), "test string")?;
+        let reference = Reference::new("from_directory")?;
+        let base = registry.image_name("test_repo", "from_directory_base")?;
+        client.push_image_from_directory(
+            dir.path(),
+            Some(&base),
+            &reference,
+            ImageConfiguration::default(),
+        )?;
+        let manifest = client.get_manifest(&reference)?;
+        assert_eq!(manifest.layers().len(), 2);
+
+        // The new layer is on the top
+        let top = client.get_blob(&Digest::new(manifest.layers()[1].digest())?)?;
+        let mut ar = tar::Archive::new(flate2::read::GzDecoder::new(top.as_slice()));
+        let mut found = None;
+        for entry in ar.entries()? {
+            let mut entry = entry?;
+            if entry.path()?.file_name() == Some(std::ffi::OsStr::new("test.txt")) {
+                let mut content = String::new();
+                entry.read_to_string(&mut content)?;
+                found = Some(content);
+            }
+        }
+        assert_eq!(found.as_deref(), Some("test string"));
+
+        let config = client.get_config_blob(&Digest::new(manifest.config().digest())?)?;
+        let config = ImageConfiguration::from_reader(config.as_slice())?;
+        assert_eq!(config.rootfs().diff_ids().len(), manifest.layers().len());
+        // Inherited from the base image as they are unset
+        assert_eq!(config.config(), base_config.config());
+        assert_eq!(config.history(), base_config.history());
+        Ok(())
+    }
+
+    #[test]
+    fn get_manifest_by_digest() -> Result<()> {
+        use std::{
+            io::{BufRead, BufReader, Write},
+            net::TcpListener,
+        };
+        let manifest = ImageManifestBuilder::default()
+            .schema_version(SCHEMA_VERSION)
+            .media_type(MediaType::ImageManifest)
+            .config(Descriptor::new(
+                MediaType::EmptyJSON,
+                2,
+                Digest::from_buf_sha256(b"{}").to_string(),
+            ))
+            .layers(Vec::new())
+            .build()?;
+        let mut body = Vec::new();
+        manifest.to_writer(&mut body)?;
+        let digest = Digest::from_buf_sha256(&body);
+
+        // Return the same manifest for any reference
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let port = listener.local_addr()?.port();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut line = String::new();
+                while reader.read_line(&mut line).unwrap() > 2 {
+                    line.clear();
+                }
+                write!(
+                    stream,
+                    "HTTP/1.1 200 OK\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                    MediaType::ImageManifest,
+                    body.len(),
+                )
+                .unwrap();
+                stream.write_all(&body).unwrap();
+            }
+        });
+        let url = Url::parse(&format!("http://localhost:{}", port))?;
+        let mut client = Client::new(url, Name::new("test")?)?;
+        assert_eq!(client.get_manifest(&Reference::new("v1")?)?, manifest);
+        assert_eq!(
+            client.get_manifest(&Reference::new(&digest.to_string())?)?,
+            manifest
+        );
+        let other = Reference::new(&Digest::from_buf_sha256(b"other").to_string())?;
+        assert!(matches!(
+            client.get_raw_manifest(&other),
+            Err(Error::DigestMismatch { .. })
+        ));
+        assert!(matches!(
+            client.get_manifest_for_current_platform(&other),
+            Err(Error::DigestMismatch { .. })
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn auto_reconnect() -> Result<()> {
+        use std::{
+            io::{BufRead, BufReader, Write},
+            net::TcpListener,
+        };
+        // The first two connections are closed without response, and later ones return empty tags
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let port = listener.local_addr()?.port();
+        std::thread::spawn(move || {
+            for (i, stream) in listener.incoming().enumerate() {
+                let mut stream = stream.unwrap();
+                if i < 2 {
+                    continue;
+                }
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut line = String::new();
+                while reader.read_line(&mut line).unwrap() > 2 {
+                    line.clear();
+                }
+                let body = r#"{"name":"test","tags":[]}"#;
+                write!(
+                    stream,
+                    "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    body.len(),
+                    body
+                )
+                .unwrap();
+            }
+        });
+        let url = Url::parse(&format!("http://localhost:{}", port))?;
+        let mut client = Client::new(url, Name::new("test")?)?;
+        assert!(matches!(client.get_tags(None), Err(Error::NetworkError(_))));
+        // The second connection fails again, and then retried
+        client.set_auto_reconnect(true);
+        assert!(client.get_tags(None)?.is_empty());
+        Ok(())
+    }
 }
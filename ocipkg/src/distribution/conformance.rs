@@ -0,0 +1,513 @@
+use super::http_log::{self, HttpExchange};
+use crate::{
+    distribution::{Client, MediaType},
+    error::*,
+    Digest, Reference,
+};
+use oci_spec::image::{Descriptor, ImageManifest, ImageManifestBuilder, SCHEMA_VERSION};
+use std::{
+    collections::HashSet,
+    fmt,
+    panic::{self, AssertUnwindSafe},
+};
+
+/// Category of tests in [run_conformance], following the OCI distribution conformance suite
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ConformanceCategory {
+    Pull,
+    Push,
+    ContentDiscovery,
+    ContentManagement,
+}
+
+impl fmt::Display for ConformanceCategory {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            ConformanceCategory::Pull => "pull",
+            ConformanceCategory::Push => "push",
+            ConformanceCategory::ContentDiscovery => "content-discovery",
+            ConformanceCategory::ContentManagement => "content-management",
+        };
+        f.write_str(name)
+    }
+}
+
+/// Result of a test in [run_conformance]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConformanceOutcome {
+    Pass,
+    /// Failed with the reason
+    Fail(String),
+    /// Not run with the reason, e.g. a test it depends on failed
+    Skip(String),
+}
+
+/// Test run by [run_conformance]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConformanceTest {
+    pub category: ConformanceCategory,
+    pub name: String,
+    pub outcome: ConformanceOutcome,
+    /// The request which caused the failure, or the last one of the failed test
+    pub exchange: Option<HttpExchange>,
+}
+
+/// Options of [run_conformance]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ConformanceOptions {
+    /// Categories not to be run. Tests in other categories depending on them are skipped too.
+    pub skip: Vec<ConformanceCategory>,
+}
+
+/// Result of [run_conformance]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ConformanceReport {
+    pub tests: Vec<ConformanceTest>,
+}
+
+impl ConformanceReport {
+    /// `true` if no test failed
+    pub fn passed(&self) -> bool {
+        !self
+            .tests
+            .iter()
+            .any(|test| matches!(test.outcome, ConformanceOutcome::Fail(_)))
+    }
+}
+
+/// Artifact type of the manifest pushed as a referrer by [run_conformance]
+const REFERRER_ARTIFACT_TYPE: &str = "application/vnd.ocipkg.conformance.referrer";
+
+/// Test the repository of `client` satisfies what ocipkg expects of OCI registries
+///
+/// Tests are run in order of push, pull, content discovery, and content management,
+/// since the content pushed first is used by the others.
+/// The content is tagged `ocipkg-conformance-{uuid}`, and deleted by the content management tests.
+/// It is left in the repository if they are skipped or fail.
+///
+/// Failures of tests are recorded in [ConformanceReport] instead of returned as error.
+pub fn run_conformance(
+    client: &mut Client,
+    options: &ConformanceOptions,
+) -> Result<ConformanceReport> {
+    use ConformanceCategory::*;
+
+    let id = uuid::Uuid::new_v4().simple().to_string();
+    let tag = Reference::new(&format!("ocipkg-conformance-{}", id))?;
+    let blob = format!("ocipkg-conformance-{}", id).into_bytes();
+    let blob_digest = Digest::from_buf_sha256(&blob);
+    let empty = b"{}";
+    let empty_digest = Digest::from_buf_sha256(empty);
+    let config = Descriptor::new(
+        MediaType::EmptyJSON,
+        empty.len() as i64,
+        empty_digest.to_string(),
+    );
+    let manifest = ImageManifestBuilder::default()
+        .schema_version(SCHEMA_VERSION)
+        .media_type(MediaType::ImageManifest)
+        .config(config.clone())
+        .layers(vec![Descriptor::new(
+            MediaType::Other("application/octet-stream".to_string()),
+            blob.len() as i64,
+            blob_digest.to_string(),
+        )])
+        .build()?;
+    let (manifest_buf, manifest_digest) = serialize(&manifest)?;
+    let mut referrer = ImageManifestBuilder::default()
+        .schema_version(SCHEMA_VERSION)
+        .media_type(MediaType::ImageManifest)
+        .artifact_type(MediaType::Other(REFERRER_ARTIFACT_TYPE.to_string()))
+        .config(config)
+        .layers(vec![Descriptor::new(
+            MediaType::EmptyJSON,
+            empty.len() as i64,
+            empty_digest.to_string(),
+        )])
+        .build()?;
+    referrer.set_subject(Some(Descriptor::new(
+        MediaType::ImageManifest,
+        manifest_buf.len() as i64,
+        manifest_digest.to_string(),
+    )));
+    let (referrer_buf, referrer_digest) = serialize(&referrer)?;
+    let manifest_ref = Reference::new(&manifest_digest.to_string())?;
+    let referrer_ref = Reference::new(&referrer_digest.to_string())?;
+    let unknown_digest = Digest::from_buf_sha256(format!("unknown-{}", id).as_bytes());
+
+    let mut suite = Suite {
+        client,
+        options,
+        passed: HashSet::new(),
+        report: ConformanceReport::default(),
+    };
+
+    suite.run(Push, "push blob", &[], |client| {
+        client.push_blob(&blob)?;
+        let stat = client
+            .head_blob(&blob_digest)?
+            .ok_or_else(|| Error::BlobNotFound(blob_digest.clone()))?;
+        ensure(
+            stat.size == blob.len() as u64,
+            format!("size of pushed blob is {}", stat.size),
+        )
+    });
+    suite.run(Push, "push empty config", &[], |client| {
+        client.push_blob(empty)?;
+        Ok(())
+    });
+    suite.run(
+        Push,
+        "push manifest by tag",
+        &["push blob", "push empty config"],
+        |client| {
+            client.push_raw_manifest(&tag, MediaType::ImageManifest, &manifest_buf)?;
+            Ok(())
+        },
+    );
+    suite.run(
+        Push,
+        "push manifest with subject by digest",
+        &["push manifest by tag"],
+        |client| {
+            client.push_raw_manifest(&referrer_ref, MediaType::ImageManifest, &referrer_buf)?;
+            Ok(())
+        },
+    );
+
+    suite.run(
+        Pull,
+        "pull manifest by tag",
+        &["push manifest by tag"],
+        |client| {
+            let (media_type, buf) = client.get_raw_manifest(&tag)?;
+            ensure(
+                media_type == MediaType::ImageManifest,
+                format!("Content-Type is {}", media_type),
+            )?;
+            equal_digest(&manifest_digest, &buf)
+        },
+    );
+    suite.run(
+        Pull,
+        "pull manifest by digest",
+        &["push manifest by tag"],
+        |client| {
+            let (_, buf) = client.get_raw_manifest(&manifest_ref)?;
+            equal_digest(&manifest_digest, &buf)
+        },
+    );
+    suite.run(
+        Pull,
+        "check manifest exists",
+        &["push manifest by tag"],
+        |client| {
+            let stat = client
+                .head_manifest(&tag)?
+                .ok_or_else(|| Error::ManifestNotFound(tag.to_string()))?;
+            ensure(
+                stat.digest == manifest_digest,
+                format!("Docker-Content-Digest is {}", stat.digest),
+            )
+        },
+    );
+    suite.run(Pull, "pull blob", &["push blob"], |client| {
+        let buf = client.get_blob(&blob_digest)?;
+        equal_digest(&blob_digest, &buf)
+    });
+    suite.run(Pull, "pull unknown manifest", &[], |client| {
+        let unknown = Reference::new(&unknown_digest.to_string())?;
+        ensure(
+            client.head_manifest(&unknown)?.is_none(),
+            "HEAD of unknown manifest succeeded",
+        )?;
+        ensure(
+            client.get_raw_manifest(&unknown).is_err(),
+            "GET of unknown manifest succeeded",
+        )
+    });
+    suite.run(Pull, "pull unknown blob", &[], |client| {
+        ensure(
+            client.head_blob(&unknown_digest)?.is_none(),
+            "HEAD of unknown blob succeeded",
+        )
+    });
+
+    suite.run(
+        ContentDiscovery,
+        "list tags",
+        &["push manifest by tag"],
+        |client| {
+            let tags = client.get_tags(None)?;
+            ensure(
+                tags.contains(&tag.to_string()),
+                format!("{} is not listed", tag),
+            )
+        },
+    );
+    suite.run(
+        ContentDiscovery,
+        "list tags by pages",
+        &["push manifest by tag"],
+        |client| {
+            let tags = client.tags_iter(1, None).collect::<Result<Vec<_>>>()?;
+            ensure(
+                tags.contains(&tag.to_string()),
+                format!("{} is not listed", tag),
+            )
+        },
+    );
+    suite.run(
+        ContentDiscovery,
+        "list referrers",
+        &["push manifest with subject by digest"],
+        |client| {
+            let index = client
+                .get_referrers(&manifest_digest, None)?
+                .ok_or_else(|| Failure::Unexpected("referrers API is not supported".into()))?;
+            ensure(
+                index
+                    .manifests()
+                    .iter()
+                    .any(|desc| desc.digest() == &referrer_digest.to_string()),
+                format!("{} is not listed", referrer_digest),
+            )
+        },
+    );
+    suite.run(
+        ContentDiscovery,
+        "list referrers by artifact type",
+        &["list referrers"],
+        |client| {
+            let index = client
+                .get_referrers(&manifest_digest, Some(REFERRER_ARTIFACT_TYPE))?
+                .ok_or_else(|| Failure::Unexpected("referrers API is not supported".into()))?;
+            ensure(
+                index.manifests().iter().all(|desc| {
+                    desc.artifact_type().as_ref().map(|ty| ty.to_string())
+                        == Some(REFERRER_ARTIFACT_TYPE.to_string())
+                }),
+                "referrers of other artifact types are listed",
+            )
+        },
+    );
+
+    suite.run(
+        ContentManagement,
+        "delete referrer manifest",
+        &["push manifest with subject by digest"],
+        |client| {
+            client.delete_manifest(&referrer_ref)?;
+            ensure(
+                client.head_manifest(&referrer_ref)?.is_none(),
+                "manifest exists after deletion",
+            )
+        },
+    );
+    suite.run(
+        ContentManagement,
+        "delete tag",
+        &["push manifest by tag"],
+        |client| {
+            client.delete_manifest(&tag)?;
+            ensure(
+                client.head_manifest(&tag)?.is_none(),
+                "tag exists after deletion",
+            )
+        },
+    );
+    suite.run(
+        ContentManagement,
+        "delete manifest",
+        &["push manifest by tag"],
+        |client| {
+            // The manifest may have been deleted with the tag
+            if client.head_manifest(&manifest_ref)?.is_some() {
+                client.delete_manifest(&manifest_ref)?;
+            }
+            ensure(
+                client.head_manifest(&manifest_ref)?.is_none(),
+                "manifest exists after deletion",
+            )
+        },
+    );
+    suite.run(
+        ContentManagement,
+        "delete blob",
+        &["push blob", "delete manifest"],
+        |client| {
+            client.delete_blob(&blob_digest)?;
+            ensure(
+                client.head_blob(&blob_digest)?.is_none(),
+                "blob exists after deletion",
+            )
+        },
+    );
+
+    Ok(suite.report)
+}
+
+/// Reason of failure of a test
+enum Failure {
+    Error(Error),
+    Unexpected(String),
+}
+
+impl From<Error> for Failure {
+    fn from(e: Error) -> Self {
+        Failure::Error(e)
+    }
+}
+
+fn ensure(condition: bool, message: impl Into<String>) -> std::result::Result<(), Failure> {
+    if condition {
+        Ok(())
+    } else {
+        Err(Failure::Unexpected(message.into()))
+    }
+}
+
+fn equal_digest(expected: &Digest, buf: &[u8]) -> std::result::Result<(), Failure> {
+    let actual = Digest::from_buf_sha256(buf);
+    if &actual != expected {
+        return Err(Error::DigestMismatch {
+            expected: expected.clone(),
+            actual,
+        }
+        .into());
+    }
+    Ok(())
+}
+
+fn serialize(manifest: &ImageManifest) -> Result<(Vec<u8>, Digest)> {
+    let mut buf = Vec::new();
+    manifest.to_writer(&mut buf)?;
+    let digest = Digest::from_buf_sha256(&buf);
+    Ok((buf, digest))
+}
+
+struct Suite<'a> {
+    client: &'a mut Client,
+    options: &'a ConformanceOptions,
+    /// Names of passed tests
+    passed: HashSet<String>,
+    report: ConformanceReport,
+}
+
+impl Suite<'_> {
+    /// Run a test after the `requires` tests passed
+    fn run(
+        &mut self,
+        category: ConformanceCategory,
+        name: &str,
+        requires: &[&str],
+        test: impl FnOnce(&mut Client) -> std::result::Result<(), Failure>,
+    ) {
+        let (outcome, exchange) = if self.options.skip.contains(&category) {
+            (
+                ConformanceOutcome::Skip(format!("{} is skipped", category)),
+                None,
+            )
+        } else if let Some(required) = requires.iter().find(|r| !self.passed.contains(**r)) {
+            (
+                ConformanceOutcome::Skip(format!("requires `{}`", required)),
+                None,
+            )
+        } else {
+            let client = &mut *self.client;
+            // The client panics for some invalid responses, e.g. lacking `Location` header
+            let (result, exchanges) =
+                http_log::capture(|| panic::catch_unwind(AssertUnwindSafe(|| test(client))));
+            let failed = exchanges
+                .iter()
+                .rev()
+                .find(|exchange| exchange.status.is_none_or(|status| status >= 400))
+                .or(exchanges.last())
+                .cloned();
+            match result {
+                Ok(Ok(())) => (ConformanceOutcome::Pass, None),
+                Ok(Err(Failure::Error(e))) => (ConformanceOutcome::Fail(e.to_string()), failed),
+                Ok(Err(Failure::Unexpected(message))) => {
+                    (ConformanceOutcome::Fail(message), failed)
+                }
+                Err(panic) => {
+                    let message = panic
+                        .downcast_ref::<&str>()
+                        .map(|s| s.to_string())
+                        .or_else(|| panic.downcast_ref::<String>().cloned())
+                        .unwrap_or_else(|| "panicked".to_string());
+                    (ConformanceOutcome::Fail(message), failed)
+                }
+            }
+        };
+        if outcome == ConformanceOutcome::Pass {
+            self.passed.insert(name.to_string());
+        }
+        log::info!("Conformance {} {}: {:?}", category, name, outcome);
+        self.report.tests.push(ConformanceTest {
+            category,
+            name: name.to_string(),
+            outcome,
+            exchange,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::distribution::{test_support::fixture_layout, RegistryServer, TestRegistry};
+
+    #[test]
+    fn writable_registry() -> Result<()> {
+        let registry = TestRegistry::start()?;
+        let mut client = registry.client("conformance")?;
+        let report = run_conformance(&mut client, &ConformanceOptions::default())?;
+        for test in &report.tests {
+            assert_eq!(test.outcome, ConformanceOutcome::Pass, "{}", test.name);
+        }
+        assert!(report.passed());
+        // Content is removed by the content management tests
+        assert!(registry.layout().get_index()?.manifests().is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn read_only_registry() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let server = RegistryServer::spawn(fixture_layout(dir.path())?, "127.0.0.1:0")?;
+        let mut client = Client::new(server.url(), crate::Name::new("test_repo")?)?;
+        let options = ConformanceOptions {
+            skip: vec![ConformanceCategory::ContentManagement],
+        };
+        let report = run_conformance(&mut client, &options)?;
+        assert!(!report.passed());
+
+        let outcome = |name: &str| {
+            report
+                .tests
+                .iter()
+                .find(|test| test.name == name)
+                .unwrap()
+                .clone()
+        };
+        let push = outcome("push blob");
+        assert!(matches!(push.outcome, ConformanceOutcome::Fail(_)));
+        let exchange = push.exchange.unwrap();
+        assert_eq!(exchange.method, "POST");
+        assert_eq!(exchange.status, Some(405));
+        assert_eq!(
+            outcome("pull manifest by tag").outcome,
+            ConformanceOutcome::Skip("requires `push manifest by tag`".to_string())
+        );
+        assert_eq!(
+            outcome("pull unknown blob").outcome,
+            ConformanceOutcome::Pass
+        );
+        assert_eq!(
+            outcome("delete tag").outcome,
+            ConformanceOutcome::Skip("content-management is skipped".to_string())
+        );
+        Ok(())
+    }
+}
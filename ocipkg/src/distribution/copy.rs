@@ -0,0 +1,543 @@
+use crate::{
+    distribution::{source::*, CancellationToken, Client, Progress, TransferEvent},
+    error::*,
+    image::{
+        find_manifest_for_platform, is_index_media_type, platform_string, Archive, OciLayout,
+        PlatformEx,
+    },
+    Digest, ImageName, Reference,
+};
+use oci_spec::image::{Descriptor, ImageIndex, ImageManifest, MediaType, Platform};
+use std::{collections::HashMap, fs, path::PathBuf, str::FromStr};
+
+/// Annotation key of `index.json` entries naming the image
+const REF_NAME: &str = "org.opencontainers.image.ref.name";
+
+/// Source or destination of [copy_image]
+///
+/// This is parsed from the transport names of
+/// [containers-transports(5)](https://github.com/containers/image/blob/main/docs/containers-transports.5.md),
+/// and `local:{image name}` for local storage of ocipkg.
+///
+/// ```
+/// use ocipkg::{distribution::Transport, ImageName};
+/// use std::path::PathBuf;
+///
+/// let transport: Transport = "docker://ghcr.io/termoshtt/ocipkg/testing:v1".parse().unwrap();
+/// assert_eq!(
+///     transport,
+///     Transport::Registry(ImageName::parse("ghcr.io/termoshtt/ocipkg/testing:v1").unwrap())
+/// );
+/// let transport: Transport = "oci:./layout:v1".parse().unwrap();
+/// assert_eq!(
+///     transport,
+///     Transport::OciLayout(PathBuf::from("./layout"), Some("v1".to_string()))
+/// );
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Transport {
+    /// Image in OCI registry, `docker://{image name}`
+    Registry(ImageName),
+    /// OCI Image Layout directory, `oci:{path}[:{reference}]`
+    OciLayout(PathBuf, Option<String>),
+    /// oci-archive, `oci-archive:{path}[:{reference}]`
+    OciArchive(PathBuf, Option<String>),
+    /// Local storage of ocipkg, `local:{image name}`. This can be used only as destination.
+    Local(ImageName),
+}
+
+impl FromStr for Transport {
+    type Err = Error;
+    fn from_str(input: &str) -> Result<Self> {
+        let invalid = || Error::UnsupportedTransport(input.to_string());
+        let (transport, rest) = input.split_once(':').ok_or_else(invalid)?;
+        if rest.is_empty() {
+            return Err(invalid());
+        }
+        match transport {
+            "docker" => Ok(Transport::Registry(ImageName::parse(
+                rest.strip_prefix("//").ok_or_else(invalid)?,
+            )?)),
+            "oci" => {
+                let (path, reference) = split_path_reference(rest);
+                Ok(Transport::OciLayout(path, reference))
+            }
+            "oci-archive" => {
+                let (path, reference) = split_path_reference(rest);
+                Ok(Transport::OciArchive(path, reference))
+            }
+            "local" => Ok(Transport::Local(ImageName::parse(rest)?)),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+impl std::fmt::Display for Transport {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Transport::Registry(name) => write!(f, "docker://{}", name),
+            Transport::OciLayout(path, None) => write!(f, "oci:{}", path.display()),
+            Transport::OciLayout(path, Some(r)) => write!(f, "oci:{}:{}", path.display(), r),
+            Transport::OciArchive(path, None) => write!(f, "oci-archive:{}", path.display()),
+            Transport::OciArchive(path, Some(r)) => {
+                write!(f, "oci-archive:{}:{}", path.display(), r)
+            }
+            Transport::Local(name) => write!(f, "local:{}", name),
+        }
+    }
+}
+
+/// Split `{path}[:{reference}]`, where the path may contain `:`, e.g. a drive letter on Windows
+fn split_path_reference(input: &str) -> (PathBuf, Option<String>) {
+    match input.rsplit_once(':') {
+        Some((path, reference))
+            if !path.is_empty() && !reference.is_empty() && !reference.contains(['/', '\\']) =>
+        {
+            (PathBuf::from(path), Some(reference.to_string()))
+        }
+        _ => (PathBuf::from(input), None),
+    }
+}
+
+/// Options of [copy_image]
+#[derive(Clone, Default)]
+pub struct CopyOptions {
+    /// Copy the image index and manifests for all platforms.
+    ///
+    /// Otherwise, only the manifest for `platform` is copied from image index.
+    pub all_platforms: bool,
+    /// Platform chosen from image index, the current platform is used if `None`
+    pub platform: Option<Platform>,
+    /// Username and password for the source registry
+    pub src_credentials: Option<(String, String)>,
+    /// Username and password for the destination registry
+    pub dest_credentials: Option<(String, String)>,
+    /// Callback for transfer of blobs
+    pub progress: Option<Progress>,
+    /// Abort the copy with [Error::Cancelled] once cancelled
+    pub cancel: Option<CancellationToken>,
+}
+
+/// Copy an image between registries, OCI Image Layout directories, oci-archives, and local storage
+///
+/// Manifests and image indices are copied as they are stored in the source,
+/// and then their digests are kept. Blobs already existing in the destination are skipped.
+/// Returns the digest of the copied manifest or image index.
+pub fn copy_image(src: &Transport, dest: &Transport, options: &CopyOptions) -> Result<Digest> {
+    let mut reader = Reader::open(src, options)?;
+    let mut writer = Writer::open(dest, options)?;
+    if !writer.is_registry() {
+        if let Reader::Registry(client, _) = &mut reader {
+            if let Some(progress) = &options.progress {
+                client.set_progress(progress.clone());
+            }
+        }
+    }
+
+    let (media_type, buf) = reader.root()?;
+    let digest = if !is_index_media_type(&media_type) {
+        copy_manifest(&mut reader, &mut writer, options, &media_type, &buf, true)?
+    } else if options.all_platforms {
+        let index = ImageIndex::from_reader(buf.as_slice())?;
+        for desc in index.manifests() {
+            let child = reader.manifest(desc)?;
+            copy_manifest(
+                &mut reader,
+                &mut writer,
+                options,
+                desc.media_type(),
+                &child,
+                false,
+            )?;
+        }
+        writer.put_manifest(&media_type, &buf, true)?
+    } else {
+        let index = ImageIndex::from_reader(buf.as_slice())?;
+        let platform = options
+            .platform
+            .clone()
+            .unwrap_or_else(Platform::from_cfg_macro);
+        let desc = find_manifest_for_platform(&index, &platform)
+            .ok_or_else(|| Error::MissingPlatform(platform_string(&platform)))?;
+        let child = reader.manifest(desc)?;
+        copy_manifest(
+            &mut reader,
+            &mut writer,
+            options,
+            desc.media_type(),
+            &child,
+            true,
+        )?
+    };
+    writer.finish()?;
+    Ok(digest)
+}
+
+fn copy_manifest(
+    reader: &mut Reader,
+    writer: &mut Writer,
+    options: &CopyOptions,
+    media_type: &MediaType,
+    buf: &[u8],
+    tagged: bool,
+) -> Result<Digest> {
+    let manifest = ImageManifest::from_reader(buf)?;
+    // Clients report the transfer by themselves
+    let progress = options
+        .progress
+        .as_ref()
+        .filter(|_| !reader.is_registry() && !writer.is_registry());
+    let report = |event: TransferEvent| {
+        if let Some(progress) = progress {
+            progress(&event);
+        }
+    };
+    for desc in std::iter::once(manifest.config()).chain(manifest.layers()) {
+        if options.cancel.as_ref().is_some_and(|c| c.is_cancelled()) {
+            return Err(Error::Cancelled);
+        }
+        let digest = Digest::new(desc.digest())?;
+        if let Some(size) = writer.has_blob(&digest)? {
+            if let Some(progress) = &options.progress {
+                progress(&TransferEvent::Skipped { digest, size });
+            }
+            continue;
+        }
+        report(TransferEvent::Started {
+            digest: digest.clone(),
+            size: Some(desc.size() as u64),
+        });
+        let blob = reader.blob(&digest)?;
+        writer.put_blob(&blob)?;
+        report(TransferEvent::Progress {
+            digest: digest.clone(),
+            transferred: blob.len() as u64,
+        });
+        report(TransferEvent::Finished { digest });
+    }
+    writer.put_manifest(media_type, buf, tagged)
+}
+
+fn verify(digest: &Digest, buf: &[u8]) -> Result<()> {
+    if digest.algorithm == "sha256" {
+        let actual = Digest::from_buf_sha256(buf);
+        if &actual != digest {
+            return Err(Error::DigestMismatch {
+                expected: digest.clone(),
+                actual,
+            });
+        }
+    }
+    Ok(())
+}
+
+enum Reader {
+    Registry(Box<Client>, Reference),
+    OciLayout(OciLayout, Option<String>),
+    OciArchive(fs::File, Option<String>),
+}
+
+impl Reader {
+    fn is_registry(&self) -> bool {
+        matches!(self, Reader::Registry(..))
+    }
+
+    fn open(src: &Transport, options: &CopyOptions) -> Result<Self> {
+        Ok(match src {
+            Transport::Registry(name) => {
+                let mut client = Client::for_pull(name)?;
+                if let Some((username, password)) = &options.src_credentials {
+                    client.set_credentials(username, password);
+                }
+                if let Some(cancel) = &options.cancel {
+                    client.set_cancellation_token(cancel.clone());
+                }
+                Reader::Registry(Box::new(client), name.reference.clone())
+            }
+            Transport::OciLayout(path, selector) => {
+                Reader::OciLayout(OciLayout::open(path)?, selector.clone())
+            }
+            Transport::OciArchive(path, selector) => {
+                Reader::OciArchive(open_archive(path)?, selector.clone())
+            }
+            Transport::Local(_) => return Err(Error::UnsupportedTransport(src.to_string())),
+        })
+    }
+
+    /// Manifest or image index to be copied with its media type
+    fn root(&mut self) -> Result<(MediaType, Vec<u8>)> {
+        let (index, selector) = match self {
+            Reader::Registry(client, reference) => return client.get_raw_manifest(reference),
+            Reader::OciLayout(layout, selector) => (layout.index()?, selector.clone()),
+            Reader::OciArchive(f, selector) => (Archive::new(f).index()?, selector.clone()),
+        };
+        let desc = select_descriptor(&index, selector.as_deref())?;
+        Ok((desc.media_type().clone(), self.manifest(desc)?))
+    }
+
+    fn manifest(&mut self, desc: &Descriptor) -> Result<Vec<u8>> {
+        let digest = Digest::new(desc.digest())?;
+        match self {
+            Reader::Registry(client, _) => {
+                let (_media_type, buf) =
+                    client.get_raw_manifest(&Reference::new(desc.digest())?)?;
+                verify(&digest, &buf)?;
+                Ok(buf)
+            }
+            _ => self.blob(&digest),
+        }
+    }
+
+    fn blob(&mut self, digest: &Digest) -> Result<Vec<u8>> {
+        match self {
+            Reader::Registry(client, _) => {
+                let buf = client.get_blob(digest)?;
+                verify(digest, &buf)?;
+                Ok(buf)
+            }
+            Reader::OciLayout(layout, _) => layout.verified_blob(digest),
+            Reader::OciArchive(f, _) => Archive::new(f).verified_blob(digest),
+        }
+    }
+}
+
+enum Writer {
+    Registry(Box<Client>, Reference),
+    OciLayout(OciLayout, Option<String>),
+    /// Layout in a temporary directory, packed into oci-archive at the path on finish
+    OciArchive(OciLayout, Option<String>, PathBuf),
+    /// Layout in a temporary directory, loaded into local storage on finish
+    Local(OciLayout, ImageName),
+}
+
+impl Writer {
+    fn is_registry(&self) -> bool {
+        matches!(self, Writer::Registry(..))
+    }
+
+    fn open(dest: &Transport, options: &CopyOptions) -> Result<Self> {
+        let temp_layout = || {
+            OciLayout::create(
+                &std::env::temp_dir().join(format!("ocipkg-copy-{}", uuid::Uuid::new_v4())),
+            )
+        };
+        Ok(match dest {
+            Transport::Registry(name) => {
+                let mut client = Client::from_image_name(name)?;
+                if let Some((username, password)) = &options.dest_credentials {
+                    client.set_credentials(username, password);
+                }
+                if let Some(progress) = &options.progress {
+                    client.set_progress(progress.clone());
+                }
+                if let Some(cancel) = &options.cancel {
+                    client.set_cancellation_token(cancel.clone());
+                }
+                Writer::Registry(Box::new(client), name.reference.clone())
+            }
+            Transport::OciLayout(path, reference) => {
+                let layout = if path.join("index.json").is_file() {
+                    OciLayout::open(path)?
+                } else {
+                    OciLayout::create(path)?
+                };
+                Writer::OciLayout(layout, reference.clone())
+            }
+            Transport::OciArchive(path, reference) => {
+                Writer::OciArchive(temp_layout()?, reference.clone(), path.clone())
+            }
+            Transport::Local(name) => Writer::Local(temp_layout()?, name.clone()),
+        })
+    }
+
+    fn layout(&self) -> Option<&OciLayout> {
+        match self {
+            Writer::Registry(..) => None,
+            Writer::OciLayout(layout, _)
+            | Writer::OciArchive(layout, _, _)
+            | Writer::Local(layout, _) => Some(layout),
+        }
+    }
+
+    /// Size of the blob if it exists in destination
+    fn has_blob(&mut self, digest: &Digest) -> Result<Option<u64>> {
+        match self {
+            Writer::Registry(client, _) => Ok(client.head_blob(digest)?.map(|stat| stat.size)),
+            _ => {
+                let path = self.layout().unwrap().blob_path(digest);
+                Ok(fs::metadata(path).ok().map(|m| m.len()))
+            }
+        }
+    }
+
+    fn put_blob(&mut self, buf: &[u8]) -> Result<()> {
+        match self {
+            Writer::Registry(client, _) => {
+                client.push_blob(buf)?;
+            }
+            _ => {
+                self.layout().unwrap().put_blob(buf)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Put manifest or image index, where `tagged` is set for the one named by destination
+    fn put_manifest(&mut self, media_type: &MediaType, buf: &[u8], tagged: bool) -> Result<Digest> {
+        let digest = Digest::from_buf_sha256(buf);
+        let ref_name = match self {
+            Writer::Registry(client, reference) => {
+                let reference = if tagged {
+                    reference.clone()
+                } else {
+                    Reference::new(&digest.to_string())?
+                };
+                client.push_raw_manifest(&reference, media_type, buf)?;
+                return Ok(digest);
+            }
+            Writer::OciLayout(_, reference) | Writer::OciArchive(_, reference, _) => {
+                reference.clone()
+            }
+            Writer::Local(_, name) => Some(name.to_string()),
+        };
+        let layout = self.layout().unwrap();
+        layout.put_blob(buf)?;
+        if tagged {
+            let mut desc =
+                Descriptor::new(media_type.clone(), buf.len() as i64, digest.to_string());
+            let mut index = layout.get_index()?;
+            let mut manifests = index.manifests().clone();
+            if let Some(ref_name) = ref_name {
+                manifests.retain(|d| {
+                    d.annotations().as_ref().and_then(|a| a.get(REF_NAME)) != Some(&ref_name)
+                });
+                desc.set_annotations(Some(HashMap::from([(REF_NAME.to_string(), ref_name)])));
+            }
+            manifests.push(desc);
+            index.set_manifests(manifests);
+            layout.set_index(&index)?;
+        }
+        Ok(digest)
+    }
+
+    fn finish(self) -> Result<()> {
+        let (layout, output) = match self {
+            Writer::Registry(..) | Writer::OciLayout(..) => return Ok(()),
+            Writer::OciArchive(layout, _, path) => (layout, Some(path)),
+            Writer::Local(layout, _) => (layout, None),
+        };
+        let result = match &output {
+            Some(path) => fs::File::create(path)
+                .map_err(Error::from)
+                .and_then(|f| layout.write_archive(f)),
+            None => {
+                let archive = layout.path().with_extension("tar");
+                let result = fs::File::create(&archive)
+                    .map_err(Error::from)
+                    .and_then(|f| layout.write_archive(f))
+                    .and_then(|_| crate::image::load(&archive));
+                let _ = fs::remove_file(&archive);
+                result
+            }
+        };
+        fs::remove_dir_all(layout.path())?;
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::image::Builder;
+
+    #[test]
+    fn parse_transport() -> Result<()> {
+        assert_eq!(
+            "oci-archive:./pkg.tar".parse::<Transport>()?,
+            Transport::OciArchive(PathBuf::from("./pkg.tar"), None)
+        );
+        assert_eq!(
+            "oci:C:\\layout".parse::<Transport>()?,
+            Transport::OciLayout(PathBuf::from("C:\\layout"), None)
+        );
+        assert_eq!(
+            "local:ghcr.io/termoshtt/ocipkg/testing:v1".parse::<Transport>()?,
+            Transport::Local(ImageName::parse("ghcr.io/termoshtt/ocipkg/testing:v1")?)
+        );
+        for input in ["docker:ghcr.io/a/b", "oci:", "unknown:x", "./pkg.tar"] {
+            assert!(input.parse::<Transport>().is_err(), "{}", input);
+        }
+        for input in [
+            "docker://ghcr.io/a/b:v1",
+            "oci:./layout:v1",
+            "local:ghcr.io/a/b:v1",
+        ] {
+            assert_eq!(input.parse::<Transport>()?.to_string(), input);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn copy_archive_to_layout_and_back() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let input = dir.path().join("test.txt");
+        fs::write(&input, "test string")?;
+        let archive = dir.path().join("src.tar");
+        let mut builder = Builder::new(fs::File::create(&archive)?);
+        builder.set_name(&ImageName::parse("ghcr.io/termoshtt/ocipkg/testing:v1")?);
+        builder.append_files(&[&input])?;
+        builder.into_inner()?;
+
+        let options = CopyOptions::default();
+        let layout = dir.path().join("layout");
+        let digest = copy_image(
+            &Transport::OciArchive(archive.clone(), None),
+            &Transport::OciLayout(layout.clone(), Some("v1".to_string())),
+            &options,
+        )?;
+        let layout = OciLayout::open(&layout)?;
+        let index = layout.get_index()?;
+        assert_eq!(index.manifests().len(), 1);
+        assert_eq!(index.manifests()[0].digest(), &digest.to_string());
+
+        let output = dir.path().join("dest.tar");
+        let copied = copy_image(
+            &Transport::OciLayout(layout.path().to_owned(), Some("v1".to_string())),
+            &Transport::OciArchive(output.clone(), None),
+            &options,
+        )?;
+        assert_eq!(copied, digest);
+        let mut f = fs::File::open(output)?;
+        assert_eq!(
+            Archive::new(&mut f).get_index()?.manifests()[0].digest(),
+            &digest.to_string()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn copy_cancelled() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let input = dir.path().join("test.txt");
+        fs::write(&input, "test string")?;
+        let archive = dir.path().join("src.tar");
+        let mut builder = Builder::new(fs::File::create(&archive)?);
+        builder.set_name(&ImageName::parse("ghcr.io/termoshtt/ocipkg/testing:v1")?);
+        builder.append_files(&[&input])?;
+        builder.into_inner()?;
+
+        let cancel = CancellationToken::new();
+        cancel.cancel();
+        let options = CopyOptions {
+            cancel: Some(cancel),
+            ..Default::default()
+        };
+        let result = copy_image(
+            &Transport::OciArchive(archive, None),
+            &Transport::OciLayout(dir.path().join("layout"), None),
+            &options,
+        );
+        assert!(matches!(result, Err(Error::Cancelled)));
+        Ok(())
+    }
+}
@@ -0,0 +1,234 @@
+use crate::{error::*, image::platform_string, Digest};
+use oci_spec::image::{Arch, ImageIndex, MediaType, Os, Platform, PlatformBuilder};
+use serde::{Deserialize, Serialize};
+
+/// Media type of Docker manifest list
+pub const DOCKER_MANIFEST_LIST: &str = "application/vnd.docker.distribution.manifest.list.v2+json";
+
+/// Manifest list defined in [Docker Image Manifest V2, Schema 2](https://distribution.github.io/distribution/spec/manifest-v2-2/#manifest-list)
+///
+/// This is the predecessor of the OCI image index, and still served by many registries.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DockerManifestList {
+    pub schema_version: u32,
+    pub media_type: String,
+    pub manifests: Vec<DockerManifestEntry>,
+}
+
+/// Entry of [DockerManifestList] pointing to the manifest for a platform
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DockerManifestEntry {
+    pub media_type: String,
+    pub size: u64,
+    pub digest: String,
+    pub platform: DockerPlatform,
+}
+
+/// Platform in [DockerManifestEntry]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DockerPlatform {
+    pub architecture: String,
+    pub os: String,
+    #[serde(
+        rename = "os.version",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub os_version: Option<String>,
+    #[serde(rename = "os.features", default, skip_serializing_if = "Vec::is_empty")]
+    pub os_features: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub variant: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub features: Vec<String>,
+}
+
+impl From<&DockerPlatform> for Platform {
+    fn from(p: &DockerPlatform) -> Self {
+        let mut builder = PlatformBuilder::default()
+            .os(Os::from(p.os.as_str()))
+            .architecture(Arch::from(p.architecture.as_str()));
+        if let Some(os_version) = &p.os_version {
+            builder = builder.os_version(os_version);
+        }
+        if !p.os_features.is_empty() {
+            builder = builder.os_features(p.os_features.clone());
+        }
+        if let Some(variant) = &p.variant {
+            builder = builder.variant(variant);
+        }
+        builder.build().unwrap()
+    }
+}
+
+/// Response of [super::Client::get_manifest_list], which depends on the media type the registry returns
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ManifestListResponse {
+    OciIndex(Box<ImageIndex>),
+    DockerList(DockerManifestList),
+}
+
+impl ManifestListResponse {
+    /// Parse the body of manifest response with its `Content-Type`
+    pub fn from_slice(media_type: &MediaType, body: &[u8]) -> Result<Self> {
+        match media_type {
+            MediaType::ImageIndex => Ok(Self::OciIndex(Box::new(serde_json::from_slice(body)?))),
+            MediaType::Other(ty) if ty == DOCKER_MANIFEST_LIST => {
+                Ok(Self::DockerList(serde_json::from_slice(body)?))
+            }
+            _ => Err(Error::NotAManifestList(media_type.to_string())),
+        }
+    }
+
+    pub fn media_type(&self) -> MediaType {
+        match self {
+            Self::OciIndex(_) => MediaType::ImageIndex,
+            Self::DockerList(_) => MediaType::Other(DOCKER_MANIFEST_LIST.to_string()),
+        }
+    }
+
+    /// Convert into the format-independent [MultiArchManifest]
+    pub fn into_multi_arch(self) -> Result<MultiArchManifest> {
+        match self {
+            Self::OciIndex(index) => MultiArchManifest::try_from(index.as_ref()),
+            Self::DockerList(list) => MultiArchManifest::try_from(&list),
+        }
+    }
+}
+
+/// Manifest for a platform listed in [MultiArchManifest]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PlatformManifest {
+    pub media_type: MediaType,
+    pub digest: Digest,
+    pub size: u64,
+    pub platform: Option<Platform>,
+}
+
+/// Common view of OCI image index and Docker manifest list
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MultiArchManifest {
+    /// Media type of the original document
+    pub media_type: MediaType,
+    pub manifests: Vec<PlatformManifest>,
+}
+
+impl MultiArchManifest {
+    /// Platforms in `{os}/{architecture}[/{variant}]` form
+    pub fn platforms(&self) -> Vec<String> {
+        self.manifests
+            .iter()
+            .filter_map(|m| m.platform.as_ref().map(platform_string))
+            .collect()
+    }
+
+    /// Find a manifest for the platform, with the same rule as [crate::image::find_manifest_for_platform]
+    pub fn find(&self, platform: &Platform) -> Option<&PlatformManifest> {
+        self.manifests.iter().find(|m| {
+            let Some(p) = &m.platform else {
+                return false;
+            };
+            let variant_matches = match (p.variant(), platform.variant()) {
+                (Some(a), Some(b)) => a == b,
+                _ => true,
+            };
+            p.os() == platform.os()
+                && p.architecture() == platform.architecture()
+                && variant_matches
+        })
+    }
+}
+
+impl TryFrom<&ImageIndex> for MultiArchManifest {
+    type Error = Error;
+    fn try_from(index: &ImageIndex) -> Result<Self> {
+        let manifests = index
+            .manifests()
+            .iter()
+            .map(|desc| {
+                Ok(PlatformManifest {
+                    media_type: desc.media_type().clone(),
+                    digest: Digest::new(desc.digest())?,
+                    size: desc.size() as u64,
+                    platform: desc.platform().clone(),
+                })
+            })
+            .collect::<Result<_>>()?;
+        Ok(MultiArchManifest {
+            media_type: MediaType::ImageIndex,
+            manifests,
+        })
+    }
+}
+
+impl TryFrom<&DockerManifestList> for MultiArchManifest {
+    type Error = Error;
+    fn try_from(list: &DockerManifestList) -> Result<Self> {
+        let manifests = list
+            .manifests
+            .iter()
+            .map(|entry| {
+                Ok(PlatformManifest {
+                    media_type: MediaType::from(entry.media_type.as_str()),
+                    digest: Digest::new(&entry.digest)?,
+                    size: entry.size,
+                    platform: Some(Platform::from(&entry.platform)),
+                })
+            })
+            .collect::<Result<_>>()?;
+        Ok(MultiArchManifest {
+            media_type: MediaType::Other(DOCKER_MANIFEST_LIST.to_string()),
+            manifests,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DOCKER_LIST: &str = r#"{
+      "schemaVersion": 2,
+      "mediaType": "application/vnd.docker.distribution.manifest.list.v2+json",
+      "manifests": [
+        {
+          "mediaType": "application/vnd.docker.distribution.manifest.v2+json",
+          "size": 7143,
+          "digest": "sha256:e692418e4cbaf90ca69d05a66403747baa33ee08806650b51fab815ad7fc331f",
+          "platform": { "architecture": "ppc64le", "os": "linux" }
+        },
+        {
+          "mediaType": "application/vnd.docker.distribution.manifest.v2+json",
+          "size": 7682,
+          "digest": "sha256:5b0bcabd1ed22e9fb1310cf6c2dec7cdef19f0ad69efa1f392e94a4333501270",
+          "platform": { "architecture": "arm64", "os": "linux", "variant": "v8", "features": ["sse4"] }
+        }
+      ]
+    }"#;
+
+    #[test]
+    fn docker_manifest_list() -> Result<()> {
+        let media_type = MediaType::Other(DOCKER_MANIFEST_LIST.to_string());
+        let res = ManifestListResponse::from_slice(&media_type, DOCKER_LIST.as_bytes())?;
+        let ManifestListResponse::DockerList(list) = &res else {
+            panic!("Docker manifest list is expected");
+        };
+        assert_eq!(list.manifests[1].platform.features, vec!["sse4"]);
+        assert_eq!(res.media_type(), media_type);
+
+        let multi = res.into_multi_arch()?;
+        assert_eq!(multi.platforms(), vec!["linux/ppc64le", "linux/arm64/v8"]);
+        let arm64 = multi
+            .find(&crate::image::parse_platform("linux/arm64")?)
+            .unwrap();
+        assert_eq!(arm64.size, 7682);
+        assert!(multi
+            .find(&crate::image::parse_platform("linux/amd64")?)
+            .is_none());
+
+        assert!(ManifestListResponse::from_slice(&MediaType::ImageManifest, b"{}").is_err());
+        Ok(())
+    }
+}
@@ -1,68 +1,339 @@
 //! Pull and Push images to OCI registry based on [OCI distribution specification](https://github.com/opencontainers/distribution-spec)
 
+mod attach;
+mod attestation;
 mod auth;
+mod cancel;
+mod chunker;
 mod client;
-mod name;
-mod reference;
+mod conformance;
+mod copy;
+mod http_log;
+mod manifest_list;
+mod notation;
+mod operation;
+mod progress;
+mod registry_copy;
+mod server;
+mod sign;
+mod source;
+mod tag_filter;
+mod tag_policy;
+#[cfg(any(test, feature = "test-support"))]
+mod test_support;
+mod transport;
+mod trust_policy;
+mod verify;
 
+pub use crate::{Name, Reference};
+pub use attach::*;
+pub use attestation::*;
 pub use auth::*;
-pub use client::Client;
-pub use name::Name;
+pub use cancel::CancellationToken;
+pub use chunker::BlobChunker;
+pub use client::{
+    BlobStat, BulkDeleteReport, Client, HealthCheckResult, LocationResult, ManifestStat,
+    TagDigestIterator, TagIterator, UploadProgress, DEFAULT_MAX_CONCURRENT_REQUESTS,
+    DEFAULT_MAX_MANIFEST_SIZE,
+};
+pub use conformance::{
+    run_conformance, ConformanceCategory, ConformanceOptions, ConformanceOutcome,
+    ConformanceReport, ConformanceTest,
+};
+pub use copy::{copy_image, CopyOptions, Transport};
+pub use http_log::{HttpExchange, HTTP_LOG_TARGET, REQUEST_ID_HEADER};
+pub use manifest_list::*;
+pub use notation::NOTATION_SIGNATURE_ARTIFACT_TYPE;
 pub use oci_spec::image::MediaType;
-pub use reference::Reference;
+pub use operation::OPERATION_LOG_TARGET;
+pub use progress::{Progress, TransferEvent};
+pub use registry_copy::{cross_registry_copy, CopyReport, CopyResult, RegistryCopyOptions};
+pub use server::{serve, RegistryServer};
+pub use sign::*;
+pub use source::ImageSource;
+pub use tag_filter::{tag_version, TagFilter};
+pub use tag_policy::TagPolicy;
+#[cfg(any(test, feature = "test-support"))]
+pub use test_support::TestRegistry;
+pub use transport::{HttpRequest, HttpTransport};
+pub use trust_policy::*;
+pub use verify::*;
 
-use crate::{error::*, Digest, ImageName};
-use std::{fs, io::Read, path::Path};
+use crate::{config::PullPolicy, error::*, image::is_index_media_type, Digest, ImageName};
+use std::{
+    fs,
+    io::{Read, Seek},
+    path::Path,
+};
 
 /// Push image to registry
-pub fn push_image(path: &Path) -> Result<()> {
+///
+/// If the oci-archive contains an image index, i.e. a multi-platform image,
+/// the manifests for each platform are pushed by their digests, and then the index is pushed.
+///
+/// Returns the names of pushed images with the digests of their manifests.
+pub fn push_image(path: &Path) -> Result<Vec<(ImageName, Digest)>> {
+    push_image_with(path, &TagPolicy::default(), None, None)
+}
+
+/// Push image to registry as [push_image] with reporting the transfer of blobs
+pub fn push_image_with_progress(
+    path: &Path,
+    progress: Progress,
+) -> Result<Vec<(ImageName, Digest)>> {
+    push_image_with(path, &TagPolicy::default(), Some(progress), None)
+}
+
+/// Push image to registry as [push_image] under the [TagPolicy]
+///
+/// All image names in the oci-archive are checked before pushing anything.
+pub fn push_image_with_tag_policy(
+    path: &Path,
+    policy: &TagPolicy,
+) -> Result<Vec<(ImageName, Digest)>> {
+    push_image_with(path, policy, None, None)
+}
+
+/// Push image to registry as [push_image] until `cancel` is cancelled
+///
+/// Returns [Error::Cancelled] once cancelled, and the upload session of the blob in transfer is deleted.
+/// Images pushed before the cancellation are kept in the registry.
+pub fn push_image_with_cancellation(
+    path: &Path,
+    cancel: &CancellationToken,
+) -> Result<Vec<(ImageName, Digest)>> {
+    push_image_with(path, &TagPolicy::default(), None, Some(cancel))
+}
+
+fn push_image_with(
+    path: &Path,
+    policy: &TagPolicy,
+    progress: Option<Progress>,
+    cancel: Option<&CancellationToken>,
+) -> Result<Vec<(ImageName, Digest)>> {
     if !path.is_file() {
         return Err(Error::NotAFile(path.to_owned()));
     }
     let mut f = fs::File::open(path)?;
     let mut ar = crate::image::Archive::new(&mut f);
-    for (image_name, manifest) in ar.get_manifests()? {
+    let index = ar.get_index()?;
+    for desc in index.manifests() {
+        policy.check(&crate::image::get_image_name(desc)?.reference)?;
+    }
+    let mut pushed = Vec::new();
+    for desc in index.manifests() {
+        let image_name = crate::image::get_image_name(desc)?;
         log::info!("Push image: {}", image_name);
-        let mut client = Client::new(image_name.registry_url()?, image_name.name)?;
-        for layer in manifest.layers() {
-            let digest = Digest::new(layer.digest())?;
-            let mut entry = ar.get_blob(&digest)?;
-            let mut buf = Vec::new();
-            entry.read_to_end(&mut buf)?;
-            client.push_blob(&buf)?;
+        let _op = operation::start("push", &image_name);
+        let mut client = Client::from_image_name(&image_name)?;
+        client.set_tag_policy(policy.clone());
+        if let Some(progress) = &progress {
+            client.set_progress(progress.clone());
+        }
+        if let Some(cancel) = cancel {
+            client.set_cancellation_token(cancel.clone());
+        }
+        let digest = Digest::new(desc.digest())?;
+        if is_index_media_type(desc.media_type()) {
+            for manifest_desc in ar.get_image_index(&digest)?.manifests() {
+                let digest = Digest::new(manifest_desc.digest())?;
+                push_manifest_with_blobs(
+                    &mut client,
+                    &mut ar,
+                    &Reference::new(manifest_desc.digest())?,
+                    &digest,
+                )?;
+            }
+            let buf = read_blob(&mut ar, &digest)?;
+            client.push_raw_manifest(&image_name.reference, &MediaType::ImageIndex, &buf)?;
+        } else {
+            push_manifest_with_blobs(&mut client, &mut ar, &image_name.reference, &digest)?;
         }
-        let digest = Digest::new(manifest.config().digest())?;
-        let mut entry = ar.get_blob(&digest)?;
-        let mut buf = Vec::new();
-        entry.read_to_end(&mut buf)?;
+        pushed.push((image_name, digest));
+    }
+    Ok(pushed)
+}
+
+fn push_manifest_with_blobs<R: Read + Seek>(
+    client: &mut Client,
+    ar: &mut crate::image::Archive<R>,
+    reference: &Reference,
+    digest: &Digest,
+) -> Result<()> {
+    let manifest = ar.get_manifest(digest)?;
+    for desc in manifest.layers().iter().chain([manifest.config()]) {
+        let blob = Digest::new(desc.digest())?;
+        if let Some(stat) = client.head_blob(&blob)? {
+            client.report(TransferEvent::Skipped {
+                digest: blob,
+                size: stat.size,
+            });
+            continue;
+        }
+        let buf = read_blob(ar, &blob)?;
         client.push_blob(&buf)?;
-        client.push_manifest(&image_name.reference, &manifest)?;
     }
+    // Push as it is stored in the archive to keep the digest
+    let buf = read_blob(ar, digest)?;
+    client.push_raw_manifest(reference, &MediaType::ImageManifest, &buf)?;
     Ok(())
 }
 
+fn read_blob<R: Read + Seek>(
+    ar: &mut crate::image::Archive<R>,
+    digest: &Digest,
+) -> Result<Vec<u8>> {
+    let mut entry = ar.get_blob(digest)?;
+    let mut buf = Vec::new();
+    entry.read_to_end(&mut buf)?;
+    Ok(buf)
+}
+
 /// Get image from registry and save it into local storage
+///
+/// The image is checked by [TrustPolicy] before anything is stored,
+/// and fetched by the digest whose signature is verified if the applied rule requires signatures.
+/// Images locked in [crate::lockfile::LOCKFILE] are also fetched by the digest after
+/// the tag is checked to be resolved to the locked one.
 pub fn get_image(image_name: &ImageName, overwrite: bool) -> Result<()> {
+    get_image_with(image_name, overwrite, None, None, None)
+}
+
+/// Get image from registry as [get_image] with reporting the transfer of blobs
+pub fn get_image_with_progress(
+    image_name: &ImageName,
+    overwrite: bool,
+    progress: Progress,
+) -> Result<()> {
+    get_image_with(image_name, overwrite, Some(progress), None, None)
+}
+
+/// Get image from registry as [get_image] after verifying it by the policy
+///
+/// The digest of the manifest, or image index, is verified before anything is stored,
+/// and then the image is fetched by the digest. Nothing is changed in local storage,
+/// even with `overwrite`, if the verification fails.
+pub fn get_verified_image(
+    image_name: &ImageName,
+    overwrite: bool,
+    policy: &VerificationPolicy,
+    progress: Option<Progress>,
+) -> Result<()> {
+    get_image_with(image_name, overwrite, progress, Some(policy), None)
+}
+
+/// Get image from registry as [get_image] until `cancel` is cancelled
+///
+/// Returns [Error::Cancelled] once cancelled, and the partially fetched image is removed from local storage.
+pub fn get_image_with_cancellation(
+    image_name: &ImageName,
+    overwrite: bool,
+    cancel: &CancellationToken,
+) -> Result<()> {
+    get_image_with(image_name, overwrite, None, None, Some(cancel))
+}
+
+fn get_image_with(
+    image_name: &ImageName,
+    overwrite: bool,
+    progress: Option<Progress>,
+    policy: Option<&VerificationPolicy>,
+    cancel: Option<&CancellationToken>,
+) -> Result<()> {
+    let config = crate::config::Config::load()?;
+    let lockfile = crate::lockfile::Lockfile::load()?;
+    let locked = lockfile.get(image_name).is_some();
+    // Locked images are pinned by the lockfile
+    if !locked {
+        config.pull_policy.check(image_name)?;
+    }
+    let trust = TrustPolicy::load()?;
+    let rule = trust.rule(image_name);
+    if let Some((pattern, rule)) = rule {
+        rule.check(pattern, image_name)?;
+    }
+    let _lock = crate::local::lock_store(false)?;
     let dest = crate::local::image_dir(image_name)?;
-    if dest.exists() {
-        if overwrite {
-            fs::remove_dir_all(&dest)?;
-        } else {
-            return Err(Error::ImageAlreadyExists(dest));
+    if dest.exists() && !overwrite {
+        return Err(Error::ImageAlreadyExists(dest));
+    }
+
+    let _op = operation::start("pull", image_name);
+    let mut client = Client::for_pull(image_name)?;
+    client.set_max_blob_size(config.max_blob_size);
+    if let Some(progress) = progress {
+        client.set_progress(progress);
+    }
+    if let Some(cancel) = cancel {
+        client.set_cancellation_token(cancel.clone());
+    }
+    let require_signature = rule.is_some_and(|(_, rule)| !rule.require_signature.is_empty());
+    let reference = if locked || policy.is_some() || require_signature {
+        let digest = client.get_manifest_digest(&image_name.reference)?;
+        lockfile.check(image_name, &digest)?;
+        if let Some(policy) = policy {
+            policy.verify(&mut client, &digest)?;
+        }
+        if let Some((_, rule)) = rule {
+            rule.verify_signature(&mut client, &digest)?;
         }
+        // Fetch by digest to get exactly what is verified
+        Reference::new(&digest.to_string())?
+    } else if config.pull_policy == PullPolicy::PreferDigest {
+        let digest = client.get_manifest_digest(&image_name.reference)?;
+        log::info!("Resolved {} to {}", image_name, digest);
+        Reference::new(&digest.to_string())?
+    } else {
+        image_name.reference.clone()
+    };
+
+    if dest.exists() {
+        fs::remove_dir_all(&dest)?;
+    }
+    let actual = config.replace_source(image_name)?;
+    let result = store_image(&mut client, image_name, &actual, &reference, &dest);
+    if result.is_err() && dest.exists() {
+        // Do not leave the partially stored image, e.g. by the cancellation
+        fs::remove_dir_all(&dest)?;
     }
+    result
+}
+
+fn store_image(
+    client: &mut Client,
+    image_name: &ImageName,
+    actual: &ImageName,
+    reference: &Reference,
+    dest: &Path,
+) -> Result<()> {
     let blob_root = dest.join(".blob");
     fs::create_dir_all(&blob_root)?;
 
-    let mut client = Client::from_image_name(image_name)?;
-
     log::info!("Get manifest: {}", image_name);
-    let manifest = client.get_manifest(&image_name.reference)?;
+    // Stored as it is to keep its digest
+    let (manifest, buf) = client.get_raw_manifest_for_current_platform(reference)?;
+    fs::write(dest.join(".manifest.json"), buf)?;
+    // Record provenance since the image may be fetched from a replaced source
+    crate::local::write_image_provenance(
+        image_name,
+        &crate::local::ImageProvenance {
+            requested: image_name.to_string(),
+            actual: actual.to_string(),
+        },
+    )?;
+
+    // Keep the image configuration to check diff_ids by crate::local::verify_image
+    let config = Digest::new(manifest.config().digest())?;
+    fs::create_dir_all(blob_root.join(&config.algorithm))?;
     fs::write(
-        dest.join(".manifest.json"),
-        serde_json::to_string_pretty(&manifest)?,
+        blob_root.join(&config.algorithm).join(&config.encoded),
+        client.get_config_blob(&config)?,
     )?;
 
+    // Refuse too large layers before downloading anything
+    for desc in manifest.layers() {
+        client.check_blob_size(desc.size() as u64)?;
+    }
     for desc in manifest.layers() {
         let digest = Digest::new(desc.digest())?;
         let dest_algorithm = blob_root.join(&digest.algorithm);
@@ -75,24 +346,78 @@ pub fn get_image(image_name: &ImageName, overwrite: bool) -> Result<()> {
         match desc.media_type() {
             MediaType::ImageLayerGzip => {
                 let buf = flate2::read::GzDecoder::new(blob.as_slice());
-                tar::Archive::new(buf).unpack(&dest)?;
+                tar::Archive::new(buf).unpack(dest)?;
             }
             MediaType::ImageLayer => {
                 let buf = blob.as_slice();
-                tar::Archive::new(buf).unpack(&dest)?;
+                tar::Archive::new(buf).unpack(dest)?;
             }
             _ => {}
         }
     }
+    crate::local::touch_image(image_name)?;
 
     Ok(())
 }
 
+/// Extract the layers of image in registry into a directory
+///
+/// The manifest for the current platform is used, and each layer is downloaded
+/// into a temporary file, and applied by [crate::image::Extractor] after its digest is verified.
+pub fn extract_remote_image(
+    image_name: &ImageName,
+    dest: &Path,
+    options: crate::image::ExtractOptions,
+) -> Result<()> {
+    let mut client = Client::for_pull(image_name)?;
+    let manifest = client.get_manifest_for_current_platform(&image_name.reference)?;
+    let mut extractor = crate::image::Extractor::new(dest, options)?;
+    let spool = std::env::temp_dir().join(format!("ocipkg-extract-{}", uuid::Uuid::new_v4()));
+    fs::create_dir_all(&spool)?;
+    let result = extractor.apply_layers(manifest.layers(), |layer| {
+        let digest = Digest::new(layer.digest())?;
+        let path = spool.join(&digest.encoded);
+        let mut blob = client.get_blob_reader(&digest)?;
+        std::io::copy(&mut blob, &mut fs::File::create(&path)?)?;
+        Ok(fs::File::open(path)?)
+    });
+    fs::remove_dir_all(&spool)?;
+    result
+}
+
+/// Write a file in the image in registry to `out`, see [crate::image::read_file]
+///
+/// Layers are streamed from the top, and the layers below the one containing the file are not downloaded.
+pub fn read_remote_image_file(
+    image_name: &ImageName,
+    path: &str,
+    out: &mut impl std::io::Write,
+) -> Result<()> {
+    let mut client = Client::for_pull(image_name)?;
+    let manifest = client.get_manifest_for_current_platform(&image_name.reference)?;
+    crate::image::read_file(
+        manifest.layers(),
+        |layer| client.get_blob_reader(&Digest::new(layer.digest())?),
+        path,
+        out,
+    )
+}
+
+/// List a directory in the image in registry, see [crate::image::list_dir]
+pub fn list_remote_image_dir(image_name: &ImageName, dir: &str) -> Result<Vec<String>> {
+    let mut client = Client::for_pull(image_name)?;
+    let manifest = client.get_manifest_for_current_platform(&image_name.reference)?;
+    crate::image::list_dir(
+        manifest.layers(),
+        |layer| client.get_blob_reader(&Digest::new(layer.digest())?),
+        dir,
+    )
+}
+
 /// Get the data blob of a specific image layer, filtering by media_type.
 pub fn get_layer_bytes(image_name: &ImageName, f: impl Fn(&MediaType) -> bool) -> Result<Vec<u8>> {
-    let registry_url = image_name.registry_url()?;
-    let mut client = Client::new(registry_url, image_name.name.clone())?;
-    let manifest = client.get_manifest(&image_name.reference)?;
+    let mut client = Client::for_pull(image_name)?;
+    let manifest = client.get_manifest_for_current_platform(&image_name.reference)?;
     dbg!(&manifest);
     let layer = manifest
         .layers()
@@ -0,0 +1,377 @@
+use crate::{distribution::Client, error::*, Digest, Reference};
+use base64::{
+    engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD},
+    Engine,
+};
+use webpki::{
+    types::{CertificateDer, SignatureVerificationAlgorithm, UnixTime},
+    EndEntityCert, KeyUsage,
+};
+
+/// Artifact type of Notation signatures attached by the referrers API
+pub const NOTATION_SIGNATURE_ARTIFACT_TYPE: &str = "application/vnd.cncf.notary.signature";
+
+/// Media type of the signature envelope in JWS JSON serialization
+const JWS_ENVELOPE: &str = "application/jose+json";
+
+/// Content type of the payload in the envelope
+const NOTATION_PAYLOAD: &str = "application/vnd.cncf.notary.payload.v1+json";
+
+/// OID 1.3.6.1.5.5.7.3.3 of code signing, required in the extended key usage of signing certificates
+const EKU_CODE_SIGNING: &[u8] = &[0x2b, 0x06, 0x01, 0x05, 0x05, 0x07, 0x03, 0x03];
+
+/// DER of certificates in PEM, e.g. the trusted roots of Notation
+pub(crate) fn parse_certificates(pem: &str) -> Result<Vec<Vec<u8>>> {
+    let mut certs = Vec::new();
+    let mut body: Option<String> = None;
+    for line in pem.lines().map(str::trim) {
+        match (line, &mut body) {
+            ("-----BEGIN CERTIFICATE-----", None) => body = Some(String::new()),
+            ("-----END CERTIFICATE-----", Some(b)) => {
+                let der = STANDARD
+                    .decode(b.as_bytes())
+                    .map_err(|_| Error::InvalidCertificate("not a base64-encoded PEM".into()))?;
+                certs.push(der);
+                body = None;
+            }
+            (line, Some(b)) => b.push_str(line),
+            _ => {}
+        }
+    }
+    if certs.is_empty() || body.is_some() {
+        return Err(Error::InvalidCertificate("no certificate in PEM".into()));
+    }
+    for cert in &certs {
+        webpki::anchor_from_trusted_cert(&CertificateDer::from(cert.as_slice()))
+            .map_err(|e| Error::InvalidCertificate(e.to_string()))?;
+    }
+    Ok(certs)
+}
+
+/// Reference to the Notation signature manifest holding a valid signature for the digest
+///
+/// Signatures are looked up by the referrers API, and then by the referrers tag schema `sha256-<hex>`.
+/// Only JWS envelopes with the `notary.x509` signing scheme are supported.
+pub(crate) fn find_notation_signature(
+    client: &mut Client,
+    digest: &Digest,
+    trusted_roots: &[Vec<u8>],
+) -> Result<Reference> {
+    let index = match client.get_referrers(digest, Some(NOTATION_SIGNATURE_ARTIFACT_TYPE)) {
+        Ok(Some(index)) => Some(index),
+        Ok(None) => None,
+        Err(e) => {
+            log::debug!("Referrers API is not available: {}", e);
+            None
+        }
+    };
+    let index = match index {
+        Some(index) => index,
+        None => {
+            let tag = Reference::new(&format!("{}-{}", digest.algorithm, digest.encoded))?;
+            match client.head_manifest(&tag)? {
+                Some(_) => client.get_index(&tag)?,
+                None => return Err(Error::SignatureMissing(digest.clone())),
+            }
+        }
+    };
+    let signatures: Vec<_> = index
+        .manifests()
+        .iter()
+        .filter(|desc| {
+            desc.artifact_type().as_ref().map(|ty| ty.to_string())
+                == Some(NOTATION_SIGNATURE_ARTIFACT_TYPE.to_string())
+        })
+        .collect();
+    if signatures.is_empty() {
+        return Err(Error::SignatureMissing(digest.clone()));
+    }
+
+    for desc in signatures {
+        let reference = Reference::new(desc.digest())?;
+        let manifest = client.get_manifest(&reference)?;
+        for layer in manifest.layers() {
+            if layer.media_type().to_string() != JWS_ENVELOPE {
+                log::warn!(
+                    "Unsupported Notation signature envelope: {}",
+                    layer.media_type()
+                );
+                continue;
+            }
+            let envelope = client.get_blob(&Digest::new(layer.digest())?)?;
+            if verify_jws(&envelope, digest, trusted_roots) {
+                return Ok(reference);
+            }
+        }
+    }
+    Err(Error::SignatureInvalid(digest.clone()))
+}
+
+#[derive(serde::Deserialize)]
+struct JwsEnvelope {
+    payload: String,
+    protected: String,
+    header: JwsHeader,
+    signature: String,
+}
+
+#[derive(serde::Deserialize)]
+struct JwsHeader {
+    x5c: Vec<String>,
+}
+
+/// Check the certificate chain in the envelope to the trusted roots,
+/// the signature by the leaf certificate, and that the payload points to the digest
+fn verify_jws(envelope: &[u8], digest: &Digest, trusted_roots: &[Vec<u8>]) -> bool {
+    let Ok(envelope) = serde_json::from_slice::<JwsEnvelope>(envelope) else {
+        return false;
+    };
+    let Some(protected) = decode_json(&envelope.protected) else {
+        return false;
+    };
+    if protected["cty"] != NOTATION_PAYLOAD
+        || protected["io.cncf.notary.signingScheme"] != "notary.x509"
+    {
+        return false;
+    }
+    let Some((alg, fixed_ecdsa)) = signature_algorithm(protected["alg"].as_str()) else {
+        return false;
+    };
+
+    // Certificate chain
+    let Ok(chain) = envelope
+        .header
+        .x5c
+        .iter()
+        .map(|cert| STANDARD.decode(cert))
+        .collect::<std::result::Result<Vec<_>, _>>()
+    else {
+        return false;
+    };
+    let Some((leaf, intermediates)) = chain.split_first() else {
+        return false;
+    };
+    let roots: Vec<_> = trusted_roots
+        .iter()
+        .map(|der| CertificateDer::from(der.as_slice()))
+        .collect();
+    let Ok(anchors) = roots
+        .iter()
+        .map(webpki::anchor_from_trusted_cert)
+        .collect::<std::result::Result<Vec<_>, _>>()
+    else {
+        return false;
+    };
+    let leaf = CertificateDer::from(leaf.as_slice());
+    let intermediates: Vec<_> = intermediates
+        .iter()
+        .map(|der| CertificateDer::from(der.as_slice()))
+        .collect();
+    let Ok(leaf) = EndEntityCert::try_from(&leaf) else {
+        return false;
+    };
+    if let Err(e) = leaf.verify_for_usage(
+        webpki::ALL_VERIFICATION_ALGS,
+        &anchors,
+        &intermediates,
+        UnixTime::now(),
+        KeyUsage::required(EKU_CODE_SIGNING),
+        None,
+        None,
+    ) {
+        log::debug!(
+            "Certificate chain of Notation signature is not trusted: {}",
+            e
+        );
+        return false;
+    }
+
+    // Signature
+    let Ok(signature) = URL_SAFE_NO_PAD.decode(&envelope.signature) else {
+        return false;
+    };
+    let signature = if fixed_ecdsa {
+        match ecdsa_der(&signature) {
+            Some(der) => der,
+            None => return false,
+        }
+    } else {
+        signature
+    };
+    let input = format!("{}.{}", envelope.protected, envelope.payload);
+    if leaf
+        .verify_signature(alg, input.as_bytes(), &signature)
+        .is_err()
+    {
+        return false;
+    }
+
+    let Some(payload) = decode_json(&envelope.payload) else {
+        return false;
+    };
+    payload["targetArtifact"]["digest"].as_str() == Some(&digest.to_string())
+}
+
+fn decode_json(input: &str) -> Option<serde_json::Value> {
+    serde_json::from_slice(&URL_SAFE_NO_PAD.decode(input).ok()?).ok()
+}
+
+/// Algorithm for `alg` in JWS, and if its signature is `r || s` of ECDSA in fixed length
+fn signature_algorithm(
+    alg: Option<&str>,
+) -> Option<(&'static dyn SignatureVerificationAlgorithm, bool)> {
+    use webpki::ring::*;
+    Some(match alg? {
+        "ES256" => (ECDSA_P256_SHA256, true),
+        "ES384" => (ECDSA_P384_SHA384, true),
+        "PS256" => (RSA_PSS_2048_8192_SHA256_LEGACY_KEY, false),
+        "PS384" => (RSA_PSS_2048_8192_SHA384_LEGACY_KEY, false),
+        "PS512" => (RSA_PSS_2048_8192_SHA512_LEGACY_KEY, false),
+        _ => return None,
+    })
+}
+
+/// Convert ECDSA signature `r || s` in JWS into ASN.1 DER used in X.509
+fn ecdsa_der(fixed: &[u8]) -> Option<Vec<u8>> {
+    if fixed.is_empty() || !fixed.len().is_multiple_of(2) || fixed.len() > 120 {
+        return None;
+    }
+    let (r, s) = fixed.split_at(fixed.len() / 2);
+    let mut seq = Vec::new();
+    for int in [r, s] {
+        let start = int.iter().position(|&b| b != 0).unwrap_or(int.len() - 1);
+        let int = &int[start..];
+        let pad = int[0] & 0x80 != 0;
+        seq.push(0x02);
+        seq.push((int.len() + pad as usize) as u8);
+        if pad {
+            seq.push(0);
+        }
+        seq.extend_from_slice(int);
+    }
+    let mut der = vec![0x30, seq.len() as u8];
+    der.extend(seq);
+    Some(der)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ring::{
+        rand::SystemRandom,
+        signature::{EcdsaKeyPair, ECDSA_P256_SHA256_FIXED_SIGNING},
+    };
+
+    // Generated by openssl, valid until 2126
+    const ROOT: &str = "\
+-----BEGIN CERTIFICATE-----
+MIIBfTCCASKgAwIBAgIUC8j9SsU1o+sThUVh/aySSi1twjIwCgYIKoZIzj0EAwIw
+GzEZMBcGA1UEAwwQb2NpcGtnIHRlc3Qgcm9vdDAgFw0yNjEwMTUwMzI2MTNaGA8y
+MTI2MDkyMTAzMjYxM1owGzEZMBcGA1UEAwwQb2NpcGtnIHRlc3Qgcm9vdDBZMBMG
+ByqGSM49AgEGCCqGSM49AwEHA0IABJffnMcsC67wgiDJWJ2ny0YPwtV4/MsK/BxB
+uHJqLdcObva0+QUmr+unNZhIQLjgVnD8e8EOYDNQ5GFAvImpf8GjQjBAMA8GA1Ud
+EwEB/wQFMAMBAf8wDgYDVR0PAQH/BAQDAgEGMB0GA1UdDgQWBBSmk6UhzF351r4Y
+9M1HWjmvBemXETAKBggqhkjOPQQDAgNJADBGAiEAqzZQ+o7QffjpuSGyqnubZNUV
+EQY+FDnCkIVKFBmf3P8CIQDsv+X5P6UUXVYHvygK88g0k5sXbYi4IlfvMO6xv/zc
+/w==
+-----END CERTIFICATE-----
+";
+
+    /// Signed by [ROOT] with code signing extended key usage
+    const LEAF: &str = "\
+-----BEGIN CERTIFICATE-----
+MIIBsjCCAVegAwIBAgIUEz2xuFFjPwWRSy4fjNRYHSz1fcUwCgYIKoZIzj0EAwIw
+GzEZMBcGA1UEAwwQb2NpcGtnIHRlc3Qgcm9vdDAgFw0yNjEwMTUwMzI2MTNaGA8y
+MTI2MDkyMTAzMjYxM1owHTEbMBkGA1UEAwwSb2NpcGtnIHRlc3Qgc2lnbmVyMFkw
+EwYHKoZIzj0CAQYIKoZIzj0DAQcDQgAEFInqUfaQW83NnFYBUAxMKgvxcyp3x/CT
+sfDMeV2ahxtock69H+HAyoIssD2+fvgyYENbUkJwA0HGHht08O2htKN1MHMwDAYD
+VR0TAQH/BAIwADAOBgNVHQ8BAf8EBAMCB4AwEwYDVR0lBAwwCgYIKwYBBQUHAwMw
+HwYDVR0jBBgwFoAUppOlIcxd+da+GPTNR1o5rwXplxEwHQYDVR0OBBYEFAPBN1Gl
+1bHdcZUWpP8/e0d6gKsyMAoGCCqGSM49BAMCA0kAMEYCIQC70YuNxaZLRLy6+0+q
+lOu5P2JM+GHGESIHOutMuNO7cgIhALuCgn2C7zeLCMQV5pUmy04X1/xUqJPtWdxR
+ASZtB5Du
+-----END CERTIFICATE-----
+";
+
+    /// PKCS#8 of [LEAF]
+    const LEAF_KEY: &str = "\
+MIGHAgEAMBMGByqGSM49AgEGCCqGSM49AwEHBG0wawIBAQQg9d/OrCEYoeKYcW6X
+UXFHeR7jLY87g/AIEPCAC5esB8GhRANCAAQUiepR9pBbzc2cVgFQDEwqC/FzKnfH
+8JOx8Mx5XZqHG2hyTr0f4cDKgiywPb5++DJgQ1tSQnADQcYeG3Tw7aG0";
+
+    fn envelope(digest: &Digest, chain: &[Vec<u8>]) -> Vec<u8> {
+        let protected = URL_SAFE_NO_PAD.encode(
+            serde_json::json!({
+                "alg": "ES256",
+                "crit": ["io.cncf.notary.signingScheme"],
+                "cty": NOTATION_PAYLOAD,
+                "io.cncf.notary.signingScheme": "notary.x509",
+                "io.cncf.notary.signingTime": "2026-10-15T00:00:00Z"
+            })
+            .to_string(),
+        );
+        let payload = URL_SAFE_NO_PAD.encode(
+            serde_json::json!({
+                "targetArtifact": {
+                    "mediaType": "application/vnd.oci.image.manifest.v1+json",
+                    "digest": digest.to_string(),
+                    "size": 100
+                }
+            })
+            .to_string(),
+        );
+        let rng = SystemRandom::new();
+        let key = EcdsaKeyPair::from_pkcs8(
+            &ECDSA_P256_SHA256_FIXED_SIGNING,
+            &STANDARD.decode(LEAF_KEY.replace('\n', "")).unwrap(),
+            &rng,
+        )
+        .unwrap();
+        let signature = key
+            .sign(&rng, format!("{}.{}", protected, payload).as_bytes())
+            .unwrap();
+        serde_json::json!({
+            "payload": payload,
+            "protected": protected,
+            "header": {
+                "x5c": chain.iter().map(|der| STANDARD.encode(der)).collect::<Vec<_>>(),
+                "io.cncf.notary.signingAgent": "ocipkg test"
+            },
+            "signature": URL_SAFE_NO_PAD.encode(signature.as_ref())
+        })
+        .to_string()
+        .into_bytes()
+    }
+
+    #[test]
+    fn jws() -> Result<()> {
+        let roots = parse_certificates(ROOT)?;
+        let leaf = parse_certificates(LEAF)?;
+        let digest = Digest::from_buf_sha256(b"manifest");
+        let chain = [leaf[0].clone(), roots[0].clone()];
+
+        assert!(verify_jws(&envelope(&digest, &leaf), &digest, &roots));
+        assert!(verify_jws(&envelope(&digest, &chain), &digest, &roots));
+        let other = Digest::from_buf_sha256(b"other");
+        assert!(!verify_jws(&envelope(&digest, &leaf), &other, &roots));
+        // The leaf certificate is not a trusted root
+        assert!(!verify_jws(&envelope(&digest, &leaf), &digest, &leaf));
+        assert!(!verify_jws(&envelope(&digest, &[]), &digest, &roots));
+
+        assert!(matches!(
+            parse_certificates("no certificate"),
+            Err(Error::InvalidCertificate(_))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn ecdsa_signature() {
+        let der = ecdsa_der(&[[0x00, 0x80].as_slice(), &[0x01, 0x02]].concat()).unwrap();
+        assert_eq!(
+            der,
+            [0x30, 0x08, 0x02, 0x02, 0x00, 0x80, 0x02, 0x02, 0x01, 0x02]
+        );
+        assert!(ecdsa_der(&[0x01]).is_none());
+    }
+}
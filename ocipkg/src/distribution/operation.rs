@@ -0,0 +1,184 @@
+use std::{
+    cell::RefCell,
+    fmt,
+    sync::atomic::{AtomicU64, Ordering},
+    time::Instant,
+};
+
+/// Target of logs for operations, e.g. `RUST_LOG=ocipkg::op=debug,ocipkg::http=debug`
+///
+/// An operation, e.g. pull of an image or push of a blob, is logged when it ends,
+/// with the fields recorded while it runs:
+///
+/// ```text
+/// op=pull-blob op_id=3 repository=ghcr.io/termoshtt/ocipkg/testing digest=sha256:... status=200 retries=0 bytes=1024 elapsed=52ms
+/// ```
+///
+/// Every request is sent with a generated `X-Request-Id` header,
+/// and the logs of [super::HTTP_LOG_TARGET] have `op_id` and `request_id` of the request
+/// to be correlated with the operation and the logs of the registry.
+/// Credentials are never recorded.
+///
+/// These are records of the `log` crate, and can be collected by any logger,
+/// e.g. `env_logger`, or `tracing-subscriber` with `tracing_log::LogTracer`.
+pub const OPERATION_LOG_TARGET: &str = "ocipkg::op";
+
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+thread_local! {
+    /// Operations running in the current thread, the innermost last
+    static RUNNING: RefCell<Vec<Fields>> = const { RefCell::new(Vec::new()) };
+}
+
+struct Fields {
+    name: &'static str,
+    id: u64,
+    repository: String,
+    reference: Option<String>,
+    digest: Option<String>,
+    status: Option<u16>,
+    retries: u32,
+    bytes: u64,
+}
+
+impl fmt::Display for Fields {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "op={} op_id={} repository={}",
+            self.name, self.id, self.repository
+        )?;
+        if let Some(reference) = &self.reference {
+            write!(f, " reference={}", reference)?;
+        }
+        if let Some(digest) = &self.digest {
+            write!(f, " digest={}", digest)?;
+        }
+        if let Some(status) = self.status {
+            write!(f, " status={}", status)?;
+        }
+        write!(f, " retries={} bytes={}", self.retries, self.bytes)
+    }
+}
+
+/// Operation running in the current thread until dropped, see [OPERATION_LOG_TARGET]
+pub(crate) struct Operation {
+    start: Instant,
+    /// Dropped in the thread where started
+    _not_send: std::marker::PhantomData<*const ()>,
+}
+
+/// Start an operation `name`, e.g. `pull-blob`, on `repository`
+pub(crate) fn start(name: &'static str, repository: impl fmt::Display) -> Operation {
+    let fields = Fields {
+        name,
+        id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+        repository: repository.to_string(),
+        reference: None,
+        digest: None,
+        status: None,
+        retries: 0,
+        bytes: 0,
+    };
+    RUNNING.with(|running| running.borrow_mut().push(fields));
+    Operation {
+        start: Instant::now(),
+        _not_send: std::marker::PhantomData,
+    }
+}
+
+impl Operation {
+    pub(crate) fn reference(self, reference: impl fmt::Display) -> Self {
+        innermost(|fields| fields.reference = Some(reference.to_string()));
+        self
+    }
+
+    pub(crate) fn digest(self, digest: impl fmt::Display) -> Self {
+        innermost(|fields| fields.digest = Some(digest.to_string()));
+        self
+    }
+}
+
+impl Drop for Operation {
+    fn drop(&mut self) {
+        let Some(fields) = RUNNING.with(|running| running.borrow_mut().pop()) else {
+            return;
+        };
+        log::debug!(
+            target: OPERATION_LOG_TARGET,
+            "{} elapsed={:?}",
+            fields,
+            self.start.elapsed()
+        );
+    }
+}
+
+fn innermost(f: impl FnOnce(&mut Fields)) {
+    RUNNING.with(|running| {
+        if let Some(fields) = running.borrow_mut().last_mut() {
+            f(fields);
+        }
+    });
+}
+
+/// `op=... op_id=...` of the innermost operation, to be attached to logs of requests
+pub(crate) fn current() -> Option<String> {
+    RUNNING.with(|running| {
+        running
+            .borrow()
+            .last()
+            .map(|fields| format!("op={} op_id={}", fields.name, fields.id))
+    })
+}
+
+/// Record the status code of the last response in the innermost operation
+pub(crate) fn record_status(status: u16) {
+    innermost(|fields| fields.status = Some(status));
+}
+
+/// Record a retried request in all running operations
+pub(crate) fn record_retry() {
+    RUNNING.with(|running| {
+        for fields in running.borrow_mut().iter_mut() {
+            fields.retries += 1;
+        }
+    });
+}
+
+/// Record transferred bytes in all running operations
+pub(crate) fn record_bytes(bytes: u64) {
+    RUNNING.with(|running| {
+        for fields in running.borrow_mut().iter_mut() {
+            fields.bytes += bytes;
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fields() -> Option<String> {
+        RUNNING.with(|running| running.borrow().last().map(|f| f.to_string()))
+    }
+
+    #[test]
+    fn nested() {
+        let outer = start("pull", "ghcr.io/a").reference("v1");
+        {
+            let _inner = start("pull-blob", "ghcr.io/a").digest("sha256:00");
+            record_status(200);
+            record_retry();
+            record_bytes(10);
+            let fields = fields().unwrap();
+            assert!(fields.starts_with("op=pull-blob op_id="), "{}", fields);
+            assert!(fields
+                .ends_with("repository=ghcr.io/a digest=sha256:00 status=200 retries=1 bytes=10"));
+        }
+        let fields = fields().unwrap();
+        assert!(fields.ends_with("repository=ghcr.io/a reference=v1 retries=1 bytes=10"));
+        assert!(current().unwrap().starts_with("op=pull op_id="));
+        drop(outer);
+        assert!(current().is_none());
+    }
+}
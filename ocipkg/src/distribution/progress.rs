@@ -0,0 +1,58 @@
+use crate::Digest;
+use std::{
+    io::{self, Read},
+    sync::Arc,
+};
+
+/// Event of blob transfer, reported to the callback set by [crate::distribution::Client::set_progress]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TransferEvent {
+    /// Transfer of the blob is started. `size` is `None` if the registry does not tell it.
+    Started { digest: Digest, size: Option<u64> },
+    /// Bytes transferred so far
+    Progress { digest: Digest, transferred: u64 },
+    /// Transfer of the blob is completed
+    Finished { digest: Digest },
+    /// The blob already exists in the destination, and is not transferred
+    Skipped { digest: Digest, size: u64 },
+}
+
+/// Callback receiving [TransferEvent]
+///
+/// This may be called from several threads, and thus must be `Send + Sync`.
+pub type Progress = Arc<dyn Fn(&TransferEvent) + Send + Sync>;
+
+/// Reader reporting [TransferEvent::Progress] for each read
+pub(crate) struct ProgressReader<'a, R: Read> {
+    inner: R,
+    digest: &'a Digest,
+    transferred: u64,
+    progress: Option<&'a Progress>,
+}
+
+impl<'a, R: Read> ProgressReader<'a, R> {
+    pub(crate) fn new(inner: R, digest: &'a Digest, progress: Option<&'a Progress>) -> Self {
+        Self {
+            inner,
+            digest,
+            transferred: 0,
+            progress,
+        }
+    }
+}
+
+impl<R: Read> Read for ProgressReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        if let Some(progress) = self.progress {
+            if n > 0 {
+                self.transferred += n as u64;
+                progress(&TransferEvent::Progress {
+                    digest: self.digest.clone(),
+                    transferred: self.transferred,
+                });
+            }
+        }
+        Ok(n)
+    }
+}
@@ -0,0 +1,476 @@
+use crate::{
+    distribution::{Client, DEFAULT_MAX_CONCURRENT_REQUESTS},
+    error::*,
+    image::is_index_media_type,
+    Digest, OciContentType, Reference,
+};
+use oci_spec::image::{Descriptor, ImageIndex, ImageManifest, MediaType};
+use std::{
+    collections::HashSet,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+use url::Url;
+
+/// Options of [cross_registry_copy]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RegistryCopyOptions {
+    /// Upper limit of blobs transferred at the same time
+    pub concurrency: usize,
+    /// Skip blobs already existing in the destination
+    pub skip_existing: bool,
+    /// Convert Docker manifests and manifest lists into OCI ones.
+    /// The digests of converted manifests differ from the source.
+    pub convert_to_oci: bool,
+    /// Check the destination has the pushed manifest and all blobs after the copy
+    pub verify_after_push: bool,
+}
+
+impl Default for RegistryCopyOptions {
+    fn default() -> Self {
+        RegistryCopyOptions {
+            concurrency: DEFAULT_MAX_CONCURRENT_REQUESTS,
+            skip_existing: true,
+            convert_to_oci: false,
+            verify_after_push: false,
+        }
+    }
+}
+
+/// Result of [cross_registry_copy]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CopyReport {
+    /// Digest of the manifest or image index pushed as the destination reference
+    pub digest: Digest,
+    /// Total size of transferred blobs, where manifests are not counted
+    pub bytes_transferred: u64,
+    pub blobs_skipped: usize,
+    pub blobs_transferred: usize,
+    pub elapsed: Duration,
+}
+
+/// Result of [Client::copy_image_if_newer]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CopyResult {
+    /// The destination reference already points to the manifest of this digest
+    AlreadyUpToDate(Digest),
+    /// The manifest of digest `from` is copied, and stored at `to` in the destination
+    Copied { from: Digest, to: Url },
+}
+
+impl Client {
+    /// Copy an image by [cross_registry_copy] only if the destination reference differs from the source
+    ///
+    /// The digests of both references are checked by concurrent `HEAD` requests,
+    /// and nothing is transferred if they match, e.g. for periodic syncs of images.
+    pub fn copy_image_if_newer(
+        src: &mut Client,
+        src_ref: &Reference,
+        dst: &mut Client,
+        dst_ref: &Reference,
+    ) -> Result<CopyResult> {
+        let (src_stat, dst_stat) = std::thread::scope(|s| {
+            let dst_stat = s.spawn(|| dst.head_manifest(dst_ref));
+            let src_stat = src.head_manifest(src_ref);
+            (src_stat, dst_stat.join().expect("HEAD request panicked"))
+        });
+        let from = src_stat?
+            .ok_or_else(|| Error::ManifestNotFound(src_ref.to_string()))?
+            .digest;
+        if dst_stat?.is_some_and(|stat| stat.digest == from) {
+            return Ok(CopyResult::AlreadyUpToDate(from));
+        }
+        let report =
+            cross_registry_copy(src, src_ref, dst, dst_ref, &RegistryCopyOptions::default())?;
+        let to = dst.manifest_url(&Reference::new(&report.digest.to_string())?)?;
+        Ok(CopyResult::Copied { from, to })
+    }
+}
+
+/// Copy an image between repositories, usually on different registries
+///
+/// Blobs are transferred from `src` to `dst` by concurrent requests without being stored locally,
+/// and then the manifests for all platforms and the image index are pushed.
+/// Unlike [crate::distribution::copy_image], the clients are configured by the caller,
+/// e.g. with credentials and a progress callback for each registry.
+pub fn cross_registry_copy(
+    src: &mut Client,
+    src_ref: &Reference,
+    dst: &mut Client,
+    dst_ref: &Reference,
+    options: &RegistryCopyOptions,
+) -> Result<CopyReport> {
+    let start = Instant::now();
+    let (media_type, buf) = src.get_raw_manifest(src_ref)?;
+
+    // Manifests to be pushed with their media types, children of image index first
+    let mut manifests = Vec::new();
+    let root = if is_index_media_type(&media_type) {
+        let mut index = ImageIndex::from_reader(buf.as_slice())?;
+        let mut children = Vec::new();
+        for desc in index.manifests() {
+            let (media_type, buf) = src.get_raw_manifest(&Reference::new(desc.digest())?)?;
+            let (media_type, buf) = convert(media_type, buf, options)?;
+            let mut desc = desc.clone();
+            desc.set_media_type(media_type.clone());
+            desc.set_digest(Digest::from_buf_sha256(&buf).to_string());
+            desc.set_size(buf.len() as i64);
+            children.push(desc);
+            manifests.push((media_type, buf));
+        }
+        if options.convert_to_oci {
+            index.set_media_type(Some(MediaType::ImageIndex));
+            index.set_manifests(children);
+            (MediaType::ImageIndex, serde_json::to_vec(&index)?)
+        } else {
+            (media_type, buf)
+        }
+    } else {
+        convert(media_type, buf, options)?
+    };
+
+    let image_manifests = if is_index_media_type(&root.0) {
+        manifests.iter().map(|(_, buf)| buf).collect()
+    } else {
+        vec![&root.1]
+    };
+    let mut blobs = Vec::new();
+    let mut seen = HashSet::new();
+    for buf in image_manifests {
+        let manifest = ImageManifest::from_reader(buf.as_slice())?;
+        for desc in std::iter::once(manifest.config()).chain(manifest.layers()) {
+            if seen.insert(desc.digest().clone()) {
+                blobs.push(desc.clone());
+            }
+        }
+    }
+    // Refuse too large blobs before transferring anything
+    for desc in &blobs {
+        src.check_blob_size(desc.size() as u64)?;
+    }
+
+    let mut report = CopyReport {
+        digest: Digest::from_buf_sha256(&root.1),
+        bytes_transferred: 0,
+        blobs_skipped: 0,
+        blobs_transferred: 0,
+        elapsed: Duration::ZERO,
+    };
+    let mut record = |copied: Option<u64>| match copied {
+        Some(size) => {
+            report.blobs_transferred += 1;
+            report.bytes_transferred += size;
+        }
+        None => report.blobs_skipped += 1,
+    };
+    // The first blob is copied alone to get tokens shared by the clients of workers
+    let mut rest = blobs.iter();
+    if let Some(desc) = rest.next() {
+        record(copy_blob(src, dst, desc, options.skip_existing)?);
+    }
+    let queue = Mutex::new(rest);
+    let copied = Mutex::new(Vec::new());
+    std::thread::scope(|s| {
+        for _ in 0..options.concurrency.max(1) {
+            let (mut src, mut dst) = (src.clone(), dst.clone());
+            let (queue, copied) = (&queue, &copied);
+            s.spawn(move || loop {
+                let Some(desc) = queue.lock().unwrap().next() else {
+                    break;
+                };
+                let result = copy_blob(&mut src, &mut dst, desc, options.skip_existing);
+                let failed = result.is_err();
+                copied.lock().unwrap().push(result);
+                if failed {
+                    break;
+                }
+            });
+        }
+    });
+    for result in copied.into_inner().unwrap() {
+        record(result?);
+    }
+
+    for (media_type, buf) in &manifests {
+        let digest = Digest::from_buf_sha256(buf);
+        dst.push_raw_manifest(&Reference::new(&digest.to_string())?, media_type, buf)?;
+    }
+    dst.push_raw_manifest(dst_ref, &root.0, &root.1)?;
+
+    if options.verify_after_push {
+        for desc in &blobs {
+            let digest = Digest::new(desc.digest())?;
+            match dst.head_blob(&digest)? {
+                Some(stat) if stat.size == desc.size() as u64 => {}
+                _ => return Err(Error::BlobNotFound(digest)),
+            }
+        }
+        let actual = dst
+            .head_manifest(dst_ref)?
+            .ok_or_else(|| Error::ManifestNotFound(dst_ref.to_string()))?
+            .digest;
+        if actual != report.digest {
+            return Err(Error::DigestMismatch {
+                expected: report.digest,
+                actual,
+            });
+        }
+    }
+    report.elapsed = start.elapsed();
+    Ok(report)
+}
+
+/// Copy a blob, and returns its size, or `None` if skipped
+fn copy_blob(
+    src: &mut Client,
+    dst: &mut Client,
+    desc: &Descriptor,
+    skip_existing: bool,
+) -> Result<Option<u64>> {
+    let digest = Digest::new(desc.digest())?;
+    if skip_existing && dst.head_blob(&digest)?.is_some() {
+        return Ok(None);
+    }
+    let blob = src.get_blob(&digest)?;
+    let actual = Digest::from_buf_sha256(&blob);
+    if digest.algorithm == "sha256" && actual != digest {
+        return Err(Error::DigestMismatch {
+            expected: digest,
+            actual,
+        });
+    }
+    dst.push_blob(&blob)?;
+    Ok(Some(blob.len() as u64))
+}
+
+/// Convert Docker manifest into OCI one if requested, and keep others as they are
+fn convert(
+    media_type: MediaType,
+    buf: Vec<u8>,
+    options: &RegistryCopyOptions,
+) -> Result<(MediaType, Vec<u8>)> {
+    if !options.convert_to_oci
+        || OciContentType::from(&media_type) != OciContentType::DockerManifestV2
+    {
+        return Ok((media_type, buf));
+    }
+    let mut manifest = ImageManifest::from_reader(buf.as_slice())?;
+    manifest.set_media_type(Some(MediaType::ImageManifest));
+    let mut config = manifest.config().clone();
+    config.set_media_type(to_oci_media_type(config.media_type()));
+    manifest.set_config(config);
+    for layer in manifest.layers_mut() {
+        layer.set_media_type(to_oci_media_type(layer.media_type()));
+    }
+    Ok((MediaType::ImageManifest, serde_json::to_vec(&manifest)?))
+}
+
+/// OCI media type corresponding to Docker one of config and layers
+fn to_oci_media_type(media_type: &MediaType) -> MediaType {
+    match OciContentType::from(media_type) {
+        OciContentType::DockerImageConfig => MediaType::ImageConfig,
+        OciContentType::DockerLayer => MediaType::ImageLayerGzip,
+        OciContentType::Unknown(ty)
+            if ty == "application/vnd.docker.image.rootfs.foreign.diff.tar.gzip" =>
+        {
+            MediaType::ImageLayerNonDistributableGzip
+        }
+        _ => media_type.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        distribution::{test_support, HttpRequest, HttpTransport, RegistryServer},
+        Name,
+    };
+    use std::{
+        collections::{HashMap, HashSet},
+        io,
+        sync::Arc,
+    };
+
+    /// Destination registry keeping only the digests of pushed blobs,
+    /// and answering the digest of manifest given by the test
+    struct Sink {
+        sizes: HashMap<String, u64>,
+        manifest_digest: Digest,
+        stored: Mutex<HashSet<String>>,
+    }
+
+    impl HttpTransport for Sink {
+        fn send(&self, request: &HttpRequest) -> io::Result<ureq::Response> {
+            let path = request.url.path();
+            let raw = match request.method.as_str() {
+                "HEAD" if path.contains("/blobs/") => {
+                    let digest = path.rsplit('/').next().unwrap();
+                    if self.stored.lock().unwrap().contains(digest) {
+                        format!(
+                            "HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n",
+                            self.sizes[digest]
+                        )
+                    } else {
+                        "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n\r\n".to_string()
+                    }
+                }
+                "HEAD" => format!(
+                    "HTTP/1.1 200 OK\r\nDocker-Content-Digest: {}\r\n\r\n",
+                    self.manifest_digest
+                ),
+                "POST" => "HTTP/1.1 202 Accepted\r\nLocation: /v2/copied/blobs/uploads/uuid\r\nContent-Length: 0\r\n\r\n".to_string(),
+                "PUT" => {
+                    if let Some((_, digest)) =
+                        request.url.query_pairs().find(|(key, _)| key == "digest")
+                    {
+                        self.stored.lock().unwrap().insert(digest.to_string());
+                    }
+                    format!(
+                        "HTTP/1.1 201 Created\r\nLocation: {}\r\nContent-Length: 0\r\n\r\n",
+                        path
+                    )
+                }
+                _ => unreachable!(),
+            };
+            Ok(raw.parse().unwrap())
+        }
+    }
+
+    fn sink_client(sink: &Arc<Sink>) -> Result<Client> {
+        let mut client = Client::new(
+            Url::parse("http://registry.test").unwrap(),
+            Name::new("copied")?,
+        )?;
+        client.set_transport(sink.clone());
+        Ok(client)
+    }
+
+    #[test]
+    fn copy_between_registries() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let server =
+            RegistryServer::spawn(test_support::fixture_layout(dir.path())?, "127.0.0.1:0")?;
+        let mut src = Client::new(server.url(), Name::new("test_repo")?)?;
+        let tag = Reference::new("tag1")?;
+        let manifest = src.get_manifest(&tag)?;
+        let sizes: HashMap<_, _> = std::iter::once(manifest.config())
+            .chain(manifest.layers())
+            .map(|desc| (desc.digest().clone(), desc.size() as u64))
+            .collect();
+        let sink = Arc::new(Sink {
+            sizes: sizes.clone(),
+            manifest_digest: src.get_manifest_digest(&tag)?,
+            stored: Mutex::new(HashSet::new()),
+        });
+        let mut dst = sink_client(&sink)?;
+        let options = RegistryCopyOptions {
+            concurrency: 2,
+            verify_after_push: true,
+            ..Default::default()
+        };
+
+        let report = cross_registry_copy(&mut src, &tag, &mut dst, &tag, &options)?;
+        assert_eq!(report.digest, sink.manifest_digest);
+        assert_eq!(report.blobs_transferred, 2);
+        assert_eq!(report.blobs_skipped, 0);
+        assert_eq!(report.bytes_transferred, sizes.values().sum::<u64>());
+        assert_eq!(sink.stored.lock().unwrap().len(), 2);
+
+        let report = cross_registry_copy(&mut src, &tag, &mut dst, &tag, &options)?;
+        assert_eq!(report.blobs_transferred, 0);
+        assert_eq!(report.blobs_skipped, 2);
+        assert_eq!(report.bytes_transferred, 0);
+
+        // Verification fails if the destination has another manifest
+        let sink = Arc::new(Sink {
+            sizes,
+            manifest_digest: src.get_manifest_digest(&Reference::new("tag2")?)?,
+            stored: Mutex::new(HashSet::new()),
+        });
+        let mut dst = sink_client(&sink)?;
+        assert!(matches!(
+            cross_registry_copy(&mut src, &tag, &mut dst, &tag, &options),
+            Err(Error::DigestMismatch { .. })
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn copy_image_if_newer() -> Result<()> {
+        let registry = test_support::TestRegistry::with_fixture()?;
+        let mut src = registry.client("test_repo")?;
+        let mut dst = registry.client("copied")?;
+        let tag = Reference::new("tag1")?;
+        let digest = src.get_manifest_digest(&tag)?;
+
+        let result = Client::copy_image_if_newer(&mut src, &tag, &mut dst, &tag)?;
+        assert_eq!(
+            result,
+            CopyResult::Copied {
+                from: digest.clone(),
+                to: registry
+                    .url()
+                    .join(&format!("/v2/copied/manifests/{}", digest))?,
+            }
+        );
+        assert_eq!(dst.get_manifest_digest(&tag)?, digest);
+
+        let result = Client::copy_image_if_newer(&mut src, &tag, &mut dst, &tag)?;
+        assert_eq!(result, CopyResult::AlreadyUpToDate(digest));
+        Ok(())
+    }
+
+    #[test]
+    fn convert_docker_manifest() -> Result<()> {
+        let docker = serde_json::json!({
+            "schemaVersion": 2,
+            "mediaType": "application/vnd.docker.distribution.manifest.v2+json",
+            "config": {
+                "mediaType": "application/vnd.docker.container.image.v1+json",
+                "size": 10,
+                "digest": "sha256:00"
+            },
+            "layers": [{
+                "mediaType": "application/vnd.docker.image.rootfs.diff.tar.gzip",
+                "size": 20,
+                "digest": "sha256:11"
+            }, {
+                "mediaType": "application/vnd.docker.image.rootfs.foreign.diff.tar.gzip",
+                "size": 30,
+                "digest": "sha256:22"
+            }]
+        })
+        .to_string()
+        .into_bytes();
+        let media_type = MediaType::from("application/vnd.docker.distribution.manifest.v2+json");
+
+        let options = RegistryCopyOptions::default();
+        let (ty, buf) = convert(media_type.clone(), docker.clone(), &options)?;
+        assert_eq!(ty, media_type);
+        assert_eq!(buf, docker);
+
+        let options = RegistryCopyOptions {
+            convert_to_oci: true,
+            ..Default::default()
+        };
+        let (ty, buf) = convert(media_type, docker, &options)?;
+        assert_eq!(ty, MediaType::ImageManifest);
+        let manifest = ImageManifest::from_reader(buf.as_slice())?;
+        assert_eq!(manifest.media_type(), &Some(MediaType::ImageManifest));
+        assert_eq!(manifest.config().media_type(), &MediaType::ImageConfig);
+        assert_eq!(
+            manifest
+                .layers()
+                .iter()
+                .map(|layer| layer.media_type().clone())
+                .collect::<Vec<_>>(),
+            [
+                MediaType::ImageLayerGzip,
+                MediaType::ImageLayerNonDistributableGzip
+            ]
+        );
+        assert_eq!(manifest.layers()[1].digest(), "sha256:22");
+        Ok(())
+    }
+}
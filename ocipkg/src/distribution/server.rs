@@ -0,0 +1,964 @@
+use crate::{error::*, image::OciLayout, Digest, ImageName, Reference};
+use oci_spec::image::{Descriptor, ImageIndex, ImageIndexBuilder, ImageManifest, MediaType};
+use std::{
+    collections::HashMap,
+    fs,
+    io::{self, BufRead, BufReader, Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    thread::JoinHandle,
+};
+use url::Url;
+
+/// Annotation key of `index.json` entries naming the image
+const REF_NAME: &str = "org.opencontainers.image.ref.name";
+
+/// Registry serving images in an OCI layout directory, e.g. for tests and local sharing
+///
+/// The server spawned by [RegistryServer::spawn] is read-only.
+/// Only `GET` and `HEAD` of the following endpoints are supported,
+/// and other requests are rejected by `405 Method Not Allowed`:
+///
+/// ```text
+/// /v2/
+/// /v2/<name>/tags/list?n=<page_size>&last=<last>
+/// /v2/<name>/manifests/<reference>
+/// /v2/<name>/blobs/<digest>
+/// /v2/<name>/referrers/<digest>?artifactType=<type>
+/// ```
+///
+/// Tags are taken from `org.opencontainers.image.ref.name` annotations in `index.json`,
+/// and `Link` header to the next page is returned if tags remain.
+/// A bare tag, e.g. `v1`, is served in any repository, while a full image name,
+/// e.g. `localhost:5000/test_repo:v1`, is served only in its repository.
+/// Manifests and blobs are served by digest in any repository.
+///
+/// The server spawned by [RegistryServer::spawn_writable] also accepts pushes and deletions
+/// into the layout, see [crate::distribution::TestRegistry].
+/// Blob uploads in progress are kept in memory, and pushed tags are recorded as full image names.
+///
+/// The server runs in a background thread, and stops when dropped.
+///
+/// ```no_run
+/// use ocipkg::{distribution::{Client, RegistryServer}, image::OciLayout, Name};
+/// use std::path::Path;
+///
+/// let layout = OciLayout::open(Path::new("oci-layout-dir"))?;
+/// let server = RegistryServer::spawn(layout, "127.0.0.1:0")?;
+/// let mut client = Client::new(server.url(), Name::new("test_repo")?)?;
+/// let tags = client.get_tags(None)?;
+/// # Ok::<(), ocipkg::error::Error>(())
+/// ```
+#[derive(Debug)]
+pub struct RegistryServer {
+    addr: SocketAddr,
+    shutdown: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl RegistryServer {
+    /// Start serving the layout at `addr`, e.g. `127.0.0.1:0` for a random port
+    pub fn spawn(layout: OciLayout, addr: impl ToSocketAddrs) -> Result<Self> {
+        Self::spawn_state(State::new(layout, false), addr)
+    }
+
+    /// Start serving the layout as [RegistryServer::spawn] with accepting pushes and deletions
+    pub fn spawn_writable(layout: OciLayout, addr: impl ToSocketAddrs) -> Result<Self> {
+        Self::spawn_state(State::new(layout, true), addr)
+    }
+
+    fn spawn_state(state: State, addr: impl ToSocketAddrs) -> Result<Self> {
+        let listener = TcpListener::bind(addr)?;
+        let addr = listener.local_addr()?;
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let handle = {
+            let shutdown = shutdown.clone();
+            std::thread::spawn(move || accept(Arc::new(state), listener, &shutdown))
+        };
+        Ok(RegistryServer {
+            addr,
+            shutdown,
+            handle: Some(handle),
+        })
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// URL of the registry, e.g. `http://127.0.0.1:5000`
+    pub fn url(&self) -> Url {
+        Url::parse(&format!("http://{}", self.addr)).expect("Socket address must be valid URL")
+    }
+}
+
+impl Drop for RegistryServer {
+    fn drop(&mut self) {
+        self.shutdown.store(true, Ordering::SeqCst);
+        // Wake up the thread waiting for a connection
+        let _ = TcpStream::connect(self.addr);
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+/// Serve the layout by the listener in the current thread, used by `ocipkg serve`
+pub fn serve(layout: &OciLayout, listener: TcpListener) -> Result<()> {
+    accept(
+        Arc::new(State::new(layout.clone(), false)),
+        listener,
+        &AtomicBool::new(false),
+    );
+    Ok(())
+}
+
+/// Layout shared by the threads handling connections
+struct State {
+    layout: OciLayout,
+    writable: bool,
+    /// Blobs being uploaded by sessions
+    uploads: Mutex<HashMap<String, Vec<u8>>>,
+    /// Lock for updating `index.json`
+    index: Mutex<()>,
+    /// Upper limit of tags in a page, as registries capping `n`, e.g. GHCR
+    max_page_size: Option<usize>,
+}
+
+impl State {
+    fn new(layout: OciLayout, writable: bool) -> Self {
+        State {
+            layout,
+            writable,
+            uploads: Mutex::new(HashMap::new()),
+            index: Mutex::new(()),
+            max_page_size: None,
+        }
+    }
+}
+
+fn accept(state: Arc<State>, listener: TcpListener, shutdown: &AtomicBool) {
+    for stream in listener.incoming() {
+        if shutdown.load(Ordering::SeqCst) {
+            break;
+        }
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                log::warn!("Failed to accept connection: {}", e);
+                continue;
+            }
+        };
+        let state = state.clone();
+        std::thread::spawn(move || {
+            if let Err(e) = handle(&state, stream) {
+                log::warn!("Failed to handle request: {}", e);
+            }
+        });
+    }
+}
+
+enum Body {
+    Bytes(Vec<u8>),
+    File(fs::File),
+}
+
+struct Response {
+    status: u16,
+    headers: Vec<(&'static str, String)>,
+    size: u64,
+    body: Body,
+}
+
+impl Response {
+    fn json(status: u16, value: serde_json::Value) -> Self {
+        let buf = value.to_string().into_bytes();
+        Response {
+            status,
+            headers: vec![("Content-Type", "application/json".to_string())],
+            size: buf.len() as u64,
+            body: Body::Bytes(buf),
+        }
+    }
+
+    /// Error response defined in OCI distribution spec
+    fn error(status: u16, code: &str, message: &str) -> Self {
+        Self::json(
+            status,
+            serde_json::json!({ "errors": [{ "code": code, "message": message }] }),
+        )
+    }
+
+    /// Response without body, e.g. `201 Created` with `Location` header
+    fn empty(status: u16, headers: Vec<(&'static str, String)>) -> Self {
+        Response {
+            status,
+            headers,
+            size: 0,
+            body: Body::Bytes(Vec::new()),
+        }
+    }
+}
+
+/// Request line, headers in lower case, and body
+struct Request {
+    method: String,
+    url: Url,
+    headers: HashMap<String, String>,
+    body: Vec<u8>,
+}
+
+impl Request {
+    fn read(reader: &mut impl BufRead) -> Result<Option<Self>> {
+        let mut line = String::new();
+        reader.read_line(&mut line)?;
+        let mut request = line.split_whitespace();
+        let (Some(method), Some(target)) = (request.next(), request.next()) else {
+            return Ok(None);
+        };
+        let mut headers = HashMap::new();
+        loop {
+            let mut header = String::new();
+            if reader.read_line(&mut header)? == 0 || header.trim().is_empty() {
+                break;
+            }
+            if let Some((key, value)) = header.split_once(':') {
+                headers.insert(key.trim().to_lowercase(), value.trim().to_string());
+            }
+        }
+        Ok(Some(Request {
+            method: method.to_string(),
+            url: Url::parse("http://localhost")?.join(target)?,
+            headers,
+            body: Vec::new(),
+        }))
+    }
+
+    /// Read the body following the headers
+    ///
+    /// Bodies larger than [MAX_BODY_SIZE] are refused by [io::ErrorKind::InvalidData]
+    /// before being allocated.
+    fn read_body(&mut self, reader: &mut impl BufRead) -> io::Result<()> {
+        if self
+            .headers
+            .get("transfer-encoding")
+            .is_some_and(|te| te.eq_ignore_ascii_case("chunked"))
+        {
+            read_chunked(reader, &mut self.body)?;
+        } else if let Some(len) = self.headers.get("content-length") {
+            let len: usize = len
+                .parse()
+                .map_err(|_| invalid_body("invalid Content-Length"))?;
+            if len > MAX_BODY_SIZE {
+                return Err(invalid_body("Content-Length is too large"));
+            }
+            reader.take(len as u64).read_to_end(&mut self.body)?;
+        }
+        Ok(())
+    }
+
+    fn query(&self, key: &str) -> Option<String> {
+        self.url
+            .query_pairs()
+            .find(|(k, _)| k == key)
+            .map(|(_, value)| value.to_string())
+    }
+}
+
+/// Upper limit of request bodies, to refuse them before allocating
+const MAX_BODY_SIZE: usize = 1 << 30;
+
+fn invalid_body(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// Read body in chunked transfer encoding
+fn read_chunked(reader: &mut impl BufRead, body: &mut Vec<u8>) -> io::Result<()> {
+    loop {
+        let mut line = String::new();
+        reader.read_line(&mut line)?;
+        let size = line.trim().split(';').next().unwrap_or_default();
+        let size =
+            usize::from_str_radix(size, 16).map_err(|_| invalid_body("invalid chunk size"))?;
+        if size == 0 {
+            // Skip trailers
+            loop {
+                line.clear();
+                if reader.read_line(&mut line)? == 0 || line.trim().is_empty() {
+                    return Ok(());
+                }
+            }
+        }
+        let start = body.len();
+        let end = start
+            .checked_add(size)
+            .filter(|end| *end <= MAX_BODY_SIZE)
+            .ok_or_else(|| invalid_body("chunked body is too large"))?;
+        body.resize(end, 0);
+        reader.read_exact(&mut body[start..])?;
+        line.clear();
+        reader.read_line(&mut line)?;
+    }
+}
+
+fn handle(state: &State, mut stream: TcpStream) -> Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let Some(mut request) = Request::read(&mut reader)? else {
+        return Ok(());
+    };
+    let res = match request.method.as_str() {
+        "GET" | "HEAD" => route(state, &request),
+        "DELETE" if state.writable => route_write(state, &request),
+        // Bodies are read only to be written, and never by read-only servers
+        "POST" | "PATCH" | "PUT" if state.writable => match request.read_body(&mut reader) {
+            Ok(()) => route_write(state, &request),
+            Err(e) if e.kind() == io::ErrorKind::InvalidData => {
+                Ok(Response::error(400, "SIZE_INVALID", &e.to_string()))
+            }
+            Err(e) => Err(e.into()),
+        },
+        _ => Ok(Response::error(
+            405,
+            "UNSUPPORTED",
+            "this registry is read-only",
+        )),
+    }
+    .unwrap_or_else(|e| {
+        log::warn!("Failed to serve {}: {}", request.url.path(), e);
+        Response::error(500, "UNKNOWN", &e.to_string())
+    });
+    write!(
+        stream,
+        "HTTP/1.1 {} {}\r\nContent-Length: {}\r\nConnection: close\r\n",
+        res.status,
+        reason(res.status),
+        res.size
+    )?;
+    for (key, value) in &res.headers {
+        write!(stream, "{}: {}\r\n", key, value)?;
+    }
+    write!(stream, "\r\n")?;
+    if request.method != "HEAD" {
+        match res.body {
+            Body::Bytes(buf) => stream.write_all(&buf)?,
+            Body::File(mut f) => {
+                io::copy(&mut f, &mut stream)?;
+            }
+        }
+    }
+    stream.flush()?;
+    Ok(())
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        201 => "Created",
+        202 => "Accepted",
+        204 => "No Content",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        _ => "Internal Server Error",
+    }
+}
+
+fn route(state: &State, request: &Request) -> Result<Response> {
+    let layout = &state.layout;
+    let path = request.url.path();
+    if path == "/v2/" || path == "/v2" {
+        return Ok(Response::json(200, serde_json::json!({})));
+    }
+    let Some(path) = path.strip_prefix("/v2/") else {
+        return Ok(Response::error(404, "NAME_UNKNOWN", "not a /v2/ endpoint"));
+    };
+    if let Some(name) = path.strip_suffix("/tags/list") {
+        let n = request.query("n").and_then(|n| n.parse::<usize>().ok());
+        let n = match (n, state.max_page_size) {
+            (Some(n), Some(max)) => Some(n.min(max)),
+            (n, max) => n.or(max),
+        };
+        return tags_list(layout, name, n, request.query("last").as_deref());
+    }
+    if let Some((name, reference)) = path.rsplit_once("/manifests/") {
+        return manifest(layout, name, reference);
+    }
+    if let Some((name, session)) = path.rsplit_once("/blobs/uploads/") {
+        return upload_status(state, name, session);
+    }
+    if let Some((_name, digest)) = path.rsplit_once("/blobs/") {
+        return blob(layout, digest);
+    }
+    if let Some((_name, digest)) = path.rsplit_once("/referrers/") {
+        return referrers(layout, digest, request.query("artifactType").as_deref());
+    }
+    Ok(Response::error(404, "NAME_UNKNOWN", "unknown endpoint"))
+}
+
+fn route_write(state: &State, request: &Request) -> Result<Response> {
+    let Some(path) = request.url.path().strip_prefix("/v2/") else {
+        return Ok(Response::error(404, "NAME_UNKNOWN", "not a /v2/ endpoint"));
+    };
+    match request.method.as_str() {
+        "POST" => {
+            if let Some(name) = path.strip_suffix("/blobs/uploads/") {
+                return start_upload(state, name, request);
+            }
+        }
+        "PATCH" => {
+            if let Some((name, session)) = path.rsplit_once("/blobs/uploads/") {
+                return append_upload(state, name, session, &request.body);
+            }
+        }
+        "PUT" => {
+            if let Some((name, session)) = path.rsplit_once("/blobs/uploads/") {
+                return finish_upload(state, name, session, request);
+            }
+            if let Some((name, reference)) = path.rsplit_once("/manifests/") {
+                return put_manifest(state, name, reference, request);
+            }
+        }
+        "DELETE" => {
+            if let Some((name, reference)) = path.rsplit_once("/manifests/") {
+                return delete_manifest(state, name, reference);
+            }
+            if let Some((_name, digest)) = path.rsplit_once("/blobs/") {
+                return delete_blob(state, digest);
+            }
+        }
+        _ => {}
+    }
+    Ok(Response::error(404, "NAME_UNKNOWN", "unknown endpoint"))
+}
+
+/// Tag of `org.opencontainers.image.ref.name` in the repository `name`
+fn ref_tag(ref_name: &str, name: &str) -> Option<String> {
+    if !ref_name.contains(['/', ':']) {
+        return Some(ref_name.to_string());
+    }
+    let image_name = ImageName::parse(ref_name).ok()?;
+    (image_name.name.as_str() == name).then(|| image_name.reference.to_string())
+}
+
+fn tags(index: &ImageIndex, name: &str) -> Vec<(String, usize)> {
+    let mut tags: Vec<(String, usize)> = index
+        .manifests()
+        .iter()
+        .enumerate()
+        .filter_map(|(i, desc)| Some((ref_tag(ref_name(desc)?, name)?, i)))
+        .collect();
+    tags.sort();
+    tags.dedup_by(|a, b| a.0 == b.0);
+    tags
+}
+
+fn tags_list(
+    layout: &OciLayout,
+    name: &str,
+    n: Option<usize>,
+    last: Option<&str>,
+) -> Result<Response> {
+    let mut tags: Vec<String> = tags(&layout.get_index()?, name)
+        .into_iter()
+        .map(|(tag, _)| tag)
+        .filter(|tag| last.is_none_or(|last| tag.as_str() > last))
+        .collect();
+    if tags.is_empty() && last.is_none() {
+        return Ok(Response::error(
+            404,
+            "NAME_UNKNOWN",
+            &format!("no image of {} in the layout", name),
+        ));
+    }
+    let mut link = None;
+    if let Some(n) = n {
+        if tags.len() > n {
+            tags.truncate(n);
+            if let Some(last) = tags.last() {
+                link = Some(format!(
+                    "</v2/{}/tags/list?n={}&last={}>; rel=\"next\"",
+                    name, n, last
+                ));
+            }
+        }
+    }
+    let mut response = Response::json(200, serde_json::json!({ "name": name, "tags": tags }));
+    if let Some(link) = link {
+        response.headers.push(("Link", link));
+    }
+    Ok(response)
+}
+
+fn manifest(layout: &OciLayout, name: &str, reference: &str) -> Result<Response> {
+    let not_found = || Response::error(404, "MANIFEST_UNKNOWN", "manifest unknown");
+    let Ok(reference) = Reference::new(reference) else {
+        return Ok(not_found());
+    };
+    let (digest, media_type) = if reference.is_digest() {
+        let digest = Digest::new(reference.as_str())?;
+        if !layout.blob_path(&digest).is_file() {
+            return Ok(not_found());
+        }
+        let value: serde_json::Value = serde_json::from_slice(&layout.get_blob(&digest)?)?;
+        let media_type = value
+            .get("mediaType")
+            .and_then(|ty| ty.as_str())
+            .map(MediaType::from)
+            .unwrap_or(MediaType::ImageManifest);
+        (digest, media_type)
+    } else {
+        let index = layout.get_index()?;
+        let Some((_, i)) = tags(&index, name)
+            .into_iter()
+            .find(|(tag, _)| tag == reference.as_str())
+        else {
+            return Ok(not_found());
+        };
+        let desc = &index.manifests()[i];
+        (Digest::new(desc.digest())?, desc.media_type().clone())
+    };
+    let buf = layout.get_blob(&digest)?;
+    Ok(Response {
+        status: 200,
+        headers: vec![
+            ("Content-Type", media_type.to_string()),
+            ("Docker-Content-Digest", digest.to_string()),
+        ],
+        size: buf.len() as u64,
+        body: Body::Bytes(buf),
+    })
+}
+
+fn blob(layout: &OciLayout, digest: &str) -> Result<Response> {
+    let not_found = || Response::error(404, "BLOB_UNKNOWN", "blob unknown");
+    let Ok(digest) = Digest::new(digest) else {
+        return Ok(not_found());
+    };
+    let path = layout.blob_path(&digest);
+    if !path.is_file() {
+        return Ok(not_found());
+    }
+    let f = fs::File::open(path)?;
+    Ok(Response {
+        status: 200,
+        headers: vec![
+            ("Content-Type", "application/octet-stream".to_string()),
+            ("Docker-Content-Digest", digest.to_string()),
+        ],
+        size: f.metadata()?.len(),
+        body: Body::File(f),
+    })
+}
+
+/// Manifests in the layout referring `digest` as their subject
+fn referrers(layout: &OciLayout, digest: &str, artifact_type: Option<&str>) -> Result<Response> {
+    let Ok(digest) = Digest::new(digest) else {
+        return Ok(Response::error(400, "DIGEST_INVALID", "invalid digest"));
+    };
+    let mut referrers: Vec<Descriptor> = Vec::new();
+    for desc in layout.get_index()?.manifests() {
+        if referrers.iter().any(|r| r.digest() == desc.digest()) {
+            continue;
+        }
+        // Image indexes and broken manifests are not referrers
+        let Ok(manifest) =
+            ImageManifest::from_reader(layout.get_blob(&Digest::new(desc.digest())?)?.as_slice())
+        else {
+            continue;
+        };
+        if manifest
+            .subject()
+            .as_ref()
+            .is_none_or(|subject| subject.digest() != &digest.to_string())
+        {
+            continue;
+        }
+        let ty = manifest
+            .artifact_type()
+            .clone()
+            .unwrap_or_else(|| manifest.config().media_type().clone());
+        if artifact_type.is_some_and(|filter| filter != ty.to_string()) {
+            continue;
+        }
+        let mut referrer = Descriptor::new(MediaType::ImageManifest, desc.size(), desc.digest());
+        referrer.set_artifact_type(Some(ty));
+        referrer.set_annotations(manifest.annotations().clone());
+        referrers.push(referrer);
+    }
+    let index = ImageIndexBuilder::default()
+        .schema_version(2_u32)
+        .media_type(MediaType::ImageIndex)
+        .manifests(referrers)
+        .build()?;
+    let buf = serde_json::to_vec(&index)?;
+    let mut headers = vec![("Content-Type", MediaType::ImageIndex.to_string())];
+    if artifact_type.is_some() {
+        headers.push(("OCI-Filters-Applied", "artifactType".to_string()));
+    }
+    Ok(Response {
+        status: 200,
+        headers,
+        size: buf.len() as u64,
+        body: Body::Bytes(buf),
+    })
+}
+
+fn upload_headers(name: &str, session: &str, len: usize) -> Vec<(&'static str, String)> {
+    let mut headers = vec![
+        (
+            "Location",
+            format!("/v2/{}/blobs/uploads/{}", name, session),
+        ),
+        ("Docker-Upload-UUID", session.to_string()),
+    ];
+    if len > 0 {
+        headers.push(("Range", format!("0-{}", len - 1)));
+    }
+    headers
+}
+
+fn upload_unknown() -> Response {
+    Response::error(404, "BLOB_UPLOAD_UNKNOWN", "blob upload unknown")
+}
+
+fn upload_status(state: &State, name: &str, session: &str) -> Result<Response> {
+    let uploads = state.uploads.lock().unwrap();
+    Ok(match uploads.get(session) {
+        Some(buf) => Response::empty(204, upload_headers(name, session, buf.len())),
+        None => upload_unknown(),
+    })
+}
+
+/// Start an upload session, or push the blob in a single `POST` if the digest is given
+fn start_upload(state: &State, name: &str, request: &Request) -> Result<Response> {
+    if let Some(digest) = request.query("digest") {
+        return store_blob(state, name, &request.body, &digest);
+    }
+    let session = uuid::Uuid::new_v4().to_string();
+    let headers = upload_headers(name, &session, request.body.len());
+    state
+        .uploads
+        .lock()
+        .unwrap()
+        .insert(session, request.body.clone());
+    Ok(Response::empty(202, headers))
+}
+
+fn append_upload(state: &State, name: &str, session: &str, body: &[u8]) -> Result<Response> {
+    let mut uploads = state.uploads.lock().unwrap();
+    let Some(buf) = uploads.get_mut(session) else {
+        return Ok(upload_unknown());
+    };
+    buf.extend_from_slice(body);
+    Ok(Response::empty(
+        202,
+        upload_headers(name, session, buf.len()),
+    ))
+}
+
+fn finish_upload(state: &State, name: &str, session: &str, request: &Request) -> Result<Response> {
+    let Some(mut buf) = state.uploads.lock().unwrap().remove(session) else {
+        return Ok(upload_unknown());
+    };
+    buf.extend_from_slice(&request.body);
+    let Some(digest) = request.query("digest") else {
+        return Ok(Response::error(400, "DIGEST_INVALID", "digest is required"));
+    };
+    store_blob(state, name, &buf, &digest)
+}
+
+fn store_blob(state: &State, name: &str, buf: &[u8], digest: &str) -> Result<Response> {
+    let actual = Digest::from_buf_sha256(buf);
+    if Digest::new(digest).ok() != Some(actual.clone()) {
+        return Ok(Response::error(
+            400,
+            "DIGEST_INVALID",
+            &format!("content has {}, and only sha256 is supported", actual),
+        ));
+    }
+    state.layout.put_blob(buf)?;
+    Ok(Response::empty(
+        201,
+        vec![
+            ("Location", format!("/v2/{}/blobs/{}", name, actual)),
+            ("Docker-Content-Digest", actual.to_string()),
+        ],
+    ))
+}
+
+/// Store the manifest, and record it in `index.json` with the tag as a full image name
+fn put_manifest(state: &State, name: &str, reference: &str, request: &Request) -> Result<Response> {
+    let Ok(reference) = Reference::new(reference) else {
+        return Ok(Response::error(400, "TAG_INVALID", "invalid reference"));
+    };
+    let Ok(value) = serde_json::from_slice::<serde_json::Value>(&request.body) else {
+        return Ok(Response::error(400, "MANIFEST_INVALID", "not a JSON"));
+    };
+    let digest = Digest::from_buf_sha256(&request.body);
+    if reference.is_digest() && Digest::new(reference.as_str()).ok() != Some(digest.clone()) {
+        return Ok(Response::error(
+            400,
+            "DIGEST_INVALID",
+            &format!("manifest has {}", digest),
+        ));
+    }
+    let media_type = request
+        .headers
+        .get("content-type")
+        .map(String::as_str)
+        .or_else(|| value.get("mediaType").and_then(|ty| ty.as_str()))
+        .map(MediaType::from)
+        .unwrap_or(MediaType::ImageManifest);
+    state.layout.put_blob(&request.body)?;
+
+    let _lock = state.index.lock().unwrap();
+    let mut index = state.layout.get_index()?;
+    let mut manifests = index.manifests().clone();
+    let mut desc = Descriptor::new(media_type, request.body.len() as i64, digest.to_string());
+    if reference.is_digest() {
+        if !manifests.iter().any(|d| d.digest() == desc.digest()) {
+            manifests.push(desc);
+        }
+    } else {
+        let host = request
+            .headers
+            .get("host")
+            .map(String::as_str)
+            .unwrap_or("localhost");
+        manifests.retain(|d| match ref_name(d) {
+            Some(ref_name) => ref_tag(ref_name, name).as_deref() != Some(reference.as_str()),
+            None => d.digest() != desc.digest(),
+        });
+        desc.set_annotations(Some(HashMap::from([(
+            REF_NAME.to_string(),
+            format!("{}/{}:{}", host, name, reference),
+        )])));
+        manifests.push(desc);
+    }
+    index.set_manifests(manifests);
+    state.layout.set_index(&index)?;
+
+    let mut headers = vec![
+        ("Location", format!("/v2/{}/manifests/{}", name, digest)),
+        ("Docker-Content-Digest", digest.to_string()),
+    ];
+    if let Some(subject) = value
+        .get("subject")
+        .and_then(|subject| subject.get("digest"))
+        .and_then(|digest| digest.as_str())
+    {
+        headers.push(("OCI-Subject", subject.to_string()));
+    }
+    Ok(Response::empty(201, headers))
+}
+
+/// Remove the tag, or all tags of the repository and the manifest itself for a digest
+fn delete_manifest(state: &State, name: &str, reference: &str) -> Result<Response> {
+    let Ok(reference) = Reference::new(reference) else {
+        return Ok(Response::error(400, "TAG_INVALID", "invalid reference"));
+    };
+    let _lock = state.index.lock().unwrap();
+    let mut index = state.layout.get_index()?;
+    let (removed, mut manifests): (Vec<_>, Vec<_>) =
+        index.manifests().iter().cloned().partition(|d| {
+            let tag = ref_name(d).and_then(|ref_name| ref_tag(ref_name, name));
+            if reference.is_digest() {
+                d.digest() == reference.as_str() && (ref_name(d).is_none() || tag.is_some())
+            } else {
+                tag.as_deref() == Some(reference.as_str())
+            }
+        });
+    if removed.is_empty() {
+        return Ok(Response::error(404, "MANIFEST_UNKNOWN", "manifest unknown"));
+    }
+    // The manifest is kept untagged when its last tag is deleted
+    if !reference.is_digest() {
+        for mut desc in removed {
+            if !manifests.iter().any(|d| d.digest() == desc.digest()) {
+                let mut annotations = desc.annotations().clone().unwrap_or_default();
+                annotations.remove(REF_NAME);
+                desc.set_annotations((!annotations.is_empty()).then_some(annotations));
+                manifests.push(desc);
+            }
+        }
+    }
+    if reference.is_digest() && !manifests.iter().any(|d| d.digest() == reference.as_str()) {
+        fs::remove_file(state.layout.blob_path(&Digest::new(reference.as_str())?))?;
+    }
+    index.set_manifests(manifests);
+    state.layout.set_index(&index)?;
+    Ok(Response::empty(202, Vec::new()))
+}
+
+fn delete_blob(state: &State, digest: &str) -> Result<Response> {
+    let path = match Digest::new(digest) {
+        Ok(digest) => state.layout.blob_path(&digest),
+        Err(_) => return Ok(Response::error(400, "DIGEST_INVALID", "invalid digest")),
+    };
+    if !path.is_file() {
+        return Ok(Response::error(404, "BLOB_UNKNOWN", "blob unknown"));
+    }
+    fs::remove_file(path)?;
+    Ok(Response::empty(202, Vec::new()))
+}
+
+fn ref_name(desc: &Descriptor) -> Option<&str> {
+    desc.annotations()
+        .as_ref()?
+        .get(REF_NAME)
+        .map(String::as_str)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{distribution::Client, Name};
+
+    #[test]
+    fn serve_layout() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let server = RegistryServer::spawn(
+            crate::distribution::test_support::fixture_layout(dir.path())?,
+            "127.0.0.1:0",
+        )?;
+
+        let mut client = Client::new(server.url(), Name::new("test_repo")?)?;
+        let reference = Reference::new("tag1")?;
+        let (_, buf) = client.get_raw_manifest(&reference)?;
+        let digest = client.get_manifest_digest(&reference)?;
+        assert_eq!(Digest::from_buf_sha256(&buf), digest);
+        let manifest = client.get_manifest(&Reference::new(&digest.to_string())?)?;
+        let layer = Digest::new(manifest.layers()[0].digest())?;
+        assert_eq!(Digest::from_buf_sha256(&client.get_blob(&layer)?), layer);
+
+        // Only the repository of the full image name in the layout is served
+        let mut other = Client::new(server.url(), Name::new("other")?)?;
+        assert!(other.get_tags(None).is_err());
+        assert!(other.head_manifest(&reference)?.is_none());
+
+        // Read-only
+        assert!(matches!(
+            client.push_blob(b"blob"),
+            Err(Error::RegistryError { status: 405, .. })
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn chunked_upload() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let server = RegistryServer::spawn_writable(OciLayout::create(dir.path())?, "127.0.0.1:0")?;
+        let url = server.url();
+        let agent = ureq::agent();
+
+        let res = agent
+            .post(url.join("/v2/repo/blobs/uploads/")?.as_str())
+            .call()?;
+        assert_eq!(res.status(), 202);
+        let session = url.join(res.header("Location").unwrap())?;
+        let res = agent.request_url("PATCH", &session).send_bytes(b"chunk1")?;
+        assert_eq!(res.header("Range"), Some("0-5"));
+        let session = url.join(res.header("Location").unwrap())?;
+
+        // Digest mismatch keeps nothing
+        let digest = Digest::from_buf_sha256(b"chunk1chunk2");
+        assert!(matches!(
+            agent
+                .request_url("PUT", &session)
+                .query("digest", &Digest::from_buf_sha256(b"other").to_string())
+                .send_bytes(b"chunk2"),
+            Err(ureq::Error::Status(400, _))
+        ));
+
+        let res = agent
+            .post(url.join("/v2/repo/blobs/uploads/")?.as_str())
+            .call()?;
+        let session = url.join(res.header("Location").unwrap())?;
+        agent.request_url("PATCH", &session).send_bytes(b"chunk1")?;
+        let res = agent
+            .request_url("PUT", &session)
+            .query("digest", &digest.to_string())
+            .send_bytes(b"chunk2")?;
+        assert_eq!(res.status(), 201);
+
+        let mut client = Client::new(url, Name::new("repo")?)?;
+        assert_eq!(client.get_blob(&digest)?, b"chunk1chunk2");
+        Ok(())
+    }
+
+    /// Send a raw request and return the status line of the response
+    fn send_raw(server: &RegistryServer, request: &str) -> Result<String> {
+        let mut stream = TcpStream::connect(server.addr())?;
+        stream.write_all(request.as_bytes())?;
+        let mut line = String::new();
+        BufReader::new(stream).read_line(&mut line)?;
+        Ok(line.trim().to_string())
+    }
+
+    #[test]
+    fn oversized_body() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let server = RegistryServer::spawn_writable(OciLayout::create(dir.path())?, "127.0.0.1:0")?;
+        for size in ["ffffffffffffffff", "7fffffffffffffff"] {
+            let request = format!(
+                "PATCH /v2/repo/blobs/uploads/x HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n{}\r\n",
+                size
+            );
+            assert_eq!(send_raw(&server, &request)?, "HTTP/1.1 400 Bad Request");
+        }
+        let request =
+            "PUT /v2/repo/manifests/v1 HTTP/1.1\r\nContent-Length: 9223372036854775807\r\n\r\n";
+        assert_eq!(send_raw(&server, request)?, "HTTP/1.1 400 Bad Request");
+
+        // Read-only server never reads the body
+        let dir = tempfile::tempdir()?;
+        let read_only = RegistryServer::spawn(OciLayout::create(dir.path())?, "127.0.0.1:0")?;
+        let request = "PATCH /v2/repo/blobs/uploads/x HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\nffffffffffffffff\r\n";
+        assert_eq!(
+            send_raw(&read_only, request)?,
+            "HTTP/1.1 405 Method Not Allowed"
+        );
+
+        // Servers are still alive
+        for server in [&server, &read_only] {
+            assert_eq!(
+                send_raw(server, "GET /v2/ HTTP/1.1\r\n\r\n")?,
+                "HTTP/1.1 200 OK"
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn capped_tag_list() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let mut state = State::new(
+            crate::distribution::test_support::fixture_layout(dir.path())?,
+            false,
+        );
+        state.max_page_size = Some(1);
+        let server = RegistryServer::spawn_state(state, "127.0.0.1:0")?;
+        let mut client = Client::new(server.url(), Name::new("test_repo")?)?;
+        // Pages are smaller than requested, and followed by `Link`
+        let tags = client.tags_iter(2, None).collect::<Result<Vec<_>>>()?;
+        assert_eq!(tags, ["tag1", "tag2", "tag3"]);
+        let tags = client
+            .get_tags_with_digests(2, None)
+            .collect::<Result<Vec<_>>>()?;
+        assert_eq!(tags.len(), 3);
+        Ok(())
+    }
+
+    #[test]
+    fn ref_tag() {
+        assert_eq!(super::ref_tag("v1", "any").as_deref(), Some("v1"));
+        assert_eq!(
+            super::ref_tag("localhost:5000/test_repo:v1", "test_repo").as_deref(),
+            Some("v1")
+        );
+        assert_eq!(super::ref_tag("localhost:5000/test_repo:v1", "other"), None);
+    }
+}
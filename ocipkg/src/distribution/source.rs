@@ -0,0 +1,300 @@
+use crate::{
+    distribution::{Client, TransferEvent},
+    error::*,
+    image::{is_index_media_type, Archive, OciLayout},
+    Digest, Reference,
+};
+use oci_spec::image::{Descriptor, ImageIndex, ImageManifest, MediaType};
+use std::{
+    fs,
+    io::{Read, Seek},
+    path::PathBuf,
+    str::FromStr,
+};
+
+/// Annotation key of `index.json` entries naming the image
+const REF_NAME: &str = "org.opencontainers.image.ref.name";
+
+/// Image outside of local storage to be pushed directly to registry
+///
+/// This is parsed from `oci-archive:{path}` or `oci:{path}`,
+/// the same transport names as [containers-transports(5)](https://github.com/containers/image/blob/main/docs/containers-transports.5.md).
+///
+/// ```
+/// use ocipkg::distribution::ImageSource;
+/// use std::path::PathBuf;
+///
+/// let source: ImageSource = "oci-archive:./pkg.tar".parse().unwrap();
+/// assert_eq!(source, ImageSource::OciArchive(PathBuf::from("./pkg.tar")));
+/// let source: ImageSource = "oci:./layout".parse().unwrap();
+/// assert_eq!(source, ImageSource::OciLayout(PathBuf::from("./layout")));
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ImageSource {
+    /// oci-archive, i.e. tar archive of OCI Image Layout
+    OciArchive(PathBuf),
+    /// OCI Image Layout directory
+    OciLayout(PathBuf),
+}
+
+impl FromStr for ImageSource {
+    type Err = Error;
+    fn from_str(input: &str) -> Result<Self> {
+        match input.split_once(':') {
+            Some(("oci-archive", path)) if !path.is_empty() => {
+                Ok(ImageSource::OciArchive(PathBuf::from(path)))
+            }
+            Some(("oci", path)) if !path.is_empty() => {
+                Ok(ImageSource::OciLayout(PathBuf::from(path)))
+            }
+            _ => Err(Error::InvalidImageSource(input.to_string())),
+        }
+    }
+}
+
+impl ImageSource {
+    /// Push an image in the source as `reference` in the repository of `client`
+    ///
+    /// `selector` chooses the image by `org.opencontainers.image.ref.name` annotation in `index.json`,
+    /// which matches either the whole name or its tag.
+    /// It can be omitted if the source contains only one image.
+    ///
+    /// Blobs are read from the source without importing into local storage,
+    /// and their digests are verified before pushing.
+    /// Returns the digest of pushed manifest or index.
+    pub fn push(
+        &self,
+        client: &mut Client,
+        reference: &Reference,
+        selector: Option<&str>,
+    ) -> Result<Digest> {
+        match self {
+            ImageSource::OciArchive(path) => {
+                let mut f = open_archive(path)?;
+                push_selected(&mut Archive::new(&mut f), client, reference, selector)
+            }
+            ImageSource::OciLayout(path) => {
+                push_selected(&mut OciLayout::open(path)?, client, reference, selector)
+            }
+        }
+    }
+
+    /// Push all images in the source into the repository of `client`
+    ///
+    /// The tag of `org.opencontainers.image.ref.name` annotation is used as the reference,
+    /// as [OciLayout::push_all].
+    /// Returns `{registry_prefix}:{reference}` and the digest for each image.
+    pub fn push_all(
+        &self,
+        client: &mut Client,
+        registry_prefix: &str,
+    ) -> Result<Vec<(String, Digest)>> {
+        match self {
+            ImageSource::OciArchive(path) => {
+                let mut f = open_archive(path)?;
+                push_every(&mut Archive::new(&mut f), client, registry_prefix)
+            }
+            ImageSource::OciLayout(path) => {
+                push_every(&mut OciLayout::open(path)?, client, registry_prefix)
+            }
+        }
+    }
+}
+
+pub(super) fn open_archive(path: &std::path::Path) -> Result<fs::File> {
+    if !path.is_file() {
+        return Err(Error::NotAFile(path.to_owned()));
+    }
+    Ok(fs::File::open(path)?)
+}
+
+/// Read access to blobs either in oci-archive or OCI Image Layout
+pub(super) trait BlobSource {
+    fn index(&mut self) -> Result<ImageIndex>;
+    fn blob(&mut self, digest: &Digest) -> Result<Vec<u8>>;
+
+    /// Read blob and check that its content matches the digest
+    fn verified_blob(&mut self, digest: &Digest) -> Result<Vec<u8>> {
+        let buf = self.blob(digest)?;
+        if digest.algorithm == "sha256" {
+            let actual = Digest::from_buf_sha256(&buf);
+            if &actual != digest {
+                return Err(Error::DigestMismatch {
+                    expected: digest.clone(),
+                    actual,
+                });
+            }
+        }
+        Ok(buf)
+    }
+}
+
+impl<R: Read + Seek> BlobSource for Archive<'_, R> {
+    fn index(&mut self) -> Result<ImageIndex> {
+        self.get_index()
+    }
+    fn blob(&mut self, digest: &Digest) -> Result<Vec<u8>> {
+        let mut entry = self.get_blob(digest)?;
+        let mut buf = Vec::new();
+        entry.read_to_end(&mut buf)?;
+        Ok(buf)
+    }
+}
+
+impl BlobSource for OciLayout {
+    fn index(&mut self) -> Result<ImageIndex> {
+        self.get_index()
+    }
+    fn blob(&mut self, digest: &Digest) -> Result<Vec<u8>> {
+        self.get_blob(digest)
+    }
+}
+
+fn ref_name(desc: &Descriptor) -> Option<&String> {
+    desc.annotations().as_ref().and_then(|a| a.get(REF_NAME))
+}
+
+/// Tag part of `org.opencontainers.image.ref.name`, which may be a full image name
+fn ref_tag(name: &str) -> &str {
+    let last = name.rsplit('/').next().unwrap_or(name);
+    last.split_once(':').map(|(_, tag)| tag).unwrap_or(last)
+}
+
+/// Select an image in `index.json` by `org.opencontainers.image.ref.name` annotation,
+/// which matches either the whole name or its tag
+///
+/// `selector` can be omitted if the index contains only one image.
+pub(super) fn select_descriptor<'a>(
+    index: &'a ImageIndex,
+    selector: Option<&str>,
+) -> Result<&'a Descriptor> {
+    match selector {
+        Some(selector) => index
+            .manifests()
+            .iter()
+            .find(|desc| ref_name(desc).is_some_and(|n| n == selector || ref_tag(n) == selector))
+            .ok_or_else(|| Error::UnknownManifestRef(selector.to_string())),
+        None => match index.manifests().as_slice() {
+            [desc] => Ok(desc),
+            descs => Err(Error::AmbiguousImage(
+                descs
+                    .iter()
+                    .map(|desc| {
+                        ref_name(desc)
+                            .cloned()
+                            .unwrap_or_else(|| desc.digest().clone())
+                    })
+                    .collect(),
+            )),
+        },
+    }
+}
+
+fn push_selected(
+    source: &mut impl BlobSource,
+    client: &mut Client,
+    reference: &Reference,
+    selector: Option<&str>,
+) -> Result<Digest> {
+    let index = source.index()?;
+    let desc = select_descriptor(&index, selector)?;
+    push_descriptor(source, client, reference, desc)
+}
+
+fn push_every(
+    source: &mut impl BlobSource,
+    client: &mut Client,
+    registry_prefix: &str,
+) -> Result<Vec<(String, Digest)>> {
+    let index = source.index()?;
+    let total = index.manifests().len();
+    let mut pushed = Vec::new();
+    for (i, desc) in index.manifests().iter().enumerate() {
+        let reference =
+            Reference::new(ref_name(desc).map_or(desc.digest().as_str(), |n| ref_tag(n)))?;
+        let name = format!("{}:{}", registry_prefix.trim_end_matches('/'), reference);
+        log::info!("Push image ({}/{}): {}", i + 1, total, name);
+        let digest = push_descriptor(source, client, &reference, desc)?;
+        pushed.push((name, digest));
+    }
+    Ok(pushed)
+}
+
+fn push_descriptor(
+    source: &mut impl BlobSource,
+    client: &mut Client,
+    reference: &Reference,
+    desc: &Descriptor,
+) -> Result<Digest> {
+    let digest = Digest::new(desc.digest())?;
+    if is_index_media_type(desc.media_type()) {
+        let buf = source.verified_blob(&digest)?;
+        let index = ImageIndex::from_reader(buf.as_slice())?;
+        for manifest in index.manifests() {
+            push_manifest_with_blobs(
+                source,
+                client,
+                &Reference::new(manifest.digest())?,
+                &Digest::new(manifest.digest())?,
+            )?;
+        }
+        client.push_raw_manifest(reference, &MediaType::ImageIndex, &buf)?;
+    } else {
+        push_manifest_with_blobs(source, client, reference, &digest)?;
+    }
+    Ok(digest)
+}
+
+fn push_manifest_with_blobs(
+    source: &mut impl BlobSource,
+    client: &mut Client,
+    reference: &Reference,
+    digest: &Digest,
+) -> Result<()> {
+    let buf = source.verified_blob(digest)?;
+    let manifest = ImageManifest::from_reader(buf.as_slice())?;
+    for desc in manifest.layers().iter().chain([manifest.config()]) {
+        let blob = Digest::new(desc.digest())?;
+        if let Some(stat) = client.head_blob(&blob)? {
+            client.report(TransferEvent::Skipped {
+                digest: blob,
+                size: stat.size,
+            });
+            continue;
+        }
+        client.push_blob(&source.verified_blob(&blob)?)?;
+    }
+    // Push as it is stored in the source to keep the digest
+    client.push_raw_manifest(reference, &MediaType::ImageManifest, &buf)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_image_source() {
+        assert!("docker://ghcr.io/termoshtt/ocipkg"
+            .parse::<ImageSource>()
+            .is_err());
+        assert!("oci-archive:".parse::<ImageSource>().is_err());
+        assert!("./pkg.tar".parse::<ImageSource>().is_err());
+        assert_eq!(ref_tag("ghcr.io/termoshtt/ocipkg/testing:v1"), "v1");
+        assert_eq!(ref_tag("v1"), "v1");
+    }
+
+    #[test]
+    fn verified_blob() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let mut layout = OciLayout::create(&dir.path().join("layout"))?;
+        let digest = layout.put_blob(b"test")?;
+        assert_eq!(layout.verified_blob(&digest)?, b"test");
+        fs::write(layout.blob_path(&digest), b"broken")?;
+        assert!(matches!(
+            layout.verified_blob(&digest),
+            Err(Error::DigestMismatch { .. })
+        ));
+        Ok(())
+    }
+}
@@ -0,0 +1,170 @@
+use crate::error::*;
+use regex::Regex;
+use semver::{Version, VersionReq};
+
+/// Composable filter of tags listed by [crate::distribution::Client::tags_iter] and others
+///
+/// A tag is accepted when it satisfies all the conditions, and [TagFilter::default] accepts any tag.
+/// The distribution spec has no query to filter tags, so tags are filtered in the client,
+/// but listing by pages stops early once tags are past the range of [TagFilter::prefix].
+///
+/// ```
+/// use ocipkg::distribution::TagFilter;
+/// use regex::Regex;
+///
+/// let filter = TagFilter::prefix("v1.")
+///     .combine(TagFilter::semver_range(">=1.2".parse().unwrap()))
+///     .combine(TagFilter::exclude(Regex::new("-rc").unwrap()));
+/// assert!(filter.matches("v1.2.0"));
+/// assert!(!filter.matches("v1.1.0"));
+/// assert!(!filter.matches("v1.3.0-rc1"));
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct TagFilter {
+    conditions: Vec<Condition>,
+}
+
+#[derive(Debug, Clone)]
+enum Condition {
+    Prefix(String),
+    Regex(Regex),
+    SemverRange(VersionReq),
+    Exclude(Regex),
+}
+
+impl TagFilter {
+    /// Tags starting with `prefix`
+    pub fn prefix(prefix: &str) -> Self {
+        Self::from_condition(Condition::Prefix(prefix.to_string()))
+    }
+
+    /// Tags matching `re`
+    pub fn regex(re: Regex) -> Self {
+        Self::from_condition(Condition::Regex(re))
+    }
+
+    /// Tags of semantic versions satisfying `req`, see [tag_version]
+    pub fn semver_range(req: VersionReq) -> Self {
+        Self::from_condition(Condition::SemverRange(req))
+    }
+
+    /// Tags not matching `pattern`
+    pub fn exclude(pattern: Regex) -> Self {
+        Self::from_condition(Condition::Exclude(pattern))
+    }
+
+    /// Glob pattern, e.g. `v1.*`, or regex surrounded by slashes, e.g. `/^v\d+$/`
+    pub fn from_pattern(pattern: &str) -> Result<Self> {
+        let re = match pattern.strip_prefix('/').and_then(|p| p.strip_suffix('/')) {
+            Some(re) => re.to_string(),
+            None => {
+                let mut re = String::from("^");
+                for c in pattern.chars() {
+                    match c {
+                        '*' => re.push_str(".*"),
+                        '?' => re.push('.'),
+                        c => re.push_str(&regex::escape(&c.to_string())),
+                    }
+                }
+                re.push('$');
+                re
+            }
+        };
+        let re = Regex::new(&re).map_err(|_| Error::InvalidTagFilter(pattern.to_string()))?;
+        Ok(Self::regex(re))
+    }
+
+    /// Tags accepted by both of filters
+    pub fn combine(mut self, other: TagFilter) -> TagFilter {
+        self.conditions.extend(other.conditions);
+        self
+    }
+
+    pub fn matches(&self, tag: &str) -> bool {
+        self.conditions.iter().all(|condition| match condition {
+            Condition::Prefix(prefix) => tag.starts_with(prefix.as_str()),
+            Condition::Regex(re) => re.is_match(tag),
+            Condition::SemverRange(req) => tag_version(tag).is_some_and(|v| req.matches(&v)),
+            Condition::Exclude(re) => !re.is_match(tag),
+        })
+    }
+
+    /// `true` if no tag after `tag` in lexicographic order is accepted because of a prefix
+    pub(crate) fn is_past(&self, tag: &str) -> bool {
+        self.conditions.iter().any(|condition| match condition {
+            Condition::Prefix(prefix) => tag > prefix.as_str() && !tag.starts_with(prefix.as_str()),
+            _ => false,
+        })
+    }
+
+    fn from_condition(condition: Condition) -> Self {
+        TagFilter {
+            conditions: vec![condition],
+        }
+    }
+}
+
+/// Semantic version of tag, where a leading `v` is ignored
+pub fn tag_version(tag: &str) -> Option<Version> {
+    Version::parse(tag.strip_prefix('v').unwrap_or(tag)).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches() -> Result<()> {
+        let tags = [
+            "latest",
+            "v1.0.0",
+            "v1.2.0",
+            "v1.2.0-rc1",
+            "1.3.0",
+            "v2.0.0",
+        ];
+        let filtered = |filter: &TagFilter| {
+            tags.iter()
+                .filter(|tag| filter.matches(tag))
+                .copied()
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(filtered(&TagFilter::default()), tags);
+        assert_eq!(
+            filtered(&TagFilter::prefix("v1.")),
+            ["v1.0.0", "v1.2.0", "v1.2.0-rc1"]
+        );
+        assert_eq!(
+            filtered(&TagFilter::semver_range(">=1.2".parse().unwrap())),
+            ["v1.2.0", "1.3.0", "v2.0.0"]
+        );
+        assert_eq!(
+            filtered(
+                &TagFilter::from_pattern("v*")?
+                    .combine(TagFilter::exclude(Regex::new("-rc").unwrap()))
+            ),
+            ["v1.0.0", "v1.2.0", "v2.0.0"]
+        );
+        assert_eq!(filtered(&TagFilter::from_pattern(r"/^\d/")?), ["1.3.0"]);
+        assert!(matches!(
+            TagFilter::from_pattern("/(/"),
+            Err(Error::InvalidTagFilter(_))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn is_past() {
+        let filter = TagFilter::prefix("v1.");
+        assert!(!filter.is_past("latest"));
+        assert!(!filter.is_past("v1.2.0"));
+        assert!(filter.is_past("v2.0.0"));
+        assert!(!TagFilter::default().is_past("v2.0.0"));
+    }
+
+    #[test]
+    fn send_sync() {
+        fn assert_send_sync<T: Send + Sync + Clone>() {}
+        assert_send_sync::<TagFilter>();
+    }
+}
@@ -0,0 +1,51 @@
+use crate::{distribution::Reference, error::*};
+
+/// Client-side policy for tags to be pushed
+///
+/// Pushing to a tag listed in `reserved_tags`, e.g. `latest`, is rejected
+/// with [Error::ReservedTag] unless `allow_reserved_tags` is set.
+/// References by digest are never rejected.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TagPolicy {
+    pub reserved_tags: Vec<String>,
+    pub allow_reserved_tags: bool,
+}
+
+impl TagPolicy {
+    /// Check if pushing to the reference is allowed
+    pub fn check(&self, reference: &Reference) -> Result<()> {
+        if self.allow_reserved_tags || reference.is_digest() {
+            return Ok(());
+        }
+        if self
+            .reserved_tags
+            .iter()
+            .any(|tag| tag == reference.as_str())
+        {
+            return Err(Error::ReservedTag(reference.to_string()));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check() -> Result<()> {
+        let mut policy = TagPolicy {
+            reserved_tags: vec!["latest".to_string()],
+            allow_reserved_tags: false,
+        };
+        assert!(matches!(
+            policy.check(&Reference::new("latest")?),
+            Err(Error::ReservedTag(tag)) if tag == "latest"
+        ));
+        policy.check(&Reference::new("1.0.0")?)?;
+
+        policy.allow_reserved_tags = true;
+        policy.check(&Reference::new("latest")?)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,170 @@
+use crate::{
+    distribution::{Client, RegistryServer},
+    error::*,
+    image::{OciLayout, PlatformEx},
+    Digest, ImageName, Name,
+};
+use oci_spec::image::{
+    Descriptor, ImageConfigurationBuilder, ImageIndexBuilder, ImageManifestBuilder, MediaType,
+    Platform, RootFsBuilder, SCHEMA_VERSION,
+};
+use std::{collections::HashMap, fs, io::Write, path::Path};
+use url::Url;
+
+/// Writable registry in a temporary directory for tests, enabled by `test-support` feature
+///
+/// This is a [RegistryServer::spawn_writable] on a random port of `127.0.0.1`,
+/// and the server stops and the directory is removed when dropped.
+/// No external registry, e.g. `registry:2` container, is required.
+///
+/// ```
+/// use ocipkg::{distribution::TestRegistry, Reference};
+///
+/// let registry = TestRegistry::with_fixture()?;
+/// let mut client = registry.client("test_repo")?;
+/// let url = client.push_blob(b"test string")?;
+/// client.retag(&Reference::new("tag1")?, &Reference::new("new_tag")?, false)?;
+/// # Ok::<(), ocipkg::error::Error>(())
+/// ```
+#[derive(Debug)]
+pub struct TestRegistry {
+    server: Option<RegistryServer>,
+    layout: OciLayout,
+}
+
+impl TestRegistry {
+    /// Start an empty registry
+    pub fn start() -> Result<Self> {
+        let layout = OciLayout::create(&temp_dir())?;
+        Self::spawn(layout)
+    }
+
+    /// Start a registry with `test_repo` of `tag1`, `tag2`, and `tag3`, same as `test/fixture.sh`
+    pub fn with_fixture() -> Result<Self> {
+        let layout = fixture_layout(&temp_dir())?;
+        Self::spawn(layout)
+    }
+
+    fn spawn(layout: OciLayout) -> Result<Self> {
+        let server = RegistryServer::spawn_writable(layout.clone(), "127.0.0.1:0")?;
+        Ok(TestRegistry {
+            server: Some(server),
+            layout,
+        })
+    }
+
+    /// URL of the registry, e.g. `http://127.0.0.1:5000`
+    pub fn url(&self) -> Url {
+        self.server().url()
+    }
+
+    /// Image name in this registry, e.g. `localhost:5000/test_repo:tag1`
+    ///
+    /// `localhost` is used as hostname since [ImageName::registry_url] uses HTTP only for it.
+    pub fn image_name(&self, name: &str, reference: &str) -> Result<ImageName> {
+        ImageName::parse(&format!(
+            "localhost:{}/{}:{}",
+            self.server().addr().port(),
+            name,
+            reference
+        ))
+    }
+
+    /// Client of the repository in this registry
+    pub fn client(&self, name: &str) -> Result<Client> {
+        Client::new(self.url(), Name::new(name)?)
+    }
+
+    /// Layout storing the pushed images
+    pub fn layout(&self) -> &OciLayout {
+        &self.layout
+    }
+
+    fn server(&self) -> &RegistryServer {
+        self.server.as_ref().expect("Server runs until dropped")
+    }
+}
+
+impl Drop for TestRegistry {
+    fn drop(&mut self) {
+        // Stop the server before removing the directory
+        self.server.take();
+        if let Err(e) = fs::remove_dir_all(self.layout.path()) {
+            log::warn!("Failed to remove {}: {}", self.layout.path().display(), e);
+        }
+    }
+}
+
+fn temp_dir() -> std::path::PathBuf {
+    std::env::temp_dir().join(format!("ocipkg-test-registry-{}", uuid::Uuid::new_v4()))
+}
+
+/// OCI layout of `test_repo` with `tag1`, `tag2`, and `tag3`, same as `test/fixture.sh`
+pub(crate) fn fixture_layout(path: &Path) -> Result<OciLayout> {
+    let layout = OciLayout::create(path)?;
+    let mut manifests = Vec::new();
+    for tag in ["tag1", "tag2", "tag3"] {
+        let mut tar = tar::Builder::new(Vec::new());
+        let content = format!("content of {}", tag);
+        let mut header = tar::Header::new_gnu();
+        header.set_size(content.len() as u64);
+        header.set_mode(0o644);
+        header.set_cksum();
+        tar.append_data(&mut header, "test.txt", content.as_bytes())?;
+        let tar = tar.into_inner()?;
+        let mut gz = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        gz.write_all(&tar)?;
+        let layer = gz.finish()?;
+        let layer_digest = layout.put_blob(&layer)?;
+
+        let platform = Platform::from_cfg_macro();
+        let config = ImageConfigurationBuilder::default()
+            .os(platform.os().clone())
+            .architecture(platform.architecture().clone())
+            .rootfs(
+                RootFsBuilder::default()
+                    .typ("layers")
+                    .diff_ids(vec![Digest::from_buf_sha256(&tar).to_string()])
+                    .build()?,
+            )
+            .build()?;
+        let mut buf = Vec::new();
+        config.to_writer(&mut buf)?;
+        let config_digest = layout.put_blob(&buf)?;
+
+        let manifest = ImageManifestBuilder::default()
+            .schema_version(SCHEMA_VERSION)
+            .media_type(MediaType::ImageManifest)
+            .config(Descriptor::new(
+                MediaType::ImageConfig,
+                buf.len() as i64,
+                config_digest.to_string(),
+            ))
+            .layers(vec![Descriptor::new(
+                MediaType::ImageLayerGzip,
+                layer.len() as i64,
+                layer_digest.to_string(),
+            )])
+            .build()?;
+        let mut buf = Vec::new();
+        manifest.to_writer(&mut buf)?;
+        let digest = layout.put_blob(&buf)?;
+        let mut desc = Descriptor::new(
+            MediaType::ImageManifest,
+            buf.len() as i64,
+            digest.to_string(),
+        );
+        desc.set_annotations(Some(HashMap::from([(
+            "org.opencontainers.image.ref.name".to_string(),
+            format!("localhost:5000/test_repo:{}", tag),
+        )])));
+        manifests.push(desc);
+    }
+    layout.set_index(
+        &ImageIndexBuilder::default()
+            .schema_version(SCHEMA_VERSION)
+            .manifests(manifests)
+            .build()?,
+    )?;
+    Ok(layout)
+}
@@ -0,0 +1,199 @@
+use super::cancel::{Cancel, CancellationToken};
+use std::{io, sync::Arc};
+use url::Url;
+
+/// HTTP transport under [crate::distribution::Client]
+///
+/// The client sends requests to the network by default.
+/// Once a transport is set by [crate::distribution::Client::set_transport],
+/// every request of the client, including the ones for authentication tokens,
+/// is passed to it instead, e.g. to script responses of a registry in unit tests.
+///
+/// Responses are returned as they are, and the client handles statuses as ones from the network,
+/// e.g. `401` with `WWW-Authenticate` header starts the authentication.
+/// An [io::Error] is handled as a connection-level failure.
+pub trait HttpTransport: Send + Sync + 'static {
+    fn send(&self, request: &HttpRequest) -> io::Result<ureq::Response>;
+}
+
+/// Request passed to [HttpTransport]
+///
+/// The body of `PUT` and `POST` requests is not exposed.
+/// Credentials in headers are redacted in [std::fmt::Debug] output.
+#[derive(Clone, PartialEq, Eq)]
+pub struct HttpRequest {
+    pub method: String,
+    pub url: Url,
+    /// Header names are in lower case
+    pub headers: Vec<(String, String)>,
+}
+
+impl std::fmt::Debug for HttpRequest {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let headers: Vec<_> = self
+            .headers
+            .iter()
+            .map(|(name, value)| (name, super::http_log::header_value(name, Some(value))))
+            .collect();
+        f.debug_struct("HttpRequest")
+            .field("method", &self.method)
+            .field("url", &self.url.as_str())
+            .field("headers", &headers)
+            .finish()
+    }
+}
+
+impl HttpRequest {
+    /// Value of the first header named `name`, case-insensitively
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// Agent sending requests into `transport`, or to the network if not given,
+/// with logging them as [super::http_log::agent]
+///
+/// Requests fail with [crate::error::Error::Cancelled] without being sent once `cancel` is cancelled.
+pub(crate) fn agent(
+    transport: Option<&Arc<dyn HttpTransport>>,
+    cancel: Option<&CancellationToken>,
+) -> ureq::Agent {
+    let mut builder = super::http_log::agent_builder();
+    if let Some(cancel) = cancel {
+        builder = builder.middleware(Cancel(cancel.clone()));
+    }
+    if let Some(transport) = transport {
+        builder = builder.middleware(Transport(transport.clone()));
+    }
+    builder.build()
+}
+
+/// Middleware answering requests by the transport without calling the next one
+struct Transport(Arc<dyn HttpTransport>);
+
+impl ureq::Middleware for Transport {
+    fn handle(
+        &self,
+        request: ureq::Request,
+        _next: ureq::MiddlewareNext,
+    ) -> Result<ureq::Response, ureq::Error> {
+        let url = Url::parse(request.url())?;
+        let headers = request
+            .header_names()
+            .into_iter()
+            .filter_map(|name| {
+                let value = request.header(&name)?.to_string();
+                Some((name.to_lowercase(), value))
+            })
+            .collect();
+        let request = HttpRequest {
+            method: request.method().to_string(),
+            url,
+            headers,
+        };
+        Ok(self.0.send(&request)?)
+    }
+}
+
+#[cfg(test)]
+pub(crate) use mock::ScriptedTransport;
+
+#[cfg(test)]
+mod mock {
+    use super::*;
+    use std::{collections::VecDeque, sync::Mutex};
+
+    /// Transport returning scripted responses in order, and recording requests
+    #[derive(Default)]
+    pub(crate) struct ScriptedTransport {
+        responses: Mutex<VecDeque<io::Result<ureq::Response>>>,
+        requests: Mutex<Vec<HttpRequest>>,
+    }
+
+    impl ScriptedTransport {
+        pub(crate) fn new() -> Arc<Self> {
+            Arc::new(Self::default())
+        }
+
+        /// Respond in raw HTTP/1.1 format, e.g. `"HTTP/1.1 200 OK\r\n\r\n"`
+        pub(crate) fn respond(&self, raw: &str) {
+            let res = raw.parse().expect("Invalid scripted response");
+            self.responses.lock().unwrap().push_back(Ok(res));
+        }
+
+        /// Fail as the connection is refused
+        pub(crate) fn fail(&self) {
+            self.responses
+                .lock()
+                .unwrap()
+                .push_back(Err(io::ErrorKind::ConnectionRefused.into()));
+        }
+
+        pub(crate) fn requests(&self) -> Vec<HttpRequest> {
+            self.requests.lock().unwrap().clone()
+        }
+
+        /// `true` if all the scripted responses are consumed
+        pub(crate) fn finished(&self) -> bool {
+            self.responses.lock().unwrap().is_empty()
+        }
+    }
+
+    impl HttpTransport for ScriptedTransport {
+        fn send(&self, request: &HttpRequest) -> io::Result<ureq::Response> {
+            self.requests.lock().unwrap().push(request.clone());
+            self.responses
+                .lock()
+                .unwrap()
+                .pop_front()
+                .unwrap_or_else(|| {
+                    panic!(
+                        "No scripted response for {} {}",
+                        request.method, request.url
+                    )
+                })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::*;
+
+    #[test]
+    fn scripted_transport() -> Result<()> {
+        let transport = ScriptedTransport::new();
+        transport.respond("HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok");
+        transport.respond("HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n\r\n");
+        let agent = agent(Some(&(transport.clone() as Arc<dyn HttpTransport>)), None);
+
+        let res = agent
+            .get("http://registry.test/v2/")
+            .set("Accept", "application/json")
+            .call()?;
+        assert_eq!(res.into_string()?, "ok");
+        assert!(matches!(
+            agent.head("http://registry.test/v2/a/manifests/b").call(),
+            Err(ureq::Error::Status(404, _))
+        ));
+        assert!(transport.finished());
+
+        let requests = transport.requests();
+        assert_eq!(requests[0].method, "GET");
+        assert_eq!(requests[0].url.as_str(), "http://registry.test/v2/");
+        assert_eq!(requests[0].header("accept"), Some("application/json"));
+        assert_eq!(requests[1].method, "HEAD");
+
+        let request = HttpRequest {
+            method: "GET".to_string(),
+            url: Url::parse("http://registry.test/v2/").unwrap(),
+            headers: vec![("authorization".to_string(), "Bearer secret".to_string())],
+        };
+        assert!(!format!("{:?}", request).contains("secret"));
+        Ok(())
+    }
+}
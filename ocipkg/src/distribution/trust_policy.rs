@@ -0,0 +1,393 @@
+use crate::{
+    config::matches_repository,
+    distribution::{Client, VerificationPolicy},
+    error::*,
+    image::lint::Check,
+    Digest, ImageName,
+};
+use serde::Deserialize;
+use std::{
+    collections::BTreeMap,
+    fmt, fs,
+    path::{Path, PathBuf},
+};
+
+/// Trust policy file in a project, searched from the current directory to its ancestors
+pub const PROJECT_TRUST_POLICY: &str = ".ocipkg/policy.toml";
+
+/// Requirements for images of repositories, loaded from `policy.toml`
+///
+/// The policy file maps glob patterns of repositories, matched as [crate::config::RegistryRule],
+/// to [TrustRule]s, and the rule of the longest matching pattern is applied:
+///
+/// ```toml
+/// ["ghcr.io/myorg"]
+/// require-signature = ["key:ops.pub"]
+///
+/// ["quay.io/*"]
+/// require-digest = true
+///
+/// ["ghcr.io/untrusted"]
+/// allow = false
+/// ```
+///
+/// The policy in the config directory, e.g. `~/.config/ocipkg/policy.toml` on Linux,
+/// is overridden for each pattern by [PROJECT_TRUST_POLICY] of the project.
+/// Images not matching any pattern are allowed without requirements.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(transparent)]
+pub struct TrustPolicy {
+    pub rules: BTreeMap<String, TrustRule>,
+}
+
+/// Requirements for images of repositories matching a pattern in [TrustPolicy]
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct TrustRule {
+    #[serde(default = "default_allow")]
+    pub allow: bool,
+    /// Images must be pinned to digests, e.g. `name@sha256:...`
+    #[serde(default)]
+    pub require_digest: bool,
+    /// Images must be signed for one of them
+    #[serde(default)]
+    pub require_signature: Vec<SignatureRequirement>,
+    /// Policy file where the rule is defined
+    #[serde(skip)]
+    pub source: Option<PathBuf>,
+}
+
+fn default_allow() -> bool {
+    true
+}
+
+/// Signature required by [TrustRule], `key:{path}` or `cert:{path}`
+///
+/// Relative paths are resolved from the directory of the policy file.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub enum SignatureRequirement {
+    /// Cosign signature by the public key in PEM, see [VerificationPolicy::PublicKey]
+    CosignKey(PathBuf),
+    /// Notation signature chained to the root certificates in PEM, see [VerificationPolicy::Notation]
+    NotationCert(PathBuf),
+}
+
+impl TryFrom<String> for SignatureRequirement {
+    type Error = String;
+
+    fn try_from(value: String) -> std::result::Result<Self, String> {
+        match value.split_once(':') {
+            Some(("key", path)) if !path.is_empty() => {
+                Ok(SignatureRequirement::CosignKey(path.into()))
+            }
+            Some(("cert", path)) if !path.is_empty() => {
+                Ok(SignatureRequirement::NotationCert(path.into()))
+            }
+            _ => Err(format!(
+                "signature requirement must be `key:{{path}}` or `cert:{{path}}`: {}",
+                value
+            )),
+        }
+    }
+}
+
+impl fmt::Display for SignatureRequirement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SignatureRequirement::CosignKey(path) => write!(f, "key:{}", path.display()),
+            SignatureRequirement::NotationCert(path) => write!(f, "cert:{}", path.display()),
+        }
+    }
+}
+
+impl SignatureRequirement {
+    pub fn verification_policy(&self) -> Result<VerificationPolicy> {
+        match self {
+            SignatureRequirement::CosignKey(path) => {
+                Ok(VerificationPolicy::PublicKey(fs::read_to_string(path)?))
+            }
+            SignatureRequirement::NotationCert(path) => {
+                VerificationPolicy::notation_from_pem(&fs::read_to_string(path)?)
+            }
+        }
+    }
+
+    fn resolve(&mut self, dir: &Path) {
+        let (SignatureRequirement::CosignKey(path) | SignatureRequirement::NotationCert(path)) =
+            self;
+        *path = dir.join(&*path);
+    }
+}
+
+impl TrustRule {
+    /// Check the image name without accessing registry, i.e. except [TrustRule::require_signature]
+    pub fn check(&self, pattern: &str, image_name: &ImageName) -> Result<()> {
+        if !self.allow {
+            return Err(Error::RegistryNotAllowed {
+                image: image_name.clone(),
+                rule: format!("allow = false for \"{}\"{}", pattern, self.location()),
+            });
+        }
+        if self.require_digest && !image_name.reference.is_digest() {
+            return Err(Error::DigestRequired {
+                image: image_name.clone(),
+                rule: format!(
+                    "require-digest = true for \"{}\"{}",
+                    pattern,
+                    self.location()
+                ),
+            });
+        }
+        Ok(())
+    }
+
+    /// Verify the manifest of `digest` is signed for one of [TrustRule::require_signature]
+    ///
+    /// Returns the requirement satisfied, or `None` if no signature is required.
+    pub fn verify_signature(
+        &self,
+        client: &mut Client,
+        digest: &Digest,
+    ) -> Result<Option<&SignatureRequirement>> {
+        let mut last_error = None;
+        for requirement in &self.require_signature {
+            match requirement.verification_policy()?.verify(client, digest) {
+                Ok(()) => return Ok(Some(requirement)),
+                Err(e @ (Error::SignatureMissing(_) | Error::SignatureInvalid(_))) => {
+                    log::debug!("Not signed for {}: {}", requirement, e);
+                    last_error = Some(e);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        match last_error {
+            Some(e) => Err(e),
+            None => Ok(None),
+        }
+    }
+
+    fn location(&self) -> String {
+        match &self.source {
+            Some(path) => format!(" in {}", path.display()),
+            None => String::new(),
+        }
+    }
+}
+
+impl TrustPolicy {
+    /// Load the policy in the config directory and [PROJECT_TRUST_POLICY]
+    pub fn load() -> Result<Self> {
+        let mut policy = TrustPolicy::default();
+        let paths = [trust_policy_path(), project_trust_policy_path()];
+        for path in paths.into_iter().flatten() {
+            if path.is_file() {
+                policy.merge(TrustPolicy::from_file(&path)?);
+            }
+        }
+        Ok(policy)
+    }
+
+    /// Load a policy file, where unknown keys are rejected by [Error::InvalidTrustPolicy]
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let mut policy: TrustPolicy = toml::from_str(&fs::read_to_string(path)?)
+            .map_err(|e| Error::InvalidTrustPolicy(path.to_owned(), e.message().to_string()))?;
+        let dir = path.parent().unwrap_or(Path::new("."));
+        for rule in policy.rules.values_mut() {
+            rule.source = Some(path.to_owned());
+            for requirement in &mut rule.require_signature {
+                requirement.resolve(dir);
+            }
+        }
+        Ok(policy)
+    }
+
+    /// Override the rules of the same patterns by `other`
+    pub fn merge(&mut self, other: TrustPolicy) {
+        self.rules.extend(other.rules);
+    }
+
+    /// The pattern and rule applied to the image, i.e. of the longest matching pattern
+    pub fn rule(&self, image_name: &ImageName) -> Option<(&str, &TrustRule)> {
+        self.rules
+            .iter()
+            .filter(|(pattern, _)| matches_repository(pattern, image_name))
+            .max_by_key(|(pattern, _)| pattern.len())
+            .map(|(pattern, rule)| (pattern.as_str(), rule))
+    }
+
+    /// Check the image name without accessing registry, see [TrustRule::check]
+    pub fn check(&self, image_name: &ImageName) -> Result<()> {
+        match self.rule(image_name) {
+            Some((pattern, rule)) => rule.check(pattern, image_name),
+            None => Ok(()),
+        }
+    }
+
+    /// Explain which rule is applied to the image, and whether it is satisfied
+    ///
+    /// Signatures are verified for the manifest in registry if required.
+    pub fn explain(&self, image_name: &ImageName) -> Result<Vec<Check>> {
+        let Some((pattern, rule)) = self.rule(image_name) else {
+            return Ok(vec![Check::pass(
+                "policy-rule",
+                "no rule matches, allowed without requirements",
+            )]);
+        };
+        let mut checks = vec![Check::pass(
+            "policy-rule",
+            format!("\"{}\"{}", pattern, rule.location()),
+        )];
+        checks.push(if rule.allow {
+            Check::pass("allow", "allowed")
+        } else {
+            Check::fail("allow", "denied by allow = false")
+        });
+        if rule.require_digest {
+            checks.push(if image_name.reference.is_digest() {
+                Check::pass("require-digest", "pinned to a digest")
+            } else {
+                Check::fail(
+                    "require-digest",
+                    "not pinned to a digest, pin it as `name@sha256:...`",
+                )
+            });
+        }
+        if !rule.require_signature.is_empty() {
+            let mut client = Client::for_pull(image_name)?;
+            let digest = client.get_manifest_digest(&image_name.reference)?;
+            checks.push(match rule.verify_signature(&mut client, &digest) {
+                Ok(requirement) => Check::pass(
+                    "require-signature",
+                    format!(
+                        "{} is signed for {}",
+                        digest,
+                        requirement.expect("Signature is required")
+                    ),
+                ),
+                Err(e @ (Error::SignatureMissing(_) | Error::SignatureInvalid(_))) => {
+                    let required = rule
+                        .require_signature
+                        .iter()
+                        .map(|r| r.to_string())
+                        .collect::<Vec<_>>();
+                    Check::fail(
+                        "require-signature",
+                        format!("{}, one of {} is required", e, required.join(", ")),
+                    )
+                }
+                Err(e) => return Err(e),
+            });
+        }
+        Ok(checks)
+    }
+}
+
+/// Path of the trust policy file in the config directory
+pub fn trust_policy_path() -> Option<PathBuf> {
+    let dirs = directories::ProjectDirs::from("", "", "ocipkg")?;
+    Some(dirs.config_dir().join("policy.toml"))
+}
+
+/// [PROJECT_TRUST_POLICY] in the current directory or its nearest ancestor
+pub fn project_trust_policy_path() -> Option<PathBuf> {
+    let cwd = std::env::current_dir().ok()?;
+    cwd.ancestors()
+        .map(|dir| dir.join(PROJECT_TRUST_POLICY))
+        .find(|path| path.is_file())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const POLICY: &str = r#"
+        ["ghcr.io/myorg"]
+        require-signature = ["key:ops.pub", "cert:/etc/ocipkg/root.pem"]
+
+        ["ghcr.io/myorg/legacy"]
+        require-digest = true
+
+        ["quay.io/*"]
+        require-digest = true
+
+        ["ghcr.io/untrusted"]
+        allow = false
+    "#;
+
+    #[test]
+    fn rule() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("policy.toml");
+        fs::write(&path, POLICY)?;
+        let policy = TrustPolicy::from_file(&path)?;
+
+        let (pattern, rule) = policy
+            .rule(&ImageName::parse("ghcr.io/myorg/app:v1")?)
+            .unwrap();
+        assert_eq!(pattern, "ghcr.io/myorg");
+        assert_eq!(
+            rule.require_signature,
+            [
+                SignatureRequirement::CosignKey(dir.path().join("ops.pub")),
+                SignatureRequirement::NotationCert("/etc/ocipkg/root.pem".into()),
+            ]
+        );
+        assert_eq!(rule.source.as_deref(), Some(path.as_path()));
+
+        // Longest pattern is applied
+        let legacy = ImageName::parse("ghcr.io/myorg/legacy/app:v1")?;
+        assert_eq!(policy.rule(&legacy).unwrap().0, "ghcr.io/myorg/legacy");
+        assert!(matches!(
+            policy.check(&legacy),
+            Err(Error::DigestRequired { .. })
+        ));
+        policy.check(&ImageName::parse(
+            "ghcr.io/myorg/legacy/app@sha256:e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855",
+        )?)?;
+        assert!(matches!(
+            policy.check(&ImageName::parse("quay.io/org/app:v1")?),
+            Err(Error::DigestRequired { .. })
+        ));
+        assert!(matches!(
+            policy.check(&ImageName::parse("ghcr.io/untrusted/app:v1")?),
+            Err(Error::RegistryNotAllowed { .. })
+        ));
+        assert!(policy
+            .rule(&ImageName::parse("ghcr.io/other/app:v1")?)
+            .is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn merge() -> Result<()> {
+        let mut policy: TrustPolicy = toml::from_str(POLICY)?;
+        policy.merge(toml::from_str(
+            r#"
+            ["ghcr.io/untrusted"]
+            allow = true
+            "#,
+        )?);
+        policy.check(&ImageName::parse("ghcr.io/untrusted/app:v1")?)?;
+        assert_eq!(policy.rules.len(), 4);
+        Ok(())
+    }
+
+    #[test]
+    fn invalid() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("policy.toml");
+        for invalid in [
+            "[\"ghcr.io\"]\nrequire-signatures = [\"key:ops.pub\"]",
+            "[\"ghcr.io\"]\nrequire-signature = [\"ops.pub\"]",
+            "require-digest = true",
+        ] {
+            fs::write(&path, invalid)?;
+            assert!(matches!(
+                TrustPolicy::from_file(&path),
+                Err(Error::InvalidTrustPolicy(..))
+            ));
+        }
+        Ok(())
+    }
+}
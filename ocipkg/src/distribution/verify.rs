@@ -0,0 +1,360 @@
+use crate::{
+    distribution::{
+        notation::{find_notation_signature, parse_certificates},
+        Client,
+    },
+    error::*,
+    image::{
+        find_manifest_for_platform, is_index_media_type,
+        lint::{check_size, Check, LintOptions},
+        platform_string, PlatformEx,
+    },
+    Digest, ImageName, Reference,
+};
+use base64::Engine;
+use oci_spec::image::{ImageConfiguration, ImageIndex, ImageManifest, Platform};
+
+/// Artifact type of cosign signatures attached by the referrers API
+pub const COSIGN_SIGNATURE_ARTIFACT_TYPE: &str = "application/vnd.dev.cosign.artifact.sig.v1+json";
+
+/// Annotation key of cosign signature layer holding the base64-encoded signature of the layer
+pub(crate) const COSIGN_SIGNATURE: &str = "dev.cosignproject.cosign/signature";
+
+/// DER header of SubjectPublicKeyInfo for ECDSA P-256 key followed by the 65 bytes uncompressed point
+const P256_SPKI_PREFIX: &[u8] = &[
+    0x30, 0x59, 0x30, 0x13, 0x06, 0x07, 0x2a, 0x86, 0x48, 0xce, 0x3d, 0x02, 0x01, 0x06, 0x08, 0x2a,
+    0x86, 0x48, 0xce, 0x3d, 0x03, 0x01, 0x07, 0x03, 0x42, 0x00,
+];
+
+/// Check an image in registry without downloading layers
+///
+/// The manifest for the current platform is fetched, and its digest is compared with the one
+/// reported by the registry or listed in the image index.
+/// Blobs are checked to exist with the expected sizes by `HEAD` requests,
+/// and the number of `diff_ids` in the image configuration is compared with the layers.
+/// The total size of layers is checked against `max_size` if set.
+pub fn verify_remote_image(
+    client: &mut Client,
+    image_name: &ImageName,
+    max_size: Option<u64>,
+) -> Result<Vec<Check>> {
+    let mut checks = Vec::new();
+    let (media_type, mut buf) = client.get_raw_manifest(&image_name.reference)?;
+    let mut expected = client.get_manifest_digest(&image_name.reference)?;
+    if is_index_media_type(&media_type) {
+        checks.push(check_digest("index", &expected, &buf));
+        let index = ImageIndex::from_reader(buf.as_slice())?;
+        let platform = Platform::from_cfg_macro();
+        let desc = find_manifest_for_platform(&index, &platform)
+            .ok_or_else(|| Error::MissingPlatform(platform_string(&platform)))?;
+        expected = Digest::new(desc.digest())?;
+        buf = client.get_raw_manifest(&Reference::new(desc.digest())?)?.1;
+    }
+    checks.push(check_digest("manifest", &expected, &buf));
+    let manifest = ImageManifest::from_reader(buf.as_slice())?;
+
+    let descs: Vec<_> = manifest
+        .layers()
+        .iter()
+        .chain([manifest.config()])
+        .collect();
+    let digests = descs
+        .iter()
+        .map(|desc| Digest::new(desc.digest()))
+        .collect::<Result<Vec<_>>>()?;
+    let concurrency = client.max_concurrent_requests();
+    let stats = client.get_blobs_metadata(&digests, concurrency)?;
+    for (desc, digest) in descs.iter().zip(&digests) {
+        let name = format!("blob {}", digest);
+        checks.push(match &stats[digest] {
+            Some(stat) if stat.size == desc.size() as u64 => {
+                Check::pass(&name, format!("{} bytes", stat.size))
+            }
+            Some(stat) => Check::fail(
+                &name,
+                format!("{} bytes, but {} bytes expected", stat.size, desc.size()),
+            ),
+            None => Check::fail(&name, "missing in registry"),
+        });
+    }
+
+    let config = client.get_blob(digests.last().unwrap())?;
+    checks.push(check_digest(
+        "config",
+        digests.last().unwrap(),
+        config.as_slice(),
+    ));
+    let config = ImageConfiguration::from_reader(config.as_slice())?;
+    let diff_ids = config.rootfs().diff_ids().len();
+    checks.push(if diff_ids == manifest.layers().len() {
+        Check::pass(
+            "diff-ids",
+            format!(
+                "{} diff_ids for layers, contents are not downloaded",
+                diff_ids
+            ),
+        )
+    } else {
+        Check::fail(
+            "diff-ids",
+            format!(
+                "{} diff_ids in configuration, but {} layers in manifest",
+                diff_ids,
+                manifest.layers().len()
+            ),
+        )
+    });
+
+    if max_size.is_some() {
+        let options = LintOptions {
+            max_size,
+            ..Default::default()
+        };
+        checks.push(check_size(image_name, &manifest, &options));
+    }
+    Ok(checks)
+}
+
+/// Verify cosign signature of an image in local storage
+///
+/// The stored manifest is kept as it is in the registry, and its digest is verified
+/// by [verify_cosign_signature] with the signature in the registry where the image was fetched,
+/// recorded by [crate::local::image_provenance].
+pub fn verify_local_image_signature(name: &ImageName, public_key_pem: &str) -> Result<Vec<Check>> {
+    let path = crate::local::image_dir(name)?.join(".manifest.json");
+    if !path.is_file() {
+        return Err(Error::NotAFile(path));
+    }
+    let digest = Digest::from_file(&path)?;
+    let source = match crate::local::image_provenance(name)? {
+        Some(provenance) => ImageName::parse(&provenance.actual)?,
+        None => name.clone(),
+    };
+    let mut client = Client::from_image_name(&source)?;
+    Ok(vec![verify_cosign_signature(
+        &mut client,
+        &digest,
+        public_key_pem,
+    )?])
+}
+
+/// Policy to verify images before they are stored by [crate::distribution::get_verified_image]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum VerificationPolicy {
+    /// Cosign signature by ECDSA P-256 public key in PEM, e.g. `cosign.pub`
+    PublicKey(String),
+    /// Notation signature in JWS envelope by a certificate chained to one of the roots in DER
+    Notation { trusted_roots: Vec<Vec<u8>> },
+}
+
+impl VerificationPolicy {
+    /// [VerificationPolicy::Notation] trusting the X.509 root certificates in PEM
+    pub fn notation_from_pem(pem: &str) -> Result<Self> {
+        Ok(VerificationPolicy::Notation {
+            trusted_roots: parse_certificates(pem)?,
+        })
+    }
+
+    /// Returns [Error::SignatureMissing] or [Error::SignatureInvalid] if the manifest of `digest` is not signed
+    pub fn verify(&self, client: &mut Client, digest: &Digest) -> Result<()> {
+        let reference = match self {
+            VerificationPolicy::PublicKey(pem) => find_cosign_signature(client, digest, pem)?,
+            VerificationPolicy::Notation { trusted_roots } => {
+                find_notation_signature(client, digest, trusted_roots)?
+            }
+        };
+        log::info!("Signature verified: {} in {}", digest, reference);
+        Ok(())
+    }
+}
+
+/// Verify cosign signature of the manifest by ECDSA P-256 public key in PEM, e.g. `cosign.pub`
+///
+/// Signatures are looked up by the referrers API, and then by the `sha256-<hex>.sig` tag
+/// used by cosign by default. Keyless signatures with Fulcio certificates are not supported.
+pub fn verify_cosign_signature(
+    client: &mut Client,
+    digest: &Digest,
+    public_key_pem: &str,
+) -> Result<Check> {
+    match find_cosign_signature(client, digest, public_key_pem) {
+        Ok(reference) => Ok(Check::pass(
+            "cosign-signature",
+            format!("signed for {} in {}", digest, reference),
+        )),
+        Err(e @ (Error::SignatureMissing(_) | Error::SignatureInvalid(_))) => {
+            Ok(Check::fail("cosign-signature", e.to_string()))
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// Reference to the signature manifest holding a valid signature for the digest
+fn find_cosign_signature(
+    client: &mut Client,
+    digest: &Digest,
+    public_key_pem: &str,
+) -> Result<Reference> {
+    let public_key = parse_public_key(public_key_pem)?;
+
+    let mut signatures = Vec::new();
+    match client.get_referrers(digest, Some(COSIGN_SIGNATURE_ARTIFACT_TYPE)) {
+        Ok(Some(index)) => {
+            for desc in index.manifests() {
+                if desc.artifact_type().as_ref().map(|ty| ty.to_string())
+                    == Some(COSIGN_SIGNATURE_ARTIFACT_TYPE.to_string())
+                {
+                    signatures.push(Reference::new(desc.digest())?);
+                }
+            }
+        }
+        Ok(None) => {}
+        Err(e) => log::debug!("Referrers API is not available: {}", e),
+    }
+    let tag = Reference::new(&format!("{}-{}.sig", digest.algorithm, digest.encoded))?;
+    if client.head_manifest(&tag)?.is_some() {
+        signatures.push(tag);
+    }
+    if signatures.is_empty() {
+        return Err(Error::SignatureMissing(digest.clone()));
+    }
+
+    for reference in signatures {
+        let manifest = client.get_manifest(&reference)?;
+        for layer in manifest.layers() {
+            let Some(signature) = layer
+                .annotations()
+                .as_ref()
+                .and_then(|a| a.get(COSIGN_SIGNATURE))
+            else {
+                continue;
+            };
+            let payload = client.get_blob(&Digest::new(layer.digest())?)?;
+            if verify_simple_signing(&public_key, &payload, signature, digest) {
+                return Ok(reference);
+            }
+        }
+    }
+    Err(Error::SignatureInvalid(digest.clone()))
+}
+
+fn check_digest(name: &str, expected: &Digest, buf: &[u8]) -> Check {
+    let actual = Digest::from_buf_sha256(buf);
+    if &actual == expected {
+        Check::pass(name, format!("{}", actual))
+    } else {
+        Check::fail(
+            name,
+            format!("content has {}, but {} expected", actual, expected),
+        )
+    }
+}
+
+/// Uncompressed point of ECDSA P-256 public key in PEM
+fn parse_public_key(pem: &str) -> Result<Vec<u8>> {
+    let der = decode_pem(pem)
+        .ok_or_else(|| Error::InvalidPublicKey("not a base64-encoded PEM".to_string()))?;
+    match der.strip_prefix(P256_SPKI_PREFIX) {
+        Some(point) if point.len() == 65 => Ok(point.to_vec()),
+        _ => Err(Error::InvalidPublicKey(
+            "not an ECDSA P-256 public key".to_string(),
+        )),
+    }
+}
+
+/// DER in PEM, ignoring the header and footer lines
+pub(crate) fn decode_pem(pem: &str) -> Option<Vec<u8>> {
+    let body: String = pem
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with("-----"))
+        .collect();
+    base64::engine::general_purpose::STANDARD.decode(body).ok()
+}
+
+/// Check the signature of the simple signing payload and that the payload points to the digest
+pub(crate) fn verify_simple_signing(
+    public_key: &[u8],
+    payload: &[u8],
+    signature: &str,
+    digest: &Digest,
+) -> bool {
+    let Ok(signature) = base64::engine::general_purpose::STANDARD.decode(signature) else {
+        return false;
+    };
+    let key = ring::signature::UnparsedPublicKey::new(
+        &ring::signature::ECDSA_P256_SHA256_ASN1,
+        public_key,
+    );
+    if key.verify(payload, &signature).is_err() {
+        return false;
+    }
+    let Ok(payload) = serde_json::from_slice::<serde_json::Value>(payload) else {
+        return false;
+    };
+    payload["critical"]["image"]["docker-manifest-digest"].as_str() == Some(&digest.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ring::{
+        rand::SystemRandom,
+        signature::{EcdsaKeyPair, KeyPair, ECDSA_P256_SHA256_ASN1_SIGNING},
+    };
+
+    #[test]
+    fn simple_signing() -> Result<()> {
+        let rng = SystemRandom::new();
+        let pkcs8 = EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_ASN1_SIGNING, &rng).unwrap();
+        let key_pair =
+            EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_ASN1_SIGNING, pkcs8.as_ref(), &rng)
+                .unwrap();
+        let der = [P256_SPKI_PREFIX, key_pair.public_key().as_ref()].concat();
+        let pem = format!(
+            "-----BEGIN PUBLIC KEY-----\n{}\n-----END PUBLIC KEY-----\n",
+            base64::engine::general_purpose::STANDARD.encode(der)
+        );
+        let public_key = parse_public_key(&pem)?;
+
+        let digest = Digest::from_buf_sha256(b"manifest");
+        let payload = serde_json::json!({
+            "critical": {
+                "identity": { "docker-reference": "ghcr.io/termoshtt/ocipkg/testing" },
+                "image": { "docker-manifest-digest": digest.to_string() },
+                "type": "cosign container image signature"
+            },
+            "optional": null
+        })
+        .to_string();
+        let signature = base64::engine::general_purpose::STANDARD
+            .encode(key_pair.sign(&rng, payload.as_bytes()).unwrap());
+
+        assert!(verify_simple_signing(
+            &public_key,
+            payload.as_bytes(),
+            &signature,
+            &digest
+        ));
+        let other = Digest::from_buf_sha256(b"other");
+        assert!(!verify_simple_signing(
+            &public_key,
+            payload.as_bytes(),
+            &signature,
+            &other
+        ));
+        let tampered = payload.replace("testing", "tampered");
+        assert!(!verify_simple_signing(
+            &public_key,
+            tampered.as_bytes(),
+            &signature,
+            &digest
+        ));
+
+        assert!(
+            parse_public_key("-----BEGIN PUBLIC KEY-----\nAAAA\n-----END PUBLIC KEY-----").is_err()
+        );
+        Ok(())
+    }
+}
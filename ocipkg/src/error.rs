@@ -1,5 +1,8 @@
-use crate::Digest;
-use oci_spec::{distribution::ErrorResponse, OciSpecError};
+use crate::{Digest, ImageName};
+use oci_spec::{
+    distribution::{ErrorCode, ErrorResponse},
+    OciSpecError,
+};
 use std::path::PathBuf;
 
 #[derive(Debug, thiserror::Error)]
@@ -11,6 +14,8 @@ pub enum Error {
     InvalidDigest(String),
     #[error("Invalid name for repository: {0}")]
     InvalidName(String),
+    #[error("Invalid hostname, IPv6 address must be in brackets as [fd00::5]: {0}")]
+    InvalidHostname(String),
     #[error(transparent)]
     InvalidPort(#[from] std::num::ParseIntError),
     #[error("Invalid reference to image: {0}")]
@@ -19,10 +24,40 @@ pub enum Error {
     InvalidUrl(#[from] url::ParseError),
     #[error("Invalid target-triple: {0}")]
     InvalidTargetTriple(String),
+    #[error("Invalid platform, must be {{os}}/{{architecture}}[/{{variant}}]: {0}")]
+    InvalidPlatform(String),
+    #[error("Invalid source replacement: {0}")]
+    InvalidSourceReplace(String),
+    #[error("Invalid image source, must be oci-archive:{{path}} or oci:{{path}}: {0}")]
+    InvalidImageSource(String),
+    #[error("Unsupported transport, must be docker://, oci:, oci-archive:, or local: {0}")]
+    UnsupportedTransport(String),
+    #[error("Invalid tag filter, must be glob or regex surrounded by slashes: {0}")]
+    InvalidTagFilter(String),
+    #[error("Invalid public key, only ECDSA P-256 key in PEM is supported: {0}")]
+    InvalidPublicKey(String),
+    #[error("Invalid private key, only unencrypted PKCS#8 of ECDSA P-256 or Ed25519 key in PEM is supported: {0}")]
+    InvalidPrivateKey(String),
+    #[error("Invalid X.509 certificate in PEM: {0}")]
+    InvalidCertificate(String),
+    #[error("Unknown SBOM format, neither SPDX nor CycloneDX in JSON: {0}")]
+    UnknownSbomFormat(PathBuf),
+    #[error("Invalid DSSE envelope: {0}")]
+    InvalidDsseEnvelope(String),
+    #[error("Invalid image configuration, {0}")]
+    InvalidImageConfig(String),
+    #[error("Invalid annotation, {0}")]
+    InvalidAnnotation(String),
     #[error("Not a file, or not exist: {0}")]
     NotAFile(PathBuf),
     #[error("Not a directory, or not exist: {0}")]
     NotADirectory(PathBuf),
+    #[error("Directory is not empty: {0}")]
+    DirectoryNotEmpty(PathBuf),
+    #[error("Push to reserved tag is not allowed: {0}")]
+    ReservedTag(String),
+    #[error("Tag already exists for another manifest: {0}")]
+    TagAlreadyExists(String),
     #[error("Try to get already existing image: {0}")]
     ImageAlreadyExists(PathBuf),
 
@@ -37,18 +72,79 @@ pub enum Error {
     MissingManifestName,
     #[error("No layer found in manifest")]
     MissingLayer,
+    #[error("No manifest for reference in index.json: {0}")]
+    UnknownManifestRef(String),
+    #[error("Several images are included, select one of them: {0:?}")]
+    AmbiguousImage(Vec<String>),
+    #[error("Digest mismatch, expected {expected}, but content has {actual}")]
+    DigestMismatch { expected: Digest, actual: Digest },
+    #[error("Blob of {size} bytes exceeds the limit of {limit} bytes")]
+    BlobTooLarge { size: u64, limit: u64 },
+    #[error("No such layer in manifest, must be index or digest: {0}")]
+    UnknownLayer(String),
+    #[error("Unsafe entry in layer, which may be written outside of destination: {0}")]
+    UnsafeLayerEntry(PathBuf),
+    #[error("No such file or directory in image: {0}")]
+    PathNotFoundInImage(PathBuf),
+    #[error("Unsupported layer media type: {0}")]
+    UnsupportedLayerType(String),
+    #[error("Expected image index or Docker manifest list, but got {0}")]
+    NotAManifestList(String),
+    #[error("{0} is a multi-platform image, use get_index and inspect the platforms of each manifest instead")]
+    UnexpectedImageIndex(String),
+    #[error("No manifest for platform {0} in image index")]
+    MissingPlatform(String),
+    #[error("No signature found for {0}")]
+    SignatureMissing(Digest),
+    #[error("No signature of {0} is valid for the key")]
+    SignatureInvalid(Digest),
+    #[error("No SBOM attached to {0}")]
+    SbomNotFound(Digest),
+    #[error("Invalid date time: {0}")]
+    InvalidDateTime(String),
     #[error(transparent)]
     InvalidJson(#[from] serde_json::error::Error),
     #[error(transparent)]
     InvalidToml(#[from] toml::de::Error),
+    #[error(transparent)]
+    TomlSerialize(#[from] toml::ser::Error),
 
     //
     // Error from OCI registry
     //
+    #[cfg(feature = "network")]
     #[error(transparent)]
     NetworkError(Box<ureq::Transport>),
-    #[error(transparent)]
-    RegistryError(#[from] ErrorResponse),
+    /// Error response defined in OCI distribution spec, with its HTTP status
+    #[error("{response}")]
+    RegistryError {
+        status: u16,
+        response: ErrorResponse,
+    },
+    #[error("Manifest not found in registry: {0}")]
+    ManifestNotFound(String),
+    #[error("Blob not found in registry: {0}")]
+    BlobNotFound(Digest),
+    #[error("Manifest {reference} did not appear in registry within {timeout:?}")]
+    Timeout {
+        reference: String,
+        timeout: std::time::Duration,
+    },
+    #[error("Manifest {reference} is Docker image manifest schema 1, which is not supported. {suggestion}")]
+    UnsupportedSchema1 {
+        reference: String,
+        suggestion: String,
+    },
+    #[error("Tag {tag} shares its manifest with other tags: {others:?}")]
+    TagSharedWithOthers { tag: String, others: Vec<String> },
+    #[error("Registry processed subject {actual}, but {expected} is expected")]
+    SubjectMismatch { expected: String, actual: String },
+    #[error("Invalid Range header from registry: {0}")]
+    InvalidRangeHeader(String),
+    #[error("Location not included in response for {0}")]
+    MissingLocation(url::Url),
+    #[error("Unexpected HTTP status from registry: {0}")]
+    UnexpectedStatus(u16),
     #[error("Authorization failed: {0}")]
     AuthorizationFailed(url::Url),
     #[error("Unsupported WWW-Authentication header: {0}")]
@@ -57,16 +153,116 @@ pub enum Error {
     //
     // System error
     //
+    #[error(
+        "Image {0} is not found in local storage, and network access is disabled in offline mode. \
+         Run `ocipkg get {0}` to prefetch it."
+    )]
+    OfflineImageMissing(ImageName),
+    #[error(
+        "Image {0} is not pinned to a digest, which is required by the pull policy. \
+         Pin it as `name@sha256:...`, lock it by `ocipkg lock update {0}`, or unset `OCIPKG_REQUIRE_DIGEST`."
+    )]
+    UnpinnedImage(ImageName),
+    #[error(
+        "Image {image} is resolved to {actual}, but locked to {locked} in ocipkg.lock. \
+         Run `ocipkg lock update {image}` if the change is intentional."
+    )]
+    LockedDigestMismatch {
+        image: String,
+        locked: Digest,
+        actual: Digest,
+    },
+    #[error("Registry of {image} is not allowed by the rule: {rule}")]
+    RegistryNotAllowed { image: ImageName, rule: String },
+    #[error("Image {image} is not pinned to a digest, which is required by the rule: {rule}")]
+    DigestRequired { image: ImageName, rule: String },
+    #[error("Invalid trust policy in {0}: {1}")]
+    InvalidTrustPolicy(PathBuf, String),
+    #[error("Environment variable is not set: {0}")]
+    MissingEnv(String),
     #[error("Project directory is tried to set twice")]
     ProjectDirectoryAlreadySet,
     #[error("No valid runtime directory where authentication info will be stored.")]
     NoValidRuntimeDirectory,
+    #[error("Operation is cancelled")]
+    Cancelled,
+    #[error("Post-publish command for {image} failed with {status}")]
+    PostPublishFailed { image: String, status: String },
     #[error(transparent)]
-    UnknownIo(#[from] std::io::Error),
+    UnknownIo(std::io::Error),
 }
 
 pub type Result<T> = std::result::Result<T, Error>;
 
+impl Error {
+    /// Worth retrying the same request later
+    ///
+    /// `true` for connection failures, timeouts, `429 Too Many Requests`,
+    /// and `500`, `502`, `503`, `504` responses regardless of their body,
+    /// or `TOOMANYREQUESTS` error. Other client errors, e.g. `404`,
+    /// and invalid inputs are not transient.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            #[cfg(feature = "network")]
+            Error::NetworkError(e) => matches!(
+                e.kind(),
+                ureq::ErrorKind::Dns
+                    | ureq::ErrorKind::ConnectionFailed
+                    | ureq::ErrorKind::Io
+                    | ureq::ErrorKind::ProxyConnect
+            ),
+            Error::UnexpectedStatus(status) => is_transient_status(*status),
+            Error::RegistryError { status, response } => {
+                is_transient_status(*status)
+                    || response
+                        .detail()
+                        .iter()
+                        .any(|info| info.code() == &ErrorCode::TooManyRequests)
+            }
+            Error::UnknownIo(e) => matches!(
+                e.kind(),
+                std::io::ErrorKind::TimedOut
+                    | std::io::ErrorKind::ConnectionRefused
+                    | std::io::ErrorKind::ConnectionReset
+                    | std::io::ErrorKind::ConnectionAborted
+                    | std::io::ErrorKind::Interrupted
+            ),
+            _ => false,
+        }
+    }
+
+    /// Rejected by the registry for the credentials, i.e. `401` or `403` response with any body,
+    /// or `UNAUTHORIZED` or `DENIED` error, which may be resolved by refreshing credentials
+    pub fn is_auth_error(&self) -> bool {
+        match self {
+            Error::UnexpectedStatus(status) => is_auth_status(*status),
+            Error::RegistryError { status, response } => {
+                is_auth_status(*status)
+                    || response.detail().iter().any(|info| {
+                        matches!(info.code(), ErrorCode::Unauthorized | ErrorCode::Denied)
+                    })
+            }
+            _ => false,
+        }
+    }
+
+    /// HTTP status of the error response from the registry
+    pub fn status(&self) -> Option<u16> {
+        match self {
+            Error::UnexpectedStatus(status) | Error::RegistryError { status, .. } => Some(*status),
+            _ => None,
+        }
+    }
+}
+
+fn is_transient_status(status: u16) -> bool {
+    matches!(status, 429 | 500 | 502 | 503 | 504)
+}
+
+fn is_auth_status(status: u16) -> bool {
+    matches!(status, 401 | 403)
+}
+
 impl From<OciSpecError> for Error {
     fn from(e: OciSpecError) -> Self {
         match e {
@@ -78,20 +274,177 @@ impl From<OciSpecError> for Error {
     }
 }
 
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Self {
+        if is_cancelled_io(&e) {
+            return Self::Cancelled;
+        }
+        if let Some(&TooLargeIo { size, limit }) =
+            e.get_ref().and_then(|e| e.downcast_ref::<TooLargeIo>())
+        {
+            return Self::BlobTooLarge { size, limit };
+        }
+        if let Some(DigestMismatchIo { expected, actual }) = e
+            .get_ref()
+            .and_then(|e| e.downcast_ref::<DigestMismatchIo>())
+        {
+            return Self::DigestMismatch {
+                expected: expected.clone(),
+                actual: actual.clone(),
+            };
+        }
+        Self::UnknownIo(e)
+    }
+}
+
 impl From<walkdir::Error> for Error {
     fn from(e: walkdir::Error) -> Self {
         Self::UnknownIo(e.into())
     }
 }
 
+#[cfg(feature = "network")]
 impl From<ureq::Error> for Error {
     fn from(e: ureq::Error) -> Self {
         match e {
-            ureq::Error::Status(_status, res) => match res.into_json::<ErrorResponse>() {
-                Ok(err) => Error::RegistryError(err),
-                Err(e) => Error::UnknownIo(e),
-            },
-            ureq::Error::Transport(e) => Error::NetworkError(e.into()),
+            ureq::Error::Status(status, res) => Error::from_status(status, res),
+            ureq::Error::Transport(e) => e.into(),
+        }
+    }
+}
+
+#[cfg(feature = "network")]
+impl From<ureq::Transport> for Error {
+    fn from(e: ureq::Transport) -> Self {
+        use std::error::Error as _;
+        let cancelled = e
+            .source()
+            .and_then(|source| source.downcast_ref::<std::io::Error>())
+            .is_some_and(is_cancelled_io);
+        if cancelled {
+            Error::Cancelled
+        } else {
+            Error::NetworkError(e.into())
         }
     }
 }
+
+/// Payload of [std::io::Error] raised by cancelled reads and requests,
+/// converted into [Error::Cancelled]
+#[derive(Debug)]
+struct CancelledIo;
+
+impl std::fmt::Display for CancelledIo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Operation is cancelled")
+    }
+}
+
+impl std::error::Error for CancelledIo {}
+
+#[cfg_attr(not(feature = "network"), allow(dead_code))]
+pub(crate) fn cancelled_io() -> std::io::Error {
+    std::io::Error::other(CancelledIo)
+}
+
+fn is_cancelled_io(e: &std::io::Error) -> bool {
+    e.get_ref().is_some_and(|e| e.is::<CancelledIo>())
+}
+
+/// Payload of [std::io::Error] raised by reads exceeding the size limit,
+/// converted into [Error::BlobTooLarge]
+#[derive(Debug, Clone, Copy)]
+struct TooLargeIo {
+    size: u64,
+    limit: u64,
+}
+
+impl std::fmt::Display for TooLargeIo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Blob of {} bytes exceeds the limit of {} bytes",
+            self.size, self.limit
+        )
+    }
+}
+
+impl std::error::Error for TooLargeIo {}
+
+#[cfg_attr(not(feature = "network"), allow(dead_code))]
+pub(crate) fn too_large_io(size: u64, limit: u64) -> std::io::Error {
+    std::io::Error::other(TooLargeIo { size, limit })
+}
+
+/// Payload of [std::io::Error] raised by reads of content not having the expected digest,
+/// converted into [Error::DigestMismatch]
+#[derive(Debug)]
+struct DigestMismatchIo {
+    expected: Digest,
+    actual: Digest,
+}
+
+impl std::fmt::Display for DigestMismatchIo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Digest mismatch, expected {}, but content has {}",
+            self.expected, self.actual
+        )
+    }
+}
+
+impl std::error::Error for DigestMismatchIo {}
+
+#[cfg_attr(not(feature = "network"), allow(dead_code))]
+pub(crate) fn digest_mismatch_io(expected: Digest, actual: Digest) -> std::io::Error {
+    std::io::Error::other(DigestMismatchIo { expected, actual })
+}
+
+#[cfg(feature = "network")]
+impl Error {
+    /// Error for non-success HTTP status
+    ///
+    /// The response body is kept with the status code if it is the error response
+    /// defined in OCI distribution spec.
+    /// Otherwise, e.g. for `HEAD` requests without body, only the status code is kept.
+    pub(crate) fn from_status(status: u16, res: ureq::Response) -> Self {
+        match res.into_json::<ErrorResponse>() {
+            Ok(response) => Error::RegistryError { status, response },
+            Err(_) => Error::UnexpectedStatus(status),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn registry_error(status: u16, code: &str) -> Error {
+        let body = format!(r#"{{"errors":[{{"code":"{}","message":"m"}}]}}"#, code);
+        Error::RegistryError {
+            status,
+            response: serde_json::from_str(&body).unwrap(),
+        }
+    }
+
+    #[test]
+    fn classify() {
+        assert!(Error::UnexpectedStatus(503).is_transient());
+        assert!(Error::UnexpectedStatus(429).is_transient());
+        assert!(registry_error(429, "TOOMANYREQUESTS").is_transient());
+        assert!(Error::from(std::io::Error::from(std::io::ErrorKind::TimedOut)).is_transient());
+        assert!(!Error::UnexpectedStatus(404).is_transient());
+        assert!(!registry_error(404, "MANIFEST_UNKNOWN").is_transient());
+        assert!(registry_error(503, "UNSUPPORTED").is_transient());
+        assert!(!Error::InvalidDigest("x".to_string()).is_transient());
+
+        assert!(registry_error(401, "UNAUTHORIZED").is_auth_error());
+        assert!(registry_error(403, "DENIED").is_auth_error());
+        assert!(Error::UnexpectedStatus(401).is_auth_error());
+        assert!(!registry_error(404, "NAME_UNKNOWN").is_auth_error());
+        assert!(registry_error(401, "NAME_UNKNOWN").is_auth_error());
+        assert!(registry_error(403, "UNSUPPORTED").is_auth_error());
+        assert!(!Error::UnexpectedStatus(503).is_auth_error());
+    }
+}
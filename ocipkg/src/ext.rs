@@ -0,0 +1,225 @@
+use crate::{error::*, Digest, ImageName};
+use oci_spec::image::{Descriptor, DescriptorBuilder, ImageManifest, MediaType};
+use std::{collections::HashMap, fs, path::Path};
+
+/// Convenient accessors to [Descriptor]
+///
+/// ```
+/// use ocipkg::{DescriptorExt, ImageIndex};
+///
+/// let index = ImageIndex::from_reader(
+///     r#"{
+///       "schemaVersion": 2,
+///       "manifests": [{
+///         "mediaType": "application/vnd.oci.image.manifest.v1+json",
+///         "digest": "sha256:4bc453b53cb3d914b45f4b250294236adba2c0e09ff6f03793949e7e39fd4cc1",
+///         "size": 100,
+///         "annotations": { "org.opencontainers.image.ref.name": "ghcr.io/termoshtt/ocipkg/testing:v1" }
+///       }]
+///     }"#
+///     .as_bytes(),
+/// )
+/// .unwrap();
+/// let desc = &index.manifests()[0];
+/// assert_eq!(desc.parsed_digest().unwrap().algorithm, "sha256");
+/// assert_eq!(desc.ref_name(), Some("ghcr.io/termoshtt/ocipkg/testing:v1"));
+/// assert_eq!(desc.image_name().unwrap().reference.as_str(), "v1");
+/// assert!(!desc.is_layer());
+/// ```
+pub trait DescriptorExt {
+    /// Descriptor of the file with its SHA-256 digest and size, where the file is not kept in memory
+    fn from_file(path: &Path, media_type: MediaType) -> Result<Self>
+    where
+        Self: Sized;
+    /// Parse `digest` field as [Digest]
+    fn parsed_digest(&self) -> Result<Digest>;
+    /// Get value of an annotation
+    fn annotation(&self, key: &str) -> Option<&str>;
+    /// Get `org.opencontainers.image.ref.name` annotation
+    fn ref_name(&self) -> Option<&str>;
+    /// Parse `org.opencontainers.image.ref.name` annotation as [ImageName],
+    /// or [Error::MissingManifestName] if it does not exist
+    fn image_name(&self) -> Result<ImageName>;
+    /// `true` if the media type is one of image layers
+    fn is_layer(&self) -> bool;
+}
+
+impl DescriptorExt for Descriptor {
+    fn from_file(path: &Path, media_type: MediaType) -> Result<Self> {
+        let size = fs::metadata(path)?.len();
+        let digest = Digest::from_file(path)?;
+        Ok(DescriptorBuilder::default()
+            .media_type(media_type)
+            .digest(digest.to_string())
+            .size(size as i64)
+            .build()?)
+    }
+
+    fn parsed_digest(&self) -> Result<Digest> {
+        Digest::new(self.digest())
+    }
+
+    fn annotation(&self, key: &str) -> Option<&str> {
+        get(self.annotations(), key)
+    }
+
+    fn ref_name(&self) -> Option<&str> {
+        self.annotation("org.opencontainers.image.ref.name")
+    }
+
+    fn image_name(&self) -> Result<ImageName> {
+        ImageName::parse(self.ref_name().ok_or(Error::MissingManifestName)?)
+    }
+
+    fn is_layer(&self) -> bool {
+        matches!(
+            self.media_type(),
+            MediaType::ImageLayer
+                | MediaType::ImageLayerGzip
+                | MediaType::ImageLayerZstd
+                | MediaType::ImageLayerNonDistributable
+                | MediaType::ImageLayerNonDistributableGzip
+                | MediaType::ImageLayerNonDistributableZstd
+        )
+    }
+}
+
+/// Convenient accessors to [ImageManifest]
+pub trait ManifestExt {
+    /// Parse digest of the configuration blob
+    fn config_digest(&self) -> Result<Digest>;
+    /// Media type of the configuration blob distinguishing the kind of artifact,
+    /// e.g. `application/vnd.oci.image.config.v1+json` for container images,
+    /// or `application/vnd.cncf.helm.config.v1+json` for Helm charts.
+    ///
+    /// `None` for the empty descriptor `application/vnd.oci.empty.v1+json`,
+    /// i.e. an artifact without configuration.
+    fn config_media_type(&self) -> Option<&MediaType>;
+    /// Number of layers
+    fn layer_count(&self) -> usize;
+    /// Parse digests of layers in the order of the manifest
+    fn layer_digests(&self) -> Result<Vec<Digest>>;
+    /// Sum of the sizes of the configuration and layers in bytes, excluding the manifest itself
+    fn total_size(&self) -> u64;
+    /// Get value of an annotation
+    fn annotation(&self, key: &str) -> Option<&str>;
+    /// Append the file as a layer by [DescriptorExt::from_file]
+    ///
+    /// Only the digest and size are recorded, and the file is pushed separately,
+    /// e.g. by [crate::distribution::Client::push_blob_from_file].
+    fn add_layer_from_file(&mut self, path: &Path, media_type: MediaType) -> Result<&mut Self>;
+}
+
+impl ManifestExt for ImageManifest {
+    fn config_digest(&self) -> Result<Digest> {
+        self.config().parsed_digest()
+    }
+
+    fn config_media_type(&self) -> Option<&MediaType> {
+        match self.config().media_type() {
+            MediaType::EmptyJSON => None,
+            media_type => Some(media_type),
+        }
+    }
+
+    fn layer_count(&self) -> usize {
+        self.layers().len()
+    }
+
+    fn layer_digests(&self) -> Result<Vec<Digest>> {
+        self.layers().iter().map(|l| l.parsed_digest()).collect()
+    }
+
+    fn total_size(&self) -> u64 {
+        std::iter::once(self.config())
+            .chain(self.layers())
+            .map(|desc| desc.size() as u64)
+            .sum()
+    }
+
+    fn annotation(&self, key: &str) -> Option<&str> {
+        get(self.annotations(), key)
+    }
+
+    fn add_layer_from_file(&mut self, path: &Path, media_type: MediaType) -> Result<&mut Self> {
+        self.layers_mut()
+            .push(Descriptor::from_file(path, media_type)?);
+        Ok(self)
+    }
+}
+
+fn get<'a>(annotations: &'a Option<HashMap<String, String>>, key: &str) -> Option<&'a str> {
+    annotations.as_ref()?.get(key).map(String::as_str)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use oci_spec::image::ImageManifestBuilder;
+
+    fn descriptor(media_type: MediaType, digest: &str, size: i64) -> Descriptor {
+        DescriptorBuilder::default()
+            .media_type(media_type)
+            .digest(digest)
+            .size(size)
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn manifest_ext() -> Result<()> {
+        let manifest = ImageManifestBuilder::default()
+            .schema_version(2_u32)
+            .config(descriptor(MediaType::ImageConfig, "sha256:00", 10))
+            .layers(vec![
+                descriptor(MediaType::ImageLayerGzip, "sha256:11", 20),
+                descriptor(MediaType::ImageLayer, "sha256:22", 30),
+            ])
+            .annotations(HashMap::from([("a".to_string(), "b".to_string())]))
+            .build()
+            .unwrap();
+        assert_eq!(manifest.config_digest()?, Digest::new("sha256:00")?);
+        assert_eq!(manifest.config_media_type(), Some(&MediaType::ImageConfig));
+        assert_eq!(manifest.layer_count(), 2);
+        assert_eq!(
+            manifest.layer_digests()?,
+            [Digest::new("sha256:11")?, Digest::new("sha256:22")?]
+        );
+        assert_eq!(manifest.total_size(), 60);
+        assert_eq!(manifest.annotation("a"), Some("b"));
+        assert_eq!(manifest.annotation("c"), None);
+        assert!(manifest.layers().iter().all(|l| l.is_layer()));
+        assert!(!manifest.config().is_layer());
+        assert!(matches!(
+            manifest.config().image_name(),
+            Err(Error::MissingManifestName)
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn add_layer_from_file() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("layer.tar.gz");
+        fs::write(&path, b"layer")?;
+        let mut manifest = ImageManifestBuilder::default()
+            .schema_version(2_u32)
+            .config(descriptor(MediaType::ImageConfig, "sha256:00", 10))
+            .layers(Vec::new())
+            .build()
+            .unwrap();
+        manifest
+            .add_layer_from_file(&path, MediaType::ImageLayerGzip)?
+            .add_layer_from_file(&path, MediaType::ImageLayer)?;
+        assert_eq!(manifest.layer_count(), 2);
+        let layer = &manifest.layers()[0];
+        assert_eq!(layer.media_type(), &MediaType::ImageLayerGzip);
+        assert_eq!(layer.parsed_digest()?, Digest::from_buf_sha256(b"layer"));
+        assert_eq!(layer.size(), 5);
+        assert_eq!(manifest.total_size(), 20);
+        assert!(manifest
+            .add_layer_from_file(&dir.path().join("missing"), MediaType::ImageLayer)
+            .is_err());
+        Ok(())
+    }
+}
@@ -1,2 +1,123 @@
 pub mod flat;
 pub mod nested;
+
+use crate::{error::*, Digest, ImageName};
+use regex::Regex;
+use std::collections::HashMap;
+
+/// Prefix of the pre-defined annotation keys in OCI image spec
+pub const PREFIX: &str = "org.opencontainers.image.";
+
+lazy_static::lazy_static! {
+    // `ref` in https://github.com/opencontainers/image-spec/blob/main/image-layout.md#indexjson-file
+    static ref REF_NAME_RE: Regex = Regex::new(
+        r"^[A-Za-z0-9]+(([-._:@+]|--)[A-Za-z0-9]+)*(/[A-Za-z0-9]+(([-._:@+]|--)[A-Za-z0-9]+)*)*$"
+    )
+    .unwrap();
+}
+
+/// Check the value of an annotation with [PREFIX] against the format defined in OCI image spec
+///
+/// Other keys are accepted with any value.
+///
+/// ```
+/// use ocipkg::image::annotations::validate;
+///
+/// assert!(validate("org.opencontainers.image.source", "https://github.com/termoshtt/ocipkg").is_ok());
+/// assert!(validate("org.opencontainers.image.source", "github.com/termoshtt/ocipkg").is_err());
+/// assert!(validate("org.opencontainers.image.created", "2024-01-01T00:00:00Z").is_ok());
+/// assert!(validate("org.opencontainers.image.unknown", "value").is_err());
+/// assert!(validate("com.example.key", "anything").is_ok());
+/// ```
+pub fn validate(key: &str, value: &str) -> Result<()> {
+    let Some(name) = key.strip_prefix(PREFIX) else {
+        return Ok(());
+    };
+    let invalid =
+        |format: &str| Error::InvalidAnnotation(format!("{} must be {}: {}", key, format, value));
+    match name {
+        "created" => {
+            chrono::DateTime::parse_from_rfc3339(value)
+                .map_err(|_| invalid("date-time as defined by RFC 3339"))?;
+        }
+        "url" | "documentation" | "source" => {
+            url::Url::parse(value).map_err(|_| invalid("URL"))?;
+        }
+        "base.digest" => {
+            Digest::new(value).map_err(|_| invalid("digest"))?;
+        }
+        "base.name" => {
+            ImageName::parse(value).map_err(|_| invalid("image name"))?;
+        }
+        "ref.name" => {
+            if !REF_NAME_RE.is_match(value) {
+                return Err(invalid("reference name"));
+            }
+        }
+        "authors" | "version" | "revision" | "vendor" | "licenses" | "title" | "description" => {}
+        _ => {
+            return Err(Error::InvalidAnnotation(format!(
+                "unknown pre-defined key: {}",
+                key
+            )))
+        }
+    }
+    Ok(())
+}
+
+/// Remove annotations of `remove` keys, and then set `set` after [validate]
+pub fn update(
+    annotations: &mut HashMap<String, String>,
+    set: &[(String, String)],
+    remove: &[String],
+) -> Result<()> {
+    for (key, value) in set {
+        validate(key, value)?;
+    }
+    for key in remove {
+        annotations.remove(key);
+    }
+    for (key, value) in set {
+        annotations.insert(key.clone(), value.clone());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn update_annotations() -> Result<()> {
+        let mut annotations = HashMap::from([
+            ("a".to_string(), "1".to_string()),
+            ("b".to_string(), "2".to_string()),
+        ]);
+        update(
+            &mut annotations,
+            &[
+                ("a".to_string(), "3".to_string()),
+                (
+                    "org.opencontainers.image.ref.name".to_string(),
+                    "v1.0--rc".to_string(),
+                ),
+            ],
+            &["b".to_string()],
+        )?;
+        assert_eq!(annotations.len(), 2);
+        assert_eq!(annotations["a"], "3");
+
+        // Nothing is changed if some value is invalid
+        let err = update(
+            &mut annotations,
+            &[(
+                "org.opencontainers.image.ref.name".to_string(),
+                "-v1".to_string(),
+            )],
+            &["a".to_string()],
+        );
+        assert!(matches!(err, Err(Error::InvalidAnnotation(_))));
+        assert_eq!(annotations["a"], "3");
+        Ok(())
+    }
+}
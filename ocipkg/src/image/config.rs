@@ -0,0 +1,240 @@
+use crate::error::*;
+use oci_spec::image::{Config, ImageConfiguration};
+use std::collections::HashMap;
+
+/// Build [ImageConfiguration] with methods corresponding to Dockerfile instructions
+///
+/// ```
+/// use ocipkg::image::ImageConfigBuilder;
+///
+/// let config = ImageConfigBuilder::new()
+///     .env("PATH", "/usr/local/bin:/usr/bin")
+///     .entrypoint(&["/usr/local/bin/app"])
+///     .cmd(&["--help"])
+///     .label("org.opencontainers.image.version", "1.0.0")
+///     .expose_port(8080, "tcp")
+///     .workdir("/app")
+///     .build()
+///     .unwrap();
+/// let inner = config.config().as_ref().unwrap();
+/// assert_eq!(inner.exposed_ports().as_ref().unwrap(), &["8080/tcp".to_string()]);
+/// assert_eq!(inner.working_dir().as_deref(), Some("/app"));
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ImageConfigBuilder {
+    base: ImageConfiguration,
+    env: Vec<(String, String)>,
+    cmd: Option<Vec<String>>,
+    entrypoint: Option<Vec<String>>,
+    labels: Vec<(String, String)>,
+    exposed_ports: Vec<(u16, String)>,
+    volumes: Vec<String>,
+    user: Option<String>,
+    workdir: Option<String>,
+}
+
+impl ImageConfigBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Inherit the settings of base image as `FROM` instruction
+    pub fn from_base(base: &ImageConfiguration) -> Self {
+        Self {
+            base: base.clone(),
+            ..Self::default()
+        }
+    }
+
+    /// Replace the base image inherited by [ImageConfigBuilder::from_base]
+    pub fn base(&mut self, base: &ImageConfiguration) -> &mut Self {
+        self.base = base.clone();
+        self
+    }
+
+    /// Set environment variable as `ENV`, which overrides the same key of the base image
+    pub fn env(&mut self, key: &str, value: &str) -> &mut Self {
+        self.env.push((key.to_string(), value.to_string()));
+        self
+    }
+
+    /// Set default arguments as `CMD`
+    pub fn cmd(&mut self, args: &[&str]) -> &mut Self {
+        self.cmd = Some(args.iter().map(|s| s.to_string()).collect());
+        self
+    }
+
+    /// Set the command as `ENTRYPOINT`.
+    /// As Dockerfile, `CMD` of the base image is cleared unless [ImageConfigBuilder::cmd] is called.
+    pub fn entrypoint(&mut self, args: &[&str]) -> &mut Self {
+        self.entrypoint = Some(args.iter().map(|s| s.to_string()).collect());
+        self
+    }
+
+    /// Set label as `LABEL`
+    pub fn label(&mut self, key: &str, value: &str) -> &mut Self {
+        self.labels.push((key.to_string(), value.to_string()));
+        self
+    }
+
+    /// Expose port as `EXPOSE`, where `protocol` is `tcp`, `udp`, or `sctp`
+    pub fn expose_port(&mut self, port: u16, protocol: &str) -> &mut Self {
+        self.exposed_ports.push((port, protocol.to_string()));
+        self
+    }
+
+    /// Add volume as `VOLUME`
+    pub fn volume(&mut self, path: &str) -> &mut Self {
+        self.volumes.push(path.to_string());
+        self
+    }
+
+    /// Set user as `USER`
+    pub fn user(&mut self, user: &str) -> &mut Self {
+        self.user = Some(user.to_string());
+        self
+    }
+
+    /// Set working directory as `WORKDIR`
+    pub fn workdir(&mut self, path: &str) -> &mut Self {
+        self.workdir = Some(path.to_string());
+        self
+    }
+
+    /// Returns [Error::InvalidImageConfig] for an empty or invalid key of environment variable,
+    /// an unknown protocol of port, or a relative path for volume or working directory.
+    pub fn build(&self) -> Result<ImageConfiguration> {
+        let mut image = self.base.clone();
+        let mut config = image.config().clone().unwrap_or_default();
+
+        if !self.env.is_empty() {
+            let mut env = config.env().clone().unwrap_or_default();
+            for (key, value) in &self.env {
+                if key.is_empty() || key.contains('=') {
+                    return Err(Error::InvalidImageConfig(format!(
+                        "environment variable name: {}",
+                        key
+                    )));
+                }
+                env.retain(|e| e.split_once('=').map_or(e.as_str(), |(k, _)| k) != key);
+                env.push(format!("{}={}", key, value));
+            }
+            config.set_env(Some(env));
+        }
+
+        if let Some(entrypoint) = &self.entrypoint {
+            config.set_entrypoint(Some(entrypoint.clone()));
+            if self.cmd.is_none() {
+                config.set_cmd(None);
+            }
+        }
+        if let Some(cmd) = &self.cmd {
+            config.set_cmd(Some(cmd.clone()));
+        }
+
+        if !self.labels.is_empty() {
+            let mut labels: HashMap<String, String> = config.labels().clone().unwrap_or_default();
+            labels.extend(self.labels.iter().cloned());
+            config.set_labels(Some(labels));
+        }
+
+        if !self.exposed_ports.is_empty() {
+            let mut ports = config.exposed_ports().clone().unwrap_or_default();
+            for (port, protocol) in &self.exposed_ports {
+                let protocol = protocol.to_lowercase();
+                if !matches!(protocol.as_str(), "tcp" | "udp" | "sctp") {
+                    return Err(Error::InvalidImageConfig(format!(
+                        "protocol of port {}: {}",
+                        port, protocol
+                    )));
+                }
+                let port = format!("{}/{}", port, protocol);
+                if !ports.contains(&port) {
+                    ports.push(port);
+                }
+            }
+            config.set_exposed_ports(Some(ports));
+        }
+
+        if !self.volumes.is_empty() {
+            let mut volumes = config.volumes().clone().unwrap_or_default();
+            for volume in &self.volumes {
+                check_absolute("volume", volume)?;
+                if !volumes.contains(volume) {
+                    volumes.push(volume.clone());
+                }
+            }
+            config.set_volumes(Some(volumes));
+        }
+
+        if let Some(user) = &self.user {
+            config.set_user(Some(user.clone()));
+        }
+        if let Some(workdir) = &self.workdir {
+            check_absolute("working directory", workdir)?;
+            config.set_working_dir(Some(workdir.clone()));
+        }
+
+        if config != Config::default() {
+            image.set_config(Some(config));
+        }
+        Ok(image)
+    }
+}
+
+fn check_absolute(name: &str, path: &str) -> Result<()> {
+    if path.starts_with('/') {
+        Ok(())
+    } else {
+        Err(Error::InvalidImageConfig(format!(
+            "{} must be absolute path: {}",
+            name, path
+        )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn inherit_base() -> Result<()> {
+        let base = ImageConfigBuilder::new()
+            .env("PATH", "/usr/bin")
+            .env("LANG", "C")
+            .cmd(&["sh"])
+            .label("a", "1")
+            .expose_port(80, "tcp")
+            .build()?;
+
+        let config = ImageConfigBuilder::from_base(&base)
+            .env("PATH", "/opt/bin:/usr/bin")
+            .entrypoint(&["/opt/bin/app"])
+            .label("b", "2")
+            .expose_port(80, "TCP")
+            .expose_port(53, "udp")
+            .volume("/data")
+            .user("nobody")
+            .build()?;
+        let config = config.config().as_ref().unwrap();
+        assert_eq!(
+            config.env().as_ref().unwrap(),
+            &["LANG=C".to_string(), "PATH=/opt/bin:/usr/bin".to_string()]
+        );
+        assert_eq!(config.cmd(), &None);
+        assert_eq!(config.labels().as_ref().unwrap().len(), 2);
+        assert_eq!(
+            config.exposed_ports().as_ref().unwrap(),
+            &["80/tcp".to_string(), "53/udp".to_string()]
+        );
+        assert_eq!(config.user().as_deref(), Some("nobody"));
+
+        assert!(ImageConfigBuilder::new().env("A=B", "C").build().is_err());
+        assert!(ImageConfigBuilder::new()
+            .expose_port(80, "http")
+            .build()
+            .is_err());
+        assert!(ImageConfigBuilder::new().workdir("app").build().is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,194 @@
+//! Compare filesystems of images
+
+use oci_spec::image::*;
+use std::{
+    collections::BTreeMap,
+    io::Read,
+    path::{Component, Path, PathBuf},
+};
+
+use crate::{digest::Digest, error::*, image::OciLayout};
+
+/// Difference of filesystems composed by the layers of two images
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LayerDiff {
+    /// Paths exist only in the target image
+    pub added: Vec<PathBuf>,
+    /// Paths exist only in the base image
+    pub removed: Vec<PathBuf>,
+    /// Paths exist in both images with different contents
+    pub modified: Vec<PathBuf>,
+}
+
+/// Files in the filesystem composed by layers, with the digest of their contents
+type FileTree = BTreeMap<PathBuf, Digest>;
+
+impl LayerDiff {
+    /// Compare the filesystems of `base_ref` in `base` layout and `target_ref` in `target` layout
+    ///
+    /// The references are resolved by `org.opencontainers.image.ref.name` annotation in `index.json`.
+    /// The layers are applied on memory, i.e. nothing is extracted to disk,
+    /// and [whiteout files](https://github.com/opencontainers/image-spec/blob/main/layer.md#whiteouts)
+    /// are handled as the OCI image spec defines.
+    pub fn compute(
+        base: &OciLayout,
+        base_ref: &str,
+        target: &OciLayout,
+        target_ref: &str,
+    ) -> Result<Self> {
+        let base = file_tree(base, base_ref)?;
+        let target = file_tree(target, target_ref)?;
+        Ok(Self::from_trees(&base, &target))
+    }
+
+    fn from_trees(base: &FileTree, target: &FileTree) -> Self {
+        let mut diff = LayerDiff::default();
+        for (path, digest) in target {
+            match base.get(path) {
+                None => diff.added.push(path.clone()),
+                Some(base_digest) if base_digest != digest => diff.modified.push(path.clone()),
+                _ => {}
+            }
+        }
+        for path in base.keys() {
+            if !target.contains_key(path) {
+                diff.removed.push(path.clone());
+            }
+        }
+        diff
+    }
+}
+
+fn file_tree(layout: &OciLayout, reference: &str) -> Result<FileTree> {
+    let manifest = layout.get_manifest_by_ref(reference)?;
+    let mut tree = FileTree::new();
+    for layer in manifest.layers() {
+        let blob = layout.get_blob(&Digest::new(layer.digest())?)?;
+        match layer.media_type() {
+            MediaType::ImageLayerGzip => {
+                apply_layer(&mut tree, flate2::read::GzDecoder::new(blob.as_slice()))?
+            }
+            MediaType::ImageLayer => apply_layer(&mut tree, blob.as_slice())?,
+            MediaType::Other(ty) if ty == "application/vnd.docker.image.rootfs.diff.tar.gzip" => {
+                apply_layer(&mut tree, flate2::read::GzDecoder::new(blob.as_slice()))?
+            }
+            ty => return Err(Error::UnsupportedLayerType(ty.to_string())),
+        }
+    }
+    Ok(tree)
+}
+
+/// Apply a layer in tar format on the file tree
+fn apply_layer(tree: &mut FileTree, layer: impl Read) -> Result<()> {
+    let mut opaque_dirs = Vec::new();
+    let mut whiteouts = Vec::new();
+    let mut files = Vec::new();
+    for entry in tar::Archive::new(layer).entries()? {
+        let mut entry = entry?;
+        let path = normalize(&entry.path()?);
+        let name = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or_default();
+        let parent = path.parent().unwrap_or(Path::new("")).to_owned();
+        if name == ".wh..wh..opq" {
+            opaque_dirs.push(parent);
+            continue;
+        }
+        if let Some(name) = name.strip_prefix(".wh.") {
+            whiteouts.push(parent.join(name));
+            continue;
+        }
+        let digest = match entry.header().entry_type() {
+            tar::EntryType::Regular | tar::EntryType::Continuous => {
+                let mut buf = Vec::new();
+                entry.read_to_end(&mut buf)?;
+                Digest::from_buf_sha256(&buf)
+            }
+            tar::EntryType::Symlink | tar::EntryType::Link => {
+                let target = entry.link_name()?.unwrap_or_default();
+                Digest::from_buf_sha256(target.to_string_lossy().as_bytes())
+            }
+            _ => continue,
+        };
+        files.push((path, digest));
+    }
+
+    // Whiteouts hide the entries in lower layers, not the entries in the same layer
+    for dir in opaque_dirs {
+        tree.retain(|path, _| !path.starts_with(&dir) || path == &dir);
+    }
+    for hidden in whiteouts {
+        tree.retain(|path, _| !path.starts_with(&hidden));
+    }
+    tree.extend(files);
+    Ok(())
+}
+
+/// Strip `./` and leading `/` in tar entry path
+fn normalize(path: &Path) -> PathBuf {
+    path.components()
+        .filter(|c| matches!(c, Component::Normal(_)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn layer(entries: &[(&str, &str)]) -> Vec<u8> {
+        let mut ar = tar::Builder::new(Vec::new());
+        for (path, content) in entries {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(content.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            ar.append_data(&mut header, path, content.as_bytes())
+                .unwrap();
+        }
+        ar.into_inner().unwrap()
+    }
+
+    #[test]
+    fn whiteout() -> Result<()> {
+        let mut base = FileTree::new();
+        apply_layer(
+            &mut base,
+            layer(&[
+                ("./a", "a"),
+                ("e", "e"),
+                ("dir/b", "b"),
+                ("dir/c", "c"),
+                ("sub/f", "f"),
+            ])
+            .as_slice(),
+        )?;
+
+        let mut target = base.clone();
+        apply_layer(
+            &mut target,
+            layer(&[
+                (".wh.a", ""),
+                ("dir/.wh..wh..opq", ""),
+                ("dir/d", "d"),
+                ("e", "modified"),
+                (".wh.sub", ""),
+            ])
+            .as_slice(),
+        )?;
+
+        let diff = LayerDiff::from_trees(&base, &target);
+        assert_eq!(diff.added, vec![PathBuf::from("dir/d")]);
+        assert_eq!(
+            diff.removed,
+            vec![
+                PathBuf::from("a"),
+                PathBuf::from("dir/b"),
+                PathBuf::from("dir/c"),
+                PathBuf::from("sub/f"),
+            ]
+        );
+        assert_eq!(diff.modified, vec![PathBuf::from("e")]);
+        Ok(())
+    }
+}
@@ -0,0 +1,509 @@
+use crate::{error::*, Digest};
+use oci_spec::image::{Descriptor, MediaType};
+use regex::Regex;
+use std::{
+    collections::HashSet,
+    fs, io,
+    io::Read,
+    path::{Component, Path, PathBuf},
+    str::FromStr,
+};
+
+/// Media type of gzip-compressed layer in Docker image manifest
+const DOCKER_LAYER_GZIP: &str = "application/vnd.docker.image.rootfs.diff.tar.gzip";
+
+/// Select a layer of manifest by its position from the bottom or its digest
+///
+/// ```
+/// use ocipkg::{image::LayerSelector, Digest};
+///
+/// assert_eq!("1".parse::<LayerSelector>().unwrap(), LayerSelector::Index(1));
+/// assert_eq!(
+///     "sha256:e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+///         .parse::<LayerSelector>()
+///         .unwrap(),
+///     LayerSelector::Digest(Digest::from_buf_sha256(&[]))
+/// );
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LayerSelector {
+    Index(usize),
+    Digest(Digest),
+}
+
+impl FromStr for LayerSelector {
+    type Err = Error;
+    fn from_str(input: &str) -> Result<Self> {
+        if let Ok(index) = input.parse() {
+            return Ok(LayerSelector::Index(index));
+        }
+        Digest::new(input)
+            .map(LayerSelector::Digest)
+            .map_err(|_| Error::UnknownLayer(input.to_string()))
+    }
+}
+
+impl std::fmt::Display for LayerSelector {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LayerSelector::Index(index) => write!(f, "{}", index),
+            LayerSelector::Digest(digest) => write!(f, "{}", digest),
+        }
+    }
+}
+
+impl LayerSelector {
+    pub fn select<'a>(&self, layers: &'a [Descriptor]) -> Result<&'a Descriptor> {
+        match self {
+            LayerSelector::Index(index) => layers.get(*index),
+            LayerSelector::Digest(digest) => layers
+                .iter()
+                .find(|layer| layer.digest() == &digest.to_string()),
+        }
+        .ok_or_else(|| Error::UnknownLayer(self.to_string()))
+    }
+}
+
+/// Options for [Extractor]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ExtractOptions {
+    /// Apply only this layer instead of all layers
+    pub layer: Option<LayerSelector>,
+    /// Glob patterns, e.g. `include/*.h`, selecting entries and directories to be extracted.
+    /// All entries are extracted if empty.
+    pub paths: Vec<String>,
+    /// Number of leading components removed from paths, as `tar --strip-components`
+    pub strip_components: usize,
+    /// Extract symbolic links pointing outside of the destination and device files.
+    /// Entries with `..` are rejected even if set.
+    pub allow_unsafe: bool,
+}
+
+/// Apply layers onto a directory
+///
+/// Layers are read as streams, and
+/// [whiteout files](https://github.com/opencontainers/image-spec/blob/main/layer.md#whiteouts)
+/// remove the entries extracted from lower layers.
+/// Entries which may write outside of the destination are rejected by [Error::UnsafeLayerEntry]
+/// unless [ExtractOptions::allow_unsafe] is set.
+#[derive(Debug)]
+pub struct Extractor {
+    dest: PathBuf,
+    options: ExtractOptions,
+    patterns: Vec<Regex>,
+}
+
+impl Extractor {
+    /// Create the destination directory if not exists
+    pub fn new(dest: &Path, options: ExtractOptions) -> Result<Self> {
+        fs::create_dir_all(dest)?;
+        let patterns = options.paths.iter().map(|p| glob(p)).collect();
+        Ok(Extractor {
+            dest: dest.canonicalize()?,
+            options,
+            patterns,
+        })
+    }
+
+    /// Apply the layers of manifest, where `open` reads the blob of each layer
+    pub fn apply_layers<R: Read>(
+        &mut self,
+        layers: &[Descriptor],
+        mut open: impl FnMut(&Descriptor) -> Result<R>,
+    ) -> Result<()> {
+        let layers = match &self.options.layer {
+            Some(selector) => vec![selector.select(layers)?],
+            None => layers.iter().collect(),
+        };
+        for layer in layers {
+            log::info!("Extract layer: {}", layer.digest());
+            let blob = open(layer)?;
+            self.apply_layer(layer.media_type(), blob)?;
+        }
+        Ok(())
+    }
+
+    /// Apply a layer in tar format, compressed by gzip or not
+    pub fn apply_layer(&mut self, media_type: &MediaType, blob: impl Read) -> Result<()> {
+        self.apply_tar(decompress(media_type, blob)?)
+    }
+
+    fn apply_tar(&mut self, layer: impl Read) -> Result<()> {
+        // Whiteouts hide the entries in lower layers, not the entries in the same layer
+        let mut written = HashSet::new();
+        for entry in tar::Archive::new(layer).entries()? {
+            let mut entry = entry?;
+            let original = entry.path()?.into_owned();
+            let Some(path) = self.target_path(&original)? else {
+                continue;
+            };
+            let name = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .unwrap_or_default();
+            let parent = path.parent().unwrap_or(Path::new("")).to_owned();
+            if name.starts_with(".wh.") && !self.is_inside_dest(&parent)? {
+                return Err(Error::UnsafeLayerEntry(original));
+            }
+            if name == ".wh..wh..opq" {
+                self.clear_dir(&parent, &written)?;
+                continue;
+            }
+            if let Some(name) = name.strip_prefix(".wh.") {
+                let hidden = parent.join(name);
+                if !written.contains(&hidden) {
+                    remove_path(&self.dest.join(hidden))?;
+                }
+                continue;
+            }
+            if !self.is_selected(&original) {
+                continue;
+            }
+
+            let dst = self.dest.join(&path);
+            let real_parent = self.create_parent(&dst, &original)?;
+            match entry.header().entry_type() {
+                tar::EntryType::Symlink => {
+                    let target = entry.link_name()?.unwrap_or_default().into_owned();
+                    if !self.options.allow_unsafe
+                        && !resolve(&real_parent, &target)
+                            .is_some_and(|resolved| resolved.starts_with(&self.dest))
+                    {
+                        return Err(Error::UnsafeLayerEntry(original));
+                    }
+                    remove_path(&dst)?;
+                    entry.unpack(&dst)?;
+                }
+                tar::EntryType::Link => {
+                    let target = entry.link_name()?.unwrap_or_default().into_owned();
+                    let Some(target) = self.target_path(&target)? else {
+                        return Err(Error::UnsafeLayerEntry(original));
+                    };
+                    let target_parent = target.parent().unwrap_or(Path::new(""));
+                    if !self.is_inside_dest(target_parent)? {
+                        return Err(Error::UnsafeLayerEntry(original));
+                    }
+                    remove_path(&dst)?;
+                    fs::hard_link(self.dest.join(target), &dst)?;
+                }
+                tar::EntryType::Block | tar::EntryType::Char | tar::EntryType::Fifo
+                    if !self.options.allow_unsafe =>
+                {
+                    return Err(Error::UnsafeLayerEntry(original));
+                }
+                tar::EntryType::Directory => {
+                    if !dst.is_dir() {
+                        remove_path(&dst)?;
+                    }
+                    entry.unpack(&dst)?;
+                }
+                _ => {
+                    if dst.is_dir() && !dst.is_symlink() {
+                        fs::remove_dir_all(&dst)?;
+                    }
+                    entry.unpack(&dst)?;
+                }
+            }
+            written.insert(path);
+        }
+        Ok(())
+    }
+
+    /// Path relative to the destination, or `None` if all components are stripped
+    fn target_path(&self, original: &Path) -> Result<Option<PathBuf>> {
+        let mut components = Vec::new();
+        for c in original.components() {
+            match c {
+                Component::Normal(c) => components.push(c),
+                Component::ParentDir => return Err(Error::UnsafeLayerEntry(original.to_owned())),
+                _ => {}
+            }
+        }
+        if components.len() <= self.options.strip_components {
+            return Ok(None);
+        }
+        Ok(Some(
+            components[self.options.strip_components..].iter().collect(),
+        ))
+    }
+
+    /// The entry or one of its parent directories matches a pattern
+    fn is_selected(&self, original: &Path) -> bool {
+        if self.patterns.is_empty() {
+            return true;
+        }
+        let path: PathBuf = original
+            .components()
+            .filter(|c| matches!(c, Component::Normal(_)))
+            .collect();
+        path.ancestors().any(|p| {
+            let p = p.to_string_lossy();
+            !p.is_empty() && self.patterns.iter().any(|re| re.is_match(&p))
+        })
+    }
+
+    /// Check the directory, if exists, is not redirected outside by symbolic links
+    fn is_inside_dest(&self, dir: &Path) -> Result<bool> {
+        let path = self.dest.join(dir);
+        if !path.exists() {
+            return Ok(true);
+        }
+        Ok(path.canonicalize()?.starts_with(&self.dest))
+    }
+
+    /// Create parent directories, and check they are not redirected outside by symbolic links
+    ///
+    /// Returns the canonicalized parent directory.
+    fn create_parent(&self, dst: &Path, original: &Path) -> Result<PathBuf> {
+        let parent = dst.parent().unwrap_or(&self.dest);
+        fs::create_dir_all(parent)?;
+        let parent = parent.canonicalize()?;
+        if !parent.starts_with(&self.dest) {
+            return Err(Error::UnsafeLayerEntry(original.to_owned()));
+        }
+        Ok(parent)
+    }
+
+    /// Remove entries in the directory except those extracted from the current layer
+    fn clear_dir(&self, dir: &Path, written: &HashSet<PathBuf>) -> Result<()> {
+        let path = self.dest.join(dir);
+        if !path.is_dir() || path.is_symlink() {
+            return Ok(());
+        }
+        for entry in fs::read_dir(path)? {
+            let entry = entry?;
+            let rel = dir.join(entry.file_name());
+            if !written.iter().any(|w| w.starts_with(&rel)) {
+                remove_path(&entry.path())?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Decompress a layer blob into tar stream
+pub(crate) fn decompress<'a>(
+    media_type: &MediaType,
+    blob: impl Read + 'a,
+) -> Result<Box<dyn Read + 'a>> {
+    match media_type {
+        MediaType::ImageLayer => Ok(Box::new(blob)),
+        MediaType::ImageLayerGzip => Ok(Box::new(flate2::read::GzDecoder::new(blob))),
+        MediaType::Other(ty) if ty == DOCKER_LAYER_GZIP => {
+            Ok(Box::new(flate2::read::GzDecoder::new(blob)))
+        }
+        ty => Err(Error::UnsupportedLayerType(ty.to_string())),
+    }
+}
+
+/// Regex matching the whole path for glob pattern, where `*` matches any characters including `/`
+pub(crate) fn glob(pattern: &str) -> Regex {
+    let mut re = String::from("^");
+    for c in pattern.trim_start_matches("./").trim_matches('/').chars() {
+        match c {
+            '*' => re.push_str(".*"),
+            '?' => re.push_str("[^/]"),
+            c => re.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    re.push('$');
+    Regex::new(&re).unwrap()
+}
+
+/// Path where the symbolic link in the canonicalized directory `dir` pointing `target` is resolved
+///
+/// `..` is normalized lexically, while existing symbolic links on the way are followed,
+/// since `..` after them leaves their real location.
+/// Returns `None` for absolute targets.
+fn resolve(dir: &Path, target: &Path) -> Option<PathBuf> {
+    let mut path = dir.to_owned();
+    for c in target.components() {
+        match c {
+            Component::Normal(c) => {
+                path.push(c);
+                if let Ok(real) = path.canonicalize() {
+                    path = real;
+                }
+            }
+            Component::CurDir => {}
+            Component::ParentDir => {
+                path.pop();
+            }
+            Component::RootDir | Component::Prefix(_) => return None,
+        }
+    }
+    Some(path)
+}
+
+fn remove_path(path: &Path) -> Result<()> {
+    let result = match fs::symlink_metadata(path) {
+        Ok(meta) if meta.is_dir() => fs::remove_dir_all(path),
+        Ok(_) => fs::remove_file(path),
+        Err(e) => Err(e),
+    };
+    match result {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e.into()),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn layer(entries: &[(&str, &str)]) -> Vec<u8> {
+        let mut ar = tar::Builder::new(Vec::new());
+        for (path, content) in entries {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(content.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            ar.append_data(&mut header, path, content.as_bytes())
+                .unwrap();
+        }
+        ar.into_inner().unwrap()
+    }
+
+    fn symlink_layer(path: &str, target: &str) -> Vec<u8> {
+        link_layer(tar::EntryType::Symlink, path, target)
+    }
+
+    fn link_layer(entry_type: tar::EntryType, path: &str, target: &str) -> Vec<u8> {
+        let mut ar = tar::Builder::new(Vec::new());
+        let mut header = tar::Header::new_gnu();
+        header.set_entry_type(entry_type);
+        header.set_size(0);
+        ar.append_link(&mut header, path, target).unwrap();
+        ar.into_inner().unwrap()
+    }
+
+    #[test]
+    fn whiteout() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let mut extractor = Extractor::new(dir.path(), ExtractOptions::default())?;
+        for layer in [
+            layer(&[("./a", "a"), ("dir/b", "b"), ("dir/c", "c"), ("sub/f", "f")]),
+            layer(&[
+                (".wh.a", ""),
+                ("dir/d", "d"),
+                ("dir/.wh..wh..opq", ""),
+                (".wh.sub", ""),
+            ]),
+        ] {
+            extractor.apply_layer(&MediaType::ImageLayer, layer.as_slice())?;
+        }
+        assert!(!dir.path().join("a").exists());
+        assert!(!dir.path().join("dir/b").exists());
+        assert_eq!(fs::read_to_string(dir.path().join("dir/d"))?, "d");
+        assert!(!dir.path().join("sub").exists());
+        Ok(())
+    }
+
+    #[test]
+    fn filter_and_strip() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let options = ExtractOptions {
+            paths: vec!["pkg/include".to_string(), "pkg/lib/*.a".to_string()],
+            strip_components: 1,
+            ..Default::default()
+        };
+        let mut extractor = Extractor::new(dir.path(), options)?;
+        extractor.apply_layer(
+            &MediaType::ImageLayer,
+            layer(&[
+                ("pkg/include/a.h", "a"),
+                ("pkg/lib/liba.a", "a"),
+                ("pkg/lib/liba.so", "a"),
+                ("top", "top"),
+            ])
+            .as_slice(),
+        )?;
+        assert!(dir.path().join("include/a.h").exists());
+        assert!(dir.path().join("lib/liba.a").exists());
+        assert!(!dir.path().join("lib/liba.so").exists());
+        assert!(!dir.path().join("top").exists());
+        Ok(())
+    }
+
+    #[test]
+    fn hostile_entries() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let dest = dir.path().join("dest");
+        let mut extractor = Extractor::new(&dest, ExtractOptions::default())?;
+        assert!(matches!(
+            extractor.apply_layer(
+                &MediaType::ImageLayer,
+                symlink_layer("link", "../outside").as_slice()
+            ),
+            Err(Error::UnsafeLayerEntry(_))
+        ));
+        extractor.apply_layer(
+            &MediaType::ImageLayer,
+            symlink_layer("dir/link", "../target").as_slice(),
+        )?;
+
+        // A symbolic link to outside created with allow_unsafe must not be followed
+        let mut unsafe_extractor = Extractor::new(
+            &dest,
+            ExtractOptions {
+                allow_unsafe: true,
+                ..Default::default()
+            },
+        )?;
+        unsafe_extractor.apply_layer(
+            &MediaType::ImageLayer,
+            symlink_layer("escape", dir.path().to_str().unwrap()).as_slice(),
+        )?;
+        assert!(matches!(
+            extractor.apply_layer(
+                &MediaType::ImageLayer,
+                layer(&[("escape/file", "x")]).as_slice()
+            ),
+            Err(Error::UnsafeLayerEntry(_))
+        ));
+        assert!(!dir.path().join("file").exists());
+
+        fs::write(dir.path().join("victim"), "x")?;
+        assert!(matches!(
+            extractor.apply_layer(
+                &MediaType::ImageLayer,
+                layer(&[("escape/.wh.victim", "")]).as_slice()
+            ),
+            Err(Error::UnsafeLayerEntry(_))
+        ));
+        assert!(dir.path().join("victim").exists());
+
+        // Hard link to a file through the symbolic link to outside
+        assert!(matches!(
+            extractor.apply_layer(
+                &MediaType::ImageLayer,
+                link_layer(tar::EntryType::Link, "hard", "escape/victim").as_slice()
+            ),
+            Err(Error::UnsafeLayerEntry(_))
+        ));
+        assert!(!dest.join("hard").exists());
+
+        // `..` of the target is resolved from the real location of the link, not the path in tar
+        extractor.apply_layer(
+            &MediaType::ImageLayer,
+            symlink_layer("a/d", "..").as_slice(),
+        )?;
+        assert!(matches!(
+            extractor.apply_layer(
+                &MediaType::ImageLayer,
+                symlink_layer("a/d/l", "../secret").as_slice()
+            ),
+            Err(Error::UnsafeLayerEntry(_))
+        ));
+        assert!(!dest.join("l").exists());
+        // Following the existing link on the way
+        assert!(matches!(
+            extractor.apply_layer(
+                &MediaType::ImageLayer,
+                symlink_layer("m", "a/d/../secret").as_slice()
+            ),
+            Err(Error::UnsafeLayerEntry(_))
+        ));
+        Ok(())
+    }
+}
@@ -0,0 +1,122 @@
+use oci_spec::image::{Descriptor, ImageIndex};
+
+/// Select entries of image index by their annotations
+///
+/// A descriptor is selected if it satisfies all conditions.
+/// Descriptors without annotations are regarded as having no keys.
+///
+/// ```
+/// use ocipkg::image::AnnotationFilter;
+///
+/// let mut filter = AnnotationFilter::new();
+/// filter
+///     .require("org.opencontainers.image.vendor", "ocipkg")
+///     .exclude("org.opencontainers.image.version", "dev")
+///     .has_key("org.opencontainers.image.ref.name");
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AnnotationFilter {
+    required: Vec<(String, String)>,
+    excluded: Vec<(String, String)>,
+    keys: Vec<String>,
+}
+
+impl AnnotationFilter {
+    /// Filter selecting every descriptor
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Select only descriptors which have the annotation `key` with `value`
+    pub fn require(&mut self, key: &str, value: &str) -> &mut Self {
+        self.required.push((key.to_string(), value.to_string()));
+        self
+    }
+
+    /// Drop descriptors which have the annotation `key` with `value`
+    pub fn exclude(&mut self, key: &str, value: &str) -> &mut Self {
+        self.excluded.push((key.to_string(), value.to_string()));
+        self
+    }
+
+    /// Select only descriptors which have the annotation `key` with any value
+    pub fn has_key(&mut self, key: &str) -> &mut Self {
+        self.keys.push(key.to_string());
+        self
+    }
+
+    /// Check if the descriptor is selected
+    pub fn matches(&self, desc: &Descriptor) -> bool {
+        let get = |key: &str| desc.annotations().as_ref().and_then(|a| a.get(key));
+        self.required
+            .iter()
+            .all(|(key, value)| get(key).is_some_and(|v| v == value))
+            && !self
+                .excluded
+                .iter()
+                .any(|(key, value)| get(key).is_some_and(|v| v == value))
+            && self.keys.iter().all(|key| get(key).is_some())
+    }
+
+    /// Descriptors in the image index selected by this filter, in the order of the index
+    pub fn apply<'a>(&self, index: &'a ImageIndex) -> Vec<&'a Descriptor> {
+        index
+            .manifests()
+            .iter()
+            .filter(|desc| self.matches(desc))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::*;
+    use oci_spec::image::*;
+
+    fn descriptor(annotations: &[(&str, &str)]) -> Descriptor {
+        let mut desc = Descriptor::new(
+            MediaType::ImageManifest,
+            2,
+            "sha256:44136fa355b3678a1146ad16f7e8649e94fb4fc21fe77e8310c060f61caaff8a",
+        );
+        if !annotations.is_empty() {
+            desc.set_annotations(Some(
+                annotations
+                    .iter()
+                    .map(|(k, v)| (k.to_string(), v.to_string()))
+                    .collect(),
+            ));
+        }
+        desc
+    }
+
+    #[test]
+    fn apply() -> Result<()> {
+        let index = ImageIndexBuilder::default()
+            .schema_version(SCHEMA_VERSION)
+            .manifests(vec![
+                descriptor(&[("branch", "main"), ("arch", "amd64")]),
+                descriptor(&[("branch", "main"), ("arch", "arm64")]),
+                descriptor(&[("branch", "dev")]),
+                descriptor(&[]),
+            ])
+            .build()?;
+
+        assert_eq!(AnnotationFilter::new().apply(&index).len(), 4);
+
+        let mut filter = AnnotationFilter::new();
+        filter.require("branch", "main");
+        assert_eq!(filter.apply(&index).len(), 2);
+        filter.exclude("arch", "arm64");
+        let selected = filter.apply(&index);
+        assert_eq!(selected, vec![&index.manifests()[0]]);
+
+        let mut filter = AnnotationFilter::new();
+        filter.exclude("branch", "main");
+        assert_eq!(filter.apply(&index).len(), 2);
+        filter.has_key("branch");
+        assert_eq!(filter.apply(&index), vec![&index.manifests()[2]]);
+        Ok(())
+    }
+}
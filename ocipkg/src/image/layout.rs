@@ -0,0 +1,827 @@
+//! Handle OCI Image Layout directory
+
+use oci_spec::image::*;
+use std::{
+    collections::HashSet,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    digest::Digest,
+    error::*,
+    image::{
+        find_manifest_for_platform, is_index_media_type, is_manifest_media_type, platform_string,
+        AnnotationFilter, PlatformEx,
+    },
+};
+
+/// Handler for [OCI Image Layout](https://github.com/opencontainers/image-spec/blob/main/image-layout.md) directory
+///
+/// ```text
+/// {path}/
+/// ├── oci-layout
+/// ├── index.json
+/// └── blobs/
+///     └── sha256/
+///         └── ...
+/// ```
+///
+/// This is the directory form of oci-archive, i.e. oci-archive is a tar archive of this directory.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OciLayout {
+    path: PathBuf,
+}
+
+/// Result of [OciLayout::gc]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct GcReport {
+    /// Digests of blobs which are not referenced from `index.json`.
+    ///
+    /// These are removed from the layout unless `dry_run` is set.
+    pub removed: Vec<Digest>,
+    /// Total size of removed blobs in bytes
+    pub bytes_freed: u64,
+}
+
+impl OciLayout {
+    /// Open an existing OCI Image Layout directory
+    pub fn open(path: &Path) -> Result<Self> {
+        if !path.is_dir() {
+            return Err(Error::NotADirectory(path.to_owned()));
+        }
+        if !path.join("index.json").is_file() {
+            return Err(Error::MissingIndex);
+        }
+        Ok(Self {
+            path: path.to_owned(),
+        })
+    }
+
+    /// Create a new empty OCI Image Layout directory
+    pub fn create(path: &Path) -> Result<Self> {
+        fs::create_dir_all(path.join("blobs"))?;
+        fs::write(path.join("oci-layout"), r#"{"imageLayoutVersion":"1.0.0"}"#)?;
+        let index = ImageIndexBuilder::default()
+            .schema_version(SCHEMA_VERSION)
+            .manifests(Vec::new())
+            .build()?;
+        index.to_file(path.join("index.json"))?;
+        Ok(Self {
+            path: path.to_owned(),
+        })
+    }
+
+    /// Open the layout at `OCI_LAYOUT_PATH` environment variable
+    ///
+    /// If it is not set, `ocipkg/layout` in the user cache directory, i.e. `$XDG_CACHE_HOME/ocipkg/layout`
+    /// on Linux or `~/Library/Caches/ocipkg/layout` on macOS, is used and created if not exists.
+    pub fn from_env() -> Result<Self> {
+        if let Some(path) = std::env::var_os("OCI_LAYOUT_PATH") {
+            return Self::open(Path::new(&path));
+        }
+        let path = directories::BaseDirs::new()
+            .ok_or_else(|| Error::MissingEnv("HOME".to_string()))?
+            .cache_dir()
+            .join("ocipkg")
+            .join("layout");
+        if path.join("index.json").is_file() {
+            Self::open(&path)
+        } else {
+            Self::create(&path)
+        }
+    }
+
+    /// Root directory of this layout
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn get_index(&self) -> Result<ImageIndex> {
+        Ok(ImageIndex::from_file(self.path.join("index.json"))?)
+    }
+
+    pub fn set_index(&self, index: &ImageIndex) -> Result<()> {
+        index.to_file(self.path.join("index.json"))?;
+        Ok(())
+    }
+
+    /// Path where the blob of the digest is stored
+    pub fn blob_path(&self, digest: &Digest) -> PathBuf {
+        self.path.join(digest.as_path())
+    }
+
+    pub fn get_blob(&self, digest: &Digest) -> Result<Vec<u8>> {
+        let path = self.blob_path(digest);
+        if !path.is_file() {
+            return Err(Error::UnknownDigest(digest.clone()));
+        }
+        Ok(fs::read(path)?)
+    }
+
+    /// Get blob as [OciLayout::get_blob] after checking its content has the digest
+    ///
+    /// Returns [Error::DigestMismatch] if the stored blob is corrupted, e.g. by bitrot.
+    pub fn get_blob_verified(&self, digest: &Digest) -> Result<Vec<u8>> {
+        let buf = self.get_blob(digest)?;
+        digest.verify_reader(buf.as_slice())?;
+        Ok(buf)
+    }
+
+    /// Store a blob, and returns its SHA-256 digest
+    pub fn put_blob(&self, buf: &[u8]) -> Result<Digest> {
+        let digest = Digest::from_buf_sha256(buf);
+        let path = self.blob_path(&digest);
+        if !path.exists() {
+            fs::create_dir_all(path.parent().unwrap())?;
+            fs::write(path, buf)?;
+        }
+        Ok(digest)
+    }
+
+    pub fn get_manifest(&self, digest: &Digest) -> Result<ImageManifest> {
+        let buf = self.get_blob(digest)?;
+        Ok(ImageManifest::from_reader(buf.as_slice())?)
+    }
+
+    /// Get manifest by `org.opencontainers.image.ref.name` annotation in `index.json`
+    ///
+    /// If the reference points to an image index, the manifest for the current platform is chosen.
+    pub fn get_manifest_by_ref(&self, reference: &str) -> Result<ImageManifest> {
+        let index = self.get_index()?;
+        let desc = index
+            .manifests()
+            .iter()
+            .find(|desc| {
+                desc.annotations()
+                    .as_ref()
+                    .and_then(|a| a.get("org.opencontainers.image.ref.name"))
+                    .is_some_and(|name| name == reference)
+            })
+            .ok_or_else(|| Error::UnknownManifestRef(reference.to_string()))?;
+        let mut digest = Digest::new(desc.digest())?;
+        if is_index_media_type(desc.media_type()) {
+            let buf = self.get_blob(&digest)?;
+            let index = ImageIndex::from_reader(buf.as_slice())?;
+            let platform = Platform::from_cfg_macro();
+            let desc = find_manifest_for_platform(&index, &platform)
+                .ok_or_else(|| Error::MissingPlatform(platform_string(&platform)))?;
+            digest = Digest::new(desc.digest())?;
+        }
+        self.get_manifest(&digest)
+    }
+
+    /// Descriptors in `index.json` satisfying the predicate, in the order of `index.json`
+    pub fn find_manifests_by(
+        &self,
+        predicate: impl Fn(&Descriptor) -> bool,
+    ) -> Result<Vec<Descriptor>> {
+        let index = self.get_index()?;
+        Ok(index
+            .manifests()
+            .iter()
+            .filter(|desc| predicate(desc))
+            .cloned()
+            .collect())
+    }
+
+    /// Descriptors in `index.json` which have the annotation `key` with `value`,
+    /// e.g. `org.opencontainers.image.base.name`
+    pub fn find_manifests_by_annotation(&self, key: &str, value: &str) -> Result<Vec<Descriptor>> {
+        self.find_manifests_by(|desc| {
+            desc.annotations()
+                .as_ref()
+                .and_then(|a| a.get(key))
+                .is_some_and(|v| v == value)
+        })
+    }
+
+    /// Descriptors in `index.json` selected by the filter
+    pub fn list_manifests_filtered(&self, filter: &AnnotationFilter) -> Result<Vec<Descriptor>> {
+        self.find_manifests_by(|desc| filter.matches(desc))
+    }
+
+    pub fn get_config(&self, digest: &Digest) -> Result<ImageConfiguration> {
+        let buf = self.get_blob(digest)?;
+        Ok(ImageConfiguration::from_reader(buf.as_slice())?)
+    }
+
+    /// Pack this layout into oci-archive
+    pub fn write_archive(&self, writer: impl std::io::Write) -> Result<()> {
+        let mut ar = tar::Builder::new(writer);
+        for entry in walkdir::WalkDir::new(&self.path).sort_by_file_name() {
+            let entry = entry?;
+            if !entry.file_type().is_file() {
+                continue;
+            }
+            let rel = entry
+                .path()
+                .strip_prefix(&self.path)
+                .expect("WalkDir returns path under the layout");
+            ar.append_path_with_name(entry.path(), rel)?;
+        }
+        ar.finish()?;
+        Ok(())
+    }
+
+    /// Create a point-in-time copy of this layout in `dest`, which must not exist or be empty
+    ///
+    /// Blobs are hard-linked if possible, and copied otherwise, e.g. across filesystems.
+    /// Sharing blobs does not make the layouts dependent on each other since blobs are never
+    /// modified in place; [OciLayout::put_blob] skips existing blobs and [OciLayout::gc] only unlinks them.
+    /// `index.json` is copied before blobs, so that all blobs it references are included
+    /// even if images are added to this layout meanwhile.
+    pub fn snapshot(&self, dest: &Path) -> Result<OciLayout> {
+        if dest.exists() && (!dest.is_dir() || fs::read_dir(dest)?.next().is_some()) {
+            return Err(Error::DirectoryNotEmpty(dest.to_owned()));
+        }
+        fs::create_dir_all(dest.join("blobs"))?;
+        for name in ["oci-layout", "index.json"] {
+            fs::copy(self.path.join(name), dest.join(name))?;
+        }
+        let blobs = self.path.join("blobs");
+        for entry in walkdir::WalkDir::new(&blobs).min_depth(1) {
+            let entry = entry?;
+            let rel = entry
+                .path()
+                .strip_prefix(&self.path)
+                .expect("WalkDir returns path under the layout");
+            let target = dest.join(rel);
+            if entry.file_type().is_dir() {
+                fs::create_dir_all(&target)?;
+            } else if entry.file_type().is_file() && fs::hard_link(entry.path(), &target).is_err() {
+                fs::copy(entry.path(), &target)?;
+            }
+        }
+        OciLayout::open(dest)
+    }
+
+    /// Remove blobs which are not referenced from any manifest in `index.json`
+    ///
+    /// Manifests and indices are traversed recursively from `index.json`,
+    /// and every blob not reached is removed.
+    /// If `dry_run` is set, nothing is removed and only the report is returned.
+    pub fn gc(&self, dry_run: bool) -> Result<GcReport> {
+        let mut referenced = HashSet::new();
+        for desc in self.get_index()?.manifests() {
+            self.collect_referenced(desc, &mut referenced)?;
+        }
+
+        let mut report = GcReport::default();
+        let blobs = self.path.join("blobs");
+        if !blobs.is_dir() {
+            return Ok(report);
+        }
+        for entry in walkdir::WalkDir::new(&blobs).min_depth(2).max_depth(2) {
+            let entry = entry?;
+            if !entry.file_type().is_file() {
+                continue;
+            }
+            let path = entry.path();
+            let rel = path
+                .strip_prefix(&blobs)
+                .expect("WalkDir returns path under blobs/");
+            let (Some(algorithm), Some(encoded)) = (
+                rel.parent().and_then(|p| p.to_str()),
+                rel.file_name().and_then(|p| p.to_str()),
+            ) else {
+                continue;
+            };
+            let digest = Digest {
+                algorithm: algorithm.to_string(),
+                encoded: encoded.to_string(),
+            };
+            if referenced.contains(&digest) {
+                continue;
+            }
+            report.bytes_freed += entry.metadata()?.len();
+            if !dry_run {
+                log::info!("Remove unreferenced blob: {}", digest);
+                fs::remove_file(path)?;
+            }
+            report.removed.push(digest);
+        }
+        report.removed.sort();
+        Ok(report)
+    }
+
+    /// Push all images listed in `index.json` into the repository of `client`
+    ///
+    /// `org.opencontainers.image.ref.name` annotation is used as the tag.
+    /// If it is a full image name, e.g. `ghcr.io/termoshtt/ocipkg/testing:v1`, its tag is used.
+    /// Images without the annotation are pushed with their digests as the reference.
+    ///
+    /// Returns `{registry_prefix}:{reference}` and the URL of pushed manifest for each image.
+    #[cfg(feature = "network")]
+    pub fn push_all(
+        &self,
+        client: &mut crate::distribution::Client,
+        registry_prefix: &str,
+    ) -> Result<Vec<(String, url::Url)>> {
+        let index = self.get_index()?;
+        let total = index.manifests().len();
+        let mut pushed = Vec::new();
+        for (i, desc) in index.manifests().iter().enumerate() {
+            let reference = match desc
+                .annotations()
+                .as_ref()
+                .and_then(|a| a.get("org.opencontainers.image.ref.name"))
+            {
+                Some(name) => {
+                    let last = name.rsplit('/').next().unwrap_or(name);
+                    let tag = last.split_once(':').map(|(_, tag)| tag).unwrap_or(last);
+                    crate::Reference::new(tag)?
+                }
+                None => crate::Reference::new(desc.digest())?,
+            };
+            let name = format!("{}:{}", registry_prefix.trim_end_matches('/'), reference);
+            log::info!("Push image ({}/{}): {}", i + 1, total, name);
+            let url = self.push_entry(client, desc, &reference)?;
+            pushed.push((name, url));
+        }
+        Ok(pushed)
+    }
+
+    /// Push images in `index.json` selected by `filter` into the repository of `client`
+    ///
+    /// The value of annotation `reference_from_annotation` is used as the tag of each image,
+    /// e.g. to promote only passing builds in a layout created by CI.
+    /// Entries without the annotation are not pushed,
+    /// and blobs referenced only from unselected images are not uploaded.
+    ///
+    /// Returns the tag and the URL of pushed manifest for each image.
+    #[cfg(feature = "network")]
+    pub fn push_subset(
+        &self,
+        client: &mut crate::distribution::Client,
+        filter: &AnnotationFilter,
+        reference_from_annotation: &str,
+    ) -> Result<Vec<(String, url::Url)>> {
+        let mut filter = filter.clone();
+        filter.has_key(reference_from_annotation);
+        let selected = self.list_manifests_filtered(&filter)?;
+        let total = selected.len();
+        let mut pushed = Vec::new();
+        for (i, desc) in selected.iter().enumerate() {
+            let tag = desc
+                .annotations()
+                .as_ref()
+                .and_then(|a| a.get(reference_from_annotation))
+                .expect("Selected by the filter");
+            let reference = crate::Reference::new(tag)?;
+            log::info!("Push image ({}/{}): {}", i + 1, total, reference);
+            let url = self.push_entry(client, desc, &reference)?;
+            pushed.push((tag.clone(), url));
+        }
+        Ok(pushed)
+    }
+
+    /// Push an entry of `index.json` as `reference`, with the manifests of an image index
+    #[cfg(feature = "network")]
+    fn push_entry(
+        &self,
+        client: &mut crate::distribution::Client,
+        desc: &Descriptor,
+        reference: &crate::Reference,
+    ) -> Result<url::Url> {
+        let digest = Digest::new(desc.digest())?;
+        if is_index_media_type(desc.media_type()) {
+            let buf = self.get_blob(&digest)?;
+            let child = ImageIndex::from_reader(buf.as_slice())?;
+            for manifest in child.manifests() {
+                self.push_manifest_with_blobs(
+                    client,
+                    &crate::Reference::new(manifest.digest())?,
+                    &Digest::new(manifest.digest())?,
+                )?;
+            }
+            client.push_raw_manifest(reference, &MediaType::ImageIndex, &buf)
+        } else {
+            self.push_manifest_with_blobs(client, reference, &digest)
+        }
+    }
+
+    #[cfg(feature = "network")]
+    fn push_manifest_with_blobs(
+        &self,
+        client: &mut crate::distribution::Client,
+        reference: &crate::Reference,
+        digest: &Digest,
+    ) -> Result<url::Url> {
+        let manifest = self.get_manifest(digest)?;
+        for desc in manifest.layers().iter().chain([manifest.config()]) {
+            let blob = Digest::new(desc.digest())?;
+            if client.head_blob(&blob)?.is_none() {
+                client.push_blob(&self.get_blob(&blob)?)?;
+            }
+        }
+        // Push as it is stored in the layout to keep the digest
+        let buf = self.get_blob(digest)?;
+        client.push_raw_manifest(reference, &MediaType::ImageManifest, &buf)
+    }
+
+    /// Pull an image or image index by `reference` from the repository of `client`, and add it to `index.json`
+    ///
+    /// The manifest and all blobs referenced from it are stored as they are in the registry,
+    /// and then the digest of the manifest is kept.
+    /// Blobs already stored in the layout are not downloaded again.
+    /// The added descriptor is annotated by `org.opencontainers.image.ref.name` as `reference`,
+    /// and replaces an existing one with the same annotation.
+    ///
+    /// Returns the descriptor added to `index.json`.
+    #[cfg(feature = "network")]
+    pub fn append_from_registry(
+        &self,
+        client: &mut crate::distribution::Client,
+        reference: &str,
+    ) -> Result<Descriptor> {
+        let (media_type, buf) = client.get_raw_manifest(&crate::Reference::new(reference)?)?;
+        let digest = Digest::from_buf_sha256(&buf);
+        self.pull_manifest_blobs(client, &media_type, &buf)?;
+        self.put_blob_as(&digest, &buf)?;
+
+        let desc = DescriptorBuilder::default()
+            .media_type(media_type)
+            .digest(digest.to_string())
+            .size(buf.len() as i64)
+            .annotations(std::collections::HashMap::from([(
+                "org.opencontainers.image.ref.name".to_string(),
+                reference.to_string(),
+            )]))
+            .build()?;
+        let mut index = self.get_index()?;
+        let mut manifests = index.manifests().clone();
+        manifests.retain(|d| {
+            d.annotations()
+                .as_ref()
+                .and_then(|a| a.get("org.opencontainers.image.ref.name"))
+                .is_none_or(|name| name != reference)
+        });
+        manifests.push(desc.clone());
+        index.set_manifests(manifests);
+        self.set_index(&index)?;
+        Ok(desc)
+    }
+
+    /// Pull blobs referenced from a manifest, or manifests and their blobs in an image index
+    #[cfg(feature = "network")]
+    fn pull_manifest_blobs(
+        &self,
+        client: &mut crate::distribution::Client,
+        media_type: &MediaType,
+        buf: &[u8],
+    ) -> Result<()> {
+        if is_index_media_type(media_type) {
+            let index = ImageIndex::from_reader(buf)?;
+            for child in index.manifests() {
+                let digest = Digest::new(child.digest())?;
+                if self.blob_path(&digest).is_file() {
+                    continue;
+                }
+                let (media_type, buf) =
+                    client.get_raw_manifest(&crate::Reference::new(child.digest())?)?;
+                self.pull_manifest_blobs(client, &media_type, &buf)?;
+                self.put_blob_as(&digest, &buf)?;
+            }
+        } else {
+            let manifest = ImageManifest::from_reader(buf)?;
+            for desc in manifest.layers().iter().chain([manifest.config()]) {
+                let digest = Digest::new(desc.digest())?;
+                if self.blob_path(&digest).is_file() {
+                    log::info!("Blob already exists in layout: {}", digest);
+                    continue;
+                }
+                let blob = client.get_blob(&digest)?;
+                self.put_blob_as(&digest, &blob)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Store a blob as `digest` after checking its content
+    #[cfg(feature = "network")]
+    fn put_blob_as(&self, digest: &Digest, buf: &[u8]) -> Result<()> {
+        digest.verify_reader(buf)?;
+        let path = self.blob_path(digest);
+        if !path.exists() {
+            fs::create_dir_all(path.parent().unwrap())?;
+            fs::write(path, buf)?;
+        }
+        Ok(())
+    }
+
+    fn collect_referenced(
+        &self,
+        desc: &Descriptor,
+        referenced: &mut HashSet<Digest>,
+    ) -> Result<()> {
+        let digest = Digest::new(desc.digest())?;
+        if !referenced.insert(digest.clone()) {
+            return Ok(());
+        }
+        // Blobs may lack in a partially written layout
+        if !self.blob_path(&digest).is_file() {
+            log::warn!("Referenced blob is missing in layout: {}", digest);
+            return Ok(());
+        }
+        if is_index_media_type(desc.media_type()) {
+            let buf = self.get_blob(&digest)?;
+            let index = ImageIndex::from_reader(buf.as_slice())?;
+            for child in index.manifests() {
+                self.collect_referenced(child, referenced)?;
+            }
+        } else if is_manifest_media_type(desc.media_type()) {
+            let manifest = self.get_manifest(&digest)?;
+            self.collect_referenced(manifest.config(), referenced)?;
+            for layer in manifest.layers() {
+                self.collect_referenced(layer, referenced)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn descriptor(media_type: MediaType, digest: &Digest, size: usize) -> Descriptor {
+        DescriptorBuilder::default()
+            .media_type(media_type)
+            .digest(digest.to_string())
+            .size(size as i64)
+            .build()
+            .unwrap()
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn from_env() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        std::env::remove_var("OCI_LAYOUT_PATH");
+        std::env::set_var("XDG_CACHE_HOME", dir.path());
+        let layout = OciLayout::from_env()?;
+        assert_eq!(layout.path(), dir.path().join("ocipkg/layout"));
+        assert!(layout.get_index()?.manifests().is_empty());
+
+        std::env::set_var("OCI_LAYOUT_PATH", dir.path().join("ocipkg/layout"));
+        assert_eq!(OciLayout::from_env()?, layout);
+        std::env::set_var("OCI_LAYOUT_PATH", dir.path().join("none"));
+        assert!(matches!(
+            OciLayout::from_env(),
+            Err(Error::NotADirectory(_))
+        ));
+        std::env::remove_var("OCI_LAYOUT_PATH");
+        Ok(())
+    }
+
+    #[test]
+    fn gc() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let layout = OciLayout::create(dir.path())?;
+
+        let layer = layout.put_blob(b"layer")?;
+        let config = layout.put_blob(b"{}")?;
+        let orphan = layout.put_blob(b"orphan")?;
+
+        let manifest = ImageManifestBuilder::default()
+            .schema_version(SCHEMA_VERSION)
+            .config(descriptor(MediaType::ImageConfig, &config, 2))
+            .layers(vec![descriptor(MediaType::ImageLayerGzip, &layer, 5)])
+            .build()?;
+        let mut buf = Vec::new();
+        manifest.to_writer(&mut buf)?;
+        let manifest = layout.put_blob(&buf)?;
+        let index = ImageIndexBuilder::default()
+            .schema_version(SCHEMA_VERSION)
+            .manifests(vec![descriptor(
+                MediaType::ImageManifest,
+                &manifest,
+                buf.len(),
+            )])
+            .build()?;
+        layout.set_index(&index)?;
+
+        let report = layout.gc(true)?;
+        assert_eq!(report.removed, vec![orphan.clone()]);
+        assert_eq!(report.bytes_freed, 6);
+        assert!(layout.blob_path(&orphan).exists());
+
+        let report = layout.gc(false)?;
+        assert_eq!(report.removed, vec![orphan.clone()]);
+        assert!(!layout.blob_path(&orphan).exists());
+        assert!(layout.blob_path(&layer).exists());
+        assert!(layout.blob_path(&config).exists());
+        assert!(layout.blob_path(&manifest).exists());
+
+        assert_eq!(layout.gc(false)?, GcReport::default());
+        Ok(())
+    }
+
+    #[test]
+    fn get_blob_verified() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let layout = OciLayout::create(dir.path())?;
+        let digest = layout.put_blob(b"blob")?;
+        assert_eq!(layout.get_blob_verified(&digest)?, b"blob");
+
+        fs::write(layout.blob_path(&digest), b"corrupted")?;
+        assert!(matches!(
+            layout.get_blob_verified(&digest),
+            Err(Error::DigestMismatch { .. })
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn write_archive() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let layout = OciLayout::create(&dir.path().join("layout"))?;
+        let digest = layout.put_blob(b"test")?;
+        let mut buf = Vec::new();
+        layout.write_archive(&mut buf)?;
+
+        let mut cursor = std::io::Cursor::new(buf);
+        let mut ar = crate::image::Archive::new(&mut cursor);
+        assert_eq!(ar.get_index()?, layout.get_index()?);
+        let mut blob = Vec::new();
+        std::io::Read::read_to_end(&mut ar.get_blob(&digest)?, &mut blob)?;
+        assert_eq!(blob, b"test");
+        Ok(())
+    }
+
+    #[test]
+    fn snapshot() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let layout = OciLayout::create(&dir.path().join("layout"))?;
+        let blob = layout.put_blob(b"{}")?;
+        let index = ImageIndexBuilder::default()
+            .schema_version(SCHEMA_VERSION)
+            .manifests(vec![descriptor(MediaType::ImageManifest, &blob, 2)])
+            .build()?;
+        layout.set_index(&index)?;
+
+        let snapshot = layout.snapshot(&dir.path().join("snapshot"))?;
+        assert_eq!(snapshot.get_index()?, index);
+        assert_eq!(snapshot.get_blob(&blob)?, b"{}");
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::MetadataExt;
+            let ino = |l: &OciLayout| fs::metadata(l.blob_path(&blob)).unwrap().ino();
+            assert_eq!(ino(&layout), ino(&snapshot));
+        }
+
+        // Changes of the original are not reflected to the snapshot, and vice versa
+        let added = layout.put_blob(b"added")?;
+        layout.set_index(
+            &ImageIndexBuilder::default()
+                .schema_version(SCHEMA_VERSION)
+                .manifests(Vec::new())
+                .build()?,
+        )?;
+        assert_eq!(snapshot.get_index()?, index);
+        assert!(!snapshot.blob_path(&added).exists());
+        layout.gc(false)?;
+        assert!(!layout.blob_path(&blob).exists());
+        assert_eq!(snapshot.get_blob(&blob)?, b"{}");
+
+        assert!(matches!(
+            layout.snapshot(snapshot.path()),
+            Err(Error::DirectoryNotEmpty(_))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn find_manifests_by_annotation() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let layout = OciLayout::create(dir.path())?;
+        let annotated = |digest: &Digest, base: &str| {
+            let mut desc = descriptor(MediaType::ImageManifest, digest, 2);
+            desc.set_annotations(Some(
+                [(
+                    "org.opencontainers.image.base.name".to_string(),
+                    base.to_string(),
+                )]
+                .into(),
+            ));
+            desc
+        };
+        let a = layout.put_blob(b"{}")?;
+        let b = layout.put_blob(b"[]")?;
+        let index = ImageIndexBuilder::default()
+            .schema_version(SCHEMA_VERSION)
+            .manifests(vec![
+                annotated(&a, "alpine"),
+                annotated(&b, "debian"),
+                descriptor(MediaType::ImageManifest, &b, 2),
+            ])
+            .build()?;
+        layout.set_index(&index)?;
+
+        let found =
+            layout.find_manifests_by_annotation("org.opencontainers.image.base.name", "debian")?;
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].digest(), &b.to_string());
+        assert!(layout
+            .find_manifests_by_annotation("org.opencontainers.image.base.name", "ubuntu")?
+            .is_empty());
+        assert_eq!(
+            layout
+                .find_manifests_by(|desc| desc.digest() == &b.to_string())?
+                .len(),
+            2
+        );
+        Ok(())
+    }
+
+    #[cfg(feature = "network")]
+    #[test]
+    fn append_from_registry() -> Result<()> {
+        let registry = crate::distribution::TestRegistry::with_fixture()?;
+        let mut client = registry.client("test_repo")?;
+        let manifest = client.get_manifest(&crate::Reference::new("tag1")?)?;
+
+        let dir = tempfile::tempdir()?;
+        let layout = OciLayout::create(dir.path())?;
+        // Already stored blobs are kept as they are
+        let config = Digest::new(manifest.config().digest())?;
+        let stored = registry.layout().get_blob(&config)?;
+        layout.put_blob(&stored)?;
+
+        let desc = layout.append_from_registry(&mut client, "tag1")?;
+        assert_eq!(
+            Digest::new(desc.digest())?,
+            client.get_manifest_digest(&crate::Reference::new("tag1")?)?
+        );
+        assert_eq!(layout.get_manifest_by_ref("tag1")?, manifest);
+        for layer in manifest.layers() {
+            layout.get_blob_verified(&Digest::new(layer.digest())?)?;
+        }
+
+        // Appending the same reference again replaces the descriptor
+        layout.append_from_registry(&mut client, "tag1")?;
+        assert_eq!(layout.get_index()?.manifests(), &vec![desc]);
+        Ok(())
+    }
+
+    #[cfg(feature = "network")]
+    #[test]
+    fn push_subset() -> Result<()> {
+        let registry = crate::distribution::TestRegistry::with_fixture()?;
+        let mut client = registry.client("test_repo")?;
+        let dir = tempfile::tempdir()?;
+        let layout = OciLayout::create(dir.path())?;
+        layout.append_from_registry(&mut client, "tag1")?;
+        layout.append_from_registry(&mut client, "tag2")?;
+
+        // Mark tag1 as a passing build to be promoted as `stable`
+        let mut index = layout.get_index()?;
+        let manifests = index
+            .manifests()
+            .iter()
+            .map(|desc| {
+                let mut annotations = desc.annotations().clone().unwrap_or_default();
+                let passed = annotations["org.opencontainers.image.ref.name"] == "tag1";
+                annotations.insert(
+                    "ci.status".to_string(),
+                    if passed { "pass" } else { "fail" }.to_string(),
+                );
+                annotations.insert("ci.tag".to_string(), "stable".to_string());
+                let mut desc = desc.clone();
+                desc.set_annotations(Some(annotations));
+                desc
+            })
+            .collect();
+        index.set_manifests(manifests);
+        layout.set_index(&index)?;
+
+        let mut filter = AnnotationFilter::new();
+        filter.require("ci.status", "pass");
+        let target = crate::distribution::TestRegistry::start()?;
+        let mut dest = target.client("promoted")?;
+        let pushed = layout.push_subset(&mut dest, &filter, "ci.tag")?;
+        assert_eq!(pushed.len(), 1);
+        assert_eq!(pushed[0].0, "stable");
+
+        let stable = crate::Reference::new("stable")?;
+        assert_eq!(
+            dest.get_manifest_digest(&stable)?,
+            client.get_manifest_digest(&crate::Reference::new("tag1")?)?
+        );
+        // Blobs only in the failing build are not pushed
+        let blobs = |manifest: ImageManifest| -> Vec<String> {
+            std::iter::once(manifest.config())
+                .chain(manifest.layers())
+                .map(|desc| desc.digest().to_string())
+                .collect()
+        };
+        let promoted = blobs(layout.get_manifest_by_ref("tag1")?);
+        for blob in blobs(layout.get_manifest_by_ref("tag2")?) {
+            if !promoted.contains(&blob) {
+                assert!(dest.head_blob(&Digest::new(&blob)?)?.is_none());
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,294 @@
+//! Check a container image before release
+
+use oci_spec::image::*;
+use serde::Serialize;
+use std::{
+    collections::BTreeMap,
+    fs,
+    io::Read,
+    path::{Path, PathBuf},
+};
+
+use crate::{digest::Digest, error::*, image::Archive};
+
+/// Result of a check
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Check {
+    pub name: String,
+    pub passed: bool,
+    pub message: String,
+}
+
+impl Check {
+    pub fn pass(name: &str, message: impl Into<String>) -> Self {
+        Self {
+            name: name.to_string(),
+            passed: true,
+            message: message.into(),
+        }
+    }
+
+    pub fn fail(name: &str, message: impl Into<String>) -> Self {
+        Self {
+            name: name.to_string(),
+            passed: false,
+            message: message.into(),
+        }
+    }
+}
+
+/// Options for [lint_archive]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LintOptions {
+    /// Annotation keys must be set in manifest or image configuration labels
+    pub required_annotations: Vec<String>,
+    /// Upper limit of the total size of layers in bytes
+    pub max_size: Option<u64>,
+    /// Paths must not be embedded in the files, e.g. home directory of the build machine
+    pub forbidden_paths: Vec<String>,
+    /// File names expected in the layers. If not set, only checks that some library exists.
+    pub expected_files: Option<Vec<String>>,
+}
+
+impl Default for LintOptions {
+    fn default() -> Self {
+        Self {
+            required_annotations: vec![
+                "org.opencontainers.image.version".to_string(),
+                "org.opencontainers.image.licenses".to_string(),
+                "org.opencontainers.image.source".to_string(),
+            ],
+            max_size: None,
+            forbidden_paths: vec![
+                "/home/".to_string(),
+                "/Users/".to_string(),
+                "C:\\Users\\".to_string(),
+            ],
+            expected_files: None,
+        }
+    }
+}
+
+/// Check images in oci-archive
+///
+/// Each image listed in `index.json` is checked for required annotations, embedded host paths,
+/// library files to be linked by [crate::link_package], and the total size of layers.
+pub fn lint_archive(path: &Path, options: &LintOptions) -> Result<Vec<Check>> {
+    let mut f = fs::File::open(path)?;
+    let mut ar = Archive::new(&mut f);
+    let mut checks = Vec::new();
+    for (name, manifest) in ar.get_manifests()? {
+        let config = ar.get_config(&Digest::new(manifest.config().digest())?)?;
+        let files = layer_files(&mut ar, &manifest)?;
+        checks.push(check_annotations(&name, &manifest, &config, options));
+        checks.push(check_embedded_paths(&name, &files, options));
+        checks.push(check_link_metadata(&name, &files, options));
+        checks.push(check_size(&name, &manifest, options));
+    }
+    Ok(checks)
+}
+
+/// Digests of files in the layers of each image in oci-archive
+///
+/// This does not depend on metadata in tar header e.g. modification time,
+/// and thus usable to compare the contents of images built twice.
+pub fn file_digests(path: &Path) -> Result<BTreeMap<PathBuf, Digest>> {
+    let mut f = fs::File::open(path)?;
+    let mut ar = Archive::new(&mut f);
+    let mut digests = BTreeMap::new();
+    for (_name, manifest) in ar.get_manifests()? {
+        for (path, buf) in layer_files(&mut ar, &manifest)? {
+            digests.insert(path, Digest::from_buf_sha256(&buf));
+        }
+    }
+    Ok(digests)
+}
+
+fn layer_files<R: Read + std::io::Seek>(
+    ar: &mut Archive<R>,
+    manifest: &ImageManifest,
+) -> Result<BTreeMap<PathBuf, Vec<u8>>> {
+    let mut files = BTreeMap::new();
+    for layer in manifest.layers() {
+        let mut blob = Vec::new();
+        ar.get_blob(&Digest::new(layer.digest())?)?
+            .read_to_end(&mut blob)?;
+        let decoded: Box<dyn Read> = match layer.media_type() {
+            MediaType::ImageLayerGzip => Box::new(flate2::read::GzDecoder::new(blob.as_slice())),
+            MediaType::ImageLayer => Box::new(blob.as_slice()),
+            ty => return Err(Error::UnsupportedLayerType(ty.to_string())),
+        };
+        let mut tar = tar::Archive::new(decoded);
+        for entry in tar.entries()? {
+            let mut entry = entry?;
+            if !entry.header().entry_type().is_file() {
+                continue;
+            }
+            let path = entry.path()?.to_path_buf();
+            let mut buf = Vec::new();
+            entry.read_to_end(&mut buf)?;
+            files.insert(path, buf);
+        }
+    }
+    Ok(files)
+}
+
+fn check_annotations(
+    name: &impl std::fmt::Display,
+    manifest: &ImageManifest,
+    config: &ImageConfiguration,
+    options: &LintOptions,
+) -> Check {
+    let labels = config.config().as_ref().and_then(|c| c.labels().as_ref());
+    let annotations = manifest.annotations().as_ref();
+    let missing: Vec<_> = options
+        .required_annotations
+        .iter()
+        .filter(|key| {
+            !labels.is_some_and(|l| l.contains_key(*key))
+                && !annotations.is_some_and(|a| a.contains_key(*key))
+        })
+        .cloned()
+        .collect();
+    if missing.is_empty() {
+        Check::pass(
+            "annotations",
+            format!("{}: required annotations are set", name),
+        )
+    } else {
+        Check::fail(
+            "annotations",
+            format!("{}: missing annotations: {}", name, missing.join(", ")),
+        )
+    }
+}
+
+fn check_embedded_paths(
+    name: &impl std::fmt::Display,
+    files: &BTreeMap<PathBuf, Vec<u8>>,
+    options: &LintOptions,
+) -> Check {
+    let mut found = Vec::new();
+    for (path, buf) in files {
+        for forbidden in &options.forbidden_paths {
+            if contains(buf, forbidden.as_bytes()) {
+                found.push(format!("{} ({})", path.display(), forbidden));
+            }
+        }
+    }
+    if found.is_empty() {
+        Check::pass("embedded-paths", format!("{}: no host path embedded", name))
+    } else {
+        Check::fail(
+            "embedded-paths",
+            format!("{}: host paths embedded in {}", name, found.join(", ")),
+        )
+    }
+}
+
+fn check_link_metadata(
+    name: &impl std::fmt::Display,
+    files: &BTreeMap<PathBuf, Vec<u8>>,
+    options: &LintOptions,
+) -> Check {
+    let names: Vec<String> = files
+        .keys()
+        .map(|path| path.to_string_lossy().to_string())
+        .collect();
+    if let Some(expected) = &options.expected_files {
+        let missing: Vec<_> = expected.iter().filter(|e| !names.contains(e)).collect();
+        let extra: Vec<_> = names.iter().filter(|n| !expected.contains(n)).collect();
+        if missing.is_empty() && extra.is_empty() {
+            return Check::pass(
+                "link-metadata",
+                format!("{}: layers contain {}", name, names.join(", ")),
+            );
+        }
+        return Check::fail(
+            "link-metadata",
+            format!(
+                "{}: missing files: {:?}, unexpected files: {:?}",
+                name, missing, extra
+            ),
+        );
+    }
+    let libraries: Vec<_> = names.iter().filter(|n| is_library(n)).collect();
+    if libraries.is_empty() {
+        Check::fail("link-metadata", format!("{}: no library in layers", name))
+    } else {
+        Check::pass(
+            "link-metadata",
+            format!("{}: libraries {:?} in layers", name, libraries),
+        )
+    }
+}
+
+pub(crate) fn check_size(
+    name: &impl std::fmt::Display,
+    manifest: &ImageManifest,
+    options: &LintOptions,
+) -> Check {
+    let size: u64 = manifest.layers().iter().map(|l| l.size() as u64).sum();
+    match options.max_size {
+        Some(max) if size > max => Check::fail(
+            "size",
+            format!("{}: {} bytes exceeds the budget {} bytes", name, size, max),
+        ),
+        _ => Check::pass("size", format!("{}: {} bytes", name, size)),
+    }
+}
+
+fn is_library(name: &str) -> bool {
+    let static_lib = name.starts_with("lib") && name.ends_with(".a") || name.ends_with(".lib");
+    let dynamic_lib = name.starts_with("lib")
+        && (name.ends_with(".so") || name.ends_with(".dylib"))
+        || name.ends_with(".dll");
+    !name.contains('/') && (static_lib || dynamic_lib)
+}
+
+fn contains(buf: &[u8], pattern: &[u8]) -> bool {
+    !pattern.is_empty() && buf.windows(pattern.len()).any(|w| w == pattern)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::image::{annotations::flat::Annotations, Builder};
+
+    #[test]
+    fn lint() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let lib = dir.path().join("libtest.a");
+        fs::write(&lib, "built in /home/user/project")?;
+        let archive = dir.path().join("test.tar");
+        let mut b = Builder::new(fs::File::create(&archive)?);
+        b.set_annotations(Annotations {
+            version: Some("0.1.0".to_string()),
+            licenses: Some("MIT".to_string()),
+            ..Default::default()
+        });
+        b.append_files(&[&lib])?;
+        b.into_inner()?;
+
+        let checks = lint_archive(
+            &archive,
+            &LintOptions {
+                max_size: Some(1),
+                ..Default::default()
+            },
+        )?;
+        let passed: BTreeMap<_, _> = checks.iter().map(|c| (c.name.as_str(), c.passed)).collect();
+        assert!(!passed["annotations"]);
+        assert!(!passed["embedded-paths"]);
+        assert!(passed["link-metadata"]);
+        assert!(!passed["size"]);
+
+        assert_eq!(
+            file_digests(&archive)?,
+            maplit::btreemap! {
+                PathBuf::from("libtest.a") => Digest::from_buf_sha256(b"built in /home/user/project"),
+            }
+        );
+        Ok(())
+    }
+}
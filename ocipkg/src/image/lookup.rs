@@ -0,0 +1,381 @@
+use super::extract::decompress;
+use crate::error::*;
+use oci_spec::image::Descriptor;
+use std::{
+    collections::{BTreeMap, HashSet},
+    io::{self, Read, Write},
+    path::{Component, Path, PathBuf},
+};
+
+/// Symbolic links are followed up to this depth, as `MAXSYMLINKS` of Linux
+const MAX_LINKS: usize = 40;
+
+const OPAQUE_WHITEOUT: &str = ".wh..wh..opq";
+
+/// Write the content of a file in the filesystem of image to `out`
+///
+/// Layers are read from the top, where `open` reads the blob of each layer,
+/// and reading stops at the first layer containing the file.
+/// Whiteouts in upper layers hide the file in lower layers, and symbolic links are followed inside the image.
+/// Returns [Error::PathNotFoundInImage] if the path does not exist.
+pub fn read_file<R: Read>(
+    layers: &[Descriptor],
+    mut open: impl FnMut(&Descriptor) -> Result<R>,
+    path: &str,
+    out: &mut impl Write,
+) -> Result<()> {
+    let mut target = resolve(Path::new(path));
+    let mut top = layers.len();
+    for _ in 0..MAX_LINKS {
+        match find(&layers[..top], &mut open, &target, out)? {
+            Found::Written => return Ok(()),
+            Found::Symlink(link) => {
+                target = link;
+                top = layers.len();
+            }
+            Found::HardLink(link, layer) => {
+                target = link;
+                top = layer;
+            }
+            Found::Nothing => break,
+        }
+    }
+    Err(Error::PathNotFoundInImage(path.into()))
+}
+
+/// Names of entries in a directory in the filesystem of image, with trailing `/` for directories
+///
+/// Layers are merged as [read_file], but all layers below the top may be read.
+pub fn list_dir<R: Read>(
+    layers: &[Descriptor],
+    mut open: impl FnMut(&Descriptor) -> Result<R>,
+    dir: &str,
+) -> Result<Vec<String>> {
+    let mut target = resolve(Path::new(dir));
+    'link: for _ in 0..MAX_LINKS {
+        // The root always exists even if the layers have no entry for it
+        let mut exists = target.as_os_str().is_empty();
+        let mut entries = BTreeMap::new();
+        let mut whiteouts = HashSet::new();
+        for layer in layers.iter().rev() {
+            log::debug!("List {} in layer: {}", target.display(), layer.digest());
+            let mut ar = tar::Archive::new(decompress(layer.media_type(), open(layer)?)?);
+            let mut hidden = false;
+            let mut layer_entries = BTreeMap::new();
+            let mut layer_whiteouts = Vec::new();
+            for entry in ar.entries()? {
+                let entry = entry?;
+                let path = resolve(&entry.path()?);
+                let parent = path.parent().unwrap_or(Path::new(""));
+                let name = file_name(&path);
+                if name == OPAQUE_WHITEOUT {
+                    if target.starts_with(parent) {
+                        hidden = true;
+                    }
+                    continue;
+                }
+                if let Some(name) = name.strip_prefix(".wh.") {
+                    if target.starts_with(parent.join(name)) {
+                        hidden = true;
+                    } else if parent == target {
+                        layer_whiteouts.push(name.to_string());
+                    }
+                    continue;
+                }
+
+                let ty = entry.header().entry_type();
+                if let Ok(rest) = path.strip_prefix(&target) {
+                    let mut components = rest.components();
+                    let Some(first) = components.next() else {
+                        match ty {
+                            tar::EntryType::Directory => exists = true,
+                            tar::EntryType::Symlink if !exists => {
+                                target = resolve(&parent.join(link_name(&entry)?));
+                                continue 'link;
+                            }
+                            _ if !exists => return Err(Error::NotADirectory(dir.into())),
+                            _ => hidden = true,
+                        }
+                        continue;
+                    };
+                    exists = true;
+                    let name = first.as_os_str().to_string_lossy().into_owned();
+                    if !whiteouts.contains(&name) {
+                        let is_dir = components.next().is_some() || ty == tar::EntryType::Directory;
+                        *layer_entries.entry(name).or_insert(false) |= is_dir;
+                    }
+                    continue;
+                }
+                if target.starts_with(&path) {
+                    match ty {
+                        tar::EntryType::Directory => {}
+                        tar::EntryType::Symlink if !exists => {
+                            let rest = target.strip_prefix(&path).unwrap();
+                            target = resolve(&parent.join(link_name(&entry)?).join(rest));
+                            continue 'link;
+                        }
+                        // Replaced by a file in this layer
+                        _ => hidden = true,
+                    }
+                }
+            }
+            for (name, is_dir) in layer_entries {
+                entries.entry(name).or_insert(is_dir);
+            }
+            whiteouts.extend(layer_whiteouts);
+            if hidden {
+                break;
+            }
+        }
+        if !exists {
+            break;
+        }
+        return Ok(entries
+            .into_iter()
+            .map(|(name, is_dir)| if is_dir { name + "/" } else { name })
+            .collect());
+    }
+    Err(Error::PathNotFoundInImage(dir.into()))
+}
+
+enum Found {
+    Written,
+    /// Resolved target of the symbolic link
+    Symlink(PathBuf),
+    /// Target of the hard link, and the number of layers where the target exists
+    HardLink(PathBuf, usize),
+    Nothing,
+}
+
+fn find<R: Read>(
+    layers: &[Descriptor],
+    open: &mut impl FnMut(&Descriptor) -> Result<R>,
+    target: &Path,
+    out: &mut impl Write,
+) -> Result<Found> {
+    for (index, layer) in layers.iter().enumerate().rev() {
+        log::debug!("Search {} in layer: {}", target.display(), layer.digest());
+        let mut ar = tar::Archive::new(decompress(layer.media_type(), open(layer)?)?);
+        // Whiteouts hide the entries in lower layers, not the entries in the same layer
+        let mut hidden = false;
+        for entry in ar.entries()? {
+            let mut entry = entry?;
+            let path = resolve(&entry.path()?);
+            let parent = path.parent().unwrap_or(Path::new(""));
+            let name = file_name(&path);
+            if name == OPAQUE_WHITEOUT {
+                if target.starts_with(parent) && target != parent {
+                    hidden = true;
+                }
+                continue;
+            }
+            if let Some(name) = name.strip_prefix(".wh.") {
+                if target.starts_with(parent.join(name)) {
+                    hidden = true;
+                }
+                continue;
+            }
+            if !target.starts_with(&path) {
+                continue;
+            }
+
+            let ty = entry.header().entry_type();
+            if path != target {
+                match ty {
+                    tar::EntryType::Directory => {}
+                    tar::EntryType::Symlink => {
+                        let rest = target.strip_prefix(&path).unwrap();
+                        let link = parent.join(link_name(&entry)?).join(rest);
+                        return Ok(Found::Symlink(resolve(&link)));
+                    }
+                    // Replaced by a file in this layer
+                    _ => hidden = true,
+                }
+                continue;
+            }
+            return match ty {
+                tar::EntryType::Symlink => {
+                    Ok(Found::Symlink(resolve(&parent.join(link_name(&entry)?))))
+                }
+                tar::EntryType::Link => {
+                    Ok(Found::HardLink(resolve(&link_name(&entry)?), index + 1))
+                }
+                ty if ty.is_file() => {
+                    io::copy(&mut entry, out)?;
+                    Ok(Found::Written)
+                }
+                _ => Err(Error::NotAFile(target.to_owned())),
+            };
+        }
+        if hidden {
+            break;
+        }
+    }
+    Ok(Found::Nothing)
+}
+
+/// Resolve `.` and `..` lexically, where the root is the top of the filesystem of image
+fn resolve(path: &Path) -> PathBuf {
+    let mut resolved = PathBuf::new();
+    for c in path.components() {
+        match c {
+            Component::Normal(c) => resolved.push(c),
+            Component::ParentDir => {
+                resolved.pop();
+            }
+            Component::RootDir => resolved = PathBuf::new(),
+            _ => {}
+        }
+    }
+    resolved
+}
+
+fn file_name(path: &Path) -> &str {
+    path.file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or_default()
+}
+
+fn link_name<R: Read>(entry: &tar::Entry<R>) -> Result<PathBuf> {
+    Ok(entry.link_name()?.unwrap_or_default().into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use oci_spec::image::{DescriptorBuilder, MediaType};
+
+    enum Entry<'a> {
+        File(&'a str, &'a str),
+        Symlink(&'a str, &'a str),
+        Dir(&'a str),
+    }
+    use Entry::*;
+
+    fn layer(entries: &[Entry]) -> Vec<u8> {
+        let mut ar = tar::Builder::new(Vec::new());
+        for entry in entries {
+            let mut header = tar::Header::new_gnu();
+            header.set_mode(0o755);
+            match entry {
+                File(path, content) => {
+                    header.set_size(content.len() as u64);
+                    header.set_cksum();
+                    ar.append_data(&mut header, path, content.as_bytes())
+                        .unwrap();
+                }
+                Symlink(path, target) => {
+                    header.set_entry_type(tar::EntryType::Symlink);
+                    header.set_size(0);
+                    ar.append_link(&mut header, path, target).unwrap();
+                }
+                Dir(path) => {
+                    header.set_entry_type(tar::EntryType::Directory);
+                    header.set_size(0);
+                    header.set_cksum();
+                    ar.append_data(&mut header, path, io::empty()).unwrap();
+                }
+            }
+        }
+        ar.into_inner().unwrap()
+    }
+
+    fn image(layers: &[Vec<u8>]) -> Vec<Descriptor> {
+        (0..layers.len())
+            .map(|i| {
+                DescriptorBuilder::default()
+                    .media_type(MediaType::ImageLayer)
+                    .digest(format!("sha256:{:02}", i))
+                    .size(layers[i].len() as i64)
+                    .build()
+                    .unwrap()
+            })
+            .collect()
+    }
+
+    fn cat(layers: &[Vec<u8>], path: &str) -> Result<String> {
+        let mut out = Vec::new();
+        read_file(
+            &image(layers),
+            |desc| {
+                let i: usize = desc.digest()["sha256:".len()..].parse().unwrap();
+                Ok(layers[i].as_slice())
+            },
+            path,
+            &mut out,
+        )?;
+        Ok(String::from_utf8(out).unwrap())
+    }
+
+    fn ls(layers: &[Vec<u8>], dir: &str) -> Result<Vec<String>> {
+        list_dir(
+            &image(layers),
+            |desc| {
+                let i: usize = desc.digest()["sha256:".len()..].parse().unwrap();
+                Ok(layers[i].as_slice())
+            },
+            dir,
+        )
+    }
+
+    #[test]
+    fn read_file_in_layers() -> Result<()> {
+        let layers = [
+            layer(&[
+                File("./a", "a0"),
+                File("dir/b", "b0"),
+                File("opq/c", "c0"),
+                File("lib/x.so", "x"),
+            ]),
+            layer(&[
+                File("a", "a1"),
+                File(".wh.dir", ""),
+                File("opq/.wh..wh..opq", ""),
+                File("opq/d", "d1"),
+                Symlink("usr/lib", "../lib"),
+                Symlink("link", "/a"),
+            ]),
+        ];
+        assert_eq!(cat(&layers, "/a")?, "a1");
+        assert_eq!(cat(&layers, "opq/d")?, "d1");
+        assert_eq!(cat(&layers, "usr/lib/x.so")?, "x");
+        assert_eq!(cat(&layers, "link")?, "a1");
+        for hidden in ["dir/b", "opq/c", "none"] {
+            assert!(matches!(
+                cat(&layers, hidden),
+                Err(Error::PathNotFoundInImage(_))
+            ));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn list_dir_in_layers() -> Result<()> {
+        let layers = [
+            layer(&[
+                Dir("etc"),
+                File("etc/a", ""),
+                File("etc/b", ""),
+                File("etc/sub/c", ""),
+                File("opq/x", ""),
+            ]),
+            layer(&[
+                File("etc/.wh.b", ""),
+                File("etc/d", ""),
+                Symlink("conf", "etc"),
+                File("opq/.wh..wh..opq", ""),
+                File("opq/y", ""),
+            ]),
+        ];
+        assert_eq!(ls(&layers, "/etc")?, ["a", "d", "sub/"]);
+        assert_eq!(ls(&layers, "conf")?, ["a", "d", "sub/"]);
+        assert_eq!(ls(&layers, "opq")?, ["y"]);
+        assert_eq!(ls(&layers, "/")?, ["conf", "etc/", "opq/"]);
+        assert!(matches!(
+            ls(&layers, "none"),
+            Err(Error::PathNotFoundInImage(_))
+        ));
+        assert!(matches!(ls(&layers, "etc/a"), Err(Error::NotADirectory(_))));
+        Ok(())
+    }
+}
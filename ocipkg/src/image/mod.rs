@@ -1,11 +1,45 @@
 //! Read and Write images based on [OCI image specification](https://github.com/opencontainers/image-spec)
 
 pub mod annotations;
+pub mod lint;
 
+mod config;
+mod diff;
+mod extract;
+mod filter;
+mod layout;
+mod lookup;
+mod pack;
 mod platform;
 mod read;
 mod write;
 
+pub use config::*;
+pub use diff::*;
+pub use extract::*;
+pub use filter::*;
+pub use layout::*;
+pub use lookup::*;
+pub use pack::*;
 pub use platform::*;
 pub use read::*;
 pub use write::*;
+
+use crate::OciContentType;
+use oci_spec::image::MediaType;
+
+/// Check if the media type is of image index, including Docker manifest list
+pub(crate) fn is_index_media_type(media_type: &MediaType) -> bool {
+    matches!(
+        OciContentType::from(media_type),
+        OciContentType::OciImageIndex | OciContentType::DockerManifestList
+    )
+}
+
+/// Check if the media type is of image manifest, including Docker image manifest V2 schema 2
+pub(crate) fn is_manifest_media_type(media_type: &MediaType) -> bool {
+    matches!(
+        OciContentType::from(media_type),
+        OciContentType::OciImageManifest | OciContentType::DockerManifestV2
+    )
+}
@@ -0,0 +1,258 @@
+use crate::{error::*, image::Layer, Digest};
+use flate2::{write::GzEncoder, Compression};
+use oci_spec::image::MediaType;
+use regex::Regex;
+use std::{fs, io::Write, path::Path};
+
+/// Compression of layers created by [pack_dir]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LayerCompression {
+    /// `application/vnd.oci.image.layer.v1.tar+gzip`
+    #[default]
+    Gzip,
+    /// `application/vnd.oci.image.layer.v1.tar`
+    None,
+}
+
+/// Options of [pack_dir]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PackOptions {
+    pub compression: LayerCompression,
+    /// Glob patterns of paths relative to the directory to be skipped,
+    /// where `*` matches any characters including `/`.
+    /// Contents of an excluded directory are also skipped.
+    pub exclude: Vec<String>,
+    /// Start a new layer when the total size of files in a layer exceeds this size.
+    /// A file larger than this size is stored in a layer by itself.
+    pub split_size: Option<u64>,
+    /// Modification time of every entry in seconds since the UNIX epoch,
+    /// e.g. the value of `SOURCE_DATE_EPOCH`
+    pub mtime: u64,
+}
+
+/// Pack the contents of directory into layers deterministically
+///
+/// Entries are sorted by their paths, and their owners and modification times are normalized,
+/// so that the same contents always result in the same layer digests.
+/// Permissions are normalized to `755` for directories and executables, and `644` otherwise.
+pub fn pack_dir(path: &Path, options: &PackOptions) -> Result<Vec<Layer>> {
+    if !path.is_dir() {
+        return Err(Error::NotADirectory(path.to_owned()));
+    }
+    let exclude: Vec<Regex> = options
+        .exclude
+        .iter()
+        .map(|p| super::extract::glob(p))
+        .collect();
+
+    let mut layers = Vec::new();
+    let mut ar = tar::Builder::new(Vec::new());
+    let mut size = 0;
+    let walk = walkdir::WalkDir::new(path)
+        .min_depth(1)
+        .sort_by_file_name()
+        .into_iter()
+        .filter_entry(|entry| {
+            let rel = entry.path().strip_prefix(path).unwrap();
+            let rel = rel.to_string_lossy();
+            !exclude.iter().any(|re| re.is_match(&rel))
+        });
+    for entry in walk {
+        let entry = entry.map_err(std::io::Error::from)?;
+        let rel = entry.path().strip_prefix(path).unwrap();
+        let metadata = entry.path().symlink_metadata()?;
+
+        let mut header = tar::Header::new_gnu();
+        header.set_uid(0);
+        header.set_gid(0);
+        header.set_mtime(options.mtime);
+        if metadata.is_dir() {
+            header.set_entry_type(tar::EntryType::Directory);
+            header.set_mode(0o755);
+            header.set_size(0);
+            ar.append_data(&mut header, rel, std::io::empty())?;
+        } else if metadata.is_symlink() {
+            header.set_entry_type(tar::EntryType::Symlink);
+            header.set_mode(0o777);
+            header.set_size(0);
+            ar.append_link(&mut header, rel, fs::read_link(entry.path())?)?;
+        } else if metadata.is_file() {
+            if let Some(split_size) = options.split_size {
+                if size > 0 && size + metadata.len() > split_size {
+                    let full = std::mem::replace(&mut ar, tar::Builder::new(Vec::new()));
+                    layers.push(finish_layer(full, options.compression)?);
+                    size = 0;
+                }
+            }
+            size += metadata.len();
+            append_file(&mut ar, header, rel, entry.path(), &metadata)?;
+        } else {
+            log::warn!("Skip special file: {}", entry.path().display());
+        }
+    }
+    layers.push(finish_layer(ar, options.compression)?);
+    Ok(layers)
+}
+
+/// Pack files into a layer on its root deterministically as [pack_dir]
+///
+/// Files are stored in the given order by their file names.
+/// [PackOptions::exclude] and [PackOptions::split_size] are not used.
+pub fn pack_files(paths: &[impl AsRef<Path>], options: &PackOptions) -> Result<Layer> {
+    let mut ar = tar::Builder::new(Vec::new());
+    for path in paths {
+        let path = path.as_ref();
+        let metadata = fs::metadata(path)?;
+        if !metadata.is_file() {
+            return Err(Error::NotAFile(path.to_owned()));
+        }
+        let name = path
+            .file_name()
+            .ok_or_else(|| Error::NotAFile(path.to_owned()))?;
+        let mut header = tar::Header::new_gnu();
+        header.set_uid(0);
+        header.set_gid(0);
+        header.set_mtime(options.mtime);
+        append_file(&mut ar, header, Path::new(name), path, &metadata)?;
+    }
+    finish_layer(ar, options.compression)
+}
+
+/// Append a regular file with the permission normalized
+fn append_file(
+    ar: &mut tar::Builder<Vec<u8>>,
+    mut header: tar::Header,
+    rel: &Path,
+    path: &Path,
+    metadata: &fs::Metadata,
+) -> Result<()> {
+    header.set_entry_type(tar::EntryType::Regular);
+    header.set_mode(if is_executable(metadata) {
+        0o755
+    } else {
+        0o644
+    });
+    header.set_size(metadata.len());
+    ar.append_data(&mut header, rel, fs::File::open(path)?)?;
+    Ok(())
+}
+
+#[cfg(unix)]
+fn is_executable(metadata: &fs::Metadata) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    metadata.permissions().mode() & 0o111 != 0
+}
+
+#[cfg(not(unix))]
+fn is_executable(_metadata: &fs::Metadata) -> bool {
+    false
+}
+
+fn finish_layer(ar: tar::Builder<Vec<u8>>, compression: LayerCompression) -> Result<Layer> {
+    let tar = ar.into_inner()?;
+    let diff_id = Digest::from_buf_sha256(&tar);
+    Ok(match compression {
+        LayerCompression::Gzip => {
+            // The header of gzip does not contain timestamp and file name by default
+            let mut gz = GzEncoder::new(Vec::new(), Compression::default());
+            gz.write_all(&tar)?;
+            Layer {
+                blob: gz.finish()?,
+                media_type: MediaType::ImageLayerGzip,
+                diff_id,
+            }
+        }
+        LayerCompression::None => Layer {
+            blob: tar,
+            media_type: MediaType::ImageLayer,
+            diff_id,
+        },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    fn entries(layer: &Layer) -> Result<Vec<String>> {
+        let mut buf = Vec::new();
+        flate2::read::GzDecoder::new(layer.blob.as_slice()).read_to_end(&mut buf)?;
+        assert_eq!(Digest::from_buf_sha256(&buf), layer.diff_id);
+        let mut ar = tar::Archive::new(buf.as_slice());
+        let mut paths = Vec::new();
+        for entry in ar.entries()? {
+            paths.push(
+                entry?
+                    .path()?
+                    .to_string_lossy()
+                    .trim_end_matches('/')
+                    .to_string(),
+            );
+        }
+        Ok(paths)
+    }
+
+    #[test]
+    fn deterministic() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        fs::create_dir_all(dir.path().join("b/c"))?;
+        fs::create_dir_all(dir.path().join("target"))?;
+        fs::write(dir.path().join("a.txt"), "aaaa")?;
+        fs::write(dir.path().join("b/c/d.txt"), "dddd")?;
+        fs::write(dir.path().join("b/e.log"), "eeee")?;
+        fs::write(dir.path().join("target/f"), "ffff")?;
+
+        let options = PackOptions {
+            exclude: vec!["*.log".to_string(), "target".to_string()],
+            ..Default::default()
+        };
+        let layers = pack_dir(dir.path(), &options)?;
+        assert_eq!(layers.len(), 1);
+        assert_eq!(entries(&layers[0])?, ["a.txt", "b", "b/c", "b/c/d.txt"]);
+
+        // Same digest regardless of modification time
+        std::thread::sleep(std::time::Duration::from_millis(10));
+        fs::write(dir.path().join("a.txt"), "aaaa")?;
+        assert_eq!(pack_dir(dir.path(), &options)?, layers);
+
+        let options = PackOptions {
+            split_size: Some(6),
+            ..options
+        };
+        let layers = pack_dir(dir.path(), &options)?;
+        assert_eq!(layers.len(), 2);
+        assert_eq!(entries(&layers[0])?, ["a.txt", "b", "b/c"]);
+        assert_eq!(entries(&layers[1])?, ["b/c/d.txt"]);
+
+        let options = PackOptions {
+            compression: LayerCompression::None,
+            ..Default::default()
+        };
+        let layer = &pack_dir(dir.path(), &options)?[0];
+        assert_eq!(layer.media_type, MediaType::ImageLayer);
+        assert_eq!(Digest::from_buf_sha256(&layer.blob), layer.diff_id);
+        Ok(())
+    }
+
+    #[test]
+    fn pack_files_deterministic() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let a = dir.path().join("liba.a");
+        let b = dir.path().join("libb.so");
+        fs::write(&a, "aaaa")?;
+        fs::write(&b, "bbbb")?;
+        let options = PackOptions::default();
+        let layer = pack_files(&[&a, &b], &options)?;
+        assert_eq!(entries(&layer)?, ["liba.a", "libb.so"]);
+
+        std::thread::sleep(std::time::Duration::from_millis(10));
+        fs::write(&a, "aaaa")?;
+        assert_eq!(pack_files(&[&a, &b], &options)?, layer);
+        assert!(matches!(
+            pack_files(&[dir.path()], &options),
+            Err(Error::NotAFile(_))
+        ));
+        Ok(())
+    }
+}
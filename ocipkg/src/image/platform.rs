@@ -17,7 +17,7 @@ impl PlatformEx for Platform {
     fn from_cfg_macro() -> Self {
         let (arch, variant): (Arch, Option<String>) = if cfg!(target_arch = "x86_64") {
             (Arch::Amd64, None)
-        } else if cfg!(target_arch = "i686") {
+        } else if cfg!(target_arch = "x86") {
             (Arch::i386, None)
         } else if cfg!(target_arch = "aarch64") {
             (Arch::ARM64, Some("v8".to_string()))
@@ -67,6 +67,53 @@ impl PlatformEx for Platform {
     }
 }
 
+/// Show platform in `{os}/{architecture}[/{variant}]` form, e.g. `linux/arm64/v8`
+pub fn platform_string(platform: &Platform) -> String {
+    if let Some(variant) = platform.variant() {
+        format!("{}/{}/{}", platform.os(), platform.architecture(), variant)
+    } else {
+        format!("{}/{}", platform.os(), platform.architecture())
+    }
+}
+
+/// Parse platform in `{os}/{architecture}[/{variant}]` form, the inverse of [platform_string]
+pub fn parse_platform(input: &str) -> Result<Platform> {
+    let parts: Vec<&str> = input.split('/').collect();
+    let (os, arch, variant) = match parts[..] {
+        [os, arch] if !os.is_empty() && !arch.is_empty() => (os, arch, None),
+        [os, arch, variant] if !os.is_empty() && !arch.is_empty() && !variant.is_empty() => {
+            (os, arch, Some(variant))
+        }
+        _ => return Err(Error::InvalidPlatform(input.to_string())),
+    };
+    let mut builder = PlatformBuilder::default()
+        .os(Os::from(os))
+        .architecture(Arch::from(arch));
+    if let Some(variant) = variant {
+        builder = builder.variant(variant);
+    }
+    Ok(builder.build().unwrap())
+}
+
+/// Find a manifest for the platform in image index
+///
+/// The OS and architecture must be the same, and the variant is compared only if both have it.
+pub fn find_manifest_for_platform<'a>(
+    index: &'a ImageIndex,
+    platform: &Platform,
+) -> Option<&'a Descriptor> {
+    index.manifests().iter().find(|desc| {
+        let Some(p) = desc.platform() else {
+            return false;
+        };
+        let variant_matches = match (p.variant(), platform.variant()) {
+            (Some(a), Some(b)) => a == b,
+            _ => true,
+        };
+        p.os() == platform.os() && p.architecture() == platform.architecture() && variant_matches
+    })
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -79,6 +126,21 @@ mod test {
         assert_eq!(platform.os(), &Os::Linux);
     }
 
+    #[test]
+    fn parse_platform_string() -> Result<()> {
+        for input in ["linux/amd64", "linux/arm64/v8", "windows/386"] {
+            assert_eq!(platform_string(&parse_platform(input)?), input);
+        }
+        assert_eq!(
+            parse_platform("linux/arm64/v8")?.variant().as_deref(),
+            Some("v8")
+        );
+        assert!(parse_platform("linux").is_err());
+        assert!(parse_platform("linux/").is_err());
+        assert!(parse_platform("linux/arm64/v8/extra").is_err());
+        Ok(())
+    }
+
     #[test]
     fn from_target_triple() {
         fn test(target_triple: &str, arch: &Arch, os: &Os) {
@@ -22,7 +22,7 @@ impl<'buf, W: Read + Seek> Archive<'buf, W> {
         }
     }
 
-    pub fn entries(&mut self) -> Result<tar::Entries<&'buf mut W>> {
+    pub fn entries(&mut self) -> Result<tar::Entries<'_, &'buf mut W>> {
         let raw = self
             .archive
             .take()
@@ -37,18 +37,25 @@ impl<'buf, W: Read + Seek> Archive<'buf, W> {
             .entries_with_seek()?)
     }
 
+    /// Get manifests listed in `index.json` with their names
+    ///
+    /// If an image index is listed, i.e. a multi-platform image,
+    /// the manifest for the current platform is chosen from it.
     pub fn get_manifests(&mut self) -> Result<Vec<(ImageName, ImageManifest)>> {
         let index = self.get_index()?;
         index
             .manifests()
             .iter()
-            .map(|manifest| {
-                let annotations = annotations::flat::Annotations::from_map(
-                    manifest.annotations().clone().unwrap_or_default(),
-                )?;
-                let image_name = annotations.ref_name.ok_or(Error::MissingManifestName)?;
-                let image_name = ImageName::parse(&image_name)?;
-                let digest = Digest::new(manifest.digest())?;
+            .map(|desc| {
+                let image_name = get_image_name(desc)?;
+                let mut digest = Digest::new(desc.digest())?;
+                if is_index_media_type(desc.media_type()) {
+                    let index = self.get_image_index(&digest)?;
+                    let platform = Platform::from_cfg_macro();
+                    let desc = find_manifest_for_platform(&index, &platform)
+                        .ok_or_else(|| Error::MissingPlatform(platform_string(&platform)))?;
+                    digest = Digest::new(desc.digest())?;
+                }
                 let manifest = self.get_manifest(&digest)?;
                 Ok((image_name, manifest))
             })
@@ -67,7 +74,7 @@ impl<'buf, W: Read + Seek> Archive<'buf, W> {
         Err(Error::MissingIndex)
     }
 
-    pub fn get_blob(&mut self, digest: &Digest) -> Result<tar::Entry<&'buf mut W>> {
+    pub fn get_blob(&mut self, digest: &Digest) -> Result<tar::Entry<'_, &'buf mut W>> {
         for entry in self.entries()? {
             let entry = entry?;
             if entry.path()? == digest.as_path() {
@@ -82,6 +89,11 @@ impl<'buf, W: Read + Seek> Archive<'buf, W> {
         Ok(ImageManifest::from_reader(entry)?)
     }
 
+    pub fn get_image_index(&mut self, digest: &Digest) -> Result<ImageIndex> {
+        let entry = self.get_blob(digest)?;
+        Ok(ImageIndex::from_reader(entry)?)
+    }
+
     pub fn get_config(&mut self, digest: &Digest) -> Result<ImageConfiguration> {
         let entry = self.get_blob(digest)?;
         Ok(ImageConfiguration::from_reader(entry)?)
@@ -96,13 +108,26 @@ impl<'buf, W: Read + Seek> Archive<'buf, W> {
                 tar::Archive::new(buf).unpack(dest)?;
                 Ok(())
             }
+            MediaType::ImageLayer => {
+                tar::Archive::new(blob).unpack(dest)?;
+                Ok(())
+            }
             _ => unimplemented!("Unsupported layer type"),
         }
     }
 }
 
+/// Get image name from `org.opencontainers.image.ref.name` annotation of a descriptor in `index.json`
+pub fn get_image_name(desc: &Descriptor) -> Result<ImageName> {
+    let annotations =
+        annotations::flat::Annotations::from_map(desc.annotations().clone().unwrap_or_default())?;
+    let image_name = annotations.ref_name.ok_or(Error::MissingManifestName)?;
+    ImageName::parse(&image_name)
+}
+
 /// Load oci-archive into local storage
 pub fn load(input: &Path) -> Result<()> {
+    let _lock = crate::local::lock_store(false)?;
     let mut f = fs::File::open(input)?;
     let mut ar = Archive::new(&mut f);
     for (image_name, manifest) in ar.get_manifests()? {
@@ -119,6 +144,7 @@ pub fn load(input: &Path) -> Result<()> {
         for layer in manifest.layers() {
             ar.unpack_layer(layer, &dest)?;
         }
+        crate::local::touch_image(&image_name)?;
     }
     Ok(())
 }
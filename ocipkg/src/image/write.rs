@@ -3,12 +3,17 @@
 use chrono::{DateTime, Utc};
 use flate2::{write::GzEncoder, Compression};
 use oci_spec::image::*;
-use std::{collections::HashMap, fs, io, path::Path};
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    io::{self, Read},
+    path::Path,
+};
 
 use crate::{
     digest::{Digest, DigestBuf},
     error::*,
-    image::annotations::flat::Annotations,
+    image::{annotations::flat::Annotations, Archive},
     ImageName,
 };
 
@@ -22,6 +27,8 @@ pub struct Builder<W: io::Write> {
     author: Option<String>,
     annotations: Option<Annotations>,
     platform: Option<Platform>,
+    artifact_type: Option<MediaType>,
+    manifest_annotations: HashMap<String, String>,
     diff_ids: Vec<Digest>,
     layers: Vec<Descriptor>,
 }
@@ -35,6 +42,8 @@ impl<W: io::Write> Builder<W> {
             author: None,
             platform: None,
             annotations: None,
+            artifact_type: None,
+            manifest_annotations: HashMap::new(),
             diff_ids: Vec::new(),
             layers: Vec::new(),
         }
@@ -68,57 +77,36 @@ impl<W: io::Write> Builder<W> {
         self.platform = Some(platform.clone());
     }
 
+    /// Set `artifactType` of image manifest
+    pub fn set_artifact_type(&mut self, artifact_type: MediaType) {
+        self.artifact_type = Some(artifact_type);
+    }
+
+    /// Add an annotation to image manifest
+    ///
+    /// Unlike [Builder::set_annotations], which are stored as labels of image configuration,
+    /// any key is allowed.
+    pub fn add_annotation(&mut self, key: &str, value: &str) {
+        self.manifest_annotations
+            .insert(key.to_string(), value.to_string());
+    }
+
     /// Append a files as a layer
     pub fn append_files(&mut self, ps: &[impl AsRef<Path>]) -> Result<()> {
-        let mut ar = tar::Builder::new(DigestBuf::new(GzEncoder::new(
-            Vec::new(),
-            Compression::default(),
-        )));
-        for path in ps {
-            let path = path.as_ref();
-            if !path.is_file() {
-                return Err(Error::NotAFile(path.to_owned()));
-            }
-            let name = path
-                .file_name()
-                .expect("This never fails since checked above")
-                .to_str()
-                .expect("Non-UTF8 file name");
-            let mut f = fs::File::open(path)?;
-            ar.append_file(name, &mut f)?;
-        }
-        let (gz, digest) = ar
-            .into_inner()
-            .expect("This never fails since tar arhive is creating on memory")
-            .finish();
-        self.diff_ids.push(digest);
-        let buf = gz
-            .finish()
-            .expect("This never fails since zip is creating on memory");
-        let layer_desc = self.save_blob(MediaType::ImageLayerGzip, &buf)?;
-        self.layers.push(layer_desc);
-        Ok(())
+        let layer = LayerBuilder::from_files(ps)?;
+        self.append_layer(layer)
     }
 
     /// Append directory as a layer
     pub fn append_dir_all(&mut self, path: &Path) -> Result<()> {
-        if !path.is_dir() {
-            return Err(Error::NotADirectory(path.to_owned()));
-        }
-        let mut ar = tar::Builder::new(DigestBuf::new(GzEncoder::new(
-            Vec::new(),
-            Compression::default(),
-        )));
-        ar.append_dir_all("", path)?;
-        let (gz, digest) = ar
-            .into_inner()
-            .expect("This never fails since tar arhive is creating on memory")
-            .finish();
-        self.diff_ids.push(digest);
-        let buf = gz
-            .finish()
-            .expect("This never fails since zip is creating on memory");
-        let layer_desc = self.save_blob(MediaType::ImageLayerGzip, &buf)?;
+        let layer = LayerBuilder::from_dir(path)?;
+        self.append_layer(layer)
+    }
+
+    /// Append a layer created by [LayerBuilder]
+    pub fn append_layer(&mut self, layer: Layer) -> Result<()> {
+        self.diff_ids.push(layer.diff_id);
+        let layer_desc = self.save_blob(layer.media_type, &layer.blob)?;
         self.layers.push(layer_desc);
         Ok(())
     }
@@ -180,73 +168,272 @@ impl<W: io::Write> Builder<W> {
         cfg.to_writer(&mut buf)?;
         let cfg_desc = self.save_blob(MediaType::ImageConfig, &buf)?;
 
-        let image_manifest = ImageManifestBuilder::default()
+        let mut image_manifest = ImageManifestBuilder::default()
             .schema_version(SCHEMA_VERSION)
             .config(cfg_desc)
             .layers(std::mem::take(&mut self.layers))
             .build()
             .unwrap();
+        image_manifest.set_artifact_type(self.artifact_type.clone());
+        if !self.manifest_annotations.is_empty() {
+            image_manifest.set_annotations(Some(self.manifest_annotations.clone()));
+        }
         let mut buf = Vec::new();
         image_manifest.to_writer(&mut buf)?;
         let mut image_manifest_desc = self.save_blob(MediaType::ImageManifest, &buf)?;
+        if let Some(platform) = &self.platform {
+            image_manifest_desc.set_platform(Some(platform.clone()));
+        }
 
-        // https://github.com/opencontainers/image-spec/blob/main/annotations.md#pre-defined-annotation-keys
-        // > SHOULD only be considered valid when on descriptors on index.json within image layout.
-        //
-        // We need to set `org.opencontainers.image.ref.name` to index.json
-        image_manifest_desc.set_annotations(Some(HashMap::from([(
-            "org.opencontainers.image.ref.name".to_string(),
-            self.name.clone().unwrap_or_default().to_string(),
-        )])));
+        let name = self.name.clone().unwrap_or_default();
+        let builder = self
+            .builder
+            .as_mut()
+            .expect("builder never becomes None except on Drop");
+        append_index_json(builder, image_manifest_desc, &name)?;
+        builder.finish()?;
 
-        let index = ImageIndexBuilder::default()
-            .schema_version(SCHEMA_VERSION)
-            .manifests(vec![image_manifest_desc])
-            .build()?;
-        let mut index_json = Vec::new();
-        index.to_writer(&mut index_json)?;
-        let index_json = String::from_utf8(index_json).expect("ImageIndex must returns valid JSON");
-        self.save_file(Path::new("index.json"), &index_json)?;
+        Ok(())
+    }
 
-        let version = r#"{"imageLayoutVersion":"1.0.0"}"#;
-        self.save_file(Path::new("oci-layout"), version)?;
+    fn save_blob(&mut self, media_type: MediaType, buf: &[u8]) -> Result<Descriptor> {
+        append_blob(
+            self.builder
+                .as_mut()
+                .expect("builder never becomes None except on Drop"),
+            media_type,
+            buf,
+        )
+    }
+}
 
-        self.builder
-            .as_mut()
-            .expect("builder never becomes None except on Drop")
-            .finish()?;
+impl<W: io::Write> Drop for Builder<W> {
+    fn drop(&mut self) {
+        if self.builder.is_some() {
+            let _ = self.finish();
+        }
+    }
+}
+
+/// A layer blob in `application/vnd.oci.image.layer.v1.tar+gzip` or `application/vnd.oci.image.layer.v1.tar` format
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Layer {
+    /// Gzip compressed or uncompressed tar archive
+    pub blob: Vec<u8>,
+    /// [MediaType::ImageLayerGzip] or [MediaType::ImageLayer]
+    pub media_type: MediaType,
+    /// Digest of uncompressed tar archive, used as `diff_ids` in image configuration
+    pub diff_id: Digest,
+}
+
+impl Layer {
+    pub fn digest(&self) -> Digest {
+        Digest::from_buf_sha256(&self.blob)
+    }
+
+    /// Descriptor of this layer to be listed in image manifest
+    pub fn descriptor(&self) -> Descriptor {
+        Descriptor::new(
+            self.media_type.clone(),
+            self.blob.len() as i64,
+            self.digest().to_string(),
+        )
+    }
+}
+
+/// Create a [Layer] on memory
+pub struct LayerBuilder {
+    ar: tar::Builder<DigestBuf<GzEncoder<Vec<u8>>>>,
+}
+
+impl Default for LayerBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl LayerBuilder {
+    pub fn new() -> Self {
+        LayerBuilder {
+            ar: tar::Builder::new(DigestBuf::new(GzEncoder::new(
+                Vec::new(),
+                Compression::default(),
+            ))),
+        }
+    }
+
+    /// Create a layer consists of files, placed on the root of the layer
+    pub fn from_files(ps: &[impl AsRef<Path>]) -> Result<Layer> {
+        let mut builder = Self::new();
+        for path in ps {
+            builder.append_file(path.as_ref())?;
+        }
+        Ok(builder.finish())
+    }
 
+    /// Create a layer consists of the contents of directory
+    pub fn from_dir(path: &Path) -> Result<Layer> {
+        let mut builder = Self::new();
+        builder.append_dir_all(path)?;
+        Ok(builder.finish())
+    }
+
+    /// Append a file on the root of the layer
+    pub fn append_file(&mut self, path: &Path) -> Result<()> {
+        if !path.is_file() {
+            return Err(Error::NotAFile(path.to_owned()));
+        }
+        let name = path
+            .file_name()
+            .expect("This never fails since checked above")
+            .to_str()
+            .expect("Non-UTF8 file name");
+        let mut f = fs::File::open(path)?;
+        self.ar.append_file(name, &mut f)?;
         Ok(())
     }
 
-    fn save_blob(&mut self, media_type: MediaType, buf: &[u8]) -> Result<Descriptor> {
-        let digest = Digest::from_buf_sha256(buf);
+    /// Append the contents of directory on the root of the layer
+    pub fn append_dir_all(&mut self, path: &Path) -> Result<()> {
+        if !path.is_dir() {
+            return Err(Error::NotADirectory(path.to_owned()));
+        }
+        self.ar.append_dir_all("", path)?;
+        Ok(())
+    }
 
-        let mut header = create_header(buf.len());
-        self.builder
-            .as_mut()
-            .expect("builder never becomes None except on Drop")
-            .append_data(&mut header, digest.as_path(), buf)?;
+    pub fn finish(self) -> Layer {
+        let (gz, diff_id) = self
+            .ar
+            .into_inner()
+            .expect("This never fails since tar arhive is creating on memory")
+            .finish();
+        let blob = gz
+            .finish()
+            .expect("This never fails since zip is creating on memory");
+        Layer {
+            blob,
+            media_type: MediaType::ImageLayerGzip,
+            diff_id,
+        }
+    }
+}
 
-        Ok(DescriptorBuilder::default()
-            .media_type(media_type)
-            .size(buf.len() as i64)
-            .digest(format!("{}", digest))
-            .build()
-            .expect("Requirement for descriptor is mediaType, digest, and size."))
+/// Build a multi-platform container in oci-archive format
+///
+/// Images for each platform, e.g. created by [Builder] with [Builder::set_platform],
+/// are bundled into an image index, and the index is registered in `index.json`.
+pub struct IndexBuilder<W: io::Write> {
+    /// Include a flag to check if finished
+    builder: Option<tar::Builder<W>>,
+    name: Option<ImageName>,
+    manifests: Vec<Descriptor>,
+    blobs: HashSet<Digest>,
+}
+
+impl<W: io::Write> IndexBuilder<W> {
+    pub fn new(writer: W) -> Self {
+        IndexBuilder {
+            builder: Some(tar::Builder::new(writer)),
+            name: None,
+            manifests: Vec::new(),
+            blobs: HashSet::new(),
+        }
+    }
+
+    /// Set name of container, used in `org.opencontainers.image.ref.name` tag.
+    ///
+    /// If not set, a random name using UUID v4 hyphenated is set.
+    pub fn set_name(&mut self, name: &ImageName) {
+        self.name = Some(name.clone());
+    }
+
+    /// Append images stored in an oci-archive
+    ///
+    /// The platform of each image is taken from `index.json` of the archive,
+    /// or from the image configuration if it is not listed there.
+    pub fn append_archive(&mut self, path: &Path) -> Result<()> {
+        if !path.is_file() {
+            return Err(Error::NotAFile(path.to_owned()));
+        }
+        let mut f = fs::File::open(path)?;
+        let mut ar = Archive::new(&mut f);
+        for desc in ar.get_index()?.manifests() {
+            let digest = Digest::new(desc.digest())?;
+            let manifest = ar.get_manifest(&digest)?;
+            let platform = if let Some(platform) = desc.platform() {
+                platform.clone()
+            } else {
+                let config = ar.get_config(&Digest::new(manifest.config().digest())?)?;
+                let mut builder = PlatformBuilder::default()
+                    .os(config.os().clone())
+                    .architecture(config.architecture().clone());
+                if let Some(variant) = config.variant() {
+                    builder = builder.variant(variant.clone());
+                }
+                builder.build()?
+            };
+            for blob in std::iter::once(manifest.config()).chain(manifest.layers()) {
+                self.copy_blob(&mut ar, blob)?;
+            }
+            let mut manifest_desc = self.copy_blob(&mut ar, desc)?;
+            manifest_desc.set_platform(Some(platform));
+            self.manifests.push(manifest_desc);
+        }
+        Ok(())
     }
 
-    fn save_file(&mut self, dest: &Path, input: &str) -> Result<()> {
-        let mut header = create_header(input.len());
-        self.builder
+    pub fn into_inner(mut self) -> Result<W> {
+        self.finish()?;
+        Ok(self.builder.take().unwrap().into_inner()?)
+    }
+
+    /// Copy blob from archive, and returns a descriptor without annotations and platform
+    fn copy_blob<R: io::Read + io::Seek>(
+        &mut self,
+        ar: &mut Archive<R>,
+        desc: &Descriptor,
+    ) -> Result<Descriptor> {
+        let digest = Digest::new(desc.digest())?;
+        let mut buf = Vec::new();
+        ar.get_blob(&digest)?.read_to_end(&mut buf)?;
+        let builder = self
+            .builder
+            .as_mut()
+            .expect("builder never becomes None except on Drop");
+        if self.blobs.insert(digest) {
+            append_blob(builder, desc.media_type().clone(), &buf)
+        } else {
+            Ok(Descriptor::new(
+                desc.media_type().clone(),
+                buf.len() as i64,
+                desc.digest(),
+            ))
+        }
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        let index = ImageIndexBuilder::default()
+            .schema_version(SCHEMA_VERSION)
+            .media_type(MediaType::ImageIndex)
+            .manifests(std::mem::take(&mut self.manifests))
+            .build()?;
+        let mut buf = Vec::new();
+        index.to_writer(&mut buf)?;
+
+        let name = self.name.clone().unwrap_or_default();
+        let builder = self
+            .builder
             .as_mut()
-            .expect("builder never becomes None except on Drop")
-            .append_data(&mut header, dest, input.as_bytes())?;
+            .expect("builder never becomes None except on Drop");
+        let index_desc = append_blob(builder, MediaType::ImageIndex, &buf)?;
+        append_index_json(builder, index_desc, &name)?;
+        builder.finish()?;
         Ok(())
     }
 }
 
-impl<W: io::Write> Drop for Builder<W> {
+impl<W: io::Write> Drop for IndexBuilder<W> {
     fn drop(&mut self) {
         if self.builder.is_some() {
             let _ = self.finish();
@@ -254,6 +441,63 @@ impl<W: io::Write> Drop for Builder<W> {
     }
 }
 
+/// Append `index.json` and `oci-layout` to finish the oci-archive
+fn append_index_json<W: io::Write>(
+    builder: &mut tar::Builder<W>,
+    mut desc: Descriptor,
+    name: &ImageName,
+) -> Result<()> {
+    // https://github.com/opencontainers/image-spec/blob/main/annotations.md#pre-defined-annotation-keys
+    // > SHOULD only be considered valid when on descriptors on index.json within image layout.
+    //
+    // We need to set `org.opencontainers.image.ref.name` to index.json
+    desc.set_annotations(Some(HashMap::from([(
+        "org.opencontainers.image.ref.name".to_string(),
+        name.to_string(),
+    )])));
+
+    let index = ImageIndexBuilder::default()
+        .schema_version(SCHEMA_VERSION)
+        .manifests(vec![desc])
+        .build()?;
+    let mut index_json = Vec::new();
+    index.to_writer(&mut index_json)?;
+    let index_json = String::from_utf8(index_json).expect("ImageIndex must returns valid JSON");
+    append_file(builder, Path::new("index.json"), &index_json)?;
+
+    let version = r#"{"imageLayoutVersion":"1.0.0"}"#;
+    append_file(builder, Path::new("oci-layout"), version)?;
+    Ok(())
+}
+
+fn append_blob<W: io::Write>(
+    builder: &mut tar::Builder<W>,
+    media_type: MediaType,
+    buf: &[u8],
+) -> Result<Descriptor> {
+    let digest = Digest::from_buf_sha256(buf);
+
+    let mut header = create_header(buf.len());
+    builder.append_data(&mut header, digest.as_path(), buf)?;
+
+    Ok(DescriptorBuilder::default()
+        .media_type(media_type)
+        .size(buf.len() as i64)
+        .digest(format!("{}", digest))
+        .build()
+        .expect("Requirement for descriptor is mediaType, digest, and size."))
+}
+
+fn append_file<W: io::Write>(
+    builder: &mut tar::Builder<W>,
+    dest: &Path,
+    input: &str,
+) -> Result<()> {
+    let mut header = create_header(input.len());
+    builder.append_data(&mut header, dest, input.as_bytes())?;
+    Ok(())
+}
+
 fn create_header(size: usize) -> tar::Header {
     let mut header = tar::Header::new_gnu();
     header.set_size(u64::try_from(size).unwrap());
@@ -262,3 +506,51 @@ fn create_header(size: usize) -> tar::Header {
     header.set_mtime(Utc::now().timestamp() as u64);
     header
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::image::{find_manifest_for_platform, PlatformEx};
+
+    #[test]
+    fn index_builder() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let input = dir.path().join("lib.txt");
+        fs::write(&input, "test")?;
+
+        let mut archives = Vec::new();
+        for target in ["x86_64-unknown-linux-gnu", "aarch64-unknown-linux-gnu"] {
+            let path = dir.path().join(format!("{}.tar", target));
+            let mut b = Builder::new(fs::File::create(&path)?);
+            b.set_platform(&Platform::from_target_triple(target)?);
+            b.append_files(&[&input])?;
+            b.into_inner()?;
+            archives.push(path);
+        }
+
+        let name = ImageName::parse("localhost:5000/test_repo:multi")?;
+        let path = dir.path().join("index.tar");
+        let mut b = IndexBuilder::new(fs::File::create(&path)?);
+        b.set_name(&name);
+        for archive in &archives {
+            b.append_archive(archive)?;
+        }
+        b.into_inner()?;
+
+        let mut f = fs::File::open(&path)?;
+        let mut ar = Archive::new(&mut f);
+        let index = ar.get_index()?;
+        assert_eq!(index.manifests().len(), 1);
+        let desc = &index.manifests()[0];
+        assert_eq!(desc.media_type(), &MediaType::ImageIndex);
+        assert_eq!(crate::image::get_image_name(desc)?, name);
+
+        let image_index = ar.get_image_index(&Digest::new(desc.digest())?)?;
+        assert_eq!(image_index.manifests().len(), 2);
+        let arm = Platform::from_target_triple("aarch64-unknown-linux-gnu")?;
+        let arm = find_manifest_for_platform(&image_index, &arm).unwrap();
+        let manifest = ar.get_manifest(&Digest::new(arm.digest())?)?;
+        assert_eq!(manifest.layers().len(), 1);
+        Ok(())
+    }
+}
@@ -1,7 +1,4 @@
-use crate::{
-    distribution::{Name, Reference},
-    error::*,
-};
+use crate::{error::*, Name, Reference};
 use std::fmt;
 use url::Url;
 
@@ -34,7 +31,7 @@ use url::Url;
 /// ```
 ///
 /// ```rust
-/// use ocipkg::{ImageName, distribution::{Name, Reference}};
+/// use ocipkg::{ImageName, Name, Reference};
 /// let name = ImageName::parse("ghcr.io/termoshtt/ocipkg/testing:latest")?;
 /// assert_eq!(
 ///     name,
@@ -59,7 +56,7 @@ use url::Url;
 /// ```
 ///
 /// ```
-/// use ocipkg::{ImageName, distribution::{Name, Reference}};
+/// use ocipkg::{ImageName, Name, Reference};
 /// let name = ImageName::parse("localhost:5000/test_repo:latest")?;
 /// assert_eq!(
 ///     name,
@@ -73,12 +70,23 @@ use url::Url;
 /// # Ok::<(), ocipkg::error::Error>(())
 /// ```
 ///
+/// An IPv6 address is written in brackets, and kept as `hostname` with them:
+///
+/// ```
+/// use ocipkg::{ImageName, Name, Reference};
+/// let name = ImageName::parse("[fd00::5]:5000/test_repo:tag1")?;
+/// assert_eq!(name.hostname, "[fd00::5]");
+/// assert_eq!(name.port, Some(5000));
+/// assert_eq!(name.registry_url()?.as_str(), "https://[fd00::5]:5000/");
+/// # Ok::<(), ocipkg::error::Error>(())
+/// ```
+///
 /// Default values
 /// ---------------
 /// If `hostname` is absent, use `registry-1.docker.io` for docker compatiblity:
 ///
 /// ```
-/// use ocipkg::{ImageName, distribution::{Name, Reference}};
+/// use ocipkg::{ImageName, Name, Reference};
 /// let name = ImageName::parse("ubuntu:20.04")?;
 /// assert_eq!(
 ///     name,
@@ -95,7 +103,7 @@ use url::Url;
 /// If `reference` is absent, use `latest`:
 ///
 /// ```
-/// use ocipkg::{ImageName, distribution::{Name, Reference}};
+/// use ocipkg::{ImageName, Name, Reference};
 /// let name = ImageName::parse("alpine").unwrap();
 /// assert_eq!(
 ///     name,
@@ -109,6 +117,17 @@ use url::Url;
 /// # Ok::<(), ocipkg::error::Error>(())
 /// ```
 ///
+/// Pinned by digest with `@` as docker, where the reference is the digest:
+///
+/// ```
+/// use ocipkg::{ImageName, Reference};
+/// let digest = "sha256:e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+/// let name = ImageName::parse(&format!("ghcr.io/termoshtt/ocipkg/testing@{}", digest))?;
+/// assert_eq!(name.reference, Reference::new(digest)?);
+/// assert!(name.reference.is_digest());
+/// # Ok::<(), ocipkg::error::Error>(())
+/// ```
+///
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct ImageName {
     pub hostname: String,
@@ -143,12 +162,11 @@ impl ImageName {
         let (hostname, name) = name
             .split_once('/')
             .unwrap_or(("registry-1.docker.io", name));
-        let (hostname, port) = if let Some((hostname, port)) = hostname.split_once(':') {
-            (hostname, Some(str::parse(port)?))
-        } else {
-            (hostname, None)
-        };
-        let (name, reference) = name.split_once(':').unwrap_or((name, "latest"));
+        let (hostname, port) = split_host_port(hostname)?;
+        let (name, reference) = name
+            .split_once('@')
+            .or_else(|| name.split_once(':'))
+            .unwrap_or((name, "latest"));
         Ok(ImageName {
             hostname: hostname.to_string(),
             port,
@@ -172,3 +190,61 @@ impl ImageName {
         Ok(Url::parse(&url)?)
     }
 }
+
+/// Split `hostname[:port]`, where an IPv6 address is in brackets, e.g. `[fd00::5]:5000`
+pub(crate) fn split_host_port(registry: &str) -> Result<(&str, Option<u16>)> {
+    let (hostname, port) = if registry.starts_with('[') {
+        let err = || Error::InvalidHostname(registry.to_string());
+        let end = registry.find(']').ok_or_else(err)? + 1;
+        let (hostname, rest) = registry.split_at(end);
+        match rest.strip_prefix(':') {
+            Some(port) => (hostname, Some(port)),
+            None if rest.is_empty() => (hostname, None),
+            None => return Err(err()),
+        }
+    } else {
+        match registry.split_once(':') {
+            Some((hostname, port)) => (hostname, Some(port)),
+            None => (registry, None),
+        }
+    };
+    Ok((hostname, port.map(str::parse).transpose()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_ipv6() -> Result<()> {
+        let name = ImageName::parse("[fd00::5]/test_repo:tag1")?;
+        assert_eq!(name.hostname, "[fd00::5]");
+        assert_eq!(name.port, None);
+        assert_eq!(name.reference, Reference::new("tag1")?);
+
+        let name = ImageName::parse("[fd00::5]:5000/test_repo:tag1")?;
+        assert_eq!(name.hostname, "[fd00::5]");
+        assert_eq!(name.port, Some(5000));
+        assert_eq!(name.name, Name::new("test_repo")?);
+        assert_eq!(name.reference, Reference::new("tag1")?);
+        assert_eq!(name.to_string(), "[fd00::5]:5000/test_repo:tag1");
+        assert_eq!(ImageName::parse(&name.to_string())?, name);
+
+        let digest = "sha256:e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+        let name = ImageName::parse(&format!("[::1]:5000/test_repo@{}", digest))?;
+        assert_eq!(name.hostname, "[::1]");
+        assert_eq!(name.port, Some(5000));
+        assert_eq!(name.reference, Reference::new(digest)?);
+        assert_eq!(name.registry_url()?.port(), Some(5000));
+
+        assert!(matches!(
+            ImageName::parse("[fd00::5/test_repo"),
+            Err(Error::InvalidHostname(_))
+        ));
+        assert!(matches!(
+            ImageName::parse("[fd00::5]5000/test_repo"),
+            Err(Error::InvalidHostname(_))
+        ));
+        Ok(())
+    }
+}
@@ -3,17 +3,47 @@
 //! See [README.md at GitHub](https://github.com/termoshtt/ocipkg) for usage of the executables.
 //! This reference describes the crate part.
 //!
+//! Features
+//! ---------
+//! - `network` (default): Pull and push images from OCI registry by [distribution] module.
+//!
+//! `build.rs` which only reads images already in local storage, e.g. prepared by `ocipkg get`,
+//! does not need the network stack:
+//!
+//! ```toml
+//! [build-dependencies]
+//! ocipkg = { version = "0.3.0", default-features = false }
+//! ```
+//!
 
+pub mod build;
+pub mod config;
+#[cfg(feature = "network")]
 pub mod distribution;
 pub mod error;
 pub mod image;
 pub mod local;
+pub mod lockfile;
 
+mod content_type;
 mod digest;
+mod ext;
 mod image_name;
+mod name;
+mod reference;
 
+pub use content_type::{LayerCompression, OciContentType};
 pub use digest::Digest;
+pub use ext::{DescriptorExt, ManifestExt};
 pub use image_name::ImageName;
+pub use name::Name;
+pub use reference::Reference;
+
+// Types of `oci_spec` appearing in the public API, so that users do not need to depend on `oci-spec` directly
+pub use oci_spec::{
+    distribution::ErrorResponse,
+    image::{Descriptor, ImageConfiguration, ImageIndex, ImageManifest, MediaType, Platform},
+};
 
 use crate::error::*;
 use std::fs;
@@ -33,12 +63,10 @@ const STATIC_EXTENSION: &str = if cfg!(target_os = "windows") {
 /// Get and link package in `build.rs` with [cargo link instructions](https://doc.rust-lang.org/cargo/reference/build-scripts.html#outputs-of-the-build-script).
 ///
 /// This is aimed to use in [build script](https://doc.rust-lang.org/cargo/reference/build-scripts.html) a.k.a. `build.rs`.
+/// Without `network` feature, the image must exist in local storage.
 pub fn link_package(image_name: &str) -> Result<()> {
     let image_name = ImageName::parse(image_name)?;
-    let dir = local::image_dir(&image_name)?;
-    if !dir.exists() {
-        distribution::get_image(&image_name, false)?;
-    }
+    let dir = build::ensure_local(&image_name)?;
     println!("cargo:rustc-link-search={}", dir.display());
     for path in fs::read_dir(&dir)?.filter_map(|entry| {
         let path = entry.ok()?.path();
@@ -1,15 +1,66 @@
 //! Manage container images stored in local storage
 
-use crate::{
-    distribution::{Name, Reference},
-    error::*,
-    ImageName,
-};
+mod verify;
+
+pub use verify::*;
+
+use crate::{error::*, Digest, ImageName, Name, Reference};
+use chrono::{DateTime, Utc};
 use directories::ProjectDirs;
-use std::{path::*, sync::OnceLock};
+use std::{
+    path::*,
+    sync::OnceLock,
+    time::{Duration, SystemTime},
+};
 
 pub const DEFAULT_PROJECT_NAME: &str = "ocipkg";
 
+/// Name of directory for extraction cache under [data_dir]
+const EXTRACT_CACHE: &str = ".extract";
+
+/// Lock file for the whole local storage under [data_dir], see [gc]
+const STORE_LOCK: &str = ".store.lock";
+
+/// File in image directory recording when the image is pulled or used last time
+pub(crate) const LAST_ACCESS: &str = ".last-access";
+
+/// File in image directory recording [ImageProvenance] of the image
+pub(crate) const SOURCE: &str = ".source.json";
+
+/// Image names requested and actually fetched, recorded when the image is pulled
+///
+/// They differ if the source is replaced by [crate::config::Config::replace_source].
+/// This is stored next to `.manifest.json`, which is kept as it is in the registry.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct ImageProvenance {
+    pub requested: String,
+    pub actual: String,
+}
+
+/// Result of [remove_images] and [prune_images]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RemoveReport {
+    /// Images removed from local storage.
+    ///
+    /// These are not removed actually if `dry_run` is set.
+    pub removed: Vec<ImageName>,
+    /// Total size of removed image directories in bytes
+    pub bytes_freed: u64,
+    /// Size of extraction cache becoming stale by the removal, reclaimable by [gc_extract_cache]
+    pub reclaimable: u64,
+}
+
+/// Result of [gc]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct GcReport {
+    /// Paths removed with their sizes in bytes.
+    ///
+    /// These are not removed actually if `dry_run` is set.
+    pub removed: Vec<(PathBuf, u64)>,
+    /// Total size of removed files in bytes
+    pub bytes_freed: u64,
+}
+
 static PROJECT_DIRS: OnceLock<ProjectDirs> = OnceLock::new();
 
 pub fn set_project_dirs(dirs: ProjectDirs) -> Result<()> {
@@ -29,6 +80,145 @@ pub fn data_dir() -> Result<PathBuf> {
     Ok(dir.to_owned())
 }
 
+/// Directory of extraction cache shared across workspaces, see [crate::build::extract_to_out_dir]
+pub fn extract_cache_dir() -> Result<PathBuf> {
+    Ok(data_dir()?.join(EXTRACT_CACHE))
+}
+
+/// Remove entries in extraction cache which do not correspond to images in local storage
+///
+/// An entry is stale if the image has been removed or updated in local storage,
+/// or the extraction has not been completed. Returns the removed directories.
+pub fn gc_extract_cache() -> Result<Vec<PathBuf>> {
+    let root = extract_cache_dir()?;
+    if !root.is_dir() {
+        return Ok(Vec::new());
+    }
+    let mut removed = Vec::new();
+    for entry in std::fs::read_dir(&root)? {
+        let path = entry?.path();
+        if !path.is_dir() {
+            continue;
+        }
+        let lock = std::fs::File::create(crate::build::lock_path(&path))?;
+        lock.lock()?;
+        if is_stale_extraction(&path) {
+            log::info!("Remove stale extraction cache: {}", path.display());
+            crate::build::remove_extracted(&path)?;
+            drop(lock);
+            std::fs::remove_file(crate::build::lock_path(&path))?;
+            removed.push(path);
+        }
+    }
+    Ok(removed)
+}
+
+/// Remove stale entries in extraction cache as [gc_extract_cache], and lock files left by removed images
+///
+/// Entries modified within `keep_within` are kept even if stale
+/// not to race with other processes using them.
+/// The whole local storage is locked while this runs, and then this waits for
+/// processes pulling, loading, or extracting images to finish.
+/// If `dry_run` is set, nothing is removed and only the report is returned.
+pub fn gc(keep_within: Duration, dry_run: bool) -> Result<GcReport> {
+    let mut report = GcReport::default();
+    let data_dir = data_dir()?;
+    if !data_dir.is_dir() {
+        return Ok(report);
+    }
+    let _lock = lock_store(true)?;
+    let recent = |path: &Path| {
+        std::fs::metadata(path)
+            .and_then(|m| m.modified())
+            .is_ok_and(|t| t.elapsed().is_ok_and(|elapsed| elapsed < keep_within))
+    };
+
+    let root = extract_cache_dir()?;
+    if root.is_dir() {
+        for entry in std::fs::read_dir(&root)? {
+            let path = entry?.path();
+            if !path.is_dir() || recent(&path) || !is_stale_extraction(&path) {
+                continue;
+            }
+            let size = dir_size(&path)?;
+            if !dry_run {
+                log::info!("Remove stale extraction cache: {}", path.display());
+                crate::build::remove_extracted(&path)?;
+            }
+            report.bytes_freed += size;
+            report.removed.push((path, size));
+        }
+    }
+
+    // Lock files are placed next to image directories and extraction cache entries
+    let mut locks = Vec::new();
+    for entry in walkdir::WalkDir::new(&data_dir)
+        .into_iter()
+        .filter_entry(|e| {
+            // Do not look into contents of images and extracted directories
+            !(e.file_type().is_dir()
+                && (e.file_name().to_string_lossy().starts_with("__")
+                    || e.path().parent() == Some(root.as_path())))
+        })
+    {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy();
+        let in_cache = entry.path().parent() == Some(root.as_path());
+        if entry.file_type().is_file()
+            && name.ends_with(".lock")
+            && (name.starts_with("__") || in_cache)
+        {
+            locks.push(entry.into_path());
+        }
+    }
+    for path in locks {
+        let target = path.with_extension("");
+        let removed = report.removed.iter().any(|(p, _)| p == &target);
+        if (target.exists() && !removed) || recent(&path) {
+            continue;
+        }
+        let size = std::fs::metadata(&path)?.len();
+        if !dry_run {
+            log::info!("Remove orphan lock file: {}", path.display());
+            std::fs::remove_file(&path)?;
+        }
+        report.bytes_freed += size;
+        report.removed.push((path, size));
+    }
+    Ok(report)
+}
+
+/// Lock the whole local storage
+///
+/// Processes writing into local storage take the shared lock,
+/// and [gc] takes the exclusive lock.
+pub(crate) fn lock_store(exclusive: bool) -> Result<std::fs::File> {
+    let dir = data_dir()?;
+    std::fs::create_dir_all(&dir)?;
+    let lock = std::fs::File::create(dir.join(STORE_LOCK))?;
+    if exclusive {
+        lock.lock()?;
+    } else {
+        lock.lock_shared()?;
+    }
+    Ok(lock)
+}
+
+/// Check if the extraction is not completed, or all the images owning it have been removed or updated
+fn is_stale_extraction(path: &Path) -> bool {
+    let alive = crate::build::read_marker(path).is_some_and(|(digest, owners)| {
+        owners.iter().any(|name| {
+            let Ok(name) = ImageName::parse(name) else {
+                return false;
+            };
+            image_dir(&name)
+                .and_then(|dir| Ok(std::fs::read(dir.join(".manifest.json"))?))
+                .is_ok_and(|buf| Digest::from_buf_sha256(&buf).to_string() == digest)
+        })
+    });
+    !alive
+}
+
 /// Resolve a path to local storage where the image will be stored
 pub fn image_dir(name: &ImageName) -> Result<PathBuf> {
     let dir = data_dir()?;
@@ -45,6 +235,251 @@ pub fn image_dir(name: &ImageName) -> Result<PathBuf> {
     }
 }
 
+/// Store the image in local storage also as another name, e.g. another tag
+///
+/// The files are copied, and then the new one is independent of the source.
+/// An existing image of `dst` is replaced only if it has the same manifest, or `force` is set.
+pub fn tag_image(src: &ImageName, dst: &ImageName, force: bool) -> Result<PathBuf> {
+    let src_dir = image_dir(src)?;
+    if !src_dir.join(".manifest.json").is_file() {
+        return Err(Error::NotADirectory(src_dir));
+    }
+    let dst_dir = image_dir(dst)?;
+    if dst_dir.exists() {
+        let same = std::fs::read(dst_dir.join(".manifest.json"))
+            .is_ok_and(|buf| std::fs::read(src_dir.join(".manifest.json")).is_ok_and(|b| b == buf));
+        if !same && !force {
+            return Err(Error::TagAlreadyExists(dst.to_string()));
+        }
+        std::fs::remove_dir_all(&dst_dir)?;
+    }
+    for entry in walkdir::WalkDir::new(&src_dir) {
+        let entry = entry?;
+        let dest = dst_dir.join(entry.path().strip_prefix(&src_dir).unwrap());
+        if entry.file_type().is_dir() {
+            std::fs::create_dir_all(&dest)?;
+        } else {
+            std::fs::copy(entry.path(), &dest)?;
+        }
+    }
+    Ok(dst_dir)
+}
+
+/// Extract the layers of image pulled into local storage into a directory
+///
+/// The layers are read from the blobs kept by [crate::distribution::get_image],
+/// so images created by [crate::image::load] are not supported.
+pub fn extract_image(
+    name: &ImageName,
+    dest: &Path,
+    options: crate::image::ExtractOptions,
+) -> Result<()> {
+    let (dir, manifest) = pulled_manifest(name)?;
+    let mut extractor = crate::image::Extractor::new(dest, options)?;
+    extractor.apply_layers(manifest.layers(), |layer| open_layer(&dir, layer))
+}
+
+/// Write a file in the image in local storage to `out`, see [crate::image::read_file]
+pub fn read_image_file(name: &ImageName, path: &str, out: &mut impl std::io::Write) -> Result<()> {
+    let (dir, manifest) = pulled_manifest(name)?;
+    crate::image::read_file(
+        manifest.layers(),
+        |layer| open_layer(&dir, layer),
+        path,
+        out,
+    )
+}
+
+/// List a directory in the image in local storage, see [crate::image::list_dir]
+pub fn list_image_dir(name: &ImageName, dir: &str) -> Result<Vec<String>> {
+    let (image_dir, manifest) = pulled_manifest(name)?;
+    crate::image::list_dir(
+        manifest.layers(),
+        |layer| open_layer(&image_dir, layer),
+        dir,
+    )
+}
+
+/// Image directory and the manifest of the image pulled by [crate::distribution::get_image]
+fn pulled_manifest(name: &ImageName) -> Result<(PathBuf, oci_spec::image::ImageManifest)> {
+    let dir = image_dir(name)?;
+    let path = dir.join(".manifest.json");
+    if !path.is_file() {
+        return Err(Error::NotAFile(path));
+    }
+    let manifest = oci_spec::image::ImageManifest::from_file(&path)?;
+    Ok((dir, manifest))
+}
+
+fn open_layer(dir: &Path, layer: &oci_spec::image::Descriptor) -> Result<std::fs::File> {
+    let digest = Digest::new(layer.digest())?;
+    let blob = dir
+        .join(".blob")
+        .join(&digest.algorithm)
+        .join(&digest.encoded);
+    Ok(std::fs::File::open(blob)?)
+}
+
+/// Digest of the manifest of the image in local storage
+pub fn image_digest(name: &ImageName) -> Result<Digest> {
+    let path = image_dir(name)?.join(".manifest.json");
+    if !path.is_file() {
+        return Err(Error::NotAFile(path));
+    }
+    Ok(Digest::from_buf_sha256(&std::fs::read(path)?))
+}
+
+/// Update annotations of the manifest of the image in local storage as [crate::image::annotations::update]
+///
+/// Only images pulled by [crate::distribution::get_image] are supported. Layers are not changed, and the manifest is replaced in place.
+/// The extraction of the old manifest becomes stale, and is removed by [gc_extract_cache].
+/// Returns the digest of the new manifest.
+pub fn annotate_image(
+    name: &ImageName,
+    set: &[(String, String)],
+    remove: &[String],
+) -> Result<Digest> {
+    let _lock = lock_store(false)?;
+    let path = image_dir(name)?.join(".manifest.json");
+    if !path.is_file() {
+        return Err(Error::NotAFile(path));
+    }
+    let mut manifest = oci_spec::image::ImageManifest::from_file(&path)?;
+    let mut annotations = manifest.annotations().clone().unwrap_or_default();
+    crate::image::annotations::update(&mut annotations, set, remove)?;
+    manifest.set_annotations((!annotations.is_empty()).then_some(annotations));
+    let buf = serde_json::to_string_pretty(&manifest)?;
+    std::fs::write(&path, &buf)?;
+    Ok(Digest::from_buf_sha256(buf.as_bytes()))
+}
+
+/// Images in local storage whose manifest has the digest
+pub fn find_images_by_digest(digest: &Digest) -> Result<Vec<ImageName>> {
+    Ok(get_image_list()?
+        .into_iter()
+        .filter(|name| image_digest(name).is_ok_and(|d| &d == digest))
+        .collect())
+}
+
+/// Record that the image is pulled or used now
+pub fn touch_image(name: &ImageName) -> Result<()> {
+    std::fs::write(image_dir(name)?.join(LAST_ACCESS), Utc::now().to_rfc3339())?;
+    Ok(())
+}
+
+/// Record where the image is pulled from
+pub fn write_image_provenance(name: &ImageName, provenance: &ImageProvenance) -> Result<()> {
+    std::fs::write(
+        image_dir(name)?.join(SOURCE),
+        serde_json::to_string_pretty(provenance)?,
+    )?;
+    Ok(())
+}
+
+/// Where the image is pulled from, or `None` for images stored without the record
+pub fn image_provenance(name: &ImageName) -> Result<Option<ImageProvenance>> {
+    let path = image_dir(name)?.join(SOURCE);
+    if !path.is_file() {
+        return Ok(None);
+    }
+    Ok(Some(serde_json::from_str(&std::fs::read_to_string(path)?)?))
+}
+
+/// When the image is pulled or used last time
+///
+/// The modification time of the image directory is used for images
+/// stored before the access is recorded.
+pub fn last_access(name: &ImageName) -> Result<DateTime<Utc>> {
+    let dir = image_dir(name)?;
+    if let Some(time) = std::fs::read_to_string(dir.join(LAST_ACCESS))
+        .ok()
+        .and_then(|s| DateTime::parse_from_rfc3339(s.trim()).ok())
+    {
+        return Ok(time.with_timezone(&Utc));
+    }
+    Ok(std::fs::metadata(&dir)?.modified()?.into())
+}
+
+/// Remove images from local storage
+///
+/// All images must exist, otherwise nothing is removed.
+/// If `dry_run` is set, nothing is removed and only the report is returned.
+pub fn remove_images(names: &[ImageName], dry_run: bool) -> Result<RemoveReport> {
+    let mut report = RemoveReport::default();
+    let mut dirs = Vec::new();
+    for name in names {
+        let dir = image_dir(name)?;
+        if !dir.is_dir() {
+            return Err(Error::NotADirectory(dir));
+        }
+        if !report.removed.contains(name) {
+            report.removed.push(name.clone());
+            dirs.push(dir);
+        }
+    }
+    for dir in &dirs {
+        report.bytes_freed += dir_size(dir)?;
+    }
+    report.reclaimable = stale_extract_cache_size(&report.removed)?;
+    if !dry_run {
+        for dir in &dirs {
+            log::info!("Remove local image: {}", dir.display());
+            std::fs::remove_dir_all(dir)?;
+            let lock = crate::build::lock_path(dir);
+            if lock.is_file() {
+                std::fs::remove_file(lock)?;
+            }
+        }
+    }
+    Ok(report)
+}
+
+/// Remove images which have not been pulled or used within `older_than`, see [last_access]
+pub fn prune_images(older_than: Duration, dry_run: bool) -> Result<RemoveReport> {
+    let Some(threshold) = SystemTime::now().checked_sub(older_than) else {
+        return Ok(RemoveReport::default());
+    };
+    let threshold = DateTime::<Utc>::from(threshold);
+    let mut names = Vec::new();
+    for name in get_image_list()? {
+        if last_access(&name)? < threshold {
+            names.push(name);
+        }
+    }
+    remove_images(&names, dry_run)
+}
+
+/// Total size of extraction cache entries owned only by the images
+fn stale_extract_cache_size(names: &[ImageName]) -> Result<u64> {
+    let root = extract_cache_dir()?;
+    if !root.is_dir() {
+        return Ok(0);
+    }
+    let names: Vec<String> = names.iter().map(|name| name.to_string()).collect();
+    let mut size = 0;
+    for entry in std::fs::read_dir(&root)? {
+        let path = entry?.path();
+        if path.is_dir()
+            && crate::build::read_marker(&path)
+                .is_some_and(|(_, owners)| owners.iter().all(|name| names.contains(name)))
+        {
+            size += dir_size(&path)?;
+        }
+    }
+    Ok(size)
+}
+
+fn dir_size(dir: &Path) -> Result<u64> {
+    let mut size = 0;
+    for entry in walkdir::WalkDir::new(dir) {
+        let entry = entry?;
+        if entry.file_type().is_file() {
+            size += entry.metadata()?.len();
+        }
+    }
+    Ok(size)
+}
+
 fn path_to_image_name(path: &Path) -> Result<ImageName> {
     let rel_path = path
         .strip_prefix(data_dir()?)
@@ -88,7 +523,10 @@ pub fn get_image_list() -> Result<Vec<ImageName>> {
     }
 
     let mut images = Vec::new();
-    for entry in walkdir::WalkDir::new(data_dir) {
+    for entry in walkdir::WalkDir::new(data_dir)
+        .into_iter()
+        .filter_entry(|e| e.file_name() != EXTRACT_CACHE)
+    {
         let entry = entry?;
         let path = entry.path();
         if !path.is_dir() {
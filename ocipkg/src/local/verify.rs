@@ -0,0 +1,178 @@
+use crate::{
+    error::*,
+    image::lint::{check_size, Check, LintOptions},
+    Digest, ImageName,
+};
+use oci_spec::image::{Descriptor, ImageConfiguration, ImageManifest, MediaType};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// Check integrity of an image in local storage pulled by [crate::distribution::get_image]
+///
+/// Each blob is hashed again and compared with its descriptor in the stored manifest.
+/// The digests of uncompressed layers are compared with `diff_ids` of the image configuration
+/// if it is stored, which is not the case for images pulled by older versions.
+/// The total size of layers is checked against `max_size` if set.
+pub fn verify_image(name: &ImageName, max_size: Option<u64>) -> Result<Vec<Check>> {
+    let dir = super::image_dir(name)?;
+    let path = dir.join(".manifest.json");
+    if !path.is_file() {
+        return Err(Error::NotAFile(path));
+    }
+    let manifest = ImageManifest::from_file(&path)?;
+    let blob_root = dir.join(".blob");
+
+    let mut checks = Vec::new();
+    let mut diff_ids = Vec::new();
+    for desc in manifest.layers() {
+        let path = blob_path(&blob_root, desc)?;
+        let check = verify_blob(&path, desc)?;
+        diff_ids.push(if check.passed {
+            diff_id(&path, desc.media_type())?
+        } else {
+            None
+        });
+        checks.push(check);
+    }
+
+    let config = manifest.config();
+    let config_path = blob_path(&blob_root, config)?;
+    if config_path.is_file() {
+        let check = verify_blob(&config_path, config)?;
+        let passed = check.passed;
+        checks.push(check);
+        if passed {
+            let config = ImageConfiguration::from_file(&config_path)?;
+            checks.push(check_diff_ids(config.rootfs().diff_ids(), &diff_ids));
+        }
+    } else {
+        checks.push(Check::pass(
+            "diff-ids",
+            "skipped, image configuration is not stored",
+        ));
+    }
+
+    if max_size.is_some() {
+        let options = LintOptions {
+            max_size,
+            ..Default::default()
+        };
+        checks.push(check_size(name, &manifest, &options));
+    }
+    Ok(checks)
+}
+
+fn blob_path(blob_root: &Path, desc: &Descriptor) -> Result<PathBuf> {
+    let digest = Digest::new(desc.digest())?;
+    Ok(blob_root.join(&digest.algorithm).join(&digest.encoded))
+}
+
+/// Compare the size and digest of the blob with the descriptor
+fn verify_blob(path: &Path, desc: &Descriptor) -> Result<Check> {
+    let name = format!("blob {}", desc.digest());
+    if !path.is_file() {
+        return Ok(Check::fail(&name, "missing in local storage"));
+    }
+    let size = fs::metadata(path)?.len();
+    if size != desc.size() as u64 {
+        return Ok(Check::fail(
+            &name,
+            format!("{} bytes, but {} bytes expected", size, desc.size()),
+        ));
+    }
+    let expected = Digest::new(desc.digest())?;
+    Ok(match expected.verify_reader(fs::File::open(path)?) {
+        Ok(()) => Check::pass(&name, format!("{} bytes", size)),
+        Err(Error::DigestMismatch { actual, .. }) => {
+            Check::fail(&name, format!("content has {}", actual))
+        }
+        Err(e) => return Err(e),
+    })
+}
+
+/// Digest of uncompressed layer, or `None` for unknown media type
+fn diff_id(path: &Path, media_type: &MediaType) -> Result<Option<Digest>> {
+    let f = fs::File::open(path)?;
+    Ok(match media_type {
+        MediaType::ImageLayer => Some(Digest::from_reader_sha256(f)?),
+        MediaType::ImageLayerGzip => {
+            Some(Digest::from_reader_sha256(flate2::read::GzDecoder::new(f))?)
+        }
+        _ => None,
+    })
+}
+
+fn check_diff_ids(expected: &[String], actual: &[Option<Digest>]) -> Check {
+    if expected.len() != actual.len() {
+        return Check::fail(
+            "diff-ids",
+            format!(
+                "{} diff_ids in configuration, but {} layers in manifest",
+                expected.len(),
+                actual.len()
+            ),
+        );
+    }
+    let mismatched: Vec<String> = expected
+        .iter()
+        .zip(actual)
+        .filter_map(|(expected, actual)| match actual {
+            Some(actual) if &actual.to_string() != expected => Some(expected.clone()),
+            _ => None,
+        })
+        .collect();
+    if mismatched.is_empty() {
+        Check::pass("diff-ids", "uncompressed layers match the configuration")
+    } else {
+        Check::fail(
+            "diff-ids",
+            format!("layers do not match {}", mismatched.join(", ")),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use oci_spec::image::DescriptorBuilder;
+
+    #[test]
+    fn verify_layer_blob() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let mut tar = tar::Builder::new(Vec::new());
+        let mut header = tar::Header::new_gnu();
+        header.set_size(4);
+        header.set_cksum();
+        tar.append_data(&mut header, "test.txt", &b"test"[..])?;
+        let tar = tar.into_inner()?;
+        let mut gz = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        std::io::Write::write_all(&mut gz, &tar)?;
+        let blob = gz.finish()?;
+
+        let desc = DescriptorBuilder::default()
+            .media_type(MediaType::ImageLayerGzip)
+            .digest(Digest::from_buf_sha256(&blob).to_string())
+            .size(blob.len() as i64)
+            .build()
+            .unwrap();
+        let path = blob_path(dir.path(), &desc)?;
+        fs::create_dir_all(path.parent().unwrap())?;
+        fs::write(&path, &blob)?;
+        assert!(verify_blob(&path, &desc)?.passed);
+
+        let diff_id = diff_id(&path, desc.media_type())?;
+        let expected = vec![Digest::from_buf_sha256(&tar).to_string()];
+        assert!(check_diff_ids(&expected, std::slice::from_ref(&diff_id)).passed);
+        assert!(!check_diff_ids(&[desc.digest().clone()], &[diff_id]).passed);
+
+        let mut corrupted = blob.clone();
+        *corrupted.last_mut().unwrap() ^= 1;
+        fs::write(&path, &corrupted)?;
+        assert!(!verify_blob(&path, &desc)?.passed);
+        fs::remove_file(&path)?;
+        assert!(!verify_blob(&path, &desc)?.passed);
+        Ok(())
+    }
+}
@@ -0,0 +1,139 @@
+//! Lockfile pinning images to digests
+//!
+//! `ocipkg.lock` in the project, found in the current directory or its ancestors,
+//! maps image names to the digests of their manifests:
+//!
+//! ```toml
+//! [images]
+//! "ghcr.io/termoshtt/ocipkg/static/linux:0.1.0" = "sha256:..."
+//! ```
+//!
+//! Pulls of locked images, e.g. by `ocipkg get` and build scripts, resolve the tag first,
+//! and fail with [Error::LockedDigestMismatch] if it points to another manifest.
+//! Images not in the lockfile are pulled as before.
+//! The lockfile is created and updated by `ocipkg lock update`.
+
+use crate::{error::*, Digest, ImageName};
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, fs, path::*};
+
+/// File name of the lockfile
+pub const LOCKFILE: &str = "ocipkg.lock";
+
+/// Content of [LOCKFILE]
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Lockfile {
+    /// Map from image names to digests of their manifests
+    #[serde(default)]
+    images: BTreeMap<String, String>,
+}
+
+impl Lockfile {
+    /// Load the lockfile of the project, or an empty one if not exists
+    pub fn load() -> Result<Self> {
+        match lockfile_path() {
+            Some(path) => Self::from_file(&path),
+            None => Ok(Self::default()),
+        }
+    }
+
+    /// Load a lockfile, where all digests are validated
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let lockfile: Self = toml::from_str(&fs::read_to_string(path)?)?;
+        for digest in lockfile.images.values() {
+            Digest::new(digest)?;
+        }
+        Ok(lockfile)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        fs::write(path, toml::to_string(self)?)?;
+        Ok(())
+    }
+
+    /// Digest locked for the image
+    pub fn get(&self, image_name: &ImageName) -> Option<Digest> {
+        let digest = self.images.get(&image_name.to_string())?;
+        Some(Digest::new(digest).expect("Validated in Lockfile::from_file"))
+    }
+
+    /// Lock the image to the digest, replacing the existing entry
+    pub fn insert(&mut self, image_name: &ImageName, digest: &Digest) {
+        self.images
+            .insert(image_name.to_string(), digest.to_string());
+    }
+
+    /// Locked images in order of their names
+    pub fn image_names(&self) -> Result<Vec<ImageName>> {
+        self.images
+            .keys()
+            .map(|name| ImageName::parse(name))
+            .collect()
+    }
+
+    /// Check the digest which the image is resolved to is the locked one
+    ///
+    /// Images not in the lockfile are always accepted.
+    pub fn check(&self, image_name: &ImageName, actual: &Digest) -> Result<()> {
+        match self.get(image_name) {
+            Some(locked) if &locked != actual => Err(Error::LockedDigestMismatch {
+                image: image_name.to_string(),
+                locked,
+                actual: actual.clone(),
+            }),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Path of [LOCKFILE] in the current directory or its ancestors
+pub fn lockfile_path() -> Option<PathBuf> {
+    let cwd = std::env::current_dir().ok()?;
+    cwd.ancestors()
+        .map(|dir| dir.join(LOCKFILE))
+        .find(|path| path.is_file())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DIGEST1: &str = "sha256:e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+    const DIGEST2: &str = "sha256:2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824";
+
+    #[test]
+    fn lockfile() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join(LOCKFILE);
+        let image_name = ImageName::parse("ghcr.io/termoshtt/ocipkg/static/linux:0.1.0")?;
+        let other = ImageName::parse("ghcr.io/termoshtt/ocipkg/dynamic/linux:0.1.0")?;
+
+        let mut lockfile = Lockfile::default();
+        lockfile.insert(&image_name, &Digest::new(DIGEST1)?);
+        lockfile.save(&path)?;
+        let lockfile = Lockfile::from_file(&path)?;
+        assert_eq!(lockfile.get(&image_name), Some(Digest::new(DIGEST1)?));
+        assert_eq!(lockfile.image_names()?, vec![image_name.clone()]);
+
+        lockfile.check(&image_name, &Digest::new(DIGEST1)?)?;
+        assert!(matches!(
+            lockfile.check(&image_name, &Digest::new(DIGEST2)?),
+            Err(Error::LockedDigestMismatch { .. })
+        ));
+        // Not locked
+        lockfile.check(&other, &Digest::new(DIGEST2)?)?;
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_digest() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join(LOCKFILE);
+        fs::write(&path, "[images]\n\"ghcr.io/a/b:1\" = \"latest\"\n")?;
+        assert!(matches!(
+            Lockfile::from_file(&path),
+            Err(Error::InvalidDigest(_))
+        ));
+        Ok(())
+    }
+}
@@ -11,6 +11,8 @@ use std::fmt;
 /// > [a-zA-Z0-9_][a-zA-Z0-9._-]{0,127}
 /// > ```
 /// This struct checks this restriction at creation.
+///
+/// `<reference>` may also be a digest, e.g. `sha256:0123...`, when the manifest is pulled or pushed by digest.
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct Reference(String);
 
@@ -29,6 +31,7 @@ impl fmt::Display for Reference {
 
 lazy_static::lazy_static! {
     static ref REF_RE: Regex = Regex::new(r"^[a-zA-Z0-9_][a-zA-Z0-9._-]{0,127}$").unwrap();
+    static ref DIGEST_RE: Regex = Regex::new(r"^[a-z0-9]+([+._-][a-z0-9]+)*:[a-zA-Z0-9=_-]+$").unwrap();
 }
 
 impl Reference {
@@ -37,12 +40,17 @@ impl Reference {
     }
 
     pub fn new(name: &str) -> Result<Self> {
-        if REF_RE.is_match(name) {
+        if REF_RE.is_match(name) || DIGEST_RE.is_match(name) {
             Ok(Reference(name.to_string()))
         } else {
             Err(Error::InvalidReference(name.to_string()))
         }
     }
+
+    /// Check if this reference is a digest rather than a tag
+    pub fn is_digest(&self) -> bool {
+        DIGEST_RE.is_match(&self.0)
+    }
 }
 
 #[cfg(test)]
@@ -55,4 +63,15 @@ mod tests {
         // @ is not allowed
         assert!(Reference::new("my_super_tag@2").is_err());
     }
+
+    #[test]
+    fn digest_reference() {
+        let r = Reference::new(
+            "sha256:6c3c624b58dbbcd3c0dd82b4c53f04194d1247c6eebdaab7c610cf7d66709b3b",
+        )
+        .unwrap();
+        assert!(r.is_digest());
+        assert!(!Reference::new("latest").unwrap().is_digest());
+        assert!(Reference::new("sha256:").is_err());
+    }
 }